@@ -3,11 +3,12 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
 use canvas_traits::canvas::{
-    CompositionOptions, FillOrStrokeStyle, FillRule, LineOptions, Path, ShadowOptions,
+    Acceleration, CanvasSizeLimits, CompositionOptions, FillOrStrokeStyle, FillRule, GlyphHinting,
+    LineOptions, Path,
 };
 use compositing_traits::SerializableImageData;
 use euclid::default::{Point2D, Rect, Size2D, Transform2D};
-use pixels::Snapshot;
+use pixels::{Snapshot, SnapshotAlphaMode, SnapshotPixelFormat};
 use webrender_api::ImageDescriptor;
 
 use crate::canvas_data::{Filter, TextRun};
@@ -18,6 +19,14 @@ pub(crate) trait GenericDrawTarget {
     type SourceSurface;
 
     fn new(size: Size2D<u32>) -> Self;
+    /// The largest draw target that this backend can allocate.
+    fn size_limits() -> CanvasSizeLimits {
+        CanvasSizeLimits::default()
+    }
+    /// Where this backend draws.
+    fn acceleration() -> Acceleration {
+        Acceleration::Software
+    }
     fn create_similar_draw_target(&self, size: &Size2D<i32>) -> Self;
 
     fn clear_rect(&mut self, rect: &Rect<f32>, transform: Transform2D<f32>);
@@ -37,13 +46,6 @@ pub(crate) trait GenericDrawTarget {
         composition_options: CompositionOptions,
         transform: Transform2D<f32>,
     );
-    fn draw_surface_with_shadow(
-        &self,
-        surface: Self::SourceSurface,
-        dest: &Point2D<f32>,
-        shadow_options: ShadowOptions,
-        composition_options: CompositionOptions,
-    );
     fn fill(
         &mut self,
         path: &Path,
@@ -57,6 +59,7 @@ pub(crate) trait GenericDrawTarget {
         text_runs: Vec<TextRun>,
         start: Point2D<f32>,
         style: FillOrStrokeStyle,
+        hinting: GlyphHinting,
         composition_options: CompositionOptions,
         transform: Transform2D<f32>,
     );
@@ -92,6 +95,27 @@ pub(crate) trait GenericDrawTarget {
         &mut self,
     ) -> (ImageDescriptor, SerializableImageData);
     fn snapshot(&mut self) -> Snapshot;
+    /// Returns a snapshot of the pixels within `rect`, which must be within the draw target.
+    /// Backends that can copy a region in place should override this, since the default
+    /// copies the whole surface first.
+    fn snapshot_rect(&mut self, rect: Rect<u32>) -> Snapshot {
+        self.snapshot().get_rect(rect)
+    }
+    /// Returns the non-premultiplied RGBA color of the pixel at `point`, which must be
+    /// within the draw target. Backends that can read a single pixel in place should
+    /// override this, since the default copies the whole surface.
+    fn get_pixel(&mut self, point: Point2D<u32>) -> [u8; 4] {
+        let (data, _, _) = self
+            .snapshot()
+            .get_rect(Rect::new(point, Size2D::new(1, 1)))
+            .to_vec(
+                Some(SnapshotAlphaMode::Transparent {
+                    premultiplied: false,
+                }),
+                Some(SnapshotPixelFormat::RGBA),
+            );
+        [data[0], data[1], data[2], data[3]]
+    }
 }
 
 #[allow(dead_code)] // used by gated backends
@@ -2,23 +2,44 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use std::collections::HashMap;
+use std::f64::consts::{SQRT_2, TAU};
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::mem;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use app_units::Au;
+use base::cross_process_instant::CrossProcessInstant;
 use canvas_traits::canvas::*;
-use compositing_traits::CrossProcessCompositorApi;
+use compositing_traits::{CrossProcessCompositorApi, SerializableImageData};
 use euclid::default::{Box2D, Point2D, Rect, Size2D, Transform2D, Vector2D};
 use euclid::point2;
 use fonts::{
-    ByteIndex, FontBaseline, FontContext, FontGroup, FontMetrics, FontRef, GlyphInfo, GlyphStore,
-    LAST_RESORT_GLYPH_ADVANCE, ShapingFlags, ShapingOptions,
+    ByteIndex, FontBaseline, FontContext, FontGroup, FontMetrics, FontRef, GlyphData, GlyphId,
+    GlyphInfo, GlyphStore, LAST_RESORT_GLYPH_ADVANCE, ShapingFlags, ShapingOptions,
 };
+use ipc_channel::ipc::IpcSharedMemory;
+use kurbo::{ParamCurve, ParamCurveArclen, PathEl, Shape};
 use log::warn;
-use pixels::Snapshot;
+use pixels::{
+    EncodedImageType, Snapshot, SnapshotAlphaMode, SnapshotPixelFormat,
+    approximate_shadow_rgba8_inplace, color_matrix_rgba8_inplace, drop_shadow_rgba8_inplace,
+    flip_x_rgba8_image_inplace, flip_y_rgba8_image_inplace, gaussian_blur_rgba8_inplace,
+    mask_rgba8_inplace, multiply_u8_color, posterize_rgba8_inplace, rgba8_linear_to_srgb_inplace,
+    rgba8_srgb_to_linear_inplace, shadow_rgba8_inplace, spread_rgba8_inplace, srgb_to_linear,
+    unmultiply_inplace, unmultiply_u8_color,
+};
 use range::Range;
+use servo_arc::Arc as ServoArc;
+use style::Atom;
+use style::color::{AbsoluteColor, ColorSpace};
+use style::properties::style_structs::Font as FontStyleStruct;
+use style::values::computed::font::{FamilyName, FontFamilyNameSyntax, SingleFontFamily};
+use unicode_bidi::{BidiInfo, Level};
 use unicode_script::Script;
-use webrender_api::ImageKey;
+use webrender_api::{ImageDescriptor, ImageKey, ImageRendering};
 
 use crate::backend::GenericDrawTarget;
 
@@ -31,6 +52,8 @@ struct UnshapedTextRun<'a> {
     font: Option<FontRef>,
     script: Script,
     string: &'a str,
+    /// Whether the run has a right-to-left bidi embedding level.
+    is_rtl: bool,
 }
 
 impl UnshapedTextRun<'_> {
@@ -46,7 +69,7 @@ impl UnshapedTextRun<'_> {
         }
     }
 
-    fn into_shaped_text_run(self) -> Option<TextRun> {
+    fn into_shaped_text_run(self, shaping_tags: ShapingTags) -> Option<TextRun> {
         let font = self.font?;
         if self.string.is_empty() {
             return None;
@@ -61,19 +84,41 @@ impl UnshapedTextRun<'_> {
             letter_spacing: None,
             word_spacing,
             script: self.script,
-            flags: ShapingFlags::empty(),
+            script_tag: shaping_tags.script,
+            language_tag: shaping_tags.language,
+            flags: if self.is_rtl {
+                ShapingFlags::RTL_FLAG
+            } else {
+                ShapingFlags::empty()
+            },
         };
         let glyphs = font.shape_text(self.string, &options);
-        Some(TextRun { font, glyphs })
+        Some(TextRun {
+            font,
+            glyphs,
+            visible_glyphs: 0..usize::MAX,
+            pixel_snapped: false,
+        })
     }
 }
 
 pub(crate) struct TextRun {
     pub(crate) font: FontRef,
     pub(crate) glyphs: Arc<GlyphStore>,
+    /// The indices of the glyphs of the run that are drawn. The others still advance the
+    /// position of the glyphs after them.
+    pub(crate) visible_glyphs: std::ops::Range<usize>,
+    /// Whether the origins of the glyphs are rounded to device pixels when they are drawn.
+    pub(crate) pixel_snapped: bool,
 }
 
 impl TextRun {
+    fn glyph_count(&self) -> usize {
+        self.glyphs
+            .iter_glyphs_for_byte_range(&Range::new(ByteIndex(0), self.glyphs.len()))
+            .count()
+    }
+
     fn bounding_box(&self) -> Rect<f32> {
         let mut bounding_box = None;
         let mut bounds_offset: f32 = 0.;
@@ -96,35 +141,465 @@ impl TextRun {
     }
 }
 
+/// The alignment-independent measurements of a string, as produced by shaping it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TextMeasurements {
+    width: f32,
+    bounding_box: Rect<f32>,
+    ascent: f32,
+    descent: f32,
+    hanging_baseline: f32,
+    ideographic_baseline: f32,
+    alphabetic_baseline: f32,
+}
+
+/// The number of distinct strings whose measurements are remembered for the current font.
+const MEASURE_TEXT_CACHE_SIZE: usize = 256;
+
+/// The number of rectangles of damage kept apart before they are merged into one.
+const MAX_DAMAGE_RECTS: usize = 8;
+
+/// The number of parts that [`interpolate_gradient_stops`] divides the space between two
+/// stops of a gradient into.
+const GRADIENT_INTERPOLATION_STEPS: usize = 16;
+
+/// The default of [`CanvasData::max_shadow_blur`]. Blurs this large already spread shadows
+/// hundreds of pixels past their shapes.
+const DEFAULT_MAX_SHADOW_BLUR: f64 = 256.;
+
+/// How many times larger than a pass needs the scratch surface of [`CanvasData`] may be
+/// before it is reallocated at the smaller size.
+const SCRATCH_SURFACE_MAX_SLACK: i64 = 4;
+
+/// The measurements of recently measured strings, so that measuring the same string again
+/// does not shape it again. All entries belong to a single font, and they are discarded
+/// as soon as a string is measured with a different one.
+#[derive(Default)]
+struct MeasureTextCache {
+    font: Option<ServoArc<FontStyleStruct>>,
+    /// The measurements, keyed by the string, whether it was measured with a right-to-left
+    /// base direction and the tags it was shaped with, along with whether they were used
+    /// since the last [`Self::trim`].
+    entries: HashMap<(String, bool, ShapingTags), (TextMeasurements, bool)>,
+}
+
+impl MeasureTextCache {
+    fn get_or_insert_with(
+        &mut self,
+        font: &ServoArc<FontStyleStruct>,
+        text: String,
+        is_rtl: bool,
+        shaping_tags: ShapingTags,
+        measure: impl FnOnce(&str) -> TextMeasurements,
+    ) -> TextMeasurements {
+        if self.font.as_ref() != Some(font) {
+            self.font = Some(font.clone());
+            self.entries.clear();
+        }
+        let key = (text, is_rtl, shaping_tags);
+        if let Some((measurements, used)) = self.entries.get_mut(&key) {
+            *used = true;
+            return *measurements;
+        }
+
+        let measurements = measure(&key.0);
+        if self.entries.len() >= MEASURE_TEXT_CACHE_SIZE {
+            self.entries.clear();
+        }
+        self.entries.insert(key, (measurements, true));
+        measurements
+    }
+
+    /// Drops the measurements that weren't used since the previous trim.
+    fn trim(&mut self) {
+        self.entries.retain(|_, (_, used)| mem::take(used));
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
 #[derive(Clone, Copy)]
 pub(crate) enum Filter {
     Bilinear,
     Nearest,
 }
 
+/// Opaque solid-color rectangles whose drawing is deferred, so that consecutive ones of the
+/// same color are filled as a single path.
+struct BatchedFill {
+    color: AbsoluteColor,
+    /// The rectangles, in device space.
+    path: Path,
+}
+
+/// A layer begun with [`CanvasData::begin_layer`], which is drawn to instead of the draw
+/// target it was begun on until it ends.
+struct Layer<DrawTarget> {
+    /// The draw target that the layer is composited onto when it ends.
+    parent: DrawTarget,
+    composition_options: CompositionOptions,
+    /// The [`CanvasData::clip_bounds`] of the parent.
+    parent_clip_bounds: Vec<Rect<f32>>,
+}
+
+/// A transaction begun with [`CanvasData::begin_transaction`].
+struct Transaction {
+    /// The pixels of the surface of the canvas when the transaction began, copied once
+    /// something was about to change them.
+    snapshot: Option<Snapshot>,
+    /// The number of layers that were open when the transaction began.
+    layer_depth: usize,
+}
+
 pub(crate) struct CanvasData<DrawTarget: GenericDrawTarget> {
     drawtarget: DrawTarget,
     compositor_api: CrossProcessCompositorApi,
     image_key: ImageKey,
     font_context: Arc<FontContext>,
+    state: DrawingState,
+    text: TextSettings,
+    rendering: RenderingSettings,
+    upload: ImageUpload,
+    diagnostics: CanvasDiagnostics,
+    /// An intermediate surface reused by shadow passes, so that they don't each allocate
+    /// their own. It is transparent black between uses.
+    scratch_draw_target: Option<DrawTarget>,
+    /// The number of device pixels per CSS pixel in each direction of the draw target,
+    /// before [`Self::render_scale`] reduces it.
+    scale_factor: u32,
+    /// The fraction of the resolution given by [`Self::scale_factor`] that the draw target
+    /// is allocated at. WebRender upscales the image to the size of the canvas.
+    render_scale: f32,
+    /// Fills that have yet to be drawn. Every other operation draws them first, so they
+    /// are never reordered with respect to it.
+    batched_fill: Option<BatchedFill>,
+    /// For each clip that [`Self::clip_path`] has pushed onto the draw target and that has
+    /// yet to be popped, the device-space bounds of its intersection with the clips below
+    /// it. Recreating the draw target drops all of them.
+    clip_bounds: Vec<Rect<f32>>,
+    /// The region of a larger logical canvas, in CSS pixels, that the draw target shows.
+    /// Drawing is offset by its origin and clipped to its size.
+    viewport: Option<Rect<f32>>,
+    /// Whether the clip to the size of [`Self::viewport`] is pushed onto the draw target,
+    /// below those of [`Self::clip_bounds`].
+    viewport_clipped: bool,
+    /// The layers that have begun and not yet ended, innermost last. While there are any,
+    /// [`Self::drawtarget`] is the surface of the innermost one.
+    layers: Vec<Layer<DrawTarget>>,
+    /// The size of the draw target, if it has yet to be allocated or
+    /// [`Self::reclaim_surface`] released it. Until [`Self::restore_reclaimed_surface`]
+    /// allocates and clears it, the draw target is a placeholder.
+    reclaimed_surface_size: Option<Size2D<i32>>,
+    /// The pixels kept by [`Self::save_region`], as the draw target stores them.
+    saved_regions: HashMap<SavedRegionId, Snapshot>,
+    next_saved_region_id: SavedRegionId,
+    /// The transaction that is open, if any.
+    transaction: Option<Transaction>,
+}
+
+/// The drawing state that persists across messages, for the operations that don't carry
+/// their own.
+struct DrawingState {
+    /// The style used by fill operations that do not specify their own.
+    fill_style: FillOrStrokeStyle,
+    /// The style used by stroke operations that do not specify their own.
+    stroke_style: FillOrStrokeStyle,
+    /// The global alpha and compositing operation used by drawing operations that do not
+    /// specify their own. They persist across messages, however those are batched, until
+    /// they are set again or the canvas is recreated.
+    composition_options: CompositionOptions,
+    /// The image smoothing state of the context, as last set. Images are drawn with the
+    /// smoothing that their messages carry.
+    image_smoothing_enabled: bool,
+    image_smoothing_quality: ImageSmoothingQuality,
+    /// The filter chain applied to the result of each drawing operation.
+    filter: Vec<FilterFunction>,
+    focus_ring_style: FocusRingStyle,
+}
+
+impl Default for DrawingState {
+    fn default() -> Self {
+        Self {
+            fill_style: FillOrStrokeStyle::Color(AbsoluteColor::BLACK),
+            stroke_style: FillOrStrokeStyle::Color(AbsoluteColor::BLACK),
+            composition_options: CompositionOptions {
+                alpha: 1.,
+                composition_operation: CompositionOrBlending::default(),
+            },
+            image_smoothing_enabled: true,
+            image_smoothing_quality: ImageSmoothingQuality::default(),
+            filter: Vec::new(),
+            focus_ring_style: FocusRingStyle::default(),
+        }
+    }
+}
+
+/// How text is shaped and rasterized.
+struct TextSettings {
+    /// How the glyphs of text are fitted to the pixel grid when they are drawn.
+    glyph_hinting: GlyphHinting,
+    /// Whether the baseline and the origins of the glyphs of text are rounded to device
+    /// pixels, rather than positioned at fractions of pixels.
+    text_pixel_snapping: bool,
+    /// The families that text falls back to, in order, for characters that the fonts of its
+    /// own families lack, before the fallback fonts of the platform.
+    fallback_font_families: Vec<SingleFontFamily>,
+    /// The gamma with which the coverage of filled text is corrected, where 1 leaves it as
+    /// rasterized.
+    text_gamma: f32,
+    measure_text_cache: MeasureTextCache,
+}
+
+impl Default for TextSettings {
+    fn default() -> Self {
+        Self {
+            glyph_hinting: GlyphHinting::default(),
+            text_pixel_snapping: false,
+            fallback_font_families: Vec::new(),
+            text_gamma: 1.,
+            measure_text_cache: MeasureTextCache::default(),
+        }
+    }
+}
+
+/// How drawing operations are rasterized and blended, trading quality for speed.
+struct RenderingSettings {
+    /// How the edges of filled and stroked rectangles are rounded to device pixels.
+    coordinate_rounding: CoordinateRounding,
+    /// Whether the canvas draws in low-power mode, in which images are not smoothed, clips to
+    /// axis-aligned rectangles are rounded to whole pixels and shadows are blurred with a box
+    /// blur rather than a Gaussian one.
+    low_power_mode: bool,
+    /// Like [`TextSettings::text_gamma`], for the edges of paths and rectangles.
+    shape_gamma: f32,
+    /// The largest shadow blur that is applied. The time that blurring takes grows with
+    /// the blur, so larger values are clamped to keep any single draw responsive.
+    max_shadow_blur: f64,
+    /// Whether the widths of lines are in device pixels rather than in user units, so that
+    /// lines keep their thickness whatever the transform.
+    device_pixel_line_widths: bool,
+    /// How colors outside of the sRGB gamut of the draw target are brought into it when
+    /// styles are set.
+    gamut_mapping: GamutMapping,
+    /// How the colors of gradients are interpolated between stops that differ in alpha,
+    /// when styles are set.
+    gradient_interpolation: GradientInterpolation,
+    /// The seed of the noise that gradient fills are dithered with, if they are dithered.
+    gradient_dither_seed: Option<u64>,
+    /// The space in which dithered gradient fills keep their mean color.
+    gradient_dither_space: DitherSpace,
+    /// How the result of each drawing operation is blended with the draw target. Outside
+    /// of the sRGB space, every operation draws into a layer first, like filtered ones.
+    blending_space: BlendingSpace,
+    /// Whether opaque solid-color rectangle fills are batched into
+    /// [`CanvasData::batched_fill`].
+    batch_opaque_fills: bool,
+}
+
+impl RenderingSettings {
+    fn new(blending_space: BlendingSpace) -> Self {
+        Self {
+            coordinate_rounding: CoordinateRounding::default(),
+            low_power_mode: false,
+            shape_gamma: 1.,
+            max_shadow_blur: DEFAULT_MAX_SHADOW_BLUR,
+            device_pixel_line_widths: false,
+            gamut_mapping: GamutMapping::Clip,
+            gradient_interpolation: GradientInterpolation::default(),
+            gradient_dither_seed: None,
+            gradient_dither_space: DitherSpace::Srgb,
+            blending_space,
+            batch_opaque_fills: false,
+        }
+    }
+}
+
+/// How and when the pixels of the draw target are sent to WebRender.
+struct ImageUpload {
+    /// Whether pixels are read back and sent to WebRender bottom-up, as GL textures expect,
+    /// which spares GL consumers from flipping every frame.
+    y_flipped: bool,
+    /// Whether updates to the image in WebRender wait for the next [`CanvasData::vsync`].
+    frame_pacing: bool,
+    /// Whether an update to the image in WebRender was requested since the last vsync.
+    image_update_pending: bool,
+    /// How the compositor is asked to filter the image when presenting it at a size other
+    /// than that of the draw target.
+    image_rendering: ImageRendering,
+    /// Whether the pixels sent to WebRender are premultiplied by their alpha.
+    premultiplied_upload: bool,
+    /// The rectangles of the draw target, in device pixels, whose pixels may have changed
+    /// since the image was last sent to WebRender.
+    damage: Vec<Rect<u32>>,
+    /// The time spent processing the messages for the canvas since the image was last sent
+    /// to WebRender, which is reported along with the next update of the image. `None`
+    /// unless the messages were timed, and then the update isn't timed either.
+    frame_time: Option<Duration>,
+    /// When the image was last sent to WebRender, if it has been.
+    last_upload: Option<Instant>,
+}
+
+impl ImageUpload {
+    fn new(y_flipped: bool) -> Self {
+        Self {
+            y_flipped,
+            frame_pacing: false,
+            image_update_pending: false,
+            image_rendering: ImageRendering::Auto,
+            premultiplied_upload: true,
+            damage: Vec::new(),
+            frame_time: None,
+            last_upload: None,
+        }
+    }
+}
+
+/// What the canvas reports about the work that it did, for profiling and testing.
+struct CanvasDiagnostics {
+    draw_stats: CanvasDrawStats,
+    acceleration: CanvasAcceleration,
+    /// Whether the operation underway is drawn on the CPU whatever the backend, as through
+    /// a filter or with corrected text coverage.
+    drawing_in_software: bool,
+    /// Whether the bounding box of the last drawing operation, once transformed, lay
+    /// entirely outside of the draw target.
+    last_draw_out_of_bounds: bool,
+    /// Whether the text last filled was wider than its maximum width.
+    last_text_truncated: bool,
+    /// The number of messages processed for the canvas, as advanced by the paint thread.
+    sequence: u64,
+    /// The number of times [`CanvasData::recreate`] replaced the draw target.
+    generation: u64,
+    /// When the pixels of the draw target were last damaged, if they have been, as of the
+    /// upload of the image or the query of this time that followed.
+    last_modified: Option<CrossProcessInstant>,
+    /// Whether the pixels were damaged since [`Self::last_modified`] was last updated.
+    modified: bool,
+    /// The rectangle and transform of the last clear, with the [`Self::sequence`] of the
+    /// message that did it. Repeating that clear in the very next message has no effect.
+    last_clear: Option<(Rect<f32>, Transform2D<f32>, u64)>,
+}
+
+impl CanvasDiagnostics {
+    fn new(acceleration: Acceleration) -> Self {
+        Self {
+            draw_stats: CanvasDrawStats::default(),
+            acceleration: CanvasAcceleration {
+                fills: acceleration,
+                strokes: acceleration,
+                images: acceleration,
+                texts: acceleration,
+                shadows: Acceleration::Software,
+                filters: Acceleration::Software,
+            },
+            drawing_in_software: false,
+            last_draw_out_of_bounds: false,
+            last_text_truncated: false,
+            sequence: 0,
+            generation: 0,
+            last_modified: None,
+            modified: false,
+            last_clear: None,
+        }
+    }
 }
 
 impl<DrawTarget: GenericDrawTarget> CanvasData<DrawTarget> {
+    /// Creates a canvas of `size` CSS pixels, which fails if it has no device pixels to
+    /// scale them to. A canvas whose backing store would be larger than the draw target
+    /// allows is empty.
     pub(crate) fn new(
         size: Size2D<u64>,
+        scale_factor: u32,
+        render_scale: f32,
         compositor_api: CrossProcessCompositorApi,
         font_context: Arc<FontContext>,
-    ) -> CanvasData<DrawTarget> {
-        let size = size.max(MIN_WR_IMAGE_SIZE);
-        let mut draw_target = DrawTarget::new(size.cast());
+        y_flipped: bool,
+        blending_space: BlendingSpace,
+    ) -> Result<CanvasData<DrawTarget>, CanvasError> {
+        if scale_factor == 0 || !(render_scale > 0. && render_scale.is_finite()) {
+            warn!("Invalid canvas scale factor {scale_factor} or render scale {render_scale}");
+            return Err(CanvasError::CreationFailed(size));
+        }
+        let size = backing_store_size(
+            size,
+            scale_factor,
+            render_scale,
+            &DrawTarget::size_limits(),
+        );
+        // Until a message needs the surface, the canvas shows a transparent placeholder, as
+        // many canvases are never drawn to.
+        let mut draw_target = DrawTarget::new(MIN_WR_IMAGE_SIZE.cast());
         let image_key = compositor_api.generate_image_key_blocking().unwrap();
         let (descriptor, data) = draw_target.image_descriptor_and_serializable_data();
+        let data = maybe_flip_image_data(y_flipped, &descriptor, data);
         compositor_api.add_image(image_key, descriptor, data);
-        CanvasData {
+        Ok(CanvasData {
             drawtarget: draw_target,
             compositor_api,
             image_key,
             font_context,
+            state: DrawingState::default(),
+            text: TextSettings::default(),
+            rendering: RenderingSettings::new(blending_space),
+            upload: ImageUpload::new(y_flipped),
+            diagnostics: CanvasDiagnostics::new(DrawTarget::acceleration()),
+            scratch_draw_target: None,
+            scale_factor,
+            render_scale,
+            batched_fill: None,
+            clip_bounds: Vec::new(),
+            viewport: None,
+            viewport_clipped: false,
+            layers: Vec::new(),
+            reclaimed_surface_size: Some(size.cast()),
+            saved_regions: HashMap::new(),
+            next_saved_region_id: SavedRegionId(0),
+            transaction: None,
+        })
+    }
+
+    /// The transform from CSS pixels to the device pixels of the draw target, which
+    /// drawing operations apply after their own.
+    pub(crate) fn default_transform(&self) -> Transform2D<f32> {
+        let scale = self.scale_factor as f32 * self.render_scale;
+        let origin = self
+            .viewport
+            .map_or(Point2D::origin(), |viewport| viewport.origin);
+        Transform2D::translation(-origin.x, -origin.y).then_scale(scale, scale)
+    }
+
+    pub(crate) fn set_viewport(&mut self, viewport: Option<Rect<f32>>) {
+        if !self.layers.is_empty() {
+            return;
+        }
+        self.pop_clips(self.clip_bounds.len());
+        if self.viewport_clipped {
+            self.drawtarget.pop_clip();
+        }
+        self.viewport = viewport;
+        self.push_viewport_clip();
+    }
+
+    /// The device pixels that the size of [`Self::viewport`] confines drawing to, if that
+    /// is less than the whole draw target.
+    fn viewport_device_rect(&self) -> Option<Rect<i32>> {
+        let viewport = self.viewport?;
+        let scale = self.scale_factor as f32 * self.render_scale;
+        let rect = Rect::from_size(viewport.size * scale).round_out().to_i32();
+        let canvas_rect = Rect::from_size(self.drawtarget.get_size());
+        (!rect.contains_rect(&canvas_rect))
+            .then(|| rect.intersection(&canvas_rect).unwrap_or_default())
+    }
+
+    /// Pushes the clip to the size of [`Self::viewport`] onto a draw target without clips.
+    fn push_viewport_clip(&mut self) {
+        self.viewport_clipped = false;
+        if let Some(rect) = self.viewport_device_rect() {
+            self.drawtarget.push_clip_rect(&rect);
+            self.viewport_clipped = true;
         }
     }
 
@@ -132,702 +607,8050 @@ impl<DrawTarget: GenericDrawTarget> CanvasData<DrawTarget> {
         self.image_key
     }
 
-    #[allow(clippy::too_many_arguments)]
-    pub(crate) fn draw_image(
-        &mut self,
-        snapshot: Snapshot,
-        dest_rect: Rect<f64>,
-        source_rect: Rect<f64>,
-        smoothing_enabled: bool,
-        shadow_options: ShadowOptions,
-        composition_options: CompositionOptions,
-        transform: Transform2D<f32>,
-    ) {
-        // We round up the floating pixel values to draw the pixels
-        let source_rect = source_rect.ceil();
-        // It discards the extra pixels (if any) that won't be painted
-        let snapshot = if Rect::from_size(snapshot.size().to_f64()).contains_rect(&source_rect) {
-            snapshot.get_rect(source_rect.to_u32())
-        } else {
-            snapshot
-        };
+    /// The fill style to draw with. The stops of its gradient are interpolated only now,
+    /// so that [`Self::set_fill_gradient_stop_colors`] still finds those that were set.
+    pub(crate) fn fill_style(&self) -> FillOrStrokeStyle {
+        let mut style = self.state.fill_style.clone();
+        interpolate_gradient_stops(&mut style, self.rendering.gradient_interpolation);
+        style
+    }
 
-        let writer = |draw_target: &mut DrawTarget, transform| {
-            write_image::<DrawTarget>(
-                draw_target,
-                snapshot,
-                dest_rect,
-                smoothing_enabled,
-                composition_options,
-                transform,
-            );
-        };
+    pub(crate) fn set_fill_style(&mut self, mut style: FillOrStrokeStyle) {
+        map_style_into_gamut(&mut style, self.rendering.gamut_mapping);
+        self.state.fill_style = style;
+    }
 
-        if shadow_options.need_to_draw_shadow() {
-            let rect = Rect::new(
-                Point2D::new(dest_rect.origin.x as f32, dest_rect.origin.y as f32),
-                Size2D::new(dest_rect.size.width as f32, dest_rect.size.height as f32),
-            );
+    /// Like [`Self::fill_style`], for the stroke style.
+    pub(crate) fn stroke_style(&self) -> FillOrStrokeStyle {
+        let mut style = self.state.stroke_style.clone();
+        interpolate_gradient_stops(&mut style, self.rendering.gradient_interpolation);
+        style
+    }
 
-            // TODO(pylbrecht) pass another closure for raqote
-            self.draw_with_shadow(
-                &rect,
-                shadow_options,
-                composition_options,
-                transform,
-                writer,
-            );
-        } else {
-            writer(&mut self.drawtarget, transform);
+    pub(crate) fn set_stroke_style(&mut self, mut style: FillOrStrokeStyle) {
+        map_style_into_gamut(&mut style, self.rendering.gamut_mapping);
+        self.state.stroke_style = style;
+    }
+
+    /// Sets how the colors of the styles set from now on are brought into the sRGB gamut.
+    /// Styles that are already set keep their colors.
+    pub(crate) fn set_gamut_mapping(&mut self, gamut_mapping: GamutMapping) {
+        self.rendering.gamut_mapping = gamut_mapping;
+    }
+
+    /// Sets how the colors of gradients are interpolated from now on, including those of the
+    /// fill and stroke styles that are already set.
+    pub(crate) fn set_gradient_interpolation(&mut self, interpolation: GradientInterpolation) {
+        self.rendering.gradient_interpolation = interpolation;
+    }
+
+    /// Recolor the stops of the gradient of the fill style in place, keeping its geometry.
+    pub(crate) fn set_fill_gradient_stop_colors(&mut self, colors: Vec<AbsoluteColor>) {
+        if !self.state.fill_style.set_gradient_stop_colors(&colors) {
+            warn!("Fill style is not a gradient with {} stops", colors.len());
         }
     }
 
-    #[allow(clippy::too_many_arguments)]
-    pub(crate) fn fill_text_with_size(
-        &mut self,
-        text: String,
-        x: f64,
-        y: f64,
-        max_width: Option<f64>,
-        is_rtl: bool,
-        size: f64,
-        style: FillOrStrokeStyle,
-        text_options: &TextOptions,
-        composition_options: CompositionOptions,
-        transform: Transform2D<f32>,
-    ) {
-        // > Step 2: Replace all ASCII whitespace in text with U+0020 SPACE characters.
-        let text = replace_ascii_whitespace(text);
+    /// Recolor the stops of the gradient of the stroke style in place, keeping its geometry.
+    pub(crate) fn set_stroke_gradient_stop_colors(&mut self, colors: Vec<AbsoluteColor>) {
+        if !self.state.stroke_style.set_gradient_stop_colors(&colors) {
+            warn!("Stroke style is not a gradient with {} stops", colors.len());
+        }
+    }
 
-        // > Step 3: Let font be the current font of target, as given by that object's font
-        // > attribute.
-        let Some(ref font_style) = text_options.font else {
-            return;
-        };
+    pub(crate) fn composition_options(&self) -> CompositionOptions {
+        self.state.composition_options
+    }
 
-        let font_group = self
-            .font_context
-            .font_group_with_size(font_style.clone(), Au::from_f64_px(size));
-        let mut font_group = font_group.write();
-        let Some(first_font) = font_group.first(&self.font_context) else {
-            warn!("Could not render canvas text, because there was no first font.");
-            return;
-        };
+    pub(crate) fn set_global_alpha(&mut self, alpha: f64) {
+        self.state.composition_options.alpha = alpha;
+    }
 
-        let runs = self.build_unshaped_text_runs(&text, &mut font_group);
-        // TODO: This doesn't do any kind of line layout at all. In particular, there needs
-        // to be some alignment along a baseline and also support for bidi text.
-        let shaped_runs: Vec<_> = runs
+    pub(crate) fn set_global_composite_operation(&mut self, operation: CompositionOrBlending) {
+        self.state.composition_options.composition_operation = operation;
+    }
+
+    pub(crate) fn set_fallback_font_families(&mut self, families: Vec<String>) {
+        self.text.fallback_font_families = families
             .into_iter()
-            .filter_map(UnshapedTextRun::into_shaped_text_run)
+            .map(|name| {
+                SingleFontFamily::FamilyName(FamilyName {
+                    name: Atom::from(name),
+                    syntax: FontFamilyNameSyntax::Quoted,
+                })
+            })
             .collect();
-        let total_advance = shaped_runs
-            .iter()
-            .map(|run| run.glyphs.total_advance())
-            .sum::<Au>()
-            .to_f64_px();
+        // Measurements are cached by font and text only.
+        self.text.measure_text_cache = MeasureTextCache::default();
+    }
 
-        // > Step 6: If maxWidth was provided and the hypothetical width of the inline box in the
-        // > hypothetical line box is greater than maxWidth CSS pixels, then change font to have a
-        // > more condensed font (if one is available or if a reasonably readable one can be
-        // > synthesized by applying a horizontal scale factor to the font) or a smaller font, and
-        // > return to the previous step.
-        //
-        // TODO: We only try decreasing the font size here. Eventually it would make sense to use
-        // other methods to try to decrease the size, such as finding a narrower font or decreasing
-        // spacing.
-        if let Some(max_width) = max_width {
-            let new_size = (max_width / total_advance * size).floor().max(5.);
-            if total_advance > max_width && new_size != size {
-                self.fill_text_with_size(
-                    text,
-                    x,
-                    y,
-                    Some(max_width),
-                    is_rtl,
-                    new_size,
-                    style,
-                    text_options,
-                    composition_options,
-                    transform,
-                );
-                return;
-            }
-        }
+    pub(crate) fn trim_glyph_cache(&mut self) {
+        self.text.measure_text_cache.trim();
+        // Fonts don't keep track of when their shaped text was last used.
+        self.font_context.clear_shape_caches();
+    }
 
-        // > Step 7: Find the anchor point for the line of text.
-        let start = self.find_anchor_point_for_line_of_text(
-            x as f32,
-            y as f32,
-            &first_font.metrics,
-            total_advance as f32,
-            is_rtl,
-            text_options,
-        );
+    pub(crate) fn glyph_cache_size(&self) -> usize {
+        self.text.measure_text_cache.len() + self.font_context.shape_cache_len()
+    }
 
-        // > Step 8: Let result be an array constructed by iterating over each glyph in the inline box
-        // > from left to right (if any), adding to the array, for each glyph, the shape of the glyph
-        // > as it is in the inline box, positioned on a coordinate space using CSS pixels with its
-        // > origin is at the anchor point.
-        self.maybe_bound_shape_with_pattern(
-            style,
-            composition_options,
-            &Rect::from_size(Size2D::new(total_advance, size)),
-            transform,
-            |self_, style| {
-                self_.drawtarget.fill_text(
-                    shaped_runs,
-                    start,
-                    style,
-                    composition_options,
-                    transform,
-                );
-            },
-        );
+    pub(crate) fn last_modified(&mut self) -> Option<CrossProcessInstant> {
+        self.update_last_modified();
+        self.diagnostics.last_modified
     }
 
-    #[allow(clippy::too_many_arguments)]
-    /// <https://html.spec.whatwg.org/multipage/#text-preparation-algorithm>
-    pub(crate) fn fill_text(
-        &mut self,
-        text: String,
-        x: f64,
-        y: f64,
-        max_width: Option<f64>,
-        is_rtl: bool,
-        style: FillOrStrokeStyle,
-        text_options: TextOptions,
-        _shadow_options: ShadowOptions,
-        composition_options: CompositionOptions,
-        transform: Transform2D<f32>,
-    ) {
-        let Some(ref font_style) = text_options.font else {
-            return;
-        };
+    /// Takes the time of the damage recorded since it was last taken, if any, once for all
+    /// of the drawing operations that did it.
+    fn update_last_modified(&mut self) {
+        if mem::take(&mut self.diagnostics.modified) {
+            self.diagnostics.last_modified = Some(CrossProcessInstant::now());
+        }
+    }
 
-        let size = font_style.font_size.computed_size();
-        self.fill_text_with_size(
-            text,
-            x,
-            y,
-            max_width,
-            is_rtl,
-            size.px() as f64,
-            style,
-            &text_options,
+    /// Adds the time since `start`, when a message for the canvas began to be processed, to
+    /// [`ImageUpload::frame_time`]. Whatever went into an update of the image that the
+    /// message sent was reported with it already.
+    pub(crate) fn add_frame_time(&mut self, start: Instant) {
+        let start = self
+            .upload
+            .last_upload
+            .map_or(start, |last_upload| last_upload.max(start));
+        *self.upload.frame_time.get_or_insert_default() += start.elapsed();
+    }
+
+    pub(crate) fn set_text_gamma(&mut self, gamma: f32) {
+        if gamma.is_finite() && gamma > 0. {
+            self.text.text_gamma = gamma;
+        }
+    }
+
+    pub(crate) fn set_shape_gamma(&mut self, gamma: f32) {
+        if gamma.is_finite() && gamma > 0. {
+            self.rendering.shape_gamma = gamma;
+        }
+    }
+
+    pub(crate) fn set_max_shadow_blur(&mut self, max_shadow_blur: f64) {
+        if max_shadow_blur >= 0. {
+            self.rendering.max_shadow_blur = max_shadow_blur;
+        }
+    }
+
+    pub(crate) fn set_device_pixel_line_widths(&mut self, device_pixel_line_widths: bool) {
+        self.rendering.device_pixel_line_widths = device_pixel_line_widths;
+    }
+
+    /// Returns `line_options` with its width in user units, converted from device pixels
+    /// if [`Self::device_pixel_line_widths`] is set. The width is divided by the scale of
+    /// `transform`, the square root of its determinant, which is exact for transforms that
+    /// scale uniformly and averages the thickness of lines under the others.
+    pub(crate) fn user_space_line_options(
+        &self,
+        mut line_options: LineOptions,
+        transform: &Transform2D<f32>,
+    ) -> LineOptions {
+        let scale = f64::from(transform.determinant().abs().sqrt());
+        // Degenerate transforms draw nothing whatever the width.
+        if self.rendering.device_pixel_line_widths && scale > 0. {
+            line_options.width /= scale;
+        }
+        line_options
+    }
+
+    pub(crate) fn set_low_power_mode(&mut self, low_power: bool) {
+        self.rendering.low_power_mode = low_power;
+    }
+
+    pub(crate) fn set_image_smoothing(&mut self, enabled: bool, quality: ImageSmoothingQuality) {
+        self.state.image_smoothing_enabled = enabled;
+        self.state.image_smoothing_quality = quality;
+    }
+
+    pub(crate) fn set_text_pixel_snapping(&mut self, snapping: bool) {
+        self.text.text_pixel_snapping = snapping;
+    }
+
+    pub(crate) fn set_coordinate_rounding(&mut self, rounding: CoordinateRounding) {
+        self.rendering.coordinate_rounding = rounding;
+    }
+
+    pub(crate) fn set_glyph_hinting(&mut self, hinting: GlyphHinting) {
+        self.text.glyph_hinting = hinting;
+    }
+
+    pub(crate) fn rendering_state(&self) -> RenderingState {
+        RenderingState {
+            image_smoothing_enabled: self.state.image_smoothing_enabled,
+            image_smoothing_quality: self.state.image_smoothing_quality,
+            glyph_hinting: self.text.glyph_hinting,
+        }
+    }
+
+    /// Starts drawing into a new, transparent layer, which [`Self::end_layer`] composites
+    /// onto the current draw target with `composition_options`.
+    pub(crate) fn begin_layer(&mut self, composition_options: CompositionOptions) {
+        self.flush_batched_fill();
+        let layer = self
+            .drawtarget
+            .create_similar_draw_target(&self.drawtarget.get_size());
+        self.layers.push(Layer {
+            parent: mem::replace(&mut self.drawtarget, layer),
+            composition_options,
+            parent_clip_bounds: mem::take(&mut self.clip_bounds),
+        });
+    }
+
+    /// Composites the innermost layer onto the draw target it was begun on, as a whole.
+    pub(crate) fn end_layer(&mut self) {
+        let Some(Layer {
+            parent,
+            composition_options,
+            parent_clip_bounds,
+        }) = self.layers.pop()
+        else {
+            return;
+        };
+        self.flush_batched_fill();
+        let mut layer = mem::replace(&mut self.drawtarget, parent);
+        self.clip_bounds = parent_clip_bounds;
+        // What was drawn onto the layer may have been sent to WebRender as damage while the
+        // layer wasn't visible yet.
+        self.damage_whole_canvas();
+
+        let snapshot = layer.snapshot();
+        let dest_rect = Rect::from_size(snapshot.size().to_f64());
+        write_image(
+            &mut self.drawtarget,
+            snapshot,
+            dest_rect,
+            false,
             composition_options,
+            Transform2D::identity(),
+        );
+    }
+
+    pub(crate) fn set_focus_ring_style(&mut self, style: FocusRingStyle) {
+        self.state.focus_ring_style = style;
+    }
+
+    /// Strokes a focus ring along `path`. Like the rings of
+    /// <https://html.spec.whatwg.org/multipage/#dom-context-2d-drawfocusifneeded>, it is
+    /// subject to the clip, but not to shadows, the global alpha or the compositing operator.
+    pub(crate) fn draw_focus_ring(&mut self, path: &Path, transform: Transform2D<f32>) {
+        let FocusRingStyle { color, width, dash } = self.state.focus_ring_style.clone();
+        self.stroke_path(
+            path,
+            FillOrStrokeStyle::Color(color),
+            LineOptions {
+                width,
+                cap_style: LineCapStyle::Butt,
+                join_style: LineJoinStyle::Round,
+                miter_limit: 10.,
+                dash,
+                dash_offset: 0.,
+                dash_gap_color: None,
+                alignment: StrokeAlignment::Center,
+            },
+            ShadowOptions {
+                offset_x: 0.,
+                offset_y: 0.,
+                blur: 0.,
+                spread: 0.,
+                color: AbsoluteColor::TRANSPARENT_BLACK,
+            },
+            CompositionOptions {
+                alpha: 1.,
+                composition_operation: CompositionOrBlending::default(),
+            },
             transform,
         );
     }
 
-    /// <https://html.spec.whatwg.org/multipage/#text-preparation-algorithm>
-    /// <https://html.spec.whatwg.org/multipage/#dom-context-2d-measuretext>
-    pub(crate) fn measure_text(&mut self, text: String, text_options: TextOptions) -> TextMetrics {
-        // > Step 2: Replace all ASCII whitespace in text with U+0020 SPACE characters.
-        let text = replace_ascii_whitespace(text);
-        let Some(ref font_style) = text_options.font else {
-            return TextMetrics::default();
+    pub(crate) fn set_gradient_dither(&mut self, seed: Option<u64>, space: DitherSpace) {
+        self.rendering.gradient_dither_seed = seed;
+        self.rendering.gradient_dither_space = space;
+    }
+
+    /// Returns the seed to dither a fill with `style` with, if any.
+    fn dither_seed(&self, style: &FillOrStrokeStyle) -> Option<u64> {
+        match style {
+            FillOrStrokeStyle::LinearGradient(..) | FillOrStrokeStyle::RadialGradient(..) => {
+                self.rendering.gradient_dither_seed
+            },
+            FillOrStrokeStyle::Color(..) | FillOrStrokeStyle::Surface(..) => None,
+        }
+    }
+
+    /// Fill the shape drawn by `draw` with the gradient `style`, dithered with noise derived
+    /// from `seed`. `draw` fills the shape with the style it is given onto a mask, and the
+    /// gradient is then evaluated at full precision for every covered pixel, so that the
+    /// noise breaks up the bands that rounding to 8 bits would otherwise leave, keeping the
+    /// mean color in [`Self::gradient_dither_space`].
+    fn fill_dithered<F>(
+        &mut self,
+        style: &FillOrStrokeStyle,
+        seed: u64,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+        draw: F,
+    ) where
+        F: FnOnce(&mut DrawTarget, FillOrStrokeStyle, CompositionOptions),
+    {
+        let Some(inverse) = transform.cast::<f64>().inverse() else {
+            return;
         };
+        // The mask is dithered on the CPU.
+        self.diagnostics.acceleration.fills = Acceleration::Software;
+        let mut mask = self
+            .drawtarget
+            .create_similar_draw_target(&self.drawtarget.get_size());
+        draw(
+            &mut mask,
+            FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(255, 255, 255, 1.)),
+            CompositionOptions {
+                alpha: 1.,
+                composition_operation: CompositionOrBlending::default(),
+            },
+        );
 
-        let font_group = self.font_context.font_group(font_style.clone());
-        let mut font_group = font_group.write();
-        let font = font_group
-            .first(&self.font_context)
-            .expect("couldn't find font");
-        let ascent = font.metrics.ascent.to_f32_px();
-        let descent = font.metrics.descent.to_f32_px();
-        let runs = self.build_unshaped_text_runs(&text, &mut font_group);
-
-        let shaped_runs: Vec<_> = runs
-            .into_iter()
-            .filter_map(UnshapedTextRun::into_shaped_text_run)
+        let mut snapshot = mask.snapshot();
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: true,
+            },
+            snapshot.format(),
+        );
+        let width = snapshot.size().width as usize;
+        let format = snapshot.format();
+        let space = self.rendering.gradient_dither_space;
+        for (index, pixel) in snapshot.as_raw_bytes_mut().chunks_exact_mut(4).enumerate() {
+            let coverage = pixel[3];
+            if coverage == 0 {
+                continue;
+            }
+            let (x, y) = (index % width, index / width);
+            let point = inverse.transform_point(Point2D::new(x as f64 + 0.5, y as f64 + 0.5));
+            let [red, green, blue, alpha] =
+                gradient_color_at(style, point).map(|component| component * coverage as f64);
+            let alpha = alpha.round();
+            let noise = dither_noise(seed, x, y);
+            let channel = |value: f64| dither_channel(value, alpha, noise, space);
+            let (red, green, blue) = (channel(red), channel(green), channel(blue));
+            pixel.copy_from_slice(&match format {
+                SnapshotPixelFormat::RGBA => [red, green, blue, alpha as u8],
+                SnapshotPixelFormat::BGRA => [blue, green, red, alpha as u8],
+            });
+        }
+
+        let dest_rect = Rect::from_size(snapshot.size().to_f64());
+        write_image(
+            &mut self.drawtarget,
+            snapshot,
+            dest_rect,
+            false,
+            composition_options,
+            Transform2D::identity(),
+        );
+    }
+
+    pub(crate) fn set_batch_opaque_fills(&mut self, batch: bool) {
+        if !batch {
+            self.flush_batched_fill();
+        }
+        self.rendering.batch_opaque_fills = batch;
+    }
+
+    /// Draws the fills batched so far, if any.
+    fn flush_batched_fill(&mut self) {
+        let Some(BatchedFill { color, path }) = self.batched_fill.take() else {
+            return;
+        };
+        self.diagnostics.draw_stats.executed_operations += 1;
+        self.drawtarget.fill(
+            &path,
+            FillRule::Nonzero,
+            FillOrStrokeStyle::Color(color),
+            CompositionOptions {
+                alpha: 1.,
+                composition_operation: CompositionOrBlending::default(),
+            },
+            Transform2D::identity(),
+        );
+    }
+
+    /// Returns the color of a rectangle fill with these options if it can be batched:
+    /// it must be opaque, so that neither it nor anything batched with it depends on the
+    /// order in which they overlap.
+    fn batchable_fill_color(
+        &self,
+        style: &FillOrStrokeStyle,
+        shadow_options: &ShadowOptions,
+        composition_options: &CompositionOptions,
+    ) -> Option<AbsoluteColor> {
+        if !self.rendering.batch_opaque_fills ||
+            shadow_options.need_to_draw_shadow() ||
+            composition_options.alpha != 1. ||
+            composition_options.composition_operation != CompositionOrBlending::default()
+        {
+            return None;
+        }
+        match style {
+            FillOrStrokeStyle::Color(color) if color.alpha == 1. => Some(*color),
+            _ => None,
+        }
+    }
+
+    /// Whether filling with `style` has no visible effect: a fully transparent fill leaves
+    /// the canvas as it is under source-over, though under operations such as `copy` it
+    /// clears what it covers.
+    fn is_invisible_fill(
+        &self,
+        style: &FillOrStrokeStyle,
+        composition_options: &CompositionOptions,
+    ) -> bool {
+        let transparent = match style {
+            FillOrStrokeStyle::Color(color) => color.alpha == 0.,
+            _ => false,
+        } || composition_options.alpha == 0.;
+        transparent && composition_options.composition_operation == CompositionOrBlending::default()
+    }
+
+    pub(crate) fn set_filter(&mut self, filter: Vec<FilterFunction>) {
+        self.state.filter = filter;
+    }
+
+    /// Whether drawing operations have to go through [`Self::draw_filtered`] rather than
+    /// draw onto the draw target directly.
+    fn draws_through_layer(&self) -> bool {
+        !self.state.filter.is_empty() || self.rendering.blending_space != BlendingSpace::Srgb
+    }
+
+    /// Runs `draw` against a transparent layer in place of the canvas, then applies the
+    /// filter chain to the layer and composites it onto the canvas with
+    /// `composition_options` in the blending space of the canvas, so that filters and
+    /// blending see the complete result of the operation. Returns what `draw` returns.
+    fn draw_filtered<F, R>(&mut self, composition_options: CompositionOptions, draw: F) -> R
+    where
+        F: FnOnce(&mut Self, CompositionOptions) -> R,
+    {
+        self.flush_batched_fill();
+        // Taken so that the drawing operations run by `draw` don't go through a layer again.
+        let filter = mem::take(&mut self.state.filter);
+        let blending_space = mem::take(&mut self.rendering.blending_space);
+        let layer = self
+            .drawtarget
+            .create_similar_draw_target(&self.drawtarget.get_size());
+        let canvas = mem::replace(&mut self.drawtarget, layer);
+        let drawing_in_software = mem::replace(&mut self.diagnostics.drawing_in_software, true);
+        let result = draw(
+            self,
+            CompositionOptions {
+                alpha: 1.,
+                composition_operation: CompositionOrBlending::default(),
+            },
+        );
+        self.diagnostics.drawing_in_software = drawing_in_software;
+        self.flush_batched_fill();
+        let mut layer = mem::replace(&mut self.drawtarget, canvas);
+
+        let mut snapshot = layer.snapshot();
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: true,
+            },
+            snapshot.format(),
+        );
+        let size = snapshot.size();
+        let format = snapshot.format();
+        for function in &filter {
+            match *function {
+                FilterFunction::DropShadow {
+                    offset_x,
+                    offset_y,
+                    std_deviation,
+                    color,
+                } => drop_shadow_rgba8_inplace(
+                    size,
+                    snapshot.as_raw_bytes_mut(),
+                    Vector2D::new(offset_x.round() as i32, offset_y.round() as i32),
+                    std_deviation,
+                    premultiplied_pixel(color, format),
+                ),
+            }
+        }
+        // Filters may spread what was drawn.
+        if !filter.is_empty() || !composition_options.is_bounded() {
+            self.damage_whole_canvas();
+        }
+        self.state.filter = filter;
+        self.rendering.blending_space = blending_space;
+
+        match blending_space {
+            BlendingSpace::Srgb => write_image(
+                &mut self.drawtarget,
+                snapshot,
+                Rect::from_size(size.to_f64()),
+                false,
+                composition_options,
+                Transform2D::identity(),
+            ),
+            BlendingSpace::Linear => {
+                self.write_image_in_linear_light(snapshot, composition_options)
+            },
+        }
+        result
+    }
+
+    /// Runs `draw` on a transparent layer, corrects the coverage of what it drew with
+    /// `gamma`, and composites the layer onto the canvas with `composition_options`.
+    /// Returns what `draw` returns.
+    fn draw_with_coverage_gamma<F, R>(
+        &mut self,
+        gamma: f32,
+        composition_options: CompositionOptions,
+        draw: F,
+    ) -> R
+    where
+        F: FnOnce(&mut Self, CompositionOptions) -> R,
+    {
+        self.flush_batched_fill();
+        // Reset so that the drawing operations run by `draw` don't go through a layer again.
+        let text_gamma = mem::replace(&mut self.text.text_gamma, 1.);
+        let shape_gamma = mem::replace(&mut self.rendering.shape_gamma, 1.);
+        let layer = self
+            .drawtarget
+            .create_similar_draw_target(&self.drawtarget.get_size());
+        let canvas = mem::replace(&mut self.drawtarget, layer);
+        let drawing_in_software = mem::replace(&mut self.diagnostics.drawing_in_software, true);
+        let result = draw(
+            self,
+            CompositionOptions {
+                alpha: 1.,
+                composition_operation: CompositionOrBlending::default(),
+            },
+        );
+        self.diagnostics.drawing_in_software = drawing_in_software;
+        self.flush_batched_fill();
+        let mut layer = mem::replace(&mut self.drawtarget, canvas);
+        self.text.text_gamma = text_gamma;
+        self.rendering.shape_gamma = shape_gamma;
+
+        let mut snapshot = layer.snapshot();
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: true,
+            },
+            snapshot.format(),
+        );
+        correct_coverage_gamma(snapshot.as_raw_bytes_mut(), gamma);
+        if !composition_options.is_bounded() {
+            self.damage_whole_canvas();
+        }
+        let size = snapshot.size();
+        write_image(
+            &mut self.drawtarget,
+            snapshot,
+            Rect::from_size(size.to_f64()),
+            false,
+            composition_options,
+            Transform2D::identity(),
+        );
+        result
+    }
+
+    /// Composites `snapshot`, which covers the whole draw target, onto it with
+    /// `composition_options`, with the channels of both in linear light. Only the pixels
+    /// that `snapshot` covers are converted there and back, and only those that the
+    /// composition changed keep the result of that round trip, so that 8-bit precision
+    /// isn't lost anywhere else. The other pixels blend to the same result in either
+    /// encoding, as the source is transparent there.
+    fn write_image_in_linear_light(
+        &mut self,
+        mut snapshot: Snapshot,
+        composition_options: CompositionOptions,
+    ) {
+        let rect = Rect::from_size(snapshot.size());
+        let covered: Vec<bool> = snapshot
+            .as_raw_bytes()
+            .chunks_exact(4)
+            .map(|pixel| pixel[3] != 0)
             .collect();
-        let total_advance = shaped_runs
-            .iter()
-            .map(|run| run.glyphs.total_advance())
-            .sum::<Au>()
-            .to_f32_px();
-        let bounding_box = shaped_runs
+        snapshot.convert_srgb_to_linear();
+
+        let mut canvas = self.drawtarget.snapshot();
+        canvas.unpremultiply();
+        let original = canvas.as_raw_bytes().to_vec();
+        let mut linear = original.clone();
+        rgba8_srgb_to_linear_inplace(&mut linear);
+        for ((pixel, linear_pixel), covered) in canvas
+            .as_raw_bytes_mut()
+            .chunks_exact_mut(4)
+            .zip(linear.chunks_exact(4))
+            .zip(&covered)
+        {
+            if *covered {
+                pixel.copy_from_slice(linear_pixel);
+            }
+        }
+        self.put_image_data(canvas, rect);
+
+        write_image(
+            &mut self.drawtarget,
+            snapshot,
+            Rect::from_size(rect.size.to_f64()),
+            false,
+            composition_options,
+            Transform2D::identity(),
+        );
+
+        let mut result = self.drawtarget.snapshot();
+        result.unpremultiply();
+        let mut encoded = result.as_raw_bytes().to_vec();
+        rgba8_linear_to_srgb_inplace(&mut encoded);
+        for (index, pixel) in result.as_raw_bytes_mut().chunks_exact_mut(4).enumerate() {
+            if !covered[index] {
+                continue;
+            }
+            let bytes = index * 4..index * 4 + 4;
+            if *pixel == linear[bytes.clone()] {
+                pixel.copy_from_slice(&original[bytes]);
+            } else {
+                pixel.copy_from_slice(&encoded[bytes]);
+            }
+        }
+        self.put_image_data(result, rect);
+    }
+
+    pub(crate) fn draw_stats(&self) -> CanvasDrawStats {
+        self.diagnostics.draw_stats
+    }
+
+    pub(crate) fn acceleration(&self) -> CanvasAcceleration {
+        self.diagnostics.acceleration
+    }
+
+    /// Where the operation underway is drawn, unless it falls back to the CPU for reasons
+    /// of its own.
+    fn drawing_acceleration(&self) -> Acceleration {
+        if self.diagnostics.drawing_in_software {
+            Acceleration::Software
+        } else {
+            DrawTarget::acceleration()
+        }
+    }
+
+    pub(crate) fn reset_draw_stats(&mut self) {
+        self.diagnostics.draw_stats = CanvasDrawStats::default();
+    }
+
+    pub(crate) fn advance_sequence(&mut self) {
+        self.diagnostics.sequence += 1;
+    }
+
+    pub(crate) fn sequence(&self) -> u64 {
+        self.diagnostics.sequence
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.diagnostics.generation
+    }
+
+    /// Adds the device pixels of the canvas covered by `rect`, once transformed, to the
+    /// pixels touched by drawing operations, and records whether it missed the canvas.
+    /// They are damaged along with those up to `reach` past `rect`, where drawing can spill
+    /// over, or the whole canvas is if `composition_options` changes pixels outside of what
+    /// is drawn.
+    fn add_touched_pixels(
+        &mut self,
+        rect: &Rect<f64>,
+        reach: f64,
+        transform: Transform2D<f32>,
+        composition_options: &CompositionOptions,
+    ) {
+        let canvas_rect = Rect::from_size(self.drawtarget.get_size().to_f64());
+        let device_rect = transform.cast::<f64>().outer_transformed_rect(rect);
+        self.diagnostics.last_draw_out_of_bounds = device_rect.is_some_and(|device_rect| {
+            !device_rect.is_empty() && !device_rect.intersects(&canvas_rect)
+        });
+        let touched_pixels = device_rect
+            .and_then(|rect| rect.intersection(&canvas_rect))
+            .map_or(0, |rect| rect.area().round() as u64);
+        self.diagnostics.draw_stats.pixels_touched += touched_pixels;
+
+        if !composition_options.is_bounded() {
+            return self.damage_whole_canvas();
+        }
+        if reach > 0. {
+            return self.add_transformed_damage(&rect.inflate(reach, reach), transform);
+        }
+        match device_rect {
+            Some(device_rect) => self.add_damage(device_rect),
+            None => self.damage_whole_canvas(),
+        }
+    }
+
+    /// Records that the device pixels covered by `rect`, once transformed, may have changed.
+    fn add_transformed_damage(&mut self, rect: &Rect<f64>, transform: Transform2D<f32>) {
+        match transform.cast::<f64>().outer_transformed_rect(rect) {
+            Some(device_rect) => self.add_damage(device_rect),
+            None => self.damage_whole_canvas(),
+        }
+    }
+
+    /// Records that the device pixels within `rect` may have changed. Rectangles within
+    /// others are dropped, and past [`MAX_DAMAGE_RECTS`], all are merged into one.
+    fn add_damage(&mut self, rect: Rect<f64>) {
+        // Every change to the pixels is damage, so this is the last moment that the
+        // transaction can copy them.
+        self.snapshot_for_transaction();
+
+        let canvas_rect = Rect::from_size(self.drawtarget.get_size().to_f64());
+        let Some(rect) = rect
+            .round_out()
+            .intersection(&canvas_rect)
+            .filter(|rect| !rect.is_empty())
+        else {
+            return;
+        };
+        self.diagnostics.modified = true;
+        let rect = rect.to_u32();
+        if self
+            .upload
+            .damage
             .iter()
-            .map(TextRun::bounding_box)
-            .reduce(|a, b| {
-                let amount = Vector2D::new(a.max_x(), 0.);
-                let bounding_box = b.translate(amount);
-                a.union(&bounding_box)
-            })
-            .unwrap_or_default();
+            .any(|damage| damage.contains_rect(&rect))
+        {
+            return;
+        }
+        self.upload
+            .damage
+            .retain(|damage| !rect.contains_rect(damage));
+        self.upload.damage.push(rect);
+        if self.upload.damage.len() > MAX_DAMAGE_RECTS {
+            let union = self
+                .upload
+                .damage
+                .iter()
+                .fold(Rect::zero(), |union, damage| union.union(damage));
+            self.upload.damage = vec![union];
+        }
+    }
 
-        let FontBaseline {
-            ideographic_baseline,
-            alphabetic_baseline,
-            hanging_baseline,
-        } = match font.baseline() {
-            Some(baseline) => baseline,
-            None => FontBaseline {
-                hanging_baseline: ascent * HANGING_BASELINE_DEFAULT,
-                ideographic_baseline: -descent * IDEOGRAPHIC_BASELINE_DEFAULT,
-                alphabetic_baseline: 0.,
-            },
+    fn damage_whole_canvas(&mut self) {
+        self.add_damage(Rect::from_size(self.drawtarget.get_size().to_f64()));
+    }
+
+    pub(crate) fn last_draw_out_of_bounds(&self) -> bool {
+        self.diagnostics.last_draw_out_of_bounds
+    }
+
+    pub(crate) fn last_text_truncated(&self) -> bool {
+        self.diagnostics.last_text_truncated
+    }
+
+    /// Whether the alpha of every pixel of the canvas is 255. This is computed when asked,
+    /// as drawing operations rarely tell for certain whether they leave the canvas opaque.
+    pub(crate) fn is_fully_opaque(&mut self) -> bool {
+        // A reclaimed surface is transparent black once it is restored.
+        if self.reclaimed_surface_size.is_some() {
+            return false;
+        }
+        let snapshot = self.snapshot(None);
+        if matches!(
+            snapshot.alpha_mode(),
+            SnapshotAlphaMode::Opaque | SnapshotAlphaMode::AsOpaque { .. }
+        ) {
+            return true;
+        }
+        snapshot
+            .as_raw_bytes()
+            .chunks_exact(4)
+            .all(|pixel| pixel[3] == 255)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn draw_image(
+        &mut self,
+        snapshot: Snapshot,
+        dest_rect: Rect<f64>,
+        source_rect: Rect<f64>,
+        smoothing_enabled: bool,
+        shadow_options: ShadowOptions,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) {
+        self.flush_batched_fill();
+        // This may be drawing for a message of another canvas, which does not advance the
+        // sequence of this one.
+        self.diagnostics.last_clear = None;
+
+        if self.draws_through_layer() {
+            self.draw_filtered(composition_options, |self_, composition_options| {
+                self_.draw_image(
+                    snapshot,
+                    dest_rect,
+                    source_rect,
+                    smoothing_enabled,
+                    shadow_options,
+                    composition_options,
+                    transform,
+                )
+            });
+            return;
+        }
+
+        self.diagnostics.draw_stats.images += 1;
+        self.diagnostics.acceleration.images = self.drawing_acceleration();
+        self.add_touched_pixels(&dest_rect, 0., transform, &composition_options);
+        let smoothing_enabled = smoothing_enabled && !self.rendering.low_power_mode;
+
+        // We round up the floating pixel values to draw the pixels
+        let source_rect = source_rect.ceil();
+        // It discards the extra pixels (if any) that won't be painted
+        let mut snapshot = if Rect::from_size(snapshot.size().to_f64()).contains_rect(&source_rect)
+        {
+            snapshot.get_rect(source_rect.to_u32())
+        } else {
+            snapshot
         };
+        // The surface of the canvas is in sRGB, so images in other color spaces have to be
+        // converted before they are composited onto it.
+        snapshot.convert_to_srgb();
 
-        let anchor_x = match text_options.align {
-            TextAlign::End => total_advance,
-            TextAlign::Center => total_advance / 2.,
-            TextAlign::Right => total_advance,
-            _ => 0.,
+        let writer = |draw_target: &mut DrawTarget, transform| {
+            write_image::<DrawTarget>(
+                draw_target,
+                snapshot,
+                dest_rect,
+                smoothing_enabled,
+                composition_options,
+                transform,
+            );
         };
-        let anchor_y = match text_options.baseline {
-            TextBaseline::Top => ascent,
-            TextBaseline::Hanging => hanging_baseline,
+
+        if shadow_options.need_to_draw_shadow() {
+            let rect = Rect::new(
+                Point2D::new(dest_rect.origin.x as f32, dest_rect.origin.y as f32),
+                Size2D::new(dest_rect.size.width as f32, dest_rect.size.height as f32),
+            );
+
+            // TODO(pylbrecht) pass another closure for raqote
+            self.draw_with_shadow(
+                &rect,
+                shadow_options,
+                composition_options,
+                transform,
+                writer,
+            );
+        } else {
+            writer(&mut self.drawtarget, transform);
+        }
+    }
+
+    /// Draws the `source_rect` region of this canvas onto `dest_rect`. The region is copied
+    /// before anything is drawn, so overlapping regions don't feed back into each other.
+    pub(crate) fn draw_image_from_self(
+        &mut self,
+        dest_rect: Rect<f64>,
+        source_rect: Rect<f64>,
+        smoothing_enabled: bool,
+        shadow_options: ShadowOptions,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) {
+        self.flush_batched_fill();
+
+        let snapshot = self.snapshot(Some(source_rect.to_u32()));
+        self.draw_image(
+            snapshot,
+            dest_rect,
+            source_rect,
+            smoothing_enabled,
+            shadow_options,
+            composition_options,
+            transform,
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn fill_text_with_size(
+        &mut self,
+        text: String,
+        x: f64,
+        y: f64,
+        max_width: Option<f64>,
+        is_rtl: bool,
+        size: f64,
+        style: FillOrStrokeStyle,
+        text_options: &TextOptions,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) -> Result<(), CanvasError> {
+        // > Step 2: Replace all ASCII whitespace in text with U+0020 SPACE characters.
+        //
+        // Tabs are kept when there are tab stops to advance them to.
+        let text = replace_ascii_whitespace(text, text_options.tab_size.is_some());
+
+        // > Step 3: Let font be the current font of target, as given by that object's font
+        // > attribute.
+        let Some(ref font_style) = text_options.font else {
+            return Ok(());
+        };
+
+        let font_group = self
+            .font_context
+            .font_group_with_size(font_style.clone(), Au::from_f64_px(size));
+        let mut font_group = font_group.write();
+        let Some(first_font) = font_group.first(&self.font_context) else {
+            return Err(CanvasError::OperationFailed(
+                "Could not render canvas text, because there was no first font.".to_owned(),
+            ));
+        };
+
+        let tab_width = text_options
+            .tab_size
+            .map(|tab_size| tab_width(tab_size, &first_font.metrics));
+        // TODO: This doesn't do any kind of line layout at all. In particular, there needs
+        // to be some alignment along a baseline.
+        let mut shaped_runs = shape_text(
+            &self.font_context,
+            &text,
+            &mut font_group,
+            &self.text.fallback_font_families,
+            is_rtl,
+            tab_width,
+            text_options.shaping_tags,
+        );
+        if let Some(ref glyph_range) = text_options.glyph_range {
+            let glyph_counts: Vec<_> = shaped_runs.iter().map(TextRun::glyph_count).collect();
+            let visible_glyphs = split_glyph_range(glyph_range, &glyph_counts);
+            for (run, visible_glyphs) in shaped_runs.iter_mut().zip(visible_glyphs) {
+                run.visible_glyphs = visible_glyphs;
+            }
+        }
+        for run in shaped_runs.iter_mut() {
+            run.pixel_snapped = self.text.text_pixel_snapping;
+        }
+        let total_advance = shaped_runs
+            .iter()
+            .map(|run| run.glyphs.total_advance())
+            .sum::<Au>()
+            .to_f64_px();
+
+        // > Step 6: If maxWidth was provided and the hypothetical width of the inline box in the
+        // > hypothetical line box is greater than maxWidth CSS pixels, then change font to have a
+        // > more condensed font (if one is available or if a reasonably readable one can be
+        // > synthesized by applying a horizontal scale factor to the font) or a smaller font, and
+        // > return to the previous step.
+        //
+        // TODO: We only try decreasing the font size here. Eventually it would make sense to use
+        // other methods to try to decrease the size, such as finding a narrower font or decreasing
+        // spacing.
+        if let Some(max_width) = max_width {
+            if total_advance > max_width {
+                self.diagnostics.last_text_truncated = true;
+            }
+            if let Some(new_size) = condensed_font_size(total_advance, max_width, size) {
+                return self.fill_text_with_size(
+                    text,
+                    x,
+                    y,
+                    Some(max_width),
+                    is_rtl,
+                    new_size,
+                    style,
+                    text_options,
+                    composition_options,
+                    transform,
+                );
+            }
+        }
+
+        // > Step 7: Find the anchor point for the line of text.
+        let mut start = self.find_anchor_point_for_line_of_text(
+            x as f32,
+            y as f32,
+            &first_font.metrics,
+            total_advance as f32,
+            is_rtl,
+            text_options,
+        );
+        if self.text.text_pixel_snapping {
+            start = snap_to_device_pixel(start, &transform);
+        }
+
+        let text_rect = Rect::new(
+            Point2D::new(start.x as f64, start.y as f64 - size),
+            Size2D::new(total_advance, size),
+        );
+        // Glyphs can reach past their advance and below the baseline.
+        self.add_touched_pixels(&text_rect, size, transform, &composition_options);
+        let missing_glyph_boxes = missing_glyph_boxes(
+            shaped_runs
+                .iter()
+                .map(|run| (&*run.glyphs, run.visible_glyphs.clone())),
+            start,
+            size,
+        );
+        let decoration_lines = text_options.decoration.map(|decoration| {
+            text_decoration_lines(&decoration, &first_font.metrics, start, total_advance)
+        });
+
+        // > Step 8: Let result be an array constructed by iterating over each glyph in the inline box
+        // > from left to right (if any), adding to the array, for each glyph, the shape of the glyph
+        // > as it is in the inline box, positioned on a coordinate space using CSS pixels with its
+        // > origin is at the anchor point.
+        self.maybe_bound_shape_with_pattern(
+            style,
+            composition_options,
+            &Rect::from_size(Size2D::new(total_advance, size)),
+            transform,
+            |self_, style| {
+                self_.drawtarget.fill_text(
+                    shaped_runs,
+                    start,
+                    style.clone(),
+                    self_.glyph_hinting,
+                    composition_options,
+                    transform,
+                );
+                if let Some(boxes) = missing_glyph_boxes {
+                    self_.drawtarget.fill(
+                        &boxes,
+                        FillRule::Evenodd,
+                        style.clone(),
+                        composition_options,
+                        transform,
+                    );
+                }
+                for (path, line_options) in decoration_lines.into_iter().flatten() {
+                    self_.drawtarget.stroke(
+                        &path,
+                        style.clone(),
+                        line_options,
+                        composition_options,
+                        transform,
+                    );
+                }
+            },
+        );
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    /// <https://html.spec.whatwg.org/multipage/#text-preparation-algorithm>
+    pub(crate) fn fill_text(
+        &mut self,
+        text: String,
+        x: f64,
+        y: f64,
+        max_width: Option<f64>,
+        is_rtl: bool,
+        style: FillOrStrokeStyle,
+        text_options: TextOptions,
+        shadow_options: ShadowOptions,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) -> Result<(), CanvasError> {
+        self.flush_batched_fill();
+        self.diagnostics.last_text_truncated = false;
+
+        if self.is_invisible_fill(&style, &composition_options) {
+            return Ok(());
+        }
+
+        if self.draws_through_layer() {
+            return self.draw_filtered(composition_options, |self_, composition_options| {
+                self_.fill_text(
+                    text,
+                    x,
+                    y,
+                    max_width,
+                    is_rtl,
+                    style,
+                    text_options,
+                    shadow_options,
+                    composition_options,
+                    transform,
+                )
+            });
+        }
+
+        if self.text.text_gamma != 1. {
+            return self.draw_with_coverage_gamma(
+                self.text.text_gamma,
+                composition_options,
+                |self_, composition_options| {
+                    self_.fill_text(
+                        text,
+                        x,
+                        y,
+                        max_width,
+                        is_rtl,
+                        style,
+                        text_options,
+                        shadow_options,
+                        composition_options,
+                        transform,
+                    )
+                },
+            );
+        }
+
+        let Some(ref font_style) = text_options.font else {
+            return Ok(());
+        };
+
+        self.diagnostics.draw_stats.texts += 1;
+        self.diagnostics.acceleration.texts = self.drawing_acceleration();
+        let size = font_style.font_size.computed_size();
+        self.fill_text_with_size(
+            text,
+            x,
+            y,
+            max_width,
+            is_rtl,
+            size.px() as f64,
+            style,
+            &text_options,
+            composition_options,
+            transform,
+        )
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/#text-preparation-algorithm>
+    /// <https://html.spec.whatwg.org/multipage/#dom-context-2d-measuretext>
+    ///
+    /// The metrics are in user units, as the font is sized in them: neither the current
+    /// transform nor the resolution of the draw target apply, so that they stay the same
+    /// on scaled canvases.
+    ///
+    /// The advance and the bounding box come from a single shaping of the text. Filling the
+    /// same text afterwards, at the size of the font, finds the shaped glyphs in the shape
+    /// cache of the font rather than shaping the text again.
+    pub(crate) fn measure_text(
+        &mut self,
+        text: String,
+        is_rtl: bool,
+        text_options: TextOptions,
+    ) -> TextMetrics {
+        // > Step 2: Replace all ASCII whitespace in text with U+0020 SPACE characters.
+        let text = replace_ascii_whitespace(text, text_options.tab_size.is_some());
+        let Some(ref font_style) = text_options.font else {
+            return TextMetrics::default();
+        };
+
+        let font_context = &self.font_context;
+        let fallback_font_families = &self.text.fallback_font_families;
+        let measure = |text: &str| {
+            measure_shaped_text(
+                font_context,
+                font_style,
+                fallback_font_families,
+                text,
+                is_rtl,
+                text_options.tab_size,
+                text_options.shaping_tags,
+            )
+        };
+        let TextMeasurements {
+            width: total_advance,
+            bounding_box,
+            ascent,
+            descent,
+            hanging_baseline,
+            ideographic_baseline,
+            alphabetic_baseline,
+        } = if text.contains('\t') {
+            // The cache doesn't know about the tab size, which the width of tabs depends on.
+            measure(&text)
+        } else {
+            self.text.measure_text_cache.get_or_insert_with(
+                font_style,
+                text,
+                is_rtl,
+                text_options.shaping_tags,
+                measure,
+            )
+        };
+
+        let anchor_x = match text_options.align {
+            TextAlign::End => total_advance,
+            TextAlign::Center => total_advance / 2.,
+            TextAlign::Right => total_advance,
+            _ => 0.,
+        };
+        let (line_ascent, line_descent) =
+            line_box_ascent_and_descent(ascent, descent, text_options.line_height);
+        let anchor_y = match text_options.baseline {
+            TextBaseline::Top => line_ascent,
+            TextBaseline::Hanging => hanging_baseline,
             TextBaseline::Ideographic => ideographic_baseline,
-            TextBaseline::Middle => (ascent - descent) / 2.,
+            TextBaseline::Middle => (line_ascent - line_descent) / 2.,
             TextBaseline::Alphabetic => alphabetic_baseline,
-            TextBaseline::Bottom => -descent,
+            TextBaseline::Bottom => -line_descent,
+        };
+
+        TextMetrics {
+            width: total_advance,
+            actual_boundingbox_left: anchor_x - bounding_box.min_x(),
+            actual_boundingbox_right: bounding_box.max_x() - anchor_x,
+            actual_boundingbox_ascent: bounding_box.max_y() - anchor_y,
+            actual_boundingbox_descent: anchor_y - bounding_box.min_y(),
+            font_boundingbox_ascent: ascent - anchor_y,
+            font_boundingbox_descent: descent + anchor_y,
+            em_height_ascent: ascent - anchor_y,
+            em_height_descent: descent + anchor_y,
+            hanging_baseline: hanging_baseline - anchor_y,
+            alphabetic_baseline: alphabetic_baseline - anchor_y,
+            ideographic_baseline: ideographic_baseline - anchor_y,
+        }
+    }
+
+    /// Returns the byte offsets in `text` at which the lines after the first start, when it
+    /// is broken after spaces into lines no wider than `max_width`. Each line is measured as
+    /// it would be filled, so it is shaped with the same spacing.
+    pub(crate) fn wrap_text(
+        &mut self,
+        text: String,
+        max_width: f64,
+        is_rtl: bool,
+        text_options: TextOptions,
+    ) -> Vec<usize> {
+        // Whitespace is replaced as when filling or measuring the text. Every replacement is
+        // a single byte, so offsets into the result are offsets into `text` too.
+        let text = replace_ascii_whitespace(text, false);
+        let Some(ref font_style) = text_options.font else {
+            return Vec::new();
         };
 
-        TextMetrics {
-            width: total_advance,
-            actual_boundingbox_left: anchor_x - bounding_box.min_x(),
-            actual_boundingbox_right: bounding_box.max_x() - anchor_x,
-            actual_boundingbox_ascent: bounding_box.max_y() - anchor_y,
-            actual_boundingbox_descent: anchor_y - bounding_box.min_y(),
-            font_boundingbox_ascent: ascent - anchor_y,
-            font_boundingbox_descent: descent + anchor_y,
-            em_height_ascent: ascent - anchor_y,
-            em_height_descent: descent + anchor_y,
-            hanging_baseline: hanging_baseline - anchor_y,
-            alphabetic_baseline: alphabetic_baseline - anchor_y,
-            ideographic_baseline: ideographic_baseline - anchor_y,
-        }
+        let font_context = &self.font_context;
+        let fallback_font_families = &self.text.fallback_font_families;
+        let cache = &mut self.text.measure_text_cache;
+        let shaping_tags = text_options.shaping_tags;
+        line_breaks(&text, max_width as f32, |line| {
+            cache
+                .get_or_insert_with(font_style, line.to_owned(), is_rtl, shaping_tags, |line| {
+                    measure_shaped_text(
+                        font_context,
+                        font_style,
+                        fallback_font_families,
+                        line,
+                        is_rtl,
+                        None,
+                        shaping_tags,
+                    )
+                })
+                .width
+        })
+    }
+
+    /// Find the *anchor_point* for the given parameters of a line of text.
+    /// See <https://html.spec.whatwg.org/multipage/#text-preparation-algorithm>.
+    fn find_anchor_point_for_line_of_text(
+        &self,
+        x: f32,
+        y: f32,
+        metrics: &FontMetrics,
+        width: f32,
+        is_rtl: bool,
+        text_options: &TextOptions,
+    ) -> Point2D<f32> {
+        let text_align = match text_options.align {
+            TextAlign::Start if is_rtl => TextAlign::Right,
+            TextAlign::Start => TextAlign::Left,
+            TextAlign::End if is_rtl => TextAlign::Left,
+            TextAlign::End => TextAlign::Right,
+            text_align => text_align,
+        };
+        let anchor_x = match text_align {
+            TextAlign::Center => -width / 2.,
+            TextAlign::Right => -width,
+            _ => 0.,
+        };
+
+        let ascent = metrics.ascent.to_f32_px();
+        let descent = metrics.descent.to_f32_px();
+        let (line_ascent, line_descent) =
+            line_box_ascent_and_descent(ascent, descent, text_options.line_height);
+        let anchor_y = match text_options.baseline {
+            TextBaseline::Top => line_ascent,
+            TextBaseline::Hanging => ascent * HANGING_BASELINE_DEFAULT,
+            TextBaseline::Ideographic => -descent * IDEOGRAPHIC_BASELINE_DEFAULT,
+            TextBaseline::Middle => (line_ascent - line_descent) / 2.,
+            TextBaseline::Alphabetic => 0.,
+            TextBaseline::Bottom => -line_descent,
+        };
+        let baseline_shift = text_options.baseline_shift.unwrap_or_default();
+
+        point2(x + anchor_x, y + anchor_y - baseline_shift)
+    }
+
+    pub(crate) fn fill_rect(
+        &mut self,
+        rect: &Rect<f32>,
+        style: FillOrStrokeStyle,
+        shadow_options: ShadowOptions,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) {
+        if self.is_invisible_fill(&style, &composition_options) {
+            return;
+        }
+
+        if self.draws_through_layer() {
+            self.draw_filtered(composition_options, |self_, composition_options| {
+                self_.fill_rect(rect, style, shadow_options, composition_options, transform)
+            });
+            return;
+        }
+
+        if self.rendering.shape_gamma != 1. && !shadow_options.need_to_draw_shadow() {
+            self.draw_with_coverage_gamma(
+                self.rendering.shape_gamma,
+                composition_options,
+                |self_, composition_options| {
+                    self_.fill_rect(rect, style, shadow_options, composition_options, transform)
+                },
+            );
+            return;
+        }
+
+        if style.is_zero_size_gradient() {
+            return; // Paint nothing if gradient size is zero.
+        }
+
+        let rect =
+            &round_rect_to_device_pixels(rect, self.rendering.coordinate_rounding, &transform);
+        self.diagnostics.draw_stats.fills += 1;
+        self.diagnostics.acceleration.fills = self.drawing_acceleration();
+        self.add_touched_pixels(&rect.cast(), 0., transform, &composition_options);
+
+        if let Some(color) =
+            self.batchable_fill_color(&style, &shadow_options, &composition_options)
+        {
+            if self
+                .batched_fill
+                .as_ref()
+                .is_some_and(|batched_fill| batched_fill.color != color)
+            {
+                self.flush_batched_fill();
+            }
+            self.diagnostics.draw_stats.submitted_operations += 1;
+            let mut path = Path::new();
+            path.rect(
+                rect.origin.x as f64,
+                rect.origin.y as f64,
+                rect.size.width as f64,
+                rect.size.height as f64,
+            );
+            path.transform(transform.cast());
+            let batched_fill = self.batched_fill.get_or_insert_with(|| BatchedFill {
+                color,
+                path: Path::new(),
+            });
+            batched_fill.path.0.extend(path.0);
+            return;
+        }
+        self.flush_batched_fill();
+
+        if let Some(seed) = self
+            .dither_seed(&style)
+            .filter(|_| !shadow_options.need_to_draw_shadow())
+        {
+            self.fill_dithered(
+                &style,
+                seed,
+                composition_options,
+                transform,
+                |draw_target, style, composition_options| {
+                    draw_target.fill_rect(rect, style, composition_options, transform)
+                },
+            );
+            return;
+        }
+
+        if shadow_options.need_to_draw_shadow() {
+            self.draw_with_shadow(
+                rect,
+                shadow_options,
+                composition_options,
+                transform,
+                |new_draw_target, transform| {
+                    new_draw_target.fill_rect(rect, style, composition_options, transform);
+                },
+            );
+        } else {
+            self.maybe_bound_shape_with_pattern(
+                style,
+                composition_options,
+                &rect.cast(),
+                transform,
+                |self_, style| {
+                    self_
+                        .drawtarget
+                        .fill_rect(rect, style, composition_options, transform);
+                },
+            );
+        }
+    }
+
+    pub(crate) fn clear_rect(&mut self, rect: &Rect<f32>, transform: Transform2D<f32>) {
+        // Nothing can have been drawn since the clear of the previous message, so clearing
+        // the same area again would leave the canvas as it is. Clears made outside of
+        // messages never share a sequence number with the one before them.
+        let repeats_last_clear =
+            self.diagnostics
+                .last_clear
+                .is_some_and(|(last_rect, last_transform, sequence)| {
+                    last_rect == *rect &&
+                        last_transform == transform &&
+                        sequence + 1 == self.diagnostics.sequence
+                });
+        self.diagnostics.last_clear = Some((*rect, transform, self.diagnostics.sequence));
+        self.diagnostics.draw_stats.submitted_operations += 1;
+        if repeats_last_clear {
+            return;
+        }
+
+        self.flush_batched_fill();
+        self.diagnostics.draw_stats.clears += 1;
+        self.diagnostics.draw_stats.executed_operations += 1;
+        self.add_transformed_damage(&rect.cast(), transform);
+        self.drawtarget.clear_rect(rect, transform);
+    }
+
+    pub(crate) fn stroke_rect(
+        &mut self,
+        rect: &Rect<f32>,
+        style: FillOrStrokeStyle,
+        line_options: LineOptions,
+        shadow_options: ShadowOptions,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) {
+        self.flush_batched_fill();
+
+        if self.draws_through_layer() {
+            self.draw_filtered(composition_options, |self_, composition_options| {
+                self_.stroke_rect(
+                    rect,
+                    style,
+                    line_options,
+                    shadow_options,
+                    composition_options,
+                    transform,
+                )
+            });
+            return;
+        }
+
+        if self.rendering.shape_gamma != 1. && !shadow_options.need_to_draw_shadow() {
+            self.draw_with_coverage_gamma(
+                self.rendering.shape_gamma,
+                composition_options,
+                |self_, composition_options| {
+                    self_.stroke_rect(
+                        rect,
+                        style,
+                        line_options,
+                        shadow_options,
+                        composition_options,
+                        transform,
+                    )
+                },
+            );
+            return;
+        }
+
+        if style.is_zero_size_gradient() {
+            return; // Paint nothing if gradient size is zero.
+        }
+
+        let rect =
+            &round_rect_to_device_pixels(rect, self.rendering.coordinate_rounding, &transform);
+        let (line_options, composition_options) =
+            hairline_options(line_options, composition_options, &transform);
+
+        if line_options.alignment != StrokeAlignment::Center {
+            let mut path = Path::new();
+            path.rect(
+                rect.origin.x.into(),
+                rect.origin.y.into(),
+                rect.size.width.into(),
+                rect.size.height.into(),
+            );
+            self.stroke_aligned(
+                &path,
+                style,
+                line_options,
+                shadow_options,
+                composition_options,
+                transform,
+            );
+            return;
+        }
+
+        if let Some((gap_style, gap_line_options)) = dash_gap_stroke(&line_options) {
+            self.stroke_rect(
+                rect,
+                gap_style,
+                gap_line_options,
+                shadow_options.clone(),
+                composition_options,
+                transform,
+            );
+        }
+
+        self.diagnostics.draw_stats.strokes += 1;
+        self.diagnostics.acceleration.strokes = self.drawing_acceleration();
+        let half_width = line_options.width / 2.;
+        // Joins and caps can reach past half the width of the line.
+        let reach = half_width * (line_options.miter_limit.max(SQRT_2) - 1.);
+        self.add_touched_pixels(
+            &rect.cast().inflate(half_width, half_width),
+            reach,
+            transform,
+            &composition_options,
+        );
+
+        if shadow_options.need_to_draw_shadow() {
+            self.draw_with_shadow(
+                rect,
+                shadow_options,
+                composition_options,
+                transform,
+                |new_draw_target, transform| {
+                    new_draw_target.stroke_rect(
+                        rect,
+                        style,
+                        line_options,
+                        composition_options,
+                        transform,
+                    );
+                },
+            );
+        } else {
+            self.maybe_bound_shape_with_pattern(
+                style,
+                composition_options,
+                &rect.cast(),
+                transform,
+                |self_, style| {
+                    self_.drawtarget.stroke_rect(
+                        rect,
+                        style,
+                        line_options,
+                        composition_options,
+                        transform,
+                    );
+                },
+            )
+        }
+    }
+
+    pub(crate) fn fill_path(
+        &mut self,
+        path: &Path,
+        fill_rule: FillRule,
+        style: FillOrStrokeStyle,
+        shadow_options: ShadowOptions,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) {
+        self.flush_batched_fill();
+
+        if self.is_invisible_fill(&style, &composition_options) {
+            return;
+        }
+
+        if self.draws_through_layer() {
+            self.draw_filtered(composition_options, |self_, composition_options| {
+                self_.fill_path(
+                    path,
+                    fill_rule,
+                    style,
+                    shadow_options,
+                    composition_options,
+                    transform,
+                )
+            });
+            return;
+        }
+
+        if self.rendering.shape_gamma != 1. {
+            self.draw_with_coverage_gamma(
+                self.rendering.shape_gamma,
+                composition_options,
+                |self_, composition_options| {
+                    self_.fill_path(
+                        path,
+                        fill_rule,
+                        style,
+                        shadow_options,
+                        composition_options,
+                        transform,
+                    )
+                },
+            );
+            return;
+        }
+
+        if style.is_zero_size_gradient() {
+            return; // Paint nothing if gradient size is zero.
+        }
+
+        self.diagnostics.draw_stats.fills += 1;
+        self.diagnostics.acceleration.fills = self.drawing_acceleration();
+        self.add_touched_pixels(&path.bounding_box(), 0., transform, &composition_options);
+
+        if let Some(seed) = self.dither_seed(&style) {
+            self.fill_dithered(
+                &style,
+                seed,
+                composition_options,
+                transform,
+                |draw_target, style, composition_options| {
+                    draw_target.fill(path, fill_rule, style, composition_options, transform)
+                },
+            );
+            return;
+        }
+
+        self.maybe_bound_shape_with_pattern(
+            style,
+            composition_options,
+            &path.bounding_box(),
+            transform,
+            |self_, style| {
+                self_
+                    .drawtarget
+                    .fill(path, fill_rule, style, composition_options, transform)
+            },
+        )
+    }
+
+    /// Fills the subpaths of `path` whose indices aren't in `holes`, and cuts the others
+    /// out of the fill whatever their winding.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn fill_path_with_holes(
+        &mut self,
+        path: &Path,
+        holes: &[usize],
+        fill_rule: FillRule,
+        style: FillOrStrokeStyle,
+        shadow_options: ShadowOptions,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) {
+        let (path, holes) = split_hole_subpaths(path, holes);
+        // Each hole is cut out by a clip to everything else, rather than all of them by a
+        // single even-odd clip, in which holes that overlap would cancel out.
+        let outside = Rect::from_size(self.drawtarget.get_size())
+            .to_f64()
+            .inflate(1., 1.);
+        for mut hole in holes.iter().cloned() {
+            hole.transform(transform.cast());
+            hole.rect(
+                outside.origin.x,
+                outside.origin.y,
+                outside.size.width,
+                outside.size.height,
+            );
+            self.clip_path(&hole, FillRule::Evenodd, Transform2D::identity());
+        }
+        self.fill_path(
+            &path,
+            fill_rule,
+            style,
+            shadow_options,
+            composition_options,
+            transform,
+        );
+        self.pop_clips(holes.len());
+    }
+
+    pub(crate) fn stroke_path(
+        &mut self,
+        path: &Path,
+        style: FillOrStrokeStyle,
+        line_options: LineOptions,
+        shadow_options: ShadowOptions,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) {
+        self.flush_batched_fill();
+
+        if self.draws_through_layer() {
+            self.draw_filtered(composition_options, |self_, composition_options| {
+                self_.stroke_path(
+                    path,
+                    style,
+                    line_options,
+                    shadow_options,
+                    composition_options,
+                    transform,
+                )
+            });
+            return;
+        }
+
+        if self.rendering.shape_gamma != 1. {
+            self.draw_with_coverage_gamma(
+                self.rendering.shape_gamma,
+                composition_options,
+                |self_, composition_options| {
+                    self_.stroke_path(
+                        path,
+                        style,
+                        line_options,
+                        shadow_options,
+                        composition_options,
+                        transform,
+                    )
+                },
+            );
+            return;
+        }
+
+        if style.is_zero_size_gradient() {
+            return; // Paint nothing if gradient size is zero.
+        }
+
+        let (line_options, composition_options) =
+            hairline_options(line_options, composition_options, &transform);
+
+        if line_options.alignment != StrokeAlignment::Center {
+            self.stroke_aligned(
+                path,
+                style,
+                line_options,
+                shadow_options,
+                composition_options,
+                transform,
+            );
+            return;
+        }
+
+        if let Some((gap_style, gap_line_options)) = dash_gap_stroke(&line_options) {
+            self.stroke_path(
+                path,
+                gap_style,
+                gap_line_options,
+                shadow_options.clone(),
+                composition_options,
+                transform,
+            );
+        }
+
+        // A closed subpath must be joined at its start with the line join, which strokers
+        // can only do when the closing segment isn't degenerate.
+        let trimmed_path = without_redundant_closing_lines(path);
+        let path = trimmed_path.as_ref().unwrap_or(path);
+
+        self.diagnostics.draw_stats.strokes += 1;
+        self.diagnostics.acceleration.strokes = self.drawing_acceleration();
+        let half_width = line_options.width / 2.;
+        // Joins and caps can reach past half the width of the line.
+        let reach = half_width * (line_options.miter_limit.max(SQRT_2) - 1.);
+        self.add_touched_pixels(
+            &path.bounding_box().inflate(half_width, half_width),
+            reach,
+            transform,
+            &composition_options,
+        );
+
+        let dots = round_dash_dots(path, &line_options);
+        self.maybe_bound_shape_with_pattern(
+            style,
+            composition_options,
+            &path.bounding_box(),
+            transform,
+            |self_, style| match dots {
+                Some((dots, dash_line_options)) => {
+                    self_.drawtarget.fill(
+                        &dots,
+                        FillRule::Nonzero,
+                        style.clone(),
+                        composition_options,
+                        transform,
+                    );
+                    if let Some(line_options) = dash_line_options {
+                        self_.drawtarget.stroke(
+                            path,
+                            style,
+                            line_options,
+                            composition_options,
+                            transform,
+                        );
+                    }
+                },
+                None => {
+                    self_.drawtarget.stroke(
+                        path,
+                        style,
+                        line_options,
+                        composition_options,
+                        transform,
+                    );
+                },
+            },
+        )
+    }
+
+    /// Strokes `path` on the inside or the outside of it, as the half of a centered stroke
+    /// twice as wide that a clip to that side of the path leaves.
+    fn stroke_aligned(
+        &mut self,
+        path: &Path,
+        style: FillOrStrokeStyle,
+        mut line_options: LineOptions,
+        shadow_options: ShadowOptions,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) {
+        let alignment = mem::take(&mut line_options.alignment);
+        line_options.width *= 2.;
+        let mut side = path.clone();
+        side.transform(transform.cast());
+        let fill_rule = match alignment {
+            StrokeAlignment::Outside => {
+                let outside = Rect::from_size(self.drawtarget.get_size())
+                    .to_f64()
+                    .inflate(1., 1.);
+                side.rect(
+                    outside.origin.x,
+                    outside.origin.y,
+                    outside.size.width,
+                    outside.size.height,
+                );
+                // Unlike the nonzero rule that the inside follows, this counts areas that
+                // the path winds around twice as outside of it.
+                FillRule::Evenodd
+            },
+            StrokeAlignment::Inside | StrokeAlignment::Center => FillRule::Nonzero,
+        };
+        self.clip_path(&side, fill_rule, Transform2D::identity());
+        self.stroke_path(
+            path,
+            style,
+            line_options,
+            shadow_options,
+            composition_options,
+            transform,
+        );
+        self.pop_clips(1);
+    }
+
+    pub(crate) fn clip_path(
+        &mut self,
+        path: &Path,
+        fill_rule: FillRule,
+        transform: Transform2D<f32>,
+    ) {
+        self.flush_batched_fill();
+
+        let mut device_path = path.clone();
+        device_path.transform(transform.cast());
+        let device_bounds = device_path.bounding_box();
+        let bounds = device_bounds
+            .cast::<f32>()
+            .intersection(&self.innermost_clip_bounds())
+            .unwrap_or_default();
+        self.clip_bounds.push(bounds);
+        // Clips have anti-aliased edges, except for a rectangle on pixel boundaries, which is
+        // clipped to exactly, as antialiasing could only bleed partial coverage into the
+        // pixels along its edges. In low-power mode, any axis-aligned rectangle is rounded to
+        // whole pixels instead, which is cheaper than masking; other shapes stay anti-aliased.
+        if self.rendering.low_power_mode || device_bounds.round() == device_bounds {
+            if let Some(rect) = axis_aligned_device_rect(path, transform) {
+                self.drawtarget.push_clip_rect(&rect);
+                return;
+            }
+        }
+        self.drawtarget.push_clip(path, fill_rule, transform);
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/#reset-the-rendering-context-to-its-default-state>
+    pub(crate) fn recreate(&mut self, size: Option<Size2D<u64>>) {
+        let current_size = self
+            .reclaimed_surface_size
+            .unwrap_or_else(|| self.drawtarget.get_size());
+        let size = size
+            .map(|size| {
+                backing_store_size(
+                    size,
+                    self.scale_factor,
+                    self.render_scale,
+                    &DrawTarget::size_limits(),
+                )
+            })
+            .unwrap_or_else(|| current_size.to_u64().max(MIN_WR_IMAGE_SIZE));
+
+        // Step 1. Clear canvas's bitmap to transparent black.
+        self.batched_fill = None;
+        self.layers.clear();
+        // There is nothing to roll back to on a draw target of another size.
+        self.transaction = None;
+        // A surface that isn't allocated stays that way, at the new size.
+        let mut draw_target_size = size.cast();
+        if let Some(reclaimed_surface_size) = self.reclaimed_surface_size.as_mut() {
+            *reclaimed_surface_size = draw_target_size;
+            draw_target_size = MIN_WR_IMAGE_SIZE.cast();
+        }
+        self.drawtarget = self
+            .drawtarget
+            .create_similar_draw_target(&draw_target_size);
+        self.diagnostics.generation += 1;
+        // The clips of states saved before now belonged to the old draw target, so restoring
+        // those states must not pop anything from the new one.
+        self.clip_bounds.clear();
+        self.push_viewport_clip();
+
+        self.scratch_draw_target = None;
+
+        // Step 4. Reset everything that drawing state consists of to their initial values.
+        self.state.fill_style = FillOrStrokeStyle::Color(AbsoluteColor::BLACK);
+        self.state.stroke_style = FillOrStrokeStyle::Color(AbsoluteColor::BLACK);
+        self.state.composition_options = CompositionOptions {
+            alpha: 1.,
+            composition_operation: CompositionOrBlending::default(),
+        };
+        self.state.image_smoothing_enabled = true;
+        self.state.image_smoothing_quality = ImageSmoothingQuality::default();
+
+        // The image in WebRender has to change size along with the canvas, so this update
+        // is never paced.
+        self.upload.damage.clear();
+        self.damage_whole_canvas();
+        self.upload_image();
+    }
+
+    /// Releases the memory held by the draw target, discarding its pixels. Clips and layers
+    /// are state of the draw target, so while any are in effect, it is kept, as it is while
+    /// a transaction may still need its pixels.
+    pub(crate) fn reclaim_surface(&mut self) {
+        if self.reclaimed_surface_size.is_some() ||
+            !self.clip_bounds.is_empty() ||
+            !self.layers.is_empty() ||
+            self.transaction.is_some()
+        {
+            return;
+        }
+        self.batched_fill = None;
+        self.scratch_draw_target = None;
+        self.reclaimed_surface_size = Some(self.drawtarget.get_size());
+        self.drawtarget = self
+            .drawtarget
+            .create_similar_draw_target(&MIN_WR_IMAGE_SIZE.cast());
+        // The placeholder replaces all of the image the next time that it is uploaded.
+        let modified = self.diagnostics.modified;
+        self.upload.damage.clear();
+        self.damage_whole_canvas();
+        self.diagnostics.modified = modified;
+    }
+
+    /// Allocates the draw target that was deferred when the canvas was created or released
+    /// by [`Self::reclaim_surface`], and clears it to transparent black, the initial color
+    /// of a canvas.
+    pub(crate) fn restore_reclaimed_surface(&mut self) {
+        let Some(size) = self.reclaimed_surface_size.take() else {
+            return;
+        };
+        self.drawtarget = self.drawtarget.create_similar_draw_target(&size);
+        // Backends are free to hand out the memory of a new surface as it is, so it is
+        // cleared rather than assumed to be transparent.
+        self.drawtarget
+            .clear_rect(&Rect::from_size(size.to_f32()), Transform2D::identity());
+        self.push_viewport_clip();
+        // Allocating the surface is no change that drawing made.
+        let modified = self.diagnostics.modified;
+        self.damage_whole_canvas();
+        self.diagnostics.modified = modified;
+    }
+
+    /// Update image in WebRender, or with frame pacing, at the next vsync. An `immediate`
+    /// update is sent right away even with frame pacing, along with any pending one.
+    pub(crate) fn update_image_rendering(&mut self, immediate: bool) {
+        if self.upload.frame_pacing && !immediate {
+            if self.upload.image_update_pending {
+                // This update is sent along with the one that is already pending.
+                let size = self.drawtarget.get_size();
+                self.diagnostics.draw_stats.upload_bytes_saved +=
+                    size.width as u64 * size.height as u64 * 4;
+            }
+            self.upload.image_update_pending = true;
+        } else {
+            self.upload_image();
+        }
+    }
+
+    pub(crate) fn set_frame_pacing(&mut self, frame_pacing: bool) {
+        self.upload.frame_pacing = frame_pacing;
+        if !frame_pacing {
+            self.vsync();
+        }
+    }
+
+    /// Sends the update to the image in WebRender that was requested since the last
+    /// vsync, if any.
+    pub(crate) fn vsync(&mut self) {
+        if self.upload.image_update_pending {
+            self.upload_image();
+        }
+    }
+
+    fn upload_image(&mut self) {
+        let start = self.upload.frame_time.is_some().then(Instant::now);
+        self.update_last_modified();
+        self.upload.image_update_pending = false;
+        self.diagnostics.draw_stats.image_uploads += 1;
+        self.flush_batched_fill();
+
+        // Layers that have yet to end aren't visible.
+        let draw_target = match self.layers.first_mut() {
+            Some(layer) => &mut layer.parent,
+            None => &mut self.drawtarget,
+        };
+        let (descriptor, data) = draw_target.image_descriptor_and_serializable_data();
+        let data = maybe_flip_image_data(self.upload.y_flipped, &descriptor, data);
+        let data = maybe_unmultiply_image_data(self.upload.premultiplied_upload, &descriptor, data);
+        let height = draw_target.get_size().height as u32;
+        let damage = mem::take(&mut self.upload.damage)
+            .into_iter()
+            .map(|mut rect| {
+                if self.upload.y_flipped {
+                    rect.origin.y = height - rect.max_y();
+                }
+                rect
+            })
+            .collect();
+        let frame_time =
+            start.map(|start| self.upload.frame_time.take().unwrap_or_default() + start.elapsed());
+
+        self.compositor_api.update_image_with_rendering(
+            self.image_key,
+            descriptor,
+            data,
+            self.upload.image_rendering,
+            damage,
+            frame_time,
+        );
+        self.upload.last_upload = Some(Instant::now());
+    }
+
+    /// Sets how the compositor should filter the image, which applies from the next update
+    /// of the image.
+    pub(crate) fn set_image_rendering(&mut self, image_rendering: ImageRendering) {
+        self.upload.image_rendering = image_rendering;
+    }
+
+    /// Sets whether the pixels sent to WebRender are premultiplied by their alpha, which
+    /// applies from the next update of the image.
+    pub(crate) fn set_premultiplied_upload(&mut self, premultiplied: bool) {
+        if self.upload.premultiplied_upload == premultiplied {
+            return;
+        }
+        self.upload.premultiplied_upload = premultiplied;
+        // None of the pixels changed, but every one of them is sent differently.
+        self.upload.damage = vec![Rect::from_size(self.drawtarget.get_size().cast())];
+    }
+
+    // https://html.spec.whatwg.org/multipage/#dom-context-2d-putimagedata
+    pub(crate) fn put_image_data(&mut self, snapshot: Snapshot, rect: Rect<u32>) {
+        self.flush_batched_fill();
+
+        assert_eq!(rect.size, snapshot.size());
+        self.add_damage(rect.to_f64());
+        let source_surface = self
+            .drawtarget
+            .create_source_surface_from_data(snapshot)
+            .unwrap();
+        self.drawtarget.copy_surface(
+            source_surface,
+            Rect::from_size(rect.size.to_i32()),
+            rect.origin.to_i32(),
+        );
+    }
+
+    /// Keeps a copy of the pixels within `rect`, clipped to the draw target, until
+    /// [`Self::release_region`].
+    pub(crate) fn save_region(&mut self, rect: Rect<u32>) -> SavedRegionId {
+        let canvas_rect = Rect::from_size(self.drawtarget.get_size().cast());
+        let snapshot = match canvas_rect.intersection(&rect) {
+            Some(rect) if !rect.is_empty() => self.snapshot(Some(rect)),
+            _ => Snapshot::empty(),
+        };
+        let id = self.next_saved_region_id;
+        self.next_saved_region_id.0 += 1;
+        self.saved_regions.insert(id, snapshot);
+        id
+    }
+
+    /// Copies the pixels kept by [`Self::save_region`] into the draw target, with their top
+    /// left corner at the origin of `rect` and clipped to it. Unknown handles are ignored.
+    pub(crate) fn restore_region(&mut self, rect: Rect<u32>, id: SavedRegionId) {
+        let Some(saved) = self.saved_regions.get(&id) else {
+            return;
+        };
+        let canvas_rect = Rect::from_size(self.drawtarget.get_size().cast());
+        let Some(dest_rect) = Rect::new(rect.origin, rect.size.min(saved.size()))
+            .intersection(&canvas_rect)
+            .filter(|rect| !rect.is_empty())
+        else {
+            return;
+        };
+        let snapshot = saved.get_rect(Rect::from_size(dest_rect.size));
+        self.put_image_data(snapshot, dest_rect);
+    }
+
+    pub(crate) fn release_region(&mut self, id: SavedRegionId) {
+        self.saved_regions.remove(&id);
+    }
+
+    pub(crate) fn begin_transaction(&mut self) {
+        if self.transaction.is_some() {
+            return;
+        }
+        // Fills batched before the transaction are not part of it.
+        self.flush_batched_fill();
+        self.transaction = Some(Transaction {
+            snapshot: None,
+            layer_depth: self.layers.len(),
+        });
+    }
+
+    pub(crate) fn commit_transaction(&mut self) {
+        self.transaction = None;
+    }
+
+    /// Puts back the pixels that the surface of the canvas had when the transaction began,
+    /// if anything was drawn since.
+    pub(crate) fn rollback_transaction(&mut self) {
+        let Some(Transaction {
+            snapshot,
+            layer_depth,
+        }) = self.transaction.take()
+        else {
+            return;
+        };
+        self.batched_fill = None;
+        // The outermost of the layers begun since holds the surface they were begun on.
+        if let Some(layer) = self.layers.drain(layer_depth..).next() {
+            self.drawtarget = layer.parent;
+            self.clip_bounds = layer.parent_clip_bounds;
+        }
+        let Some(snapshot) = snapshot else {
+            return;
+        };
+        self.damage_whole_canvas();
+        let surface = self
+            .layers
+            .first_mut()
+            .map_or(&mut self.drawtarget, |layer| &mut layer.parent);
+        let size = snapshot.size();
+        let source_surface = surface.create_source_surface_from_data(snapshot).unwrap();
+        surface.copy_surface(
+            source_surface,
+            Rect::from_size(size.to_i32()),
+            Point2D::zero(),
+        );
+    }
+
+    /// Copies the pixels of the surface of the canvas into the open transaction, unless it
+    /// has them already.
+    fn snapshot_for_transaction(&mut self) {
+        let Some(transaction) = self.transaction.as_mut() else {
+            return;
+        };
+        if transaction.snapshot.is_some() {
+            return;
+        }
+        let surface = self
+            .layers
+            .first_mut()
+            .map_or(&mut self.drawtarget, |layer| &mut layer.parent);
+        transaction.snapshot = Some(surface.snapshot());
+    }
+
+    /// Replace the contents of the canvas with `snapshot`, scaled to the size of the canvas,
+    /// and update the image in WebRender. The frame ignores the drawing state, apart from
+    /// the clip.
+    pub(crate) fn present_frame(&mut self, snapshot: Snapshot) {
+        self.batched_fill = None;
+        self.damage_whole_canvas();
+
+        let dest_rect = Rect::from_size(self.drawtarget.get_size().to_f64());
+        write_image::<DrawTarget>(
+            &mut self.drawtarget,
+            snapshot,
+            dest_rect,
+            true,
+            CompositionOptions {
+                alpha: 1.,
+                composition_operation: CompositionOrBlending::Composition(CompositionStyle::Copy),
+            },
+            Transform2D::identity(),
+        );
+        self.update_image_rendering(false);
+    }
+
+    /// Blur the existing pixels of the surface within `rect` in place with a Gaussian of the
+    /// given standard deviation. Only pixels inside of `rect` are sampled.
+    pub(crate) fn blur_region(&mut self, rect: Rect<u32>, std_deviation: f64) {
+        let canvas_rect = Rect::from_size(self.drawtarget.get_size().cast());
+        let Some(rect) = canvas_rect
+            .intersection(&rect)
+            .filter(|rect| !rect.is_empty())
+        else {
+            return;
+        };
+
+        // Not `read_pixels`, which may flip the rows.
+        let mut snapshot = self.snapshot(Some(rect));
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: true,
+            },
+            snapshot.format(),
+        );
+        gaussian_blur_rgba8_inplace(rect.size, snapshot.as_raw_bytes_mut(), std_deviation);
+        self.put_image_data(snapshot, rect);
+    }
+
+    /// Apply a 4x5 color matrix, in the form of SVG `feColorMatrix`, to the existing pixels
+    /// of the surface within `rect` in place.
+    pub(crate) fn apply_color_matrix(&mut self, rect: Rect<u32>, matrix: &[f32; 20]) {
+        let canvas_rect = Rect::from_size(self.drawtarget.get_size().cast());
+        let Some(rect) = canvas_rect
+            .intersection(&rect)
+            .filter(|rect| !rect.is_empty())
+        else {
+            return;
+        };
+
+        // Not `read_pixels`, which may flip the rows.
+        let mut snapshot = self.snapshot(Some(rect));
+        // The matrix applies to non-premultiplied channels, in RGBA order.
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            SnapshotPixelFormat::RGBA,
+        );
+        color_matrix_rgba8_inplace(snapshot.as_raw_bytes_mut(), matrix);
+        self.put_image_data(snapshot, rect);
+    }
+
+    /// Multiply the alpha of the existing pixels of the surface within `rect` by `factor`,
+    /// clamped between 0 and 1, in place. As the surface is premultiplied, so are their
+    /// color channels, which leaves their non-premultiplied colors as they were.
+    pub(crate) fn fade_region(&mut self, rect: Rect<u32>, factor: f64) {
+        if factor.is_nan() {
+            return;
+        }
+        let canvas_rect = Rect::from_size(self.drawtarget.get_size().cast());
+        let Some(rect) = canvas_rect
+            .intersection(&rect)
+            .filter(|rect| !rect.is_empty())
+        else {
+            return;
+        };
+
+        // Not `read_pixels`, which may flip the rows.
+        let mut snapshot = self.snapshot(Some(rect));
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: true,
+            },
+            snapshot.format(),
+        );
+        let factor = factor.clamp(0., 1.);
+        for channel in snapshot.as_raw_bytes_mut() {
+            *channel = (*channel as f64 * factor).round() as u8;
+        }
+        self.put_image_data(snapshot, rect);
+    }
+
+    /// Reduces each color channel of the existing pixels of the surface within `rect` to
+    /// `levels` evenly spaced values, in place. Fewer than two levels are taken as two.
+    pub(crate) fn posterize(&mut self, rect: Rect<u32>, levels: u32) {
+        let canvas_rect = Rect::from_size(self.drawtarget.get_size().cast());
+        let Some(rect) = canvas_rect
+            .intersection(&rect)
+            .filter(|rect| !rect.is_empty())
+        else {
+            return;
+        };
+
+        // Not `read_pixels`, which may flip the rows.
+        let mut snapshot = self.snapshot(Some(rect));
+        // The levels apply to non-premultiplied channels, in either order.
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            snapshot.format(),
+        );
+        posterize_rgba8_inplace(snapshot.as_raw_bytes_mut(), levels);
+        self.put_image_data(snapshot, rect);
+    }
+
+    /// Draws the whole of `snapshot` into `dest_rect` with its corners rounded by `radii`,
+    /// as [`Path::round_rect`] rounds them. The clip is only narrowed while it draws.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn draw_image_rounded(
+        &mut self,
+        snapshot: Snapshot,
+        dest_rect: Rect<f64>,
+        radii: [Size2D<f64>; 4],
+        smoothing_enabled: bool,
+        shadow_options: ShadowOptions,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) {
+        let mut path = Path::new();
+        path.round_rect(
+            dest_rect.origin.x,
+            dest_rect.origin.y,
+            dest_rect.size.width,
+            dest_rect.size.height,
+            radii,
+        );
+        let source_rect = Rect::from_size(snapshot.size().to_f64());
+        self.clip_path(&path, FillRule::Nonzero, transform);
+        self.draw_image(
+            snapshot,
+            dest_rect,
+            source_rect,
+            smoothing_enabled,
+            shadow_options,
+            composition_options,
+            transform,
+        );
+        self.pop_clips(1);
+    }
+
+    /// Draws `source` into `dest_rect` with each of its pixels made as opaque as the pixel at
+    /// the same place of `mask`. Nothing is drawn if the two aren't the same size.
+    pub(crate) fn composite_with_mask(
+        &mut self,
+        mut source: Snapshot,
+        mut mask: Snapshot,
+        dest_rect: Rect<f64>,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) {
+        if source.size() != mask.size() {
+            warn!(
+                "Mask of size {:?} doesn't match the source of size {:?}",
+                mask.size(),
+                source.size()
+            );
+            return;
+        }
+
+        let alpha_mode = SnapshotAlphaMode::Transparent {
+            premultiplied: true,
+        };
+        source.transform(alpha_mode, source.format());
+        // Opaque snapshots may not store their alpha.
+        mask.transform(alpha_mode, source.format());
+        mask_rgba8_inplace(source.as_raw_bytes_mut(), mask.as_raw_bytes());
+        let source_rect = Rect::from_size(source.size().to_f64());
+        self.draw_image(
+            source,
+            dest_rect,
+            source_rect,
+            true,
+            ShadowOptions {
+                offset_x: 0.,
+                offset_y: 0.,
+                blur: 0.,
+                spread: 0.,
+                color: AbsoluteColor::TRANSPARENT_BLACK,
+            },
+            composition_options,
+            transform,
+        );
+    }
+
+    /// Writes the pixels of the canvas, as [`Self::read_pixels`] returns them, to a PNG file
+    /// at `path`.
+    pub(crate) fn debug_dump(&mut self, path: &std::path::Path) -> Result<(), CanvasError> {
+        let mut snapshot = self.read_pixels(None);
+        let result = File::create(path)
+            .map_err(|error| error.to_string())
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                snapshot
+                    .encode_for_mime_type(&EncodedImageType::Png, None, &mut writer)
+                    .map_err(|error| error.to_string())?;
+                writer.flush().map_err(|error| error.to_string())
+            });
+        result.map_err(|error| {
+            CanvasError::OperationFailed(format!(
+                "Could not dump the canvas to {}: {error}",
+                path.display()
+            ))
+        })
+    }
+
+    pub(crate) fn flip_surface(&mut self, axis: FlipAxis) {
+        // Not `read_pixels`, which may flip the rows.
+        let mut snapshot = self.snapshot(None);
+        let size = snapshot.size();
+        match axis {
+            FlipAxis::Horizontal => flip_x_rgba8_image_inplace(size, snapshot.as_raw_bytes_mut()),
+            FlipAxis::Vertical => flip_y_rgba8_image_inplace(size, snapshot.as_raw_bytes_mut()),
+        }
+        self.put_image_data(snapshot, Rect::from_size(size));
+    }
+
+    /// Returns the memory held by the pixels of the surface and of the buffers kept between
+    /// drawing operations.
+    pub(crate) fn memory_usage(&self) -> CanvasMemoryUsage {
+        let bytes = |draw_target: &DrawTarget| draw_target.get_size().area() as usize * 4;
+        // While layers are open, the surface of the canvas is the parent of the outermost.
+        let surface = bytes(
+            self.layers
+                .first()
+                .map_or(&self.drawtarget, |layer| &layer.parent),
+        );
+        let all_layers: usize = self
+            .layers
+            .iter()
+            .map(|layer| bytes(&layer.parent))
+            .sum::<usize>() +
+            bytes(&self.drawtarget);
+        let saved_regions: usize = self
+            .saved_regions
+            .values()
+            .map(|snapshot| snapshot.as_raw_bytes().len())
+            .sum::<usize>() +
+            self.transaction
+                .as_ref()
+                .and_then(|transaction| transaction.snapshot.as_ref())
+                .map_or(0, |snapshot| snapshot.as_raw_bytes().len());
+        CanvasMemoryUsage {
+            surface,
+            auxiliary: all_layers - surface +
+                self.scratch_draw_target.as_ref().map_or(0, bytes) +
+                saved_regions,
+        }
+    }
+
+    /// Takes the scratch surface, reallocating it if it can't hold `size`, or if it is more
+    /// than [`SCRATCH_SURFACE_MAX_SLACK`] times as large as `size` so that a pass over a
+    /// large area doesn't keep that much memory around. Return it with
+    /// [`Self::return_scratch_draw_target`] once done.
+    fn take_scratch_draw_target(&mut self, size: Size2D<i32>) -> DrawTarget {
+        let area = size.to_i64().area().max(1);
+        match self.scratch_draw_target.take() {
+            Some(draw_target)
+                if !draw_target.get_size().lower_than(size).any() &&
+                    draw_target.get_size().to_i64().area() <=
+                        area * SCRATCH_SURFACE_MAX_SLACK =>
+            {
+                draw_target
+            },
+            _ => {
+                self.diagnostics.draw_stats.scratch_allocations += 1;
+                self.drawtarget.create_similar_draw_target(&size)
+            },
+        }
+    }
+
+    /// Clears the `used` part of the scratch surface and keeps it around for the next pass.
+    fn return_scratch_draw_target(&mut self, mut draw_target: DrawTarget, used: Size2D<i32>) {
+        let rect = Rect::from_size(used.cast());
+        draw_target.clear_rect(&rect, Transform2D::identity());
+        self.scratch_draw_target = Some(draw_target);
+    }
+
+    /// Draws the shape drawn by `draw_shadow_source`, after compositing its shadow.
+    /// <https://html.spec.whatwg.org/multipage/#when-shadows-are-drawn>
+    fn draw_with_shadow<F>(
+        &mut self,
+        rect: &Rect<f32>,
+        shadow_options: ShadowOptions,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+        draw_shadow_source: F,
+    ) where
+        F: FnOnce(&mut DrawTarget, Transform2D<f32>),
+    {
+        let mut blur = shadow_options.blur;
+        if blur > self.rendering.max_shadow_blur {
+            warn!(
+                "Clamping canvas shadow blur {blur} to {}",
+                self.rendering.max_shadow_blur
+            );
+            blur = self.rendering.max_shadow_blur;
+        }
+        // > Perform a 2D Gaussian Blur on B, using σ as the standard deviation, where σ is
+        // > half the value of the shadowBlur attribute.
+        let std_deviation = blur / 2.;
+        // The blur spreads the shadow up to three standard deviations past the shape, after
+        // the spread has grown it.
+        let margin = (std_deviation * 3. + shadow_options.spread.max(0.)).ceil() as f32;
+        let shadow_src_rect = transform
+            .outer_transformed_rect(rect)
+            .inflate(margin, margin)
+            .round_out();
+        let shadow_src_size = shadow_src_rect.size.cast::<i32>();
+        let mut new_draw_target = self.take_scratch_draw_target(shadow_src_size);
+        let shadow_transform = transform.then(
+            &Transform2D::identity()
+                .pre_translate(-shadow_src_rect.origin.to_vector().cast::<f32>()),
+        );
+        draw_shadow_source(&mut new_draw_target, shadow_transform);
+
+        // The scratch surface may be larger than the shadow needs, so only the part drawn
+        // into is blurred.
+        let mut source = if new_draw_target.get_size() == shadow_src_size {
+            new_draw_target.snapshot()
+        } else {
+            new_draw_target.snapshot_rect(Rect::from_size(shadow_src_size.cast()))
+        };
+        source.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: true,
+            },
+            source.format(),
+        );
+        self.return_scratch_draw_target(new_draw_target, shadow_src_size);
+
+        let mut shadow = source.clone();
+        spread_rgba8_inplace(
+            shadow.size(),
+            shadow.as_raw_bytes_mut(),
+            shadow_options.spread,
+        );
+        let format = shadow.format();
+        let shadow_color = premultiplied_pixel(shadow_options.color, format);
+        if self.rendering.low_power_mode {
+            self.diagnostics.draw_stats.approximated_shadows += 1;
+            approximate_shadow_rgba8_inplace(
+                shadow.size(),
+                shadow.as_raw_bytes_mut(),
+                std_deviation,
+                shadow_color,
+            );
+        } else {
+            shadow_rgba8_inplace(
+                shadow.size(),
+                shadow.as_raw_bytes_mut(),
+                std_deviation,
+                shadow_color,
+            );
+        }
+
+        // Shadow offsets are not affected by the current transformation.
+        let source_rect = Rect::new(shadow_src_rect.origin.cast(), source.size().to_f64());
+        let shadow_rect = source_rect.translate(Vector2D::new(
+            shadow_options.offset_x,
+            shadow_options.offset_y,
+        ));
+        self.add_damage(shadow_rect);
+        write_image(
+            &mut self.drawtarget,
+            shadow,
+            shadow_rect,
+            false,
+            composition_options,
+            Transform2D::identity(),
+        );
+        write_image(
+            &mut self.drawtarget,
+            source,
+            source_rect,
+            false,
+            composition_options,
+            Transform2D::identity(),
+        );
+    }
+
+    /// Push a clip to the draw target to respect the non-repeating bound (either x, y, or both)
+    /// of the given pattern.
+    fn maybe_bound_shape_with_pattern<F>(
+        &mut self,
+        style: FillOrStrokeStyle,
+        composition_options: CompositionOptions,
+        path_bound_box: &Rect<f64>,
+        transform: Transform2D<f32>,
+        draw_shape: F,
+    ) where
+        F: FnOnce(&mut Self, FillOrStrokeStyle),
+    {
+        let x_bound = style.x_bound();
+        let y_bound = style.y_bound();
+        // Clear operations are also unbounded.
+        if matches!(
+            composition_options.composition_operation,
+            CompositionOrBlending::Composition(CompositionStyle::Clear)
+        ) || (x_bound.is_none() && y_bound.is_none())
+        {
+            draw_shape(self, style);
+            return;
+        }
+        let rect = Rect::from_size(Size2D::new(
+            x_bound.unwrap_or(path_bound_box.size.width.ceil() as u32),
+            y_bound.unwrap_or(path_bound_box.size.height.ceil() as u32),
+        ))
+        .cast();
+        let rect = transform.outer_transformed_rect(&rect);
+        self.drawtarget.push_clip_rect(&rect.cast());
+        draw_shape(self, style);
+        self.drawtarget.pop_clip();
+    }
+
+    /// Copies the pixels in `read_rect`, or in the whole canvas, with rows top-down
+    /// regardless of whether the canvas is read back bottom-up.
+    pub(crate) fn snapshot(&mut self, read_rect: Option<Rect<u32>>) -> Snapshot {
+        self.flush_batched_fill();
+
+        let Some(read_rect) = read_rect else {
+            return self.drawtarget.snapshot();
+        };
+
+        let canvas_rect = Rect::from_size(self.drawtarget.get_size().cast());
+        if canvas_rect
+            .intersection(&read_rect)
+            .is_none_or(|rect| rect.is_empty())
+        {
+            return Snapshot::empty();
+        }
+        self.drawtarget.snapshot_rect(read_rect)
+    }
+
+    /// Returns the non-premultiplied RGBA color of the pixel at (`x`, `y`), or transparent
+    /// black when it is outside of the canvas. Unlike [`Self::read_pixels`], this does not
+    /// allocate a snapshot.
+    pub(crate) fn get_pixel(&mut self, x: i32, y: i32) -> [u8; 4] {
+        self.flush_batched_fill();
+
+        let size = self.drawtarget.get_size();
+        if !(0..size.width).contains(&x) || !(0..size.height).contains(&y) {
+            return [0; 4];
+        }
+        self.drawtarget.get_pixel(Point2D::new(x as u32, y as u32))
+    }
+
+    /// Counts the values of each non-premultiplied channel of the pixels within `rect`,
+    /// clipped to the canvas.
+    pub(crate) fn histogram(&mut self, rect: Rect<u32>) -> CanvasHistogram {
+        let mut histogram = CanvasHistogram::default();
+        let canvas_rect = Rect::from_size(self.drawtarget.get_size().cast());
+        let Some(rect) = canvas_rect
+            .intersection(&rect)
+            .filter(|rect| !rect.is_empty())
+        else {
+            return histogram;
+        };
+
+        let mut snapshot = self.snapshot(Some(rect));
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            SnapshotPixelFormat::RGBA,
+        );
+        for pixel in snapshot.as_raw_bytes().chunks_exact(4) {
+            histogram.red[pixel[0] as usize] += 1;
+            histogram.green[pixel[1] as usize] += 1;
+            histogram.blue[pixel[2] as usize] += 1;
+            histogram.alpha[pixel[3] as usize] += 1;
+        }
+        histogram
+    }
+
+    /// Reads back the pixels within `read_rect`, or the whole canvas, like
+    /// [`Self::read_pixels`], as normalized floats. Colors are unpremultiplied, and decoded
+    /// to linear light for [`ColorEncoding::Linear`], in floating point, so that no
+    /// precision is lost to 8-bit intermediate values.
+    pub(crate) fn read_pixels_as_float(
+        &mut self,
+        read_rect: Option<Rect<u32>>,
+        encoding: ColorEncoding,
+    ) -> FloatImageData {
+        let mut snapshot = self.read_pixels(read_rect);
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: true,
+            },
+            SnapshotPixelFormat::RGBA,
+        );
+        let pixels = snapshot
+            .as_raw_bytes()
+            .chunks_exact(4)
+            .flat_map(|pixel| {
+                let alpha = pixel[3] as f32 / 255.;
+                let color = |channel: u8| {
+                    if alpha == 0. {
+                        return 0.;
+                    }
+                    let value = (channel as f32 / 255. / alpha).min(1.);
+                    match encoding {
+                        ColorEncoding::Srgb => value,
+                        ColorEncoding::Linear => srgb_to_linear(value),
+                    }
+                };
+                [color(pixel[0]), color(pixel[1]), color(pixel[2]), alpha]
+            })
+            .collect();
+        FloatImageData {
+            size: snapshot.size(),
+            pixels,
+        }
+    }
+
+    /// Reads back the alpha of the pixels within `rect`, like [`Self::read_pixels`].
+    /// Reads back the pixels within `read_rect` as non-premultiplied RGBA, with each row
+    /// padded to a multiple of `alignment` bytes as OpenGL packs them. Alignments other
+    /// than 1, 2, 4 and 8 are taken as 1, which packs the rows tightly.
+    pub(crate) fn read_pixels_aligned(
+        &mut self,
+        read_rect: Option<Rect<u32>>,
+        alignment: u32,
+    ) -> AlignedImageData {
+        let mut snapshot = self.read_pixels(read_rect);
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            SnapshotPixelFormat::RGBA,
+        );
+        let alignment = match alignment {
+            1 | 2 | 4 | 8 => alignment as usize,
+            _ => 1,
+        };
+        let size = snapshot.size();
+        let row_length = size.width as usize * 4;
+        let stride = row_length.next_multiple_of(alignment);
+        let mut data = Vec::with_capacity(stride * size.height as usize);
+        for row in snapshot.as_raw_bytes().chunks_exact(row_length.max(1)) {
+            data.extend_from_slice(row);
+            data.resize(data.len() + stride - row_length, 0);
+        }
+        AlignedImageData { size, stride, data }
+    }
+
+    pub(crate) fn read_alpha_mask(&mut self, rect: Rect<u32>) -> AlphaMask {
+        let snapshot = self.read_pixels(Some(rect));
+        AlphaMask {
+            size: snapshot.size(),
+            alpha: snapshot
+                .as_raw_bytes()
+                .chunks_exact(4)
+                .map(|pixel| pixel[3])
+                .collect(),
+        }
+    }
+
+    /// Reads back the pixels within `read_rect`, or the whole canvas. Backends that defer
+    /// drawing flush it first, so the result reflects every operation processed so far. This
+    /// must stay synchronous: replying from elsewhere could let later operations leak into
+    /// the result.
+    #[allow(unsafe_code)]
+    pub(crate) fn read_pixels(&mut self, read_rect: Option<Rect<u32>>) -> Snapshot {
+        let mut snapshot = self.snapshot(read_rect);
+        if self.upload.y_flipped {
+            let size = snapshot.size();
+            flip_y_rgba8_image_inplace(size, snapshot.as_raw_bytes_mut());
+        }
+        snapshot
+    }
+
+    /// Reads back the pixels within `read_rect`, or the whole canvas, like
+    /// [`Self::read_pixels`], but in horizontal tiles of at most `tile_height` rows. Each
+    /// tile is passed to `tile` from top to bottom, along with the size of the whole image
+    /// and the row of the image that the tile starts at.
+    pub(crate) fn read_pixels_in_tiles(
+        &mut self,
+        read_rect: Option<Rect<u32>>,
+        tile_height: u32,
+        tile: &mut dyn FnMut(Size2D<u32>, u32, Snapshot),
+    ) {
+        let canvas_rect = Rect::from_size(self.drawtarget.get_size().cast());
+        let read_rect = read_rect.unwrap_or(canvas_rect);
+        if canvas_rect
+            .intersection(&read_rect)
+            .is_none_or(|rect| rect.is_empty())
+        {
+            tile(Size2D::zero(), 0, Snapshot::empty());
+            return;
+        }
+
+        let tile_height = tile_height.max(1);
+        let mut y = 0;
+        while y < read_rect.height() {
+            let height = tile_height.min(read_rect.height() - y);
+            // `read_pixels` flips each tile of a flipped canvas, so its tiles are read from
+            // the bottom of the rectangle up.
+            let source_y = if self.upload.y_flipped {
+                read_rect.max_y() - y - height
+            } else {
+                read_rect.min_y() + y
+            };
+            let tile_rect = Rect::new(
+                Point2D::new(read_rect.min_x(), source_y),
+                Size2D::new(read_rect.width(), height),
+            );
+            tile(read_rect.size, y, self.read_pixels(Some(tile_rect)));
+            y += height;
+        }
+    }
+
+    /// Reads back the pixels within `read_rect`, or the whole canvas, once, and returns
+    /// them converted to each of `color_spaces`, in order.
+    pub(crate) fn read_pixels_in_color_spaces(
+        &mut self,
+        read_rect: Option<Rect<u32>>,
+        color_spaces: &[PredefinedColorSpace],
+    ) -> Vec<Snapshot> {
+        let snapshot = self.read_pixels(read_rect);
+        color_spaces
+            .iter()
+            .map(|color_space| {
+                let mut snapshot = snapshot.clone();
+                match color_space {
+                    PredefinedColorSpace::Srgb => {},
+                    PredefinedColorSpace::DisplayP3 => snapshot.convert_srgb_to_display_p3(),
+                }
+                snapshot
+            })
+            .collect()
+    }
+
+    /// Pops every clip pushed onto the draw target, then clips to `path` if there is one.
+    pub(crate) fn set_clip(
+        &mut self,
+        path: Option<&Path>,
+        fill_rule: FillRule,
+        transform: Transform2D<f32>,
+    ) {
+        self.pop_clips(self.clip_bounds.len());
+        if let Some(path) = path {
+            self.clip_path(path, fill_rule, transform);
+        }
+    }
+
+    /// Replaces the clips in effect with a circle, as [`Self::set_clip`] does with a path.
+    /// A circle without area clips everything out, and one that covers the whole draw target
+    /// removes the clips.
+    pub(crate) fn set_radial_clip(
+        &mut self,
+        center: Point2D<f64>,
+        radius: f64,
+        fill_rule: FillRule,
+        transform: Transform2D<f32>,
+    ) {
+        // An empty path clips everything out.
+        let mut path = Path::new();
+        if radius > 0. {
+            let size = self.drawtarget.get_size().to_f64();
+            let covers_draw_target = transform.cast::<f64>().inverse().is_some_and(|inverse| {
+                [
+                    Point2D::origin(),
+                    Point2D::new(size.width, 0.),
+                    Point2D::new(0., size.height),
+                    Point2D::new(size.width, size.height),
+                ]
+                .into_iter()
+                .all(|corner| (inverse.transform_point(corner) - center).length() <= radius)
+            });
+            if covers_draw_target {
+                self.set_clip(None, fill_rule, transform);
+                return;
+            }
+            let _ = path.arc(center.x, center.y, radius, 0., TAU, false);
+        }
+        self.set_clip(Some(&path), fill_rule, transform);
+    }
+
+    pub(crate) fn pop_clips(&mut self, clips: usize) {
+        self.flush_batched_fill();
+        let clips = clips.min(self.clip_bounds.len());
+        self.clip_bounds.truncate(self.clip_bounds.len() - clips);
+        for _ in 0..clips {
+            self.drawtarget.pop_clip();
+        }
+    }
+
+    /// The bounds of the clip of the current draw target, or of the whole draw target if
+    /// nothing clips it.
+    fn innermost_clip_bounds(&self) -> Rect<f32> {
+        self.clip_bounds.last().copied().unwrap_or_else(|| {
+            self.viewport_device_rect()
+                .unwrap_or_else(|| Rect::from_size(self.drawtarget.get_size()))
+                .to_f32()
+        })
+    }
+
+    /// The device-space bounding box of the area that drawing is currently confined to,
+    /// which takes the clips in effect where each enclosing layer began into account.
+    pub(crate) fn clip_bounds(&self) -> Rect<f32> {
+        self.layers
+            .iter()
+            .filter_map(|layer| layer.parent_clip_bounds.last())
+            .try_fold(self.innermost_clip_bounds(), |bounds, parent_bounds| {
+                bounds.intersection(parent_bounds)
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl<D: GenericDrawTarget> Drop for CanvasData<D> {
+    fn drop(&mut self) {
+        self.compositor_api.delete_image(self.image_key);
+    }
+}
+
+const HANGING_BASELINE_DEFAULT: f32 = 0.8;
+const IDEOGRAPHIC_BASELINE_DEFAULT: f32 = 0.5;
+
+/// It writes an image to the destination target
+/// draw_target: the destination target where the image_data will be copied
+/// image_data: Pixel information of the image to be written. It takes RGBA8
+/// image_size: The size of the image to be written
+/// dest_rect: Area of the destination target where the pixels will be copied
+/// smoothing_enabled: It determines if smoothing is applied to the image result
+/// premultiply: Determines whenever the image data should be premultiplied or not
+fn write_image<DrawTarget: GenericDrawTarget>(
+    draw_target: &mut DrawTarget,
+    snapshot: Snapshot,
+    dest_rect: Rect<f64>,
+    smoothing_enabled: bool,
+    composition_options: CompositionOptions,
+    transform: Transform2D<f32>,
+) {
+    if snapshot.size().is_empty() {
+        return;
+    }
+
+    let image_rect = Rect::new(Point2D::zero(), snapshot.size().cast());
+
+    // From spec https://html.spec.whatwg.org/multipage/#dom-context-2d-drawimage
+    // When scaling up, if the imageSmoothingEnabled attribute is set to true, the user agent should attempt
+    // to apply a smoothing algorithm to the image data when it is scaled.
+    // Otherwise, the image must be rendered using nearest-neighbor interpolation.
+    let filter = if smoothing_enabled {
+        Filter::Bilinear
+    } else {
+        Filter::Nearest
+    };
+
+    let source_surface = draw_target
+        .create_source_surface_from_data(snapshot)
+        .unwrap();
+
+    draw_target.draw_surface(
+        source_surface,
+        dest_rect,
+        image_rect,
+        filter,
+        composition_options,
+        transform,
+    );
+}
+
+/// Composites `overlay` onto `base` with `composition_options`, on a draw target of their
+/// own, and returns the result. The overlay is placed at the origin of the base.
+pub(crate) fn blend_snapshots<DrawTarget: GenericDrawTarget>(
+    mut base: Snapshot,
+    mut overlay: Snapshot,
+    composition_options: CompositionOptions,
+) -> Snapshot {
+    // The draw target is in sRGB, like the surface of a canvas.
+    base.convert_to_srgb();
+    overlay.convert_to_srgb();
+    let mut draw_target = DrawTarget::new(base.size());
+    let base_rect = Rect::from_size(base.size().to_f64());
+    let overlay_rect = Rect::from_size(overlay.size().to_f64());
+    write_image(
+        &mut draw_target,
+        base,
+        base_rect,
+        false,
+        CompositionOptions {
+            alpha: 1.,
+            composition_operation: CompositionOrBlending::default(),
+        },
+        Transform2D::identity(),
+    );
+    write_image(
+        &mut draw_target,
+        overlay,
+        overlay_rect,
+        false,
+        composition_options,
+        Transform2D::identity(),
+    );
+    draw_target.snapshot()
+}
+
+pub(crate) trait RectToi32 {
+    fn ceil(&self) -> Rect<f64>;
+}
+
+impl RectToi32 for Rect<f64> {
+    fn ceil(&self) -> Rect<f64> {
+        Rect::new(
+            Point2D::new(self.origin.x.ceil(), self.origin.y.ceil()),
+            Size2D::new(self.size.width.ceil(), self.size.height.ceil()),
+        )
+    }
+}
+
+/// The size of the draw target of a canvas of `size` CSS pixels, rounded up so that every CSS
+/// pixel is covered. A draw target that would be larger than `limits` allow is left empty.
+fn backing_store_size(
+    size: Size2D<u64>,
+    scale_factor: u32,
+    render_scale: f32,
+    limits: &CanvasSizeLimits,
+) -> Size2D<u64> {
+    let scale = scale_factor as f64 * render_scale as f64;
+    let size = (size.to_f64() * scale).ceil().to_u64();
+    if !size.is_empty() && !limits.allows(size) {
+        warn!("Canvas backing store of size {size:?} exceeds {limits:?}");
+    }
+    limits.clamp(size).max(MIN_WR_IMAGE_SIZE)
+}
+
+/// Flips raw image `data` described by `descriptor` so that its rows are bottom-up, if
+/// `y_flipped` is set.
+fn maybe_flip_image_data(
+    y_flipped: bool,
+    descriptor: &ImageDescriptor,
+    data: SerializableImageData,
+) -> SerializableImageData {
+    let SerializableImageData::Raw(bytes) = data else {
+        return data;
+    };
+    if !y_flipped {
+        return SerializableImageData::Raw(bytes);
+    }
+
+    // Rows may be padded, so flip whole strides.
+    let stride = descriptor.compute_stride() as usize;
+    let height = descriptor.size.height as usize;
+    let offset = descriptor.offset as usize;
+    let mut bytes = bytes.to_vec();
+    flip_y_rgba8_image_inplace(
+        Size2D::new(stride as u32 / 4, height as u32),
+        &mut bytes[offset..offset + stride * height],
+    );
+    SerializableImageData::Raw(IpcSharedMemory::from_bytes(&bytes))
+}
+
+/// Returns `data`, with its color channels divided by its alpha unless `premultiplied`.
+fn maybe_unmultiply_image_data(
+    premultiplied: bool,
+    descriptor: &ImageDescriptor,
+    data: SerializableImageData,
+) -> SerializableImageData {
+    let SerializableImageData::Raw(bytes) = data else {
+        return data;
+    };
+    if premultiplied {
+        return SerializableImageData::Raw(bytes);
+    }
+
+    // Rows may be padded, so whole strides are converted.
+    let stride = descriptor.compute_stride() as usize;
+    let height = descriptor.size.height as usize;
+    let offset = descriptor.offset as usize;
+    let mut bytes = bytes.to_vec();
+    unmultiply_inplace::<false>(&mut bytes[offset..offset + stride * height]);
+    SerializableImageData::Raw(IpcSharedMemory::from_bytes(&bytes))
+}
+
+/// Returns `path` without the final line of each closed subpath that ends where the subpath
+/// started, or `None` if there is no such line. Closing the subpath draws that line anyway,
+/// but keeping it leaves a zero-length closing segment between the last and first segments.
+fn without_redundant_closing_lines(path: &Path) -> Option<Path> {
+    let elements = path.0.elements();
+    let mut result = Vec::with_capacity(elements.len());
+    let mut subpath_start = None;
+    for (index, element) in elements.iter().enumerate() {
+        match *element {
+            PathEl::MoveTo(point) => subpath_start = Some(point),
+            PathEl::LineTo(point)
+                if Some(point) == subpath_start &&
+                    matches!(elements.get(index + 1), Some(PathEl::ClosePath)) &&
+                    !matches!(result.last(), Some(PathEl::MoveTo(_))) =>
+            {
+                continue;
+            },
+            _ => {},
+        }
+        result.push(*element);
+    }
+    (result.len() != elements.len()).then(|| Path(kurbo::BezPath::from_vec(result)))
+}
+
+/// Returns the options that a line narrower than a device pixel under `transform` is
+/// stroked with: one device pixel wide, with its alpha scaled by its width in device pixels,
+/// so that it fades in proportion to its width rather than vanishing or snapping to a whole
+/// pixel. Wider lines keep their options. The width in device pixels is taken along the
+/// direction that `transform` shrinks the most, its smaller singular value, so that a
+/// non-uniform scale can't hide a hairline behind a large area scale.
+fn hairline_options(
+    mut line_options: LineOptions,
+    mut composition_options: CompositionOptions,
+    transform: &Transform2D<f32>,
+) -> (LineOptions, CompositionOptions) {
+    let [m11, m12, m21, m22] =
+        [transform.m11, transform.m12, transform.m21, transform.m22].map(f64::from);
+    let sum_of_squares = m11 * m11 + m12 * m12 + m21 * m21 + m22 * m22;
+    let determinant = m11 * m22 - m12 * m21;
+    let discriminant = (sum_of_squares * sum_of_squares - 4. * determinant * determinant).max(0.);
+    let scale = ((sum_of_squares - discriminant.sqrt()) / 2.).max(0.).sqrt();
+    let device_width = line_options.width * scale;
+    if device_width > 0. && device_width < 1. {
+        line_options.width /= device_width;
+        composition_options.alpha *= device_width;
+    }
+    (line_options, composition_options)
+}
+
+/// The style and options that stroke the gaps between the dashes of `line_options` in
+/// their gap color, if they have dashes and a gap color. The dash list is rotated by one
+/// entry so that its dashes are the original gaps, and the offset is moved back to match.
+fn dash_gap_stroke(line_options: &LineOptions) -> Option<(FillOrStrokeStyle, LineOptions)> {
+    let color = line_options.dash_gap_color?;
+    let mut dash = line_options.dash.clone();
+    // A list with an odd number of entries repeats twice over, with dashes and gaps swapped.
+    if dash.len() % 2 == 1 {
+        dash.extend_from_within(..);
+    }
+    let period: f64 = dash.iter().map(|length| *length as f64).sum();
+    if period <= 0. {
+        return None;
+    }
+    let first_dash = dash[0] as f64;
+    dash.rotate_left(1);
+    let gap_line_options = LineOptions {
+        width: line_options.width,
+        // Caps would extend the gaps over the dashes.
+        cap_style: LineCapStyle::Butt,
+        join_style: line_options.join_style,
+        miter_limit: line_options.miter_limit,
+        dash,
+        dash_offset: (line_options.dash_offset - first_dash).rem_euclid(period),
+        dash_gap_color: None,
+        alignment: line_options.alignment,
+    };
+    Some((FillOrStrokeStyle::Color(color), gap_line_options))
+}
+
+/// Returns the dots that the zero-length dashes of `line_options` make along `path` with
+/// round caps, as circles to fill, along with the options that stroke its other dashes, if
+/// there are any. Those are stroked with the zero-length dashes left out, so that no
+/// stroker is relied upon to cap an empty dash, nor draws a dot twice by doing so.
+fn round_dash_dots(path: &Path, line_options: &LineOptions) -> Option<(Path, Option<LineOptions>)> {
+    if line_options.cap_style != LineCapStyle::Round {
+        return None;
+    }
+    let mut dash: Vec<f64> = line_options
+        .dash
+        .iter()
+        .map(|length| *length as f64)
+        .collect();
+    // A list with an odd number of entries repeats twice over, with dashes and gaps swapped.
+    if dash.len() % 2 == 1 {
+        dash.extend_from_within(..);
+    }
+    let period: f64 = dash.iter().sum();
+    if period <= 0. || !dash.iter().step_by(2).any(|length| *length == 0.) {
+        return None;
+    }
+
+    // The positions of the dots within a period of the pattern, in increasing order.
+    let mut dot_phases = Vec::new();
+    let mut phase = 0.;
+    for (index, length) in dash.iter().enumerate() {
+        if index % 2 == 0 && *length == 0. {
+            dot_phases.push(phase);
+        }
+        phase += length;
+    }
+
+    const ACCURACY: f64 = 1e-3;
+    let radius = line_options.width / 2.;
+    let mut dots = kurbo::BezPath::new();
+    let elements = path.0.elements();
+    let subpath_starts = elements
+        .iter()
+        .enumerate()
+        .filter(|(_, element)| matches!(element, PathEl::MoveTo(_)))
+        .map(|(index, _)| index)
+        .chain([elements.len()])
+        .collect::<Vec<_>>();
+    for bounds in subpath_starts.windows(2) {
+        let subpath = &elements[bounds[0]..bounds[1]];
+        let segments: Vec<_> = kurbo::segments(subpath.iter().copied())
+            .map(|segment| (segment, segment.arclen(ACCURACY)))
+            .collect();
+        let length: f64 = segments.iter().map(|(_, length)| length).sum();
+        // The end of a closed subpath is its start, which has a dot already if it needs one.
+        let closed = matches!(subpath.last(), Some(PathEl::ClosePath));
+        let point_at = |position: f64| {
+            let mut start = 0.;
+            for (segment, length) in &segments {
+                if position <= start + length {
+                    return segment.eval(segment.inv_arclen(position - start, ACCURACY));
+                }
+                start += length;
+            }
+            segments.last().map(|(segment, _)| segment.end())
+        };
+
+        // The pattern starts over with every subpath.
+        let mut period_start = -line_options.dash_offset.rem_euclid(period);
+        while !segments.is_empty() && period_start <= length {
+            for phase in &dot_phases {
+                let position = period_start + phase;
+                if position < 0. {
+                    continue;
+                }
+                if position > length || (closed && position == length) {
+                    break;
+                }
+                if let Some(center) = point_at(position) {
+                    for element in kurbo::Circle::new(center, radius).path_elements(ACCURACY) {
+                        dots.push(element);
+                    }
+                }
+            }
+            period_start += period;
+        }
+    }
+
+    // Rotated so that the list starts with a dash that isn't empty, which every empty one
+    // comes after, and can merge the gaps around it with.
+    let dash_line_options =
+        dash.iter()
+            .step_by(2)
+            .position(|length| *length > 0.)
+            .map(|first_dash| {
+                let first_dash = first_dash * 2;
+                let shift: f64 = dash[..first_dash].iter().sum();
+                dash.rotate_left(first_dash);
+                let mut merged: Vec<f32> = Vec::new();
+                for pair in dash.chunks(2) {
+                    match merged.last_mut() {
+                        Some(gap) if pair[0] == 0. => *gap += pair[1] as f32,
+                        _ => merged.extend([pair[0] as f32, pair[1] as f32]),
+                    }
+                }
+                LineOptions {
+                    width: line_options.width,
+                    cap_style: line_options.cap_style,
+                    join_style: line_options.join_style,
+                    miter_limit: line_options.miter_limit,
+                    dash: merged,
+                    dash_offset: (line_options.dash_offset - shift).rem_euclid(period),
+                    dash_gap_color: line_options.dash_gap_color,
+                    alignment: line_options.alignment,
+                }
+            });
+    Some((Path(dots), dash_line_options))
+}
+
+/// Returns the rectangle covered by `path` in device space, rounded to whole pixels, if the
+/// path consists of a single axis-aligned rectangle once `transform` is applied.
+fn axis_aligned_device_rect(path: &Path, transform: Transform2D<f32>) -> Option<Rect<i32>> {
+    let mut path = path.clone();
+    path.transform(transform.cast());
+    let bounds = path.bounding_box();
+    let is_corner = |point: kurbo::Point| {
+        (point.x == bounds.min_x() || point.x == bounds.max_x()) &&
+            (point.y == bounds.min_y() || point.y == bounds.max_y())
+    };
+    let is_axis_aligned = |from: kurbo::Point, to: kurbo::Point| from.x == to.x || from.y == to.y;
+
+    let mut subpaths_with_edges = 0;
+    let mut subpath_has_edges = false;
+    let mut subpath_start = None;
+    let mut previous = None;
+    for element in path.0.elements() {
+        match *element {
+            PathEl::MoveTo(point) if is_corner(point) => {
+                subpath_start = Some(point);
+                subpath_has_edges = false;
+                previous = Some(point);
+            },
+            PathEl::LineTo(point) if is_corner(point) => {
+                if !is_axis_aligned(previous?, point) {
+                    return None;
+                }
+                if !subpath_has_edges {
+                    subpath_has_edges = true;
+                    subpaths_with_edges += 1;
+                }
+                previous = Some(point);
+            },
+            PathEl::ClosePath => {
+                if !is_axis_aligned(previous?, subpath_start?) {
+                    return None;
+                }
+                previous = subpath_start;
+            },
+            _ => return None,
+        }
+    }
+
+    if subpaths_with_edges != 1 {
+        return None;
+    }
+    Some(bounds.round().to_i32())
+}
+
+/// Splits `text` into runs of characters that share a bidi embedding level, as resolved by the
+/// Unicode Bidirectional Algorithm with a right-to-left base direction if `is_rtl`, and returns
+/// them in visual order. See <https://unicode.org/reports/tr9/>.
+fn bidi_runs(text: &str, is_rtl: bool) -> Vec<(&str, Level)> {
+    let base_level = if is_rtl { Level::rtl() } else { Level::ltr() };
+    let bidi_info = BidiInfo::new(text, Some(base_level));
+
+    let mut runs = Vec::new();
+    for paragraph in &bidi_info.paragraphs {
+        // Canvas text is never broken into lines, so each paragraph is reordered as one line.
+        let line = paragraph.range.clone();
+        let levels = bidi_info.reordered_levels(paragraph, line.clone());
+
+        let mut level_runs = Vec::new();
+        let mut run_start = line.start;
+        for (offset, _) in text[line.clone()].char_indices().skip(1) {
+            let index = line.start + offset;
+            if levels[index] != levels[run_start] {
+                level_runs.push((&text[run_start..index], levels[run_start]));
+                run_start = index;
+            }
+        }
+        level_runs.push((&text[run_start..line.end], levels[run_start]));
+
+        let run_levels: Vec<_> = level_runs.iter().map(|(_, level)| *level).collect();
+        runs.extend(
+            BidiInfo::reorder_visual(&run_levels)
+                .into_iter()
+                .map(|index| level_runs[index]),
+        );
+    }
+    runs
+}
+
+/// Splits `text` into runs that can each be shaped with a single font, script and direction,
+/// in the visual order in which they are drawn.
+fn build_unshaped_text_runs<'b>(
+    font_context: &FontContext,
+    text: &'b str,
+    font_group: &mut FontGroup,
+    fallback_font_families: &[SingleFontFamily],
+    is_rtl: bool,
+) -> Vec<UnshapedTextRun<'b>> {
+    let mut runs = Vec::new();
+    for (bidi_run, level) in bidi_runs(text, is_rtl) {
+        let mut level_runs = Vec::new();
+        let mut current_text_run = UnshapedTextRun {
+            is_rtl: level.is_rtl(),
+            ..Default::default()
+        };
+        let mut current_text_run_start_index = 0;
+
+        for (index, character) in bidi_run.char_indices() {
+            // TODO: This should ultimately handle emoji variation selectors, but raqote does not
+            // yet have support for color glyphs.
+            let script = Script::from(character);
+            let font = font_group.find_by_codepoint_with_fallback_families(
+                font_context,
+                character,
+                None,
+                None,
+                fallback_font_families,
+            );
+
+            if !current_text_run.script_and_font_compatible(script, &font) {
+                let previous_text_run = mem::replace(
+                    &mut current_text_run,
+                    UnshapedTextRun {
+                        font: font.clone(),
+                        script,
+                        is_rtl: level.is_rtl(),
+                        ..Default::default()
+                    },
+                );
+                current_text_run_start_index = index;
+                level_runs.push(previous_text_run)
+            }
+
+            current_text_run.string =
+                &bidi_run[current_text_run_start_index..index + character.len_utf8()];
+        }
+        level_runs.push(current_text_run);
+
+        // The runs of a right-to-left level are drawn from right to left.
+        if level.is_rtl() {
+            level_runs.reverse();
+        }
+        runs.extend(level_runs);
+    }
+    runs
+}
+
+/// Raises the alpha of each of the premultiplied `pixels`, as a coverage between 0 and 1,
+/// to the power `1 / gamma`, scaling their color channels along so that their color is
+/// unchanged.
+fn correct_coverage_gamma(pixels: &mut [u8], gamma: f32) {
+    let corrected_alpha: Vec<u8> = (0..=255)
+        .map(|alpha| ((alpha as f32 / 255.).powf(1. / gamma) * 255.).round() as u8)
+        .collect();
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3];
+        if alpha == 0 || alpha == 255 {
+            continue;
+        }
+        let corrected = corrected_alpha[alpha as usize];
+        for channel in &mut pixel[..3] {
+            *channel = multiply_u8_color(unmultiply_u8_color(*channel, alpha), corrected);
+        }
+        pixel[3] = corrected;
+    }
+}
+
+/// Splits `glyph_range`, a range of indices into the glyphs of consecutive runs that have
+/// `glyph_counts` glyphs each, into the range of indices it covers in each run. The range is
+/// clamped to the glyphs there are, so the ranges of the runs it does not reach are empty.
+fn split_glyph_range(
+    glyph_range: &std::ops::Range<usize>,
+    glyph_counts: &[usize],
+) -> Vec<std::ops::Range<usize>> {
+    let mut first_glyph = 0;
+    glyph_counts
+        .iter()
+        .map(|&glyph_count| {
+            let start = glyph_range
+                .start
+                .clamp(first_glyph, first_glyph + glyph_count);
+            let end = glyph_range.end.clamp(start, first_glyph + glyph_count);
+            let range = start - first_glyph..end - first_glyph;
+            first_glyph += glyph_count;
+            range
+        })
+        .collect()
+}
+
+/// The glyphs of a shaped run with the origins to draw them at, starting from `pen` and
+/// advancing it past them. The shaper positions a combining mark over its base with an
+/// offset, and usually gives it no advance, so laying out by advances alone would put the
+/// mark beside the base. With a `snapping_transform`, each origin is rounded to the device
+/// pixel that it is transformed to, while the pen keeps advancing by fractional pixels so
+/// that rounding errors don't add up along the run.
+pub(crate) fn positioned_glyphs<'a>(
+    glyphs: &'a GlyphStore,
+    pen: &'a mut Point2D<f32>,
+    snapping_transform: Option<&'a Transform2D<f32>>,
+) -> impl Iterator<Item = (GlyphId, Point2D<f32>)> + 'a {
+    glyphs
+        .iter_glyphs_for_byte_range(&Range::new(ByteIndex(0), glyphs.len()))
+        .map(move |glyph| {
+            let offset = glyph.offset().unwrap_or(Point2D::zero());
+            let origin = Point2D::new(pen.x + offset.x.to_f32_px(), pen.y + offset.y.to_f32_px());
+            pen.x += glyph.advance().to_f32_px();
+            let origin = match snapping_transform {
+                Some(transform) => snap_to_device_pixel(origin, transform),
+                None => origin,
+            };
+            (glyph.id(), origin)
+        })
+}
+
+/// The rectangle, in user space, whose edges `transform` takes to device pixel boundaries,
+/// rounding those of `rect` as `rounding` says. Rectangles that a rotation or skew doesn't
+/// keep axis-aligned in device space are left as they are.
+fn round_rect_to_device_pixels(
+    rect: &Rect<f32>,
+    rounding: CoordinateRounding,
+    transform: &Transform2D<f32>,
+) -> Rect<f32> {
+    if rounding == CoordinateRounding::None || transform.m12 != 0. || transform.m21 != 0. {
+        return *rect;
+    }
+    let Some(inverse) = transform.inverse() else {
+        return *rect;
+    };
+    let device_box = transform.outer_transformed_rect(rect).to_box2d();
+    let rounded_box = match rounding {
+        CoordinateRounding::None => device_box,
+        CoordinateRounding::Round => device_box.round(),
+        CoordinateRounding::Floor => Box2D::new(device_box.min.floor(), device_box.max.floor()),
+        CoordinateRounding::Ceil => Box2D::new(device_box.min.ceil(), device_box.max.ceil()),
+    };
+    inverse.outer_transformed_rect(&rounded_box.to_rect())
+}
+
+/// Moves `point`, in user space, to where `transform` takes it to the nearest device pixel
+/// corner. Points that can't be mapped back to user space are left where they are.
+fn snap_to_device_pixel(point: Point2D<f32>, transform: &Transform2D<f32>) -> Point2D<f32> {
+    transform.inverse().map_or(point, |inverse| {
+        inverse.transform_point(transform.transform_point(point).round())
+    })
+}
+
+/// Returns the outlines of boxes standing in for the `.notdef` glyphs of `runs`, which
+/// shapers emit for characters that no font in the fallback chain supports. Fonts may
+/// leave `.notdef` empty, and a box keeps those characters visible, as in browsers.
+/// Each run is given as its glyphs and the indices of those that are drawn.
+fn missing_glyph_boxes<'a>(
+    runs: impl IntoIterator<Item = (&'a GlyphStore, std::ops::Range<usize>)>,
+    start: Point2D<f32>,
+    size: f64,
+) -> Option<Path> {
+    // By convention, the `.notdef` glyph is the first glyph of a font.
+    const NOTDEF_GLYPH_ID: u32 = 0;
+
+    let line_width = (size / 16.).max(1.);
+    let height = size * 0.7;
+    let mut boxes = None;
+    let mut x = start.x as f64;
+    for (glyphs, visible_glyphs) in runs {
+        let glyphs = glyphs.iter_glyphs_for_byte_range(&Range::new(ByteIndex(0), glyphs.len()));
+        for (index, glyph) in glyphs.enumerate() {
+            let advance = glyph.advance().to_f64_px();
+            if glyph.id() == NOTDEF_GLYPH_ID && visible_glyphs.contains(&index) {
+                let width = if advance > 0. {
+                    advance * 0.8
+                } else {
+                    size * 0.5
+                };
+                let left = x + (advance - width).max(0.) / 2.;
+                let top = start.y as f64 - height;
+                let path = boxes.get_or_insert_with(Path::new);
+                path.rect(left, top, width, height);
+                path.rect(
+                    left + line_width,
+                    top + line_width,
+                    (width - 2. * line_width).max(0.),
+                    (height - 2. * line_width).max(0.),
+                );
+            }
+            x += advance;
+        }
+    }
+    boxes
+}
+
+/// Returns the lines of `decoration` for a line of text that is `width` wide and whose
+/// baseline starts at `start`, each as a path along its middle and the options to stroke it
+/// with. As in CSS, the lines are placed and sized according to `metrics`.
+fn text_decoration_lines(
+    decoration: &TextDecoration,
+    metrics: &FontMetrics,
+    start: Point2D<f32>,
+    width: f64,
+) -> Vec<(Path, LineOptions)> {
+    let x = start.x as f64;
+    let baseline = start.y as f64;
+    let line = |top: f64, font_thickness: f64| {
+        // Fonts that lack the metric report a thickness of zero.
+        let thickness = decoration.thickness.unwrap_or(font_thickness.max(1.));
+        let y = top + thickness / 2.;
+        let mut path = Path::new();
+        path.move_to(x, y);
+        path.line_to(x + width, y);
+        let dash = match decoration.style {
+            TextDecorationStyle::Solid => Vec::new(),
+            TextDecorationStyle::Dashed => vec![2. * thickness as f32; 2],
+        };
+        let line_options = LineOptions {
+            width: thickness,
+            cap_style: LineCapStyle::Butt,
+            join_style: LineJoinStyle::Miter,
+            miter_limit: 10.,
+            dash,
+            dash_offset: 0.,
+            dash_gap_color: None,
+            alignment: StrokeAlignment::Center,
+        };
+        (path, line_options)
+    };
+
+    // The offsets of the metrics are distances above the baseline to the top of the line.
+    let underline_size = metrics.underline_size.to_f64_px();
+    let mut lines = Vec::new();
+    if decoration.underline {
+        lines.push(line(
+            baseline - metrics.underline_offset.to_f64_px(),
+            underline_size,
+        ));
+    }
+    if decoration.overline {
+        lines.push(line(baseline - metrics.ascent.to_f64_px(), underline_size));
+    }
+    if decoration.line_through {
+        lines.push(line(
+            baseline - metrics.strikeout_offset.to_f64_px(),
+            metrics.strikeout_size.to_f64_px(),
+        ));
+    }
+    lines
+}
+
+/// Returns the distances from the baseline to the top and bottom of a line box of
+/// `line_height`, if given, for a font with `ascent` and `descent`. Like the half-leading
+/// of CSS, what the font lacks of the height, or has beyond it, is split evenly above and
+/// below.
+fn line_box_ascent_and_descent(ascent: f32, descent: f32, line_height: Option<f32>) -> (f32, f32) {
+    match line_height.filter(|line_height| line_height.is_finite() && *line_height >= 0.) {
+        Some(line_height) => {
+            let half_leading = (line_height - (ascent + descent)) / 2.;
+            (ascent + half_leading, descent + half_leading)
+        },
+        None => (ascent, descent),
+    }
+}
+
+/// Shapes `text` with the font described by `font_style` and measures the result.
+/// Splits `path` into its subpaths whose indices aren't in `holes`, and those that are.
+fn split_hole_subpaths(path: &Path, holes: &[usize]) -> (Path, Vec<Path>) {
+    let mut rest = Path::new();
+    let mut hole_paths = Vec::new();
+    let mut subpath = 0;
+    let mut is_hole = false;
+    for (index, element) in path.0.elements().iter().enumerate() {
+        if let PathEl::MoveTo(_) = element {
+            if index > 0 {
+                subpath += 1;
+            }
+            is_hole = holes.contains(&subpath);
+            if is_hole {
+                hole_paths.push(Path::new());
+            }
+        }
+        match hole_paths.last_mut() {
+            Some(hole) if is_hole => hole.0.push(*element),
+            _ => rest.0.push(*element),
+        }
+    }
+    (rest, hole_paths)
+}
+
+/// Shapes `text` into runs in visual order. With a tab width, the pieces of the text
+/// between its tabs are shaped separately, and each tab becomes a run of a single undrawn
+/// glyph that advances to the next tab stop.
+fn shape_text(
+    font_context: &FontContext,
+    text: &str,
+    font_group: &mut FontGroup,
+    fallback_font_families: &[SingleFontFamily],
+    is_rtl: bool,
+    tab_width: Option<f32>,
+    shaping_tags: ShapingTags,
+) -> Vec<TextRun> {
+    let shape = |text: &str, font_group: &mut FontGroup| -> Vec<TextRun> {
+        build_unshaped_text_runs(
+            font_context,
+            text,
+            font_group,
+            fallback_font_families,
+            is_rtl,
+        )
+        .into_iter()
+        .filter_map(|run| run.into_shaped_text_run(shaping_tags))
+        .collect()
+    };
+    let Some(tab_width) = tab_width else {
+        return shape(text, font_group);
+    };
+
+    // Tabs separate the text into segments that are laid out in the base direction, see
+    // <https://unicode.org/reports/tr9/#L1>, so the pieces are ordered by it too.
+    let mut pieces = Vec::new();
+    let mut width = 0.;
+    for (index, piece) in text.split('\t').enumerate() {
+        if index > 0 {
+            if let Some(font) = font_group.first(font_context) {
+                let advance = tab_advance(width, tab_width);
+                width += advance;
+                pieces.push(vec![tab_run(font, advance)]);
+            }
+        }
+        let runs = shape(piece, font_group);
+        width += runs
+            .iter()
+            .map(|run| run.glyphs.total_advance().to_f32_px())
+            .sum::<f32>();
+        pieces.push(runs);
+    }
+    if is_rtl {
+        pieces.reverse();
+    }
+    pieces.into_iter().flatten().collect()
+}
+
+/// The size to fill text with instead of `size` so that it fits in `max_width`, if it is
+/// `advance` wide at `size` and overflows it. Text is not condensed below 5 pixels, so it may
+/// still overflow.
+fn condensed_font_size(advance: f64, max_width: f64, size: f64) -> Option<f64> {
+    let new_size = (max_width / advance * size).floor().max(5.);
+    (advance > max_width && new_size != size).then_some(new_size)
+}
+
+/// The distance between tab stops for the font with `metrics`.
+fn tab_width(tab_size: TabSize, metrics: &FontMetrics) -> f32 {
+    match tab_size {
+        TabSize::Spaces(spaces) => spaces * metrics.space_advance.to_f32_px(),
+        TabSize::Pixels(pixels) => pixels,
+    }
+}
+
+/// The advance of a tab `x` from the start of the text to the next of the tab stops that
+/// are `tab_width` apart. A tab right at a tab stop advances to the one after it.
+fn tab_advance(x: f32, tab_width: f32) -> f32 {
+    if tab_width <= 0. || !tab_width.is_finite() {
+        return 0.;
+    }
+    ((x / tab_width).floor() + 1.) * tab_width - x
+}
+
+/// A run of a single glyph for a tab, which isn't drawn and advances by `advance`.
+fn tab_run(font: FontRef, advance: f32) -> TextRun {
+    let mut glyphs = GlyphStore::new(1, true, true, false, false);
+    let glyph_id = font.glyph_index(' ').unwrap_or_default();
+    let glyph = GlyphData::new(glyph_id, Au::from_f32_px(advance), None, true, true);
+    glyphs.add_glyph_for_byte_index(ByteIndex(0), '\t', &glyph);
+    glyphs.finalize_changes();
+    TextRun {
+        font,
+        glyphs: Arc::new(glyphs),
+        visible_glyphs: 0..0,
+        pixel_snapped: false,
+    }
+}
+
+fn measure_shaped_text(
+    font_context: &FontContext,
+    font_style: &ServoArc<FontStyleStruct>,
+    fallback_font_families: &[SingleFontFamily],
+    text: &str,
+    is_rtl: bool,
+    tab_size: Option<TabSize>,
+    shaping_tags: ShapingTags,
+) -> TextMeasurements {
+    let font_group = font_context.font_group(font_style.clone());
+    let mut font_group = font_group.write();
+    let font = font_group.first(font_context).expect("couldn't find font");
+    let ascent = font.metrics.ascent.to_f32_px();
+    let descent = font.metrics.descent.to_f32_px();
+    let shaped_runs = shape_text(
+        font_context,
+        text,
+        &mut font_group,
+        fallback_font_families,
+        is_rtl,
+        tab_size.map(|tab_size| tab_width(tab_size, &font.metrics)),
+        shaping_tags,
+    );
+    let width = shaped_runs
+        .iter()
+        .map(|run| run.glyphs.total_advance())
+        .sum::<Au>()
+        .to_f32_px();
+    let bounding_box = shaped_runs
+        .iter()
+        .map(TextRun::bounding_box)
+        .reduce(|a, b| {
+            let amount = Vector2D::new(a.max_x(), 0.);
+            let bounding_box = b.translate(amount);
+            a.union(&bounding_box)
+        })
+        .unwrap_or_default();
+
+    let FontBaseline {
+        ideographic_baseline,
+        alphabetic_baseline,
+        hanging_baseline,
+    } = match font.baseline() {
+        Some(baseline) => baseline,
+        None => FontBaseline {
+            hanging_baseline: ascent * HANGING_BASELINE_DEFAULT,
+            ideographic_baseline: -descent * IDEOGRAPHIC_BASELINE_DEFAULT,
+            alphabetic_baseline: 0.,
+        },
+    };
+
+    TextMeasurements {
+        width,
+        bounding_box,
+        ascent,
+        descent,
+        hanging_baseline,
+        ideographic_baseline,
+        alphabetic_baseline,
+    }
+}
+
+/// Greedily breaks `text` after runs of spaces into lines whose width, as given by `measure`,
+/// is at most `max_width`, and returns the byte offset at which each line after the first
+/// starts. The spaces at the end of a line are not measured. A word that does not fit on a
+/// line of its own overflows it.
+fn line_breaks(text: &str, max_width: f32, mut measure: impl FnMut(&str) -> f32) -> Vec<usize> {
+    let mut line_breaks = Vec::new();
+    let mut line_start = 0;
+    // The start of the word after the last one placed on the current line, if any.
+    let mut next_word_start = None;
+    let mut word_start = 0;
+    while word_start < text.len() {
+        let word_end = text[word_start..]
+            .find(' ')
+            .map_or(text.len(), |offset| word_start + offset);
+        let following_word_start = text[word_end..]
+            .find(|character| character != ' ')
+            .map_or(text.len(), |offset| word_end + offset);
+
+        if let Some(break_offset) = next_word_start {
+            if measure(&text[line_start..word_end]) > max_width {
+                line_breaks.push(break_offset);
+                line_start = break_offset;
+            }
+        }
+        next_word_start = Some(following_word_start);
+        word_start = following_word_start;
+    }
+    line_breaks
+}
+
+/// Returns `color` as a premultiplied pixel with the channel order of `format`.
+fn premultiplied_pixel(color: AbsoluteColor, format: SnapshotPixelFormat) -> [u8; 4] {
+    let srgb = color.into_srgb_legacy();
+    let alpha = srgb.alpha.clamp(0., 1.);
+    let channel = |value: f32| (value.clamp(0., 1.) * alpha * 255.).round() as u8;
+    let red = channel(srgb.components.0);
+    let green = channel(srgb.components.1);
+    let blue = channel(srgb.components.2);
+    let alpha = (alpha * 255.).round() as u8;
+    match format {
+        SnapshotPixelFormat::RGBA => [red, green, blue, alpha],
+        SnapshotPixelFormat::BGRA => [blue, green, red, alpha],
+    }
+}
+
+/// Brings the colors of `style` into the sRGB gamut of the draw target as `gamut_mapping`
+/// says. Clipping is left to the backends, which clamp every channel anyway.
+fn map_style_into_gamut(style: &mut FillOrStrokeStyle, gamut_mapping: GamutMapping) {
+    if gamut_mapping == GamutMapping::Clip {
+        return;
+    }
+    match style {
+        FillOrStrokeStyle::Color(color) => *color = map_into_srgb_gamut(*color, gamut_mapping),
+        FillOrStrokeStyle::LinearGradient(LinearGradientStyle { stops, .. }) |
+        FillOrStrokeStyle::RadialGradient(RadialGradientStyle { stops, .. }) => {
+            for stop in stops {
+                stop.color = map_into_srgb_gamut(stop.color, gamut_mapping);
+            }
+        },
+        FillOrStrokeStyle::Surface(..) => {},
+    }
+}
+
+/// Adds stops to the gradient of `style`, if it is one, between each pair of stops that
+/// differ in alpha, with the colors that `interpolation` gives at their offsets. The colors
+/// in between are then nearly the same whether the backend premultiplies them or not, and
+/// exactly the same next to a transparent stop in premultiplied interpolation, where the
+/// color is that of the other stop all along.
+fn interpolate_gradient_stops(style: &mut FillOrStrokeStyle, interpolation: GradientInterpolation) {
+    let stops = match style {
+        FillOrStrokeStyle::LinearGradient(LinearGradientStyle { stops, .. }) |
+        FillOrStrokeStyle::RadialGradient(RadialGradientStyle { stops, .. }) => stops,
+        FillOrStrokeStyle::Color(..) | FillOrStrokeStyle::Surface(..) => return,
+    };
+    // Stops at the same offset keep their order, as they do when the backends sort them.
+    stops.sort_by(|a, b| a.offset.total_cmp(&b.offset));
+
+    let channels = |stop: &CanvasGradientStop| {
+        let srgb = stop.color.into_srgb_legacy();
+        [
+            srgb.components.0,
+            srgb.components.1,
+            srgb.components.2,
+            srgb.alpha,
+        ]
+        .map(|value| value.clamp(0., 1.))
+    };
+    let mut interpolated = Vec::with_capacity(stops.len());
+    for pair in stops.windows(2) {
+        let (start, end) = (&pair[0], &pair[1]);
+        interpolated.push(start.clone());
+        let (start_channels, end_channels) = (channels(start), channels(end));
+        if start_channels[3] == end_channels[3] || start.offset == end.offset {
+            continue;
+        }
+        for step in 1..GRADIENT_INTERPOLATION_STEPS {
+            let progress = step as f32 / GRADIENT_INTERPOLATION_STEPS as f32;
+            let lerp = |from: f32, to: f32| from + (to - from) * progress;
+            let alpha = lerp(start_channels[3], end_channels[3]);
+            let channel = |index: usize| match interpolation {
+                GradientInterpolation::Premultiplied if alpha > 0. => {
+                    lerp(
+                        start_channels[index] * start_channels[3],
+                        end_channels[index] * end_channels[3],
+                    ) / alpha
+                },
+                GradientInterpolation::Premultiplied => 0.,
+                GradientInterpolation::Unpremultiplied => {
+                    lerp(start_channels[index], end_channels[index])
+                },
+            };
+            interpolated.push(CanvasGradientStop {
+                offset: start.offset + (end.offset - start.offset) * progress as f64,
+                color: AbsoluteColor::new(
+                    ColorSpace::Srgb,
+                    channel(0),
+                    channel(1),
+                    channel(2),
+                    alpha,
+                ),
+            });
+        }
+    }
+    if let Some(last) = stops.last() {
+        interpolated.push(last.clone());
+    }
+    *stops = interpolated;
+}
+
+/// Returns `color`, if it is within the sRGB gamut, or the color that `gamut_mapping`
+/// brings it to otherwise.
+fn map_into_srgb_gamut(color: AbsoluteColor, gamut_mapping: GamutMapping) -> AbsoluteColor {
+    if is_in_srgb_gamut(&color) {
+        return color;
+    }
+    match gamut_mapping {
+        GamutMapping::Clip => color,
+        GamutMapping::Scale => scale_into_srgb_gamut(color),
+        GamutMapping::Perceptual => perceptually_map_into_srgb_gamut(color),
+    }
+}
+
+fn is_in_srgb_gamut(color: &AbsoluteColor) -> bool {
+    // Tolerate the error of converting colors that are on the edge of the gamut.
+    const EPSILON: f32 = 1e-5;
+    let srgb = color.to_color_space(ColorSpace::Srgb);
+    [srgb.components.0, srgb.components.1, srgb.components.2]
+        .iter()
+        .all(|channel| (-EPSILON..=1. + EPSILON).contains(channel))
+}
+
+fn clip_into_srgb_gamut(color: &AbsoluteColor) -> AbsoluteColor {
+    let mut srgb = color.to_color_space(ColorSpace::Srgb);
+    srgb.components.0 = srgb.components.0.clamp(0., 1.);
+    srgb.components.1 = srgb.components.1.clamp(0., 1.);
+    srgb.components.2 = srgb.components.2.clamp(0., 1.);
+    srgb
+}
+
+/// See [`GamutMapping::Scale`].
+fn scale_into_srgb_gamut(color: AbsoluteColor) -> AbsoluteColor {
+    let mut linear = color.to_color_space(ColorSpace::SrgbLinear);
+    let mut channels = [linear.components.0, linear.components.1, linear.components.2];
+    let [red, green, blue] = channels;
+    let luminance = (0.2126 * red + 0.7152 * green + 0.0722 * blue).max(0.);
+    let min = red.min(green).min(blue);
+    if min < 0. {
+        let saturation = luminance / (luminance - min);
+        channels = channels.map(|channel| luminance + (channel - luminance) * saturation);
+    }
+    let max = channels[0].max(channels[1]).max(channels[2]);
+    if max > 1. {
+        channels = channels.map(|channel| channel / max);
+    }
+    linear.components.0 = channels[0];
+    linear.components.1 = channels[1];
+    linear.components.2 = channels[2];
+    clip_into_srgb_gamut(&linear)
+}
+
+/// See [`GamutMapping::Perceptual`] and
+/// <https://drafts.csswg.org/css-color-4/#binsearch>.
+fn perceptually_map_into_srgb_gamut(color: AbsoluteColor) -> AbsoluteColor {
+    // The difference below which clipping is imperceptible, and the precision of the search.
+    const JND: f32 = 0.02;
+    const MIN_CONVERGENCE: f32 = 0.0001;
+    let delta_eok = |first: &AbsoluteColor, second: &AbsoluteColor| {
+        let first = first.to_color_space(ColorSpace::Oklab).components;
+        let second = second.to_color_space(ColorSpace::Oklab).components;
+        let (dl, da, db) = (first.0 - second.0, first.1 - second.1, first.2 - second.2);
+        (dl * dl + da * da + db * db).sqrt()
+    };
+
+    let mut current = color.to_color_space(ColorSpace::Oklch);
+    let lightness = current.components.0;
+    if lightness >= 1. || lightness <= 0. {
+        current.components.1 = 0.;
+        return clip_into_srgb_gamut(&current);
+    }
+    let clipped = clip_into_srgb_gamut(&current);
+    if delta_eok(&clipped, &current) < JND {
+        return clipped;
+    }
+
+    let (mut min, mut max) = (0., current.components.1);
+    let mut min_in_gamut = true;
+    while max - min > MIN_CONVERGENCE {
+        let chroma = (min + max) / 2.;
+        current.components.1 = chroma;
+        if min_in_gamut && is_in_srgb_gamut(&current) {
+            min = chroma;
+            continue;
+        }
+        let clipped = clip_into_srgb_gamut(&current);
+        let error = delta_eok(&clipped, &current);
+        if error < JND {
+            if JND - error < MIN_CONVERGENCE {
+                return clipped;
+            }
+            min_in_gamut = false;
+            min = chroma;
+        } else {
+            max = chroma;
+        }
+    }
+    clip_into_srgb_gamut(&current)
+}
+
+/// Returns the premultiplied color, with components between 0 and 1, of the gradient
+/// `style` at `point`, in the coordinate space of the gradient.
+fn gradient_color_at(style: &FillOrStrokeStyle, point: Point2D<f64>) -> [f64; 4] {
+    let (offset, stops) = match style {
+        FillOrStrokeStyle::LinearGradient(gradient) => {
+            let (dx, dy) = (gradient.x1 - gradient.x0, gradient.y1 - gradient.y0);
+            let offset =
+                ((point.x - gradient.x0) * dx + (point.y - gradient.y0) * dy) / (dx * dx + dy * dy);
+            let offset = if gradient.repeating {
+                offset.rem_euclid(1.)
+            } else {
+                offset
+            };
+            (offset, &gradient.stops)
+        },
+        FillOrStrokeStyle::RadialGradient(gradient) => {
+            let Some(offset) = radial_gradient_offset(gradient, point) else {
+                return [0.; 4];
+            };
+            (offset, &gradient.stops)
+        },
+        FillOrStrokeStyle::Color(..) | FillOrStrokeStyle::Surface(..) => {
+            unreachable!("Only gradients are evaluated per pixel")
+        },
+    };
+
+    let premultiplied = |stop: &CanvasGradientStop| {
+        let srgb = stop.color.into_srgb_legacy();
+        let alpha = srgb.alpha.clamp(0., 1.) as f64;
+        let channel = |value: f32| value.clamp(0., 1.) as f64 * alpha;
+        [
+            channel(srgb.components.0),
+            channel(srgb.components.1),
+            channel(srgb.components.2),
+            alpha,
+        ]
+    };
+    let offset = offset.clamp(0., 1.);
+    let next = stops.partition_point(|stop| stop.offset <= offset);
+    if next == 0 {
+        return premultiplied(&stops[0]);
+    }
+    if next == stops.len() {
+        return premultiplied(&stops[next - 1]);
+    }
+    let (start, end) = (&stops[next - 1], &stops[next]);
+    let progress = (offset - start.offset) / (end.offset - start.offset);
+    let (start, end) = (premultiplied(start), premultiplied(end));
+    [0, 1, 2, 3].map(|index| start[index] + (end[index] - start[index]) * progress)
+}
+
+/// Returns the largest `t` for which `point` lies on the circle interpolated between the
+/// start and end circles of `gradient` with a non-negative radius, or `None` if there is
+/// none. See <https://html.spec.whatwg.org/multipage/#dom-context-2d-createradialgradient>.
+fn radial_gradient_offset(gradient: &RadialGradientStyle, point: Point2D<f64>) -> Option<f64> {
+    let (cdx, cdy) = (gradient.x1 - gradient.x0, gradient.y1 - gradient.y0);
+    let (pdx, pdy) = (point.x - gradient.x0, point.y - gradient.y0);
+    let dr = gradient.r1 - gradient.r0;
+    // |point - center(t)| = radius(t), as a quadratic a·t² - 2b·t + c = 0.
+    let a = cdx * cdx + cdy * cdy - dr * dr;
+    let b = pdx * cdx + pdy * cdy + gradient.r0 * dr;
+    let c = pdx * pdx + pdy * pdy - gradient.r0 * gradient.r0;
+    let has_radius = |t: f64| gradient.r0 + t * dr >= 0.;
+
+    if a.abs() < f64::EPSILON {
+        let t = c / (2. * b);
+        return (t.is_finite() && has_radius(t)).then_some(t);
+    }
+    let discriminant = b * b - a * c;
+    if discriminant < 0. {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    let (first, second) = ((b + root) / a, (b - root) / a);
+    [first.max(second), first.min(second)]
+        .into_iter()
+        .find(|t| has_radius(*t))
+}
+
+/// Returns noise between -0.5 and 0.5 for the pixel at (`x`, `y`), which only depends on
+/// `seed` and the position of the pixel, so that dithered drawing is reproducible.
+fn dither_noise(seed: u64, x: usize, y: usize) -> f64 {
+    // SplitMix64, see <https://prng.di.unimi.it/splitmix64.c>.
+    let mut z = seed ^ (((x as u64) << 32) | (y as u64 & 0xffff_ffff));
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+}
+
+/// Rounds the premultiplied channel `value`, out of `alpha`, to one of the two integers
+/// nearest to it, picking the larger when `noise`, between -0.5 and 0.5, is high enough. Over
+/// uniform noise, the results average to `value` as it is encoded with the sRGB transfer
+/// function, or in linear light, according to `space`.
+fn dither_channel(value: f64, alpha: f64, noise: f64, space: DitherSpace) -> u8 {
+    let value = value.clamp(0., alpha);
+    let lower = value.floor();
+    let upper = (lower + 1.).min(alpha);
+    if upper <= lower {
+        return lower as u8;
+    }
+    let fraction = match space {
+        DitherSpace::Srgb => value - lower,
+        DitherSpace::Linear => {
+            let linear = |value: f64| f64::from(srgb_to_linear((value / alpha) as f32));
+            (linear(value) - linear(lower)) / (linear(upper) - linear(lower))
+        },
+    };
+    if noise + 0.5 >= 1. - fraction {
+        upper as u8
+    } else {
+        lower as u8
+    }
+}
+
+/// Replaces the ASCII whitespace in `text` with spaces, except for tabs if `keep_tabs`.
+fn replace_ascii_whitespace(text: String, keep_tabs: bool) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\t' if keep_tabs => c,
+            ' ' | '\t' | '\n' | '\r' | '\x0C' => '\x20',
+            _ => c,
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "raqote"))]
+pub(crate) mod test {
+    use std::cell::Cell;
+    use std::f64::consts::{FRAC_PI_2, PI};
+    use std::ops::RangeInclusive;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use app_units::Au;
+    use base::cross_process_instant::CrossProcessInstant;
+    use canvas_traits::canvas::{
+        BlendingSpace, CanvasCreationOptions, CanvasDrawStats, CanvasError, CanvasGradientStop,
+        CanvasHistogram, CanvasMemoryUsage, ColorEncoding, CompositionOptions,
+        CompositionOrBlending, CompositionStyle, CoordinateRounding, DitherSpace,
+        FillOrStrokeStyle, FillRule, FilterFunction, FlipAxis, FocusRingStyle, GamutMapping,
+        GlyphHinting, GradientInterpolation, ImageDataTile, ImageOrientation, LineCapStyle,
+        LineJoinStyle, LineOptions, LinearGradientStyle, Path, PredefinedColorSpace,
+        RadialGradientStyle, SavedRegionId, ShadowOptions, ShapingTags, StrokeAlignment,
+        SurfaceStyle, TabSize, TextAlign, TextBaseline, TextDecoration, TextDecorationStyle,
+        TextOptions,
+    };
+    use compositing_traits::{
+        CompositorMsg, CrossProcessCompositorApi, ImageUpdate, SerializableImageData,
+    };
+    use crossbeam_channel::Receiver;
+    use euclid::default::{Point2D, Rect, Size2D, Transform2D, Vector2D};
+    use fonts::{
+        ByteIndex, FontContext, FontMetrics, GlyphData, GlyphStore, SystemFontServiceProxy,
+        SystemFontServiceProxySender,
+    };
+    use ipc_channel::ipc;
+    use net_traits::ResourceThreads;
+    use pixels::{
+        EncodedImageType, Snapshot, SnapshotAlphaMode, SnapshotColorSpace, SnapshotPixelFormat,
+        rgba8_srgb_to_linear_inplace, srgb_to_linear,
+    };
+    use servo_arc::Arc as ServoArc;
+    use style::color::{AbsoluteColor, ColorSpace};
+    use style::properties::style_structs::Font as FontStyleStruct;
+    use style::values::computed::font::FontWeight;
+    use unicode_bidi::Level;
+    use webrender_api::{IdNamespace, ImageKey, ImageRendering};
+
+    use super::{
+        CanvasData, MeasureTextCache, TextMeasurements, bidi_runs, condensed_font_size,
+        correct_coverage_gamma, dash_gap_stroke, dither_channel, gradient_color_at, line_breaks,
+        missing_glyph_boxes, positioned_glyphs, split_glyph_range, tab_advance, tab_width,
+        text_decoration_lines,
+    };
+    use crate::raqote_backend::draw_glyphs_with_hinting;
+
+    /// Creates a [`CrossProcessCompositorApi`] backed by a thread that hands out image keys
+    /// and ignores every other message, so that canvases can be created and drawn to
+    /// without a running compositor.
+    pub(crate) fn mock_compositor_api() -> CrossProcessCompositorApi {
+        recording_compositor_api().0
+    }
+
+    /// A mock compositor that forwards the image updates it receives.
+    pub(crate) fn recording_compositor_api() -> (CrossProcessCompositorApi, Receiver<ImageUpdate>) {
+        let (sender, receiver) = ipc::channel().unwrap();
+        let (update_sender, update_receiver) = crossbeam_channel::unbounded();
+        thread::Builder::new()
+            .name("MockCompositor".to_owned())
+            .spawn(move || {
+                let mut next_key = 0;
+                while let Ok(message) = receiver.recv() {
+                    match message {
+                        CompositorMsg::GenerateImageKey(result_sender) => {
+                            next_key += 1;
+                            let _ = result_sender.send(ImageKey::new(IdNamespace(0), next_key));
+                        },
+                        CompositorMsg::UpdateImages(updates) => {
+                            for update in updates {
+                                let _ = update_sender.send(update);
+                            }
+                        },
+                        _ => {},
+                    }
+                }
+            })
+            .expect("Thread spawning failed");
+        (CrossProcessCompositorApi(sender), update_receiver)
+    }
+
+    fn new_canvas_data(size: Size2D<u64>) -> CanvasData<raqote::DrawTarget> {
+        new_canvas_data_with_options(size, CanvasCreationOptions::default(), mock_compositor_api())
+    }
+
+    /// Creates a canvas with its surface allocated, that sends its image updates to
+    /// `compositor_api`.
+    fn new_canvas_data_with_options(
+        size: Size2D<u64>,
+        options: CanvasCreationOptions,
+        compositor_api: CrossProcessCompositorApi,
+    ) -> CanvasData<raqote::DrawTarget> {
+        let (system_font_service, resource_threads) = disconnected_services();
+        let font_context = Arc::new(FontContext::new(
+            system_font_service,
+            compositor_api.clone(),
+            resource_threads,
+        ));
+        let mut canvas_data = CanvasData::new(
+            size,
+            options.scale_factor,
+            options.render_scale,
+            compositor_api,
+            font_context,
+            options.y_flipped,
+            options.blending_space,
+        )
+        .unwrap();
+        // The paint thread allocates the surface before the first message that uses it.
+        canvas_data.restore_reclaimed_surface();
+        canvas_data
+    }
+
+    /// Creates a system font service and resource threads whose receiving ends are gone,
+    /// for canvases that never load fonts or resources.
+    pub(crate) fn disconnected_services() -> (Arc<SystemFontServiceProxy>, ResourceThreads) {
+        let (system_font_service_sender, _) = ipc::channel().unwrap();
+        let (core_sender, _) = ipc::channel().unwrap();
+        let (storage_sender, _) = ipc::channel().unwrap();
+        let (indexeddb_sender, _) = ipc::channel().unwrap();
+        (
+            Arc::new(SystemFontServiceProxySender(system_font_service_sender).to_proxy()),
+            ResourceThreads::new(core_sender, storage_sender, indexeddb_sender),
+        )
+    }
+
+    pub(crate) fn red() -> FillOrStrokeStyle {
+        FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(255, 0, 0, 1.))
+    }
+
+    pub(crate) fn no_shadow() -> ShadowOptions {
+        ShadowOptions {
+            offset_x: 0.,
+            offset_y: 0.,
+            blur: 0.,
+            spread: 0.,
+            color: AbsoluteColor::TRANSPARENT_BLACK,
+        }
+    }
+
+    fn source_over() -> CompositionOptions {
+        CompositionOptions {
+            alpha: 1.,
+            composition_operation: CompositionOrBlending::default(),
+        }
+    }
+
+    fn gradient_stop(offset: f64, color: AbsoluteColor) -> CanvasGradientStop {
+        CanvasGradientStop { offset, color }
+    }
+
+    /// A gradient from gray 100 to gray 104 across `width` pixels, shallow enough to band
+    /// unless it is dithered.
+    pub(crate) fn shallow_gray_gradient(width: f64) -> LinearGradientStyle {
+        let gray = |value| AbsoluteColor::srgb_legacy(value, value, value, 1.);
+        LinearGradientStyle::new(
+            0.,
+            0.,
+            width,
+            0.,
+            vec![gradient_stop(0., gray(100)), gradient_stop(1., gray(104))],
+            false,
+        )
+    }
+
+    #[test]
+    fn test_gamut_mapping() {
+        let fill = |gamut_mapping, color| {
+            let mut canvas_data = new_canvas_data(Size2D::new(1, 1));
+            canvas_data.set_gamut_mapping(gamut_mapping);
+            canvas_data.set_fill_style(FillOrStrokeStyle::Color(color));
+            let style = canvas_data.fill_style();
+            canvas_data.fill_rect(
+                &Rect::from_size(Size2D::new(1., 1.)),
+                style,
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            canvas_data.get_pixel(0, 0)
+        };
+        let modes = [
+            GamutMapping::Clip,
+            GamutMapping::Scale,
+            GamutMapping::Perceptual,
+        ];
+
+        // The green of Display P3 lies outside of the sRGB gamut.
+        let p3_green = AbsoluteColor::new(ColorSpace::DisplayP3, 0., 1., 0., 1.);
+        let [clipped, scaled, mapped] = modes.map(|gamut_mapping| fill(gamut_mapping, p3_green));
+        assert_eq!(clipped, [0, 255, 0, 255]);
+        assert_ne!(scaled, clipped);
+        assert_ne!(mapped, clipped);
+        assert_ne!(mapped, scaled);
+        // Scaling desaturates the color rather than dropping its negative blue.
+        assert!(scaled[2] > 0, "{scaled:?}");
+
+        // Colors within the gamut are left alone.
+        let orange = AbsoluteColor::srgb_legacy(255, 128, 0, 1.);
+        for gamut_mapping in modes {
+            assert_eq!(fill(gamut_mapping, orange), [255, 128, 0, 255]);
+        }
+    }
+
+    #[test]
+    fn test_fill_rect_pixels() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(1., 1.), Size2D::new(2., 2.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+
+        let mut snapshot = canvas_data.read_pixels(None);
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            SnapshotPixelFormat::RGBA,
+        );
+        assert_eq!(snapshot.size(), Size2D::new(4, 4));
+        for (index, pixel) in snapshot.as_raw_bytes().chunks(4).enumerate() {
+            let (x, y) = (index % 4, index / 4);
+            let expected = if (1..3).contains(&x) && (1..3).contains(&y) {
+                [255, 0, 0, 255]
+            } else {
+                [0, 0, 0, 0]
+            };
+            assert_eq!(pixel, expected, "Unexpected pixel at ({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn test_viewports_stitch() {
+        // Shapes with anti-aliased edges on both sides of the border between the tiles.
+        let draw = |canvas_data: &mut CanvasData<raqote::DrawTarget>| {
+            let transform = canvas_data.default_transform();
+            canvas_data.fill_rect(
+                &Rect::new(Point2D::new(2.5, 0.5), Size2D::new(3., 2.25)),
+                red(),
+                no_shadow(),
+                source_over(),
+                transform,
+            );
+            let mut path = Path::new();
+            path.move_to(0.5, 4.);
+            path.line_to(7.5, 1.);
+            path.line_to(6., 4.);
+            path.close_path();
+            canvas_data.fill_path(
+                &path,
+                FillRule::Nonzero,
+                FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(0, 0, 255, 0.5)),
+                no_shadow(),
+                source_over(),
+                transform,
+            );
+        };
+        let pixels = |canvas_data: &mut CanvasData<raqote::DrawTarget>, width: i32| {
+            (0..4)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| canvas_data.get_pixel(x, y))
+                .collect::<Vec<_>>()
+        };
+
+        let mut whole = new_canvas_data(Size2D::new(8, 4));
+        draw(&mut whole);
+        let whole = pixels(&mut whole, 8);
+
+        let tile = |x| {
+            let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+            canvas_data.set_viewport(Some(Rect::new(Point2D::new(x, 0.), Size2D::new(4., 4.))));
+            draw(&mut canvas_data);
+            pixels(&mut canvas_data, 4)
+        };
+        let (left, right) = (tile(0.), tile(4.));
+        let stitched: Vec<_> = left
+            .chunks(4)
+            .zip(right.chunks(4))
+            .flat_map(|(left, right)| left.iter().chain(right))
+            .copied()
+            .collect();
+        assert_eq!(stitched, whole);
+
+        // Drawing is clipped to a viewport smaller than the canvas.
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        canvas_data.set_viewport(Some(Rect::new(Point2D::new(4., 0.), Size2D::new(2., 4.))));
+        let transform = canvas_data.default_transform();
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(4., 0.), Size2D::new(4., 4.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            transform,
+        );
+        assert_eq!(canvas_data.get_pixel(1, 1), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(2, 1), [0, 0, 0, 0]);
+        assert_eq!(
+            canvas_data.clip_bounds(),
+            Rect::from_size(Size2D::new(2., 4.))
+        );
+    }
+
+    #[test]
+    fn test_scaled_canvas() {
+        let mut canvas_data = new_canvas_data_with_options(
+            Size2D::new(100, 100),
+            CanvasCreationOptions {
+                scale_factor: 2,
+                ..Default::default()
+            },
+            mock_compositor_api(),
+        );
+        assert_eq!(canvas_data.read_pixels(None).size(), Size2D::new(200, 200));
+        assert_eq!(canvas_data.default_transform(), Transform2D::scale(2., 2.));
+    }
+
+    #[test]
+    fn test_image_rendering_hint() {
+        let (compositor_api, updates) = recording_compositor_api();
+        let mut canvas_data = new_canvas_data_with_options(
+            Size2D::new(4, 4),
+            CanvasCreationOptions::default(),
+            compositor_api,
+        );
+        let canvas_image_key = canvas_data.image_key();
+        let next_image_rendering = || loop {
+            if let ImageUpdate::SetImageRendering(image_key, image_rendering) =
+                updates.recv().unwrap()
+            {
+                assert_eq!(image_key, canvas_image_key);
+                return image_rendering;
+            }
+        };
+
+        canvas_data.update_image_rendering(false);
+        assert_eq!(next_image_rendering(), ImageRendering::Auto);
+
+        canvas_data.set_image_rendering(ImageRendering::Pixelated);
+        canvas_data.update_image_rendering(false);
+        assert_eq!(next_image_rendering(), ImageRendering::Pixelated);
+    }
+
+    #[test]
+    fn test_frame_time() {
+        let (compositor_api, updates) = recording_compositor_api();
+        let mut canvas_data = new_canvas_data_with_options(
+            Size2D::new(4, 4),
+            CanvasCreationOptions::default(),
+            compositor_api,
+        );
+        let canvas_image_key = canvas_data.image_key();
+        // The frame time follows the pixels of the update that it was attached to. Returns
+        // it with the number of updates up to and including that one.
+        let next_frame_time = || {
+            let mut update_count = 0;
+            loop {
+                match updates.recv().unwrap() {
+                    ImageUpdate::UpdateImagePartially(image_key, ..) => {
+                        assert_eq!(image_key, canvas_image_key);
+                        update_count += 1;
+                    },
+                    ImageUpdate::SetFrameTime(image_key, frame_time) => {
+                        assert_eq!(image_key, canvas_image_key);
+                        assert!(update_count > 0, "Frame time should come with an update");
+                        return (update_count, frame_time);
+                    },
+                    _ => {},
+                }
+            }
+        };
+
+        let start = Instant::now();
+        thread::sleep(Duration::from_millis(5));
+        canvas_data.add_frame_time(start);
+        canvas_data.update_image_rendering(false);
+        let (update_count, frame_time) = next_frame_time();
+        assert_eq!(update_count, 1);
+        assert!(frame_time >= Duration::from_millis(5));
+
+        // Time spent before the last update isn't reported again.
+        canvas_data.add_frame_time(start);
+        canvas_data.update_image_rendering(false);
+        let (update_count, frame_time) = next_frame_time();
+        assert_eq!(update_count, 1);
+        assert!(frame_time < Duration::from_millis(5));
+
+        // Without timed messages, the update has no frame time.
+        canvas_data.update_image_rendering(false);
+        canvas_data.add_frame_time(Instant::now());
+        canvas_data.update_image_rendering(false);
+        assert_eq!(next_frame_time().0, 2);
+    }
+
+    #[test]
+    fn test_premultiplied_upload() {
+        let (compositor_api, updates) = recording_compositor_api();
+        let mut canvas_data = new_canvas_data_with_options(
+            Size2D::new(2, 1),
+            CanvasCreationOptions::default(),
+            compositor_api,
+        );
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(0., 0.), Size2D::new(1., 1.)),
+            FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(255, 0, 0, 0.6)),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        let next_upload = |canvas_data: &mut CanvasData<raqote::DrawTarget>| {
+            canvas_data.update_image_rendering(false);
+            loop {
+                if let ImageUpdate::UpdateImagePartially(_, _, data, damage) =
+                    updates.recv().unwrap()
+                {
+                    let SerializableImageData::Raw(bytes) = data else {
+                        panic!("The image should be uploaded as raw pixels");
+                    };
+                    return (bytes.to_vec(), damage);
+                }
+            }
+        };
+
+        // The surface stores BGRA, so red is the third byte.
+        let (premultiplied, _) = next_upload(&mut canvas_data);
+        assert_eq!(&premultiplied[..4], &[0, 0, 153, 153]);
+
+        canvas_data.set_premultiplied_upload(false);
+        let (unmultiplied, damage) = next_upload(&mut canvas_data);
+        assert_eq!(&unmultiplied[..4], &[0, 0, 255, 153]);
+        assert_eq!(&unmultiplied[4..], &[0, 0, 0, 0]);
+        // WebRender keeps none of the pixels it had.
+        assert_eq!(
+            damage,
+            vec![Rect::new(Point2D::new(0, 0), Size2D::new(2, 1))]
+        );
+    }
+
+    #[test]
+    fn test_image_damage() {
+        let (compositor_api, updates) = recording_compositor_api();
+        let mut canvas_data = new_canvas_data_with_options(
+            Size2D::new(8, 8),
+            CanvasCreationOptions::default(),
+            compositor_api,
+        );
+        let next_damage = |canvas_data: &mut CanvasData<raqote::DrawTarget>| {
+            canvas_data.update_image_rendering(false);
+            loop {
+                if let ImageUpdate::UpdateImagePartially(_, _, _, damage) = updates.recv().unwrap()
+                {
+                    return damage;
+                }
+            }
+        };
+        let fill_rect = |canvas_data: &mut CanvasData<raqote::DrawTarget>,
+                         rect: Rect<f32>,
+                         composition_options| {
+            canvas_data.fill_rect(
+                &rect,
+                red(),
+                no_shadow(),
+                composition_options,
+                Transform2D::identity(),
+            );
+        };
+
+        // Allocating the surface damages all of it.
+        assert_eq!(
+            next_damage(&mut canvas_data),
+            vec![Rect::new(Point2D::new(0, 0), Size2D::new(8, 8))]
+        );
+
+        fill_rect(
+            &mut canvas_data,
+            Rect::new(Point2D::new(1., 1.), Size2D::new(2., 2.)),
+            source_over(),
+        );
+        fill_rect(
+            &mut canvas_data,
+            Rect::new(Point2D::new(5., 4.), Size2D::new(2., 3.)),
+            source_over(),
+        );
+        // Within the first rectangle, so it adds nothing.
+        fill_rect(
+            &mut canvas_data,
+            Rect::new(Point2D::new(1.5, 1.5), Size2D::new(1., 1.)),
+            source_over(),
+        );
+        assert_eq!(
+            next_damage(&mut canvas_data),
+            vec![
+                Rect::new(Point2D::new(1, 1), Size2D::new(2, 2)),
+                Rect::new(Point2D::new(5, 4), Size2D::new(2, 3)),
+            ]
+        );
+        // The damage is reset by each update.
+        assert_eq!(next_damage(&mut canvas_data), vec![]);
+
+        // A stroke damages as far as its joins can reach, past half the width of the line.
+        canvas_data.stroke_rect(
+            &Rect::new(Point2D::new(3., 3.), Size2D::new(2., 2.)),
+            red(),
+            LineOptions {
+                width: 2.,
+                miter_limit: 1.,
+                ..line_options(LineCapStyle::Butt, LineJoinStyle::Miter)
+            },
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        assert_eq!(
+            next_damage(&mut canvas_data),
+            vec![Rect::new(Point2D::new(1, 1), Size2D::new(6, 6))]
+        );
+
+        // `copy` clears everything outside of what it draws.
+        fill_rect(
+            &mut canvas_data,
+            Rect::new(Point2D::new(1., 1.), Size2D::new(2., 2.)),
+            CompositionOptions {
+                alpha: 1.,
+                composition_operation: CompositionOrBlending::Composition(CompositionStyle::Copy),
+            },
+        );
+        assert_eq!(
+            next_damage(&mut canvas_data),
+            vec![Rect::new(Point2D::new(0, 0), Size2D::new(8, 8))]
+        );
+    }
+
+    #[test]
+    fn test_reduced_render_scale() {
+        let mut canvas_data = new_canvas_data_with_options(
+            Size2D::new(100, 100),
+            CanvasCreationOptions {
+                render_scale: 0.5,
+                ..Default::default()
+            },
+            mock_compositor_api(),
+        );
+        assert_eq!(canvas_data.read_pixels(None).size(), Size2D::new(50, 50));
+
+        // The rectangle is given in CSS pixels, and covers the same part of the canvas as it
+        // would at full resolution.
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(0., 0.), Size2D::new(50., 50.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            canvas_data.default_transform(),
+        );
+        assert_eq!(canvas_data.get_pixel(24, 24), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(25, 0), [0, 0, 0, 0]);
+        assert_eq!(canvas_data.get_pixel(0, 25), [0, 0, 0, 0]);
+
+        // Odd sizes are rounded up so that every CSS pixel is covered.
+        let canvas_data = new_canvas_data_with_options(
+            Size2D::new(3, 5),
+            CanvasCreationOptions {
+                scale_factor: 2,
+                render_scale: 0.25,
+                ..Default::default()
+            },
+            mock_compositor_api(),
+        );
+        assert_eq!(canvas_data.drawtarget.get_size(), Size2D::new(2, 3));
+    }
+
+    #[test]
+    fn test_shadow_blur_is_gaussian() {
+        for blur in [2., 4., 8.] {
+            let mut canvas_data = new_canvas_data(Size2D::new(41, 1));
+            // A one pixel wide line, tall enough that the vertical blur leaves its shadow
+            // unattenuated, so each row of the shadow has the one-dimensional profile.
+            canvas_data.fill_rect(
+                &Rect::new(Point2D::new(20., -40.), Size2D::new(1., 81.)),
+                red(),
+                ShadowOptions {
+                    offset_x: 0.,
+                    offset_y: 0.,
+                    blur,
+                    spread: 0.,
+                    color: AbsoluteColor::BLACK,
+                },
+                source_over(),
+                Transform2D::identity(),
+            );
+
+            let mut snapshot = canvas_data.read_pixels(None);
+            snapshot.transform(
+                SnapshotAlphaMode::Transparent {
+                    premultiplied: false,
+                },
+                SnapshotPixelFormat::RGBA,
+            );
+            let alphas: Vec<_> = snapshot
+                .as_raw_bytes()
+                .chunks(4)
+                .map(|pixel| pixel[3])
+                .collect();
+            let std_deviation: f64 = blur / 2.;
+            for distance in 1..=(std_deviation * 3.) as usize {
+                let gaussian = (-(distance as f64).powi(2) / (2. * std_deviation.powi(2))).exp() /
+                    ((2. * std::f64::consts::PI).sqrt() * std_deviation);
+                let expected = gaussian * 255.;
+                for alpha in [alphas[20 - distance], alphas[20 + distance]] {
+                    assert!(
+                        (alpha as f64 - expected).abs() <= 2.,
+                        "Shadow alpha {alpha} at distance {distance} with blur {blur}, \
+                         expected {expected}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_shadow_blur() {
+        let draw_with_blur = |blur, max_shadow_blur| {
+            let mut canvas_data = new_canvas_data(Size2D::new(20, 20));
+            canvas_data.set_max_shadow_blur(max_shadow_blur);
+            canvas_data.fill_rect(
+                &Rect::new(Point2D::new(8., 8.), Size2D::new(4., 4.)),
+                red(),
+                ShadowOptions {
+                    offset_x: 0.,
+                    offset_y: 0.,
+                    blur,
+                    spread: 0.,
+                    color: AbsoluteColor::BLACK,
+                },
+                source_over(),
+                Transform2D::identity(),
+            );
+            canvas_data.read_pixels(None).as_raw_bytes().to_vec()
+        };
+
+        // Without the clamp, the shadow surface alone would be billions of pixels wide.
+        let start = Instant::now();
+        let clamped = draw_with_blur(1e9, 6.);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(clamped, draw_with_blur(6., 6.));
+        assert_ne!(clamped, draw_with_blur(2., 6.));
+    }
+
+    #[test]
+    fn test_shadow_spread() {
+        let shadow_alpha = |offset_x, spread, x| {
+            let mut canvas_data = new_canvas_data(Size2D::new(30, 30));
+            canvas_data.fill_rect(
+                &Rect::new(Point2D::new(10., 10.), Size2D::new(6., 6.)),
+                red(),
+                ShadowOptions {
+                    offset_x,
+                    offset_y: 0.,
+                    blur: 2.,
+                    spread,
+                    color: AbsoluteColor::BLACK,
+                },
+                source_over(),
+                Transform2D::identity(),
+            );
+            canvas_data.get_pixel(x, 13)[3]
+        };
+
+        // Four pixels from the shape, the blur alone doesn't reach, but it does once the
+        // shape has grown by three.
+        assert_eq!(shadow_alpha(0., 0., 6), 0);
+        assert_ne!(shadow_alpha(0., 3., 6), 0);
+        assert!(shadow_alpha(0., 3., 9) > shadow_alpha(0., 0., 9));
+
+        // A negative spread shrinks the shadow, here offset to the right of the shape.
+        assert!(shadow_alpha(10., -2., 21) < shadow_alpha(10., 0., 21));
+    }
+
+    #[test]
+    fn test_low_power_mode() {
+        let draw_shadow = |canvas_data: &mut CanvasData<raqote::DrawTarget>| {
+            canvas_data.fill_rect(
+                &Rect::new(Point2D::new(8., 8.), Size2D::new(4., 4.)),
+                red(),
+                ShadowOptions {
+                    offset_x: 0.,
+                    offset_y: 0.,
+                    blur: 4.,
+                    spread: 0.,
+                    color: AbsoluteColor::BLACK,
+                },
+                source_over(),
+                Transform2D::identity(),
+            );
+        };
+
+        let mut canvas_data = new_canvas_data(Size2D::new(20, 20));
+        draw_shadow(&mut canvas_data);
+        assert_eq!(canvas_data.draw_stats().approximated_shadows, 0);
+        let gaussian = canvas_data.read_pixels(None).as_raw_bytes().to_vec();
+
+        let mut canvas_data = new_canvas_data(Size2D::new(20, 20));
+        canvas_data.set_low_power_mode(true);
+        draw_shadow(&mut canvas_data);
+        assert_eq!(canvas_data.draw_stats().approximated_shadows, 1);
+        // The approximation still spreads the shadow past the shape.
+        assert_ne!(canvas_data.get_pixel(6, 10)[3], 0);
+        assert_ne!(canvas_data.read_pixels(None).as_raw_bytes(), &gaussian[..]);
+
+        canvas_data.set_low_power_mode(false);
+        draw_shadow(&mut canvas_data);
+        assert_eq!(canvas_data.draw_stats().approximated_shadows, 1);
+    }
+
+    #[test]
+    fn test_draw_stats() {
+        let mut canvas_data = new_canvas_data(Size2D::new(10, 10));
+        let identity = Transform2D::identity();
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(0., 0.), Size2D::new(2., 3.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            identity,
+        );
+        // Only the part of the rectangle that is on the canvas counts.
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(8., 8.), Size2D::new(5., 5.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            identity,
+        );
+        canvas_data.stroke_rect(
+            &Rect::new(Point2D::new(2., 2.), Size2D::new(2., 2.)),
+            red(),
+            LineOptions {
+                width: 2.,
+                cap_style: LineCapStyle::Butt,
+                join_style: LineJoinStyle::Miter,
+                miter_limit: 10.,
+                dash: Vec::new(),
+                dash_offset: 0.,
+                dash_gap_color: None,
+                alignment: StrokeAlignment::Center,
+            },
+            no_shadow(),
+            source_over(),
+            identity,
+        );
+        canvas_data.draw_image(
+            Snapshot::cleared(Size2D::new(2, 2)),
+            Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+            Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+            true,
+            no_shadow(),
+            source_over(),
+            identity.then_scale(2., 2.),
+        );
+
+        assert_eq!(
+            canvas_data.draw_stats(),
+            CanvasDrawStats {
+                fills: 2,
+                strokes: 1,
+                images: 1,
+                texts: 0,
+                clears: 0,
+                image_uploads: 0,
+                pixels_touched: 6 + 4 + 16 + 16,
+                approximated_shadows: 0,
+                submitted_operations: 0,
+                executed_operations: 0,
+                upload_bytes_saved: 0,
+                scratch_allocations: 0,
+            }
+        );
+
+        canvas_data.reset_draw_stats();
+        assert_eq!(canvas_data.draw_stats(), CanvasDrawStats::default());
+    }
+
+    #[test]
+    fn test_draw_overlapping_region_from_self() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 1));
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+        for (x, [red, green, blue]) in colors.into_iter().enumerate() {
+            canvas_data.fill_rect(
+                &Rect::new(Point2D::new(x as f32, 0.), Size2D::new(1., 1.)),
+                FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(red, green, blue, 1.)),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+        }
+
+        // Shift the first three pixels one to the right.
+        canvas_data.draw_image_from_self(
+            Rect::new(Point2D::new(1., 0.), Size2D::new(3., 1.)),
+            Rect::new(Point2D::new(0., 0.), Size2D::new(3., 1.)),
+            false,
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+
+        let mut snapshot = canvas_data.read_pixels(None);
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            SnapshotPixelFormat::RGBA,
+        );
+        let pixels: Vec<_> = snapshot
+            .as_raw_bytes()
+            .chunks(4)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect();
+        assert_eq!(pixels, [colors[0], colors[0], colors[1], colors[2]]);
+    }
+
+    #[test]
+    fn test_batched_opaque_fills_match_unbatched() {
+        let draw = |batch_opaque_fills| {
+            let mut canvas_data = new_canvas_data(Size2D::new(8, 8));
+            canvas_data.set_batch_opaque_fills(batch_opaque_fills);
+            let green = FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(0, 255, 0, 1.));
+            let translucent_blue =
+                FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(0, 0, 255, 0.5));
+            let rects = [
+                (0., 0., 4., 4., red()),
+                (2., 2., 4., 4., red()),
+                (1., 1., 4., 4., translucent_blue),
+                (3., 3., 4., 4., red()),
+                (4., 0., 4., 4., green.clone()),
+                (0., 4., 4., 4., green),
+            ];
+            for (x, y, width, height, style) in rects {
+                canvas_data.fill_rect(
+                    &Rect::new(Point2D::new(x, y), Size2D::new(width, height)),
+                    style,
+                    no_shadow(),
+                    source_over(),
+                    Transform2D::identity(),
+                );
+            }
+            canvas_data.read_pixels(None).as_raw_bytes().to_vec()
+        };
+
+        assert_eq!(draw(true), draw(false));
+    }
+
+    #[test]
+    fn test_coalescing_stats() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        canvas_data.set_batch_opaque_fills(true);
+        canvas_data.set_frame_pacing(true);
+        canvas_data.reset_draw_stats();
+
+        for x in 0..4 {
+            canvas_data.fill_rect(
+                &Rect::new(Point2D::new(x as f32, 0.), Size2D::new(1., 1.)),
+                red(),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            canvas_data.update_image_rendering(false);
+        }
+        canvas_data.vsync();
+
+        let stats = canvas_data.draw_stats();
+        assert_eq!(stats.submitted_operations, 4);
+        assert_eq!(stats.executed_operations, 1);
+        // The last three updates were sent with the first.
+        assert_eq!(stats.image_uploads, 1);
+        assert_eq!(stats.upload_bytes_saved, 3 * 4 * 4 * 4);
+    }
+
+    #[test]
+    fn test_set_global_alpha_keeps_composite_operation() {
+        let mut canvas_data = new_canvas_data(Size2D::new(1, 1));
+        let xor = CompositionOrBlending::Composition(CompositionStyle::Xor);
+        canvas_data.set_global_composite_operation(xor);
+        canvas_data.set_global_alpha(0.5);
+
+        let composition_options = canvas_data.composition_options();
+        assert_eq!(composition_options.alpha, 0.5);
+        assert_eq!(composition_options.composition_operation, xor);
+    }
+
+    #[test]
+    fn test_read_pixels_in_tiles() {
+        let mut canvas_data = new_canvas_data(Size2D::new(300, 200));
+        for index in 0..10 {
+            canvas_data.fill_rect(
+                &Rect::new(
+                    Point2D::new(index as f32 * 25., index as f32 * 17.),
+                    Size2D::new(40., 30.),
+                ),
+                FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(index * 25, 0, 255, 0.75)),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+        }
+
+        for read_rect in [
+            None,
+            Some(Rect::new(Point2D::new(10, 30), Size2D::new(250, 150))),
+        ] {
+            let (sender, receiver) = ipc::channel().unwrap();
+            canvas_data.read_pixels_in_tiles(read_rect, 64, &mut |image_size, y, snapshot| {
+                assert!(snapshot.size().height <= 64);
+                let tile = ImageDataTile {
+                    image_size,
+                    y,
+                    pixels: snapshot.as_ipc(),
+                };
+                sender.send(tile).unwrap();
+            });
+            let tiled = ImageDataTile::receive_image(&receiver).unwrap();
+            let direct = canvas_data.read_pixels(read_rect);
+            assert_eq!(tiled.size(), direct.size());
+            assert_eq!(tiled.as_raw_bytes(), direct.as_raw_bytes());
+        }
+    }
+
+    #[test]
+    fn test_read_pixels_in_color_spaces() {
+        let mut canvas_data = new_canvas_data(Size2D::new(1, 1));
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::zero(), Size2D::new(1., 1.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+
+        let snapshots = canvas_data.read_pixels_in_color_spaces(
+            None,
+            &[PredefinedColorSpace::Srgb, PredefinedColorSpace::DisplayP3],
+        );
+        let pixels: Vec<_> = snapshots
+            .into_iter()
+            .map(|mut snapshot| {
+                snapshot.transform(
+                    SnapshotAlphaMode::Transparent {
+                        premultiplied: false,
+                    },
+                    SnapshotPixelFormat::RGBA,
+                );
+                snapshot.as_raw_bytes().to_vec()
+            })
+            .collect();
+        assert_eq!(pixels[0], [255, 0, 0, 255]);
+        // sRGB red is color(display-p3 0.9175 0.2003 0.1386).
+        assert_eq!(pixels[1], [234, 51, 35, 255]);
+    }
+
+    #[test]
+    fn test_get_pixel() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(1., 1.), Size2D::new(2., 2.)),
+            FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(255, 0, 0, 0.5)),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+
+        assert_eq!(canvas_data.get_pixel(2, 1), [255, 0, 0, 128]);
+        assert_eq!(canvas_data.get_pixel(0, 0), [0, 0, 0, 0]);
+        assert_eq!(canvas_data.get_pixel(-1, 2), [0, 0, 0, 0]);
+        assert_eq!(canvas_data.get_pixel(2, 4), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_present_frame() {
+        let frame = |size: Size2D<u32>, color: [u8; 4]| {
+            Snapshot::from_vec(
+                size,
+                SnapshotPixelFormat::RGBA,
+                SnapshotAlphaMode::Transparent {
+                    premultiplied: false,
+                },
+                color.repeat(size.area() as usize),
+            )
+        };
+
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        canvas_data.present_frame(frame(Size2D::new(4, 4), [255, 0, 0, 255]));
+        assert_eq!(canvas_data.get_pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(3, 3), [255, 0, 0, 255]);
+
+        // The next frame replaces the previous one and is scaled to fill the canvas.
+        canvas_data.present_frame(frame(Size2D::new(2, 2), [0, 0, 255, 255]));
+        assert_eq!(canvas_data.get_pixel(0, 0), [0, 0, 255, 255]);
+        assert_eq!(canvas_data.get_pixel(3, 3), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_restore_after_recreate_with_saved_clip() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        let mut path = Path::new();
+        path.rect(0., 0., 2., 2.);
+        canvas_data.clip_path(&path, FillRule::Nonzero, Transform2D::identity());
+
+        // Resizing recreates the surface without the clip, and restoring the state saved
+        // before the clip then pops it again.
+        canvas_data.recreate(Some(Size2D::new(8, 8)));
+        canvas_data.pop_clips(1);
+
+        // A clip pushed on the new surface is still popped as usual.
+        path = Path::new();
+        path.rect(0., 0., 1., 1.);
+        canvas_data.clip_path(&path, FillRule::Nonzero, Transform2D::identity());
+        canvas_data.pop_clips(1);
+
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::zero(), Size2D::new(8., 8.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        assert_eq!(canvas_data.get_pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(7, 7), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let small = new_canvas_data(Size2D::new(10, 20));
+        let mut large = new_canvas_data(Size2D::new(300, 150));
+        assert_eq!(small.memory_usage().surface, 10 * 20 * 4);
+        assert_eq!(large.memory_usage().surface, 300 * 150 * 4);
+        assert_eq!(
+            new_canvas_data_with_options(
+                Size2D::new(10, 20),
+                CanvasCreationOptions {
+                    scale_factor: 2,
+                    ..Default::default()
+                },
+                mock_compositor_api(),
+            )
+            .memory_usage()
+            .surface,
+            20 * 40 * 4
+        );
+
+        let usage: CanvasMemoryUsage = [&small, &large]
+            .into_iter()
+            .map(|canvas_data| canvas_data.memory_usage())
+            .sum();
+        assert_eq!(usage.total(), (10 * 20 + 300 * 150) * 4);
+
+        // Drawing a shadow keeps a scratch surface around.
+        large.fill_rect(
+            &Rect::new(Point2D::new(10., 10.), Size2D::new(20., 20.)),
+            red(),
+            ShadowOptions {
+                offset_x: 5.,
+                offset_y: 5.,
+                blur: 0.,
+                spread: 0.,
+                color: AbsoluteColor::BLACK,
+            },
+            source_over(),
+            Transform2D::identity(),
+        );
+        assert!(large.memory_usage().auxiliary > 0);
+    }
+
+    #[test]
+    fn test_scratch_surface_reuse() {
+        let mut canvas_data = new_canvas_data(Size2D::new(100, 100));
+        let fill_with_shadow = |canvas_data: &mut CanvasData<_>, origin, size| {
+            canvas_data.fill_rect(
+                &Rect::new(Point2D::new(origin, origin), Size2D::new(size, size)),
+                red(),
+                ShadowOptions {
+                    offset_x: 5.,
+                    offset_y: 5.,
+                    blur: 0.,
+                    spread: 0.,
+                    color: AbsoluteColor::BLACK,
+                },
+                source_over(),
+                Transform2D::identity(),
+            );
+            canvas_data.draw_stats().scratch_allocations
+        };
+
+        for _ in 0..50 {
+            assert_eq!(fill_with_shadow(&mut canvas_data, 0., 20.), 1);
+        }
+        // A larger pass grows the surface, which smaller passes then reuse.
+        assert_eq!(fill_with_shadow(&mut canvas_data, 0., 40.), 2);
+        let grown = canvas_data.memory_usage().auxiliary;
+        assert_eq!(fill_with_shadow(&mut canvas_data, 50., 20.), 2);
+        assert_eq!(canvas_data.get_pixel(52, 52), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(72, 72), [0, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(77, 77), [0; 4]);
+
+        // One much smaller than the surface reallocates it at its own size.
+        assert_eq!(fill_with_shadow(&mut canvas_data, 0., 10.), 3);
+        assert_eq!(grown - canvas_data.memory_usage().auxiliary, (40 * 40 - 10 * 10) * 4);
+    }
+
+    #[test]
+    fn test_repeating_linear_gradient() {
+        let fill = |repeating| {
+            // Red over the first half of every 4 pixels and blue over the second.
+            let red = AbsoluteColor::srgb_legacy(255, 0, 0, 1.);
+            let blue = AbsoluteColor::srgb_legacy(0, 0, 255, 1.);
+            let gradient = LinearGradientStyle::new(
+                0.,
+                0.,
+                4.,
+                0.,
+                vec![
+                    gradient_stop(0., red),
+                    gradient_stop(0.5, red),
+                    gradient_stop(0.5, blue),
+                    gradient_stop(1., blue),
+                ],
+                repeating,
+            );
+            let mut canvas_data = new_canvas_data(Size2D::new(16, 1));
+            canvas_data.fill_rect(
+                &Rect::from_size(Size2D::new(16., 1.)),
+                FillOrStrokeStyle::LinearGradient(gradient),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            (0..16)
+                .map(|x| canvas_data.get_pixel(x, 0))
+                .collect::<Vec<_>>()
+        };
+
+        let (red, blue) = ([255, 0, 0, 255], [0, 0, 255, 255]);
+        let pixels = fill(true);
+        for (x, pixel) in pixels.iter().enumerate() {
+            let expected = if x % 4 < 2 { red } else { blue };
+            assert_eq!(*pixel, expected, "pixel {x}");
+        }
+
+        // Without repeating, the color at the end extends past it.
+        let pixels = fill(false);
+        assert_eq!(pixels[1], red);
+        assert!(pixels[4..].iter().all(|pixel| *pixel == blue));
+    }
+
+    #[test]
+    fn test_debug_dump() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 3));
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(1., 1.), Size2D::new(2., 1.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        let path = std::env::temp_dir().join(format!(
+            "servo-canvas-debug-dump-{}.png",
+            std::process::id()
+        ));
+        canvas_data.debug_dump(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut dump = Snapshot::decode(&bytes, &EncodedImageType::Png).unwrap();
+        assert_eq!(dump.size(), Size2D::new(4, 3));
+        dump.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            SnapshotPixelFormat::RGBA,
+        );
+        let pixel = |x: usize, y: usize| &dump.as_raw_bytes()[(y * 4 + x) * 4..][..4];
+        assert_eq!(pixel(1, 1), [255, 0, 0, 255]);
+        assert_eq!(pixel(2, 1), [255, 0, 0, 255]);
+        assert_eq!(pixel(0, 0), [0, 0, 0, 0]);
+
+        // A path that can't be written to is reported, not fatal.
+        let result =
+            canvas_data.debug_dump(&std::env::temp_dir().join("missing-directory/dump.png"));
+        assert!(matches!(result, Err(CanvasError::OperationFailed(_))));
+    }
+
+    #[test]
+    fn test_destination_over() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 2));
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        let blue = AbsoluteColor::srgb_legacy(0, 0, 255, 1.);
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(1., 0.), Size2D::new(3., 1.)),
+            FillOrStrokeStyle::Color(blue),
+            no_shadow(),
+            CompositionOptions {
+                alpha: 1.,
+                composition_operation: CompositionOrBlending::Composition(
+                    CompositionStyle::DestinationOver,
+                ),
+            },
+            Transform2D::identity(),
+        );
+
+        let (red, blue, transparent) = ([255, 0, 0, 255], [0, 0, 255, 255], [0, 0, 0, 0]);
+        // The red stays in front, and the blue shows where there was nothing.
+        assert_eq!(canvas_data.get_pixel(1, 0), red);
+        assert_eq!(canvas_data.get_pixel(2, 0), blue);
+        assert_eq!(canvas_data.get_pixel(3, 0), blue);
+        // Outside of the blue rectangle, nothing changed.
+        assert_eq!(canvas_data.get_pixel(0, 0), red);
+        assert_eq!(canvas_data.get_pixel(1, 1), red);
+        assert_eq!(canvas_data.get_pixel(2, 1), transparent);
+    }
+
+    #[test]
+    fn test_flip_surface() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 3));
+        // An L in the top left corner.
+        for rect in [
+            Rect::new(Point2D::new(0., 0.), Size2D::new(1., 2.)),
+            Rect::new(Point2D::new(1., 1.), Size2D::new(1., 1.)),
+        ] {
+            canvas_data.fill_rect(
+                &rect,
+                red(),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+        }
+        let red_pixels = |canvas_data: &mut CanvasData<raqote::DrawTarget>| {
+            let mut red_pixels = Vec::new();
+            for y in 0..3 {
+                for x in 0..4 {
+                    if canvas_data.get_pixel(x, y) == [255, 0, 0, 255] {
+                        red_pixels.push((x, y));
+                    }
+                }
+            }
+            red_pixels
+        };
+        assert_eq!(red_pixels(&mut canvas_data), vec![(0, 0), (0, 1), (1, 1)]);
+
+        canvas_data.flip_surface(FlipAxis::Horizontal);
+        assert_eq!(red_pixels(&mut canvas_data), vec![(3, 0), (2, 1), (3, 1)]);
+
+        canvas_data.flip_surface(FlipAxis::Vertical);
+        assert_eq!(red_pixels(&mut canvas_data), vec![(2, 1), (3, 1), (3, 2)]);
+    }
+
+    #[test]
+    fn test_focal_radial_gradient() {
+        // White at a focal point 4 pixels left of the center of the end circle, fading to
+        // black at its edge.
+        let gray = |value| AbsoluteColor::srgb_legacy(value, value, value, 1.);
+        let style = FillOrStrokeStyle::RadialGradient(RadialGradientStyle::new(
+            6.5,
+            10.5,
+            0.,
+            10.5,
+            10.5,
+            8.,
+            vec![gradient_stop(0., gray(255)), gradient_stop(1., gray(0))],
+        ));
+        let mut canvas_data = new_canvas_data(Size2D::new(20, 20));
+        canvas_data.fill_rect(
+            &Rect::from_size(Size2D::new(20., 20.)),
+            style.clone(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+
+        let pixels: Vec<_> = (0..20)
+            .flat_map(|y| (0..20).map(move |x| (x, y)))
+            .map(|(x, y)| ((x, y), canvas_data.get_pixel(x, y)[0]))
+            .collect();
+        let highlight = pixels.iter().max_by_key(|(_, red)| *red).unwrap();
+        assert_eq!(highlight.0, (6, 10));
+        // The gradient falls off faster towards the nearer edge of the end circle.
+        assert!(canvas_data.get_pixel(4, 10)[0] < canvas_data.get_pixel(8, 10)[0]);
+
+        for ((x, y), red) in pixels {
+            let point = Point2D::new(x as f64 + 0.5, y as f64 + 0.5);
+            let expected = gradient_color_at(&style, point)[0] * 255.;
+            assert!(
+                (red as f64 - expected).abs() <= 3.,
+                "pixel ({x}, {y}) is {red}, expected {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_gradient_interpolation() {
+        let fill = |interpolation, to| {
+            let mut canvas_data = new_canvas_data(Size2D::new(101, 1));
+            canvas_data.set_gradient_interpolation(interpolation);
+            canvas_data.set_fill_style(FillOrStrokeStyle::LinearGradient(
+                LinearGradientStyle::new(
+                    0.5,
+                    0.,
+                    100.5,
+                    0.,
+                    vec![
+                        gradient_stop(0., AbsoluteColor::srgb_legacy(255, 0, 0, 1.)),
+                        gradient_stop(1., to),
+                    ],
+                    false,
+                ),
+            ));
+            let style = canvas_data.fill_style();
+            canvas_data.fill_rect(
+                &Rect::from_size(Size2D::new(101., 1.)),
+                style,
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            canvas_data.get_pixel(50, 0)
+        };
+        let transparent_red = AbsoluteColor::srgb_legacy(255, 0, 0, 0.);
+        let transparent_black = AbsoluteColor::TRANSPARENT_BLACK;
+
+        // Fading red out stays red halfway through with premultiplied interpolation, the
+        // default, whatever the color of the transparent stop.
+        for to in [transparent_red, transparent_black] {
+            let [red, green, blue, alpha] = fill(GradientInterpolation::Premultiplied, to);
+            assert!(red >= 250, "{red}");
+            assert_eq!([green, blue], [0, 0]);
+            assert!(alpha.abs_diff(128) <= 2, "{alpha}");
+        }
+
+        // Without premultiplying, the black of a transparent black stop shows halfway.
+        let [red, _, _, alpha] = fill(GradientInterpolation::Unpremultiplied, transparent_black);
+        assert!(red.abs_diff(128) <= 4, "{red}");
+        assert!(alpha.abs_diff(128) <= 2, "{alpha}");
+        let [red, _, _, _] = fill(GradientInterpolation::Unpremultiplied, transparent_red);
+        assert!(red >= 250, "{red}");
+    }
+
+    #[test]
+    fn test_set_gradient_stop_colors() {
+        let gradient = |from, to| {
+            FillOrStrokeStyle::RadialGradient(RadialGradientStyle::new(
+                6.5,
+                10.5,
+                0.,
+                10.5,
+                10.5,
+                8.,
+                vec![
+                    gradient_stop(0., from),
+                    gradient_stop(0.4, from),
+                    gradient_stop(1., to),
+                ],
+            ))
+        };
+        let (white, black) = (
+            AbsoluteColor::srgb_legacy(255, 255, 255, 1.),
+            AbsoluteColor::BLACK,
+        );
+        let (red, blue) = (
+            AbsoluteColor::srgb_legacy(255, 0, 0, 1.),
+            AbsoluteColor::srgb_legacy(0, 0, 255, 0.5),
+        );
+        let fill = |canvas_data: &mut CanvasData<raqote::DrawTarget>| {
+            let style = canvas_data.fill_style();
+            canvas_data.fill_rect(
+                &Rect::from_size(Size2D::new(20., 20.)),
+                style,
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            canvas_data.read_pixels(None).as_raw_bytes().to_vec()
+        };
+
+        let mut rebuilt = new_canvas_data(Size2D::new(20, 20));
+        rebuilt.set_fill_style(gradient(red, blue));
+        let expected = fill(&mut rebuilt);
+
+        let mut recolored = new_canvas_data(Size2D::new(20, 20));
+        recolored.set_fill_style(gradient(white, black));
+        recolored.set_fill_gradient_stop_colors(vec![red, red, blue]);
+        assert_eq!(fill(&mut recolored), expected);
+
+        // Colors that don't match the stops leave the gradient as it is.
+        recolored.set_fill_gradient_stop_colors(vec![white, black]);
+        recolored.clear_rect(
+            &Rect::from_size(Size2D::new(20., 20.)),
+            Transform2D::identity(),
+        );
+        assert_eq!(fill(&mut recolored), expected);
+    }
+
+    /// The size of the canvas that [`dithered_gradient_pixels`] draws to.
+    const DITHERED_GRADIENT_SIZE: Size2D<u64> = Size2D::new(256, 16);
+
+    /// Fills a canvas with [`shallow_gray_gradient`], dithered with `seed`, and returns its
+    /// non-premultiplied RGBA pixels.
+    fn dithered_gradient_pixels(seed: Option<u64>, space: DitherSpace) -> Vec<u8> {
+        let size = DITHERED_GRADIENT_SIZE;
+        let mut canvas_data = new_canvas_data(size);
+        canvas_data.set_gradient_dither(seed, space);
+        canvas_data.fill_rect(
+            &Rect::from_size(size.to_f32()),
+            FillOrStrokeStyle::LinearGradient(shallow_gray_gradient(size.width as f64)),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        let mut snapshot = canvas_data.snapshot(None);
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            SnapshotPixelFormat::RGBA,
+        );
+        snapshot.as_raw_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_dithered_gradient_fill() {
+        let (width, height) = (
+            DITHERED_GRADIENT_SIZE.width as usize,
+            DITHERED_GRADIENT_SIZE.height as usize,
+        );
+        // The mean distance between the average of each column and the exact gradient, which
+        // dithering should bring well below what rounding every pixel alone achieves.
+        let banding = |pixels: &[u8]| {
+            let column_error = |x: usize| {
+                let sum: f64 = (0..height)
+                    .map(|y| pixels[(y * width + x) * 4] as f64)
+                    .sum();
+                let exact = 100. + 4. * (x as f64 + 0.5) / width as f64;
+                (sum / height as f64 - exact).abs()
+            };
+            (0..width).map(column_error).sum::<f64>() / width as f64
+        };
+
+        let undithered = dithered_gradient_pixels(None, DitherSpace::Srgb);
+        for space in [DitherSpace::Srgb, DitherSpace::Linear] {
+            let dithered = dithered_gradient_pixels(Some(7), space);
+            assert_eq!(
+                dithered,
+                dithered_gradient_pixels(Some(7), space),
+                "Dithering should be reproducible"
+            );
+            assert_ne!(dithered, dithered_gradient_pixels(Some(8), space));
+            assert!(banding(&dithered) < banding(&undithered) / 2.);
+        }
+    }
+
+    #[test]
+    fn test_dithered_gradient_mean() {
+        let width = DITHERED_GRADIENT_SIZE.width;
+        // The mean of the red channel of all pixels, as stored and in linear light.
+        let mean = |pixels: &[u8]| {
+            let count = DITHERED_GRADIENT_SIZE.area() as f64;
+            let red = || pixels.iter().step_by(4).map(|red| *red as f32 / 255.);
+            (
+                red().map(f64::from).sum::<f64>() / count,
+                red().map(|red| f64::from(srgb_to_linear(red))).sum::<f64>() / count,
+            )
+        };
+        let exact = |x: u64| (100. + 4. * (x as f32 + 0.5) / width as f32) / 255.;
+        let exact_stored = (0..width).map(|x| f64::from(exact(x))).sum::<f64>() / width as f64;
+        let exact_linear = (0..width)
+            .map(|x| f64::from(srgb_to_linear(exact(x))))
+            .sum::<f64>() /
+            width as f64;
+
+        // Each space keeps the mean color of the gradient in its own terms.
+        let (stored, _) = mean(&dithered_gradient_pixels(Some(7), DitherSpace::Srgb));
+        let error = (stored - exact_stored).abs();
+        assert!(error < 0.1 / 255., "the stored mean color is off by {error}");
+        let (_, linear) = mean(&dithered_gradient_pixels(Some(7), DitherSpace::Linear));
+        let error = (linear - exact_linear).abs() / exact_linear;
+        assert!(error < 0.002, "the linear mean color is off by {error}");
+    }
+
+    #[test]
+    fn test_dither_channel_mean() {
+        // Averaged over uniform noise, each space keeps its own mean of a value halfway
+        // between two levels, and the linear one picks the upper level less often.
+        let mean = |space| {
+            let noise = (0..1000).map(|index| (index as f64 + 0.5) / 1000. - 0.5);
+            let levels: Vec<_> = noise
+                .map(|noise| dither_channel(20.5, 255., noise, space))
+                .collect();
+            let stored = levels.iter().map(|level| *level as f64).sum::<f64>() / 1000.;
+            let linear = levels
+                .iter()
+                .map(|level| f64::from(srgb_to_linear(*level as f32 / 255.)))
+                .sum::<f64>() /
+                1000.;
+            (stored, linear)
+        };
+        let exact_linear = f64::from(srgb_to_linear(20.5 / 255.));
+
+        let (srgb_stored, _) = mean(DitherSpace::Srgb);
+        assert!((srgb_stored - 20.5).abs() < 0.002, "{srgb_stored}");
+        let (linear_stored, linear) = mean(DitherSpace::Linear);
+        assert!((linear - exact_linear).abs() / exact_linear < 0.001, "{linear}");
+        assert!(linear_stored < srgb_stored);
+    }
+
+    #[test]
+    fn test_ellipse_counterclockwise() {
+        let end_point = |path: &Path| path.0.elements().last().unwrap().end_point().unwrap();
+
+        // Three quarters of a turn counterclockwise pass through the left of the ellipse.
+        let mut path = Path::new();
+        path.ellipse(0., 0., 10., 5., 0., 0., -3. * FRAC_PI_2, true)
+            .unwrap();
+        let end = end_point(&path);
+        assert!(end.x.abs() < 1e-6 && (end.y - 5.).abs() < 1e-6);
+        let bounds = path.bounding_box();
+        assert!(bounds.min_x() < -9. && bounds.min_y() < -4.);
+
+        // The same angles clockwise only sweep a quarter of a turn.
+        let mut path = Path::new();
+        path.ellipse(0., 0., 10., 5., 0., 0., -3. * FRAC_PI_2, false)
+            .unwrap();
+        let bounds = path.bounding_box();
+        assert!(bounds.min_x() > -1e-2 && bounds.min_y() > -1e-2);
+
+        // Sweeping more than a full turn draws the whole ellipse, ending where it started.
+        for (end_angle, counterclockwise) in [(3. * PI, false), (-3. * PI, true)] {
+            let mut path = Path::new();
+            path.ellipse(0., 0., 10., 5., 0., 0., end_angle, counterclockwise)
+                .unwrap();
+            let end = end_point(&path);
+            assert!((end.x - 10.).abs() < 1e-6 && end.y.abs() < 1e-6);
+            assert!(path.bounding_box().min_x() < -9.);
+        }
+    }
+
+    pub(crate) fn line_options(cap_style: LineCapStyle, join_style: LineJoinStyle) -> LineOptions {
+        LineOptions {
+            width: 4.,
+            cap_style,
+            join_style,
+            miter_limit: 10.,
+            dash: Vec::new(),
+            dash_offset: 0.,
+            dash_gap_color: None,
+            alignment: StrokeAlignment::Center,
+        }
+    }
+
+    #[test]
+    fn test_closed_path_join() {
+        let stroke_triangle = |join_style| {
+            let mut canvas_data = new_canvas_data(Size2D::new(40, 40));
+            let mut path = Path::new();
+            path.move_to(10., 10.);
+            path.line_to(30., 10.);
+            path.line_to(10., 30.);
+            path.line_to(10., 10.);
+            path.close_path();
+            canvas_data.stroke_path(
+                &path,
+                red(),
+                LineOptions {
+                    width: 8.,
+                    ..line_options(LineCapStyle::Square, join_style)
+                },
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            canvas_data
+        };
+
+        // The corner where the triangle closes is rounded, not squared off by caps.
+        let mut canvas_data = stroke_triangle(LineJoinStyle::Round);
+        assert_eq!(canvas_data.get_pixel(6, 6)[3], 0);
+        assert_eq!(canvas_data.get_pixel(8, 8), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(6, 12), [255, 0, 0, 255]);
+
+        // Whereas a miter join fills it.
+        let mut canvas_data = stroke_triangle(LineJoinStyle::Miter);
+        assert_eq!(canvas_data.get_pixel(6, 6), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_round_dotted_stroke() {
+        let mut canvas_data = new_canvas_data(Size2D::new(45, 10));
+        let mut path = Path::new();
+        path.move_to(2., 5.);
+        path.line_to(42., 5.);
+        canvas_data.stroke_path(
+            &path,
+            red(),
+            LineOptions {
+                dash: vec![0., 10.],
+                ..line_options(LineCapStyle::Round, LineJoinStyle::default())
+            },
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        // A dot of the width of the line every 10 pixels, the last at the end of the line.
+        for x in [2, 12, 22, 32, 42] {
+            assert_eq!(canvas_data.get_pixel(x, 5), [255, 0, 0, 255], "dot at {x}");
+            assert_eq!(canvas_data.get_pixel(x, 4)[3], 255, "dot at {x}");
+            assert_eq!(canvas_data.get_pixel(x + 5, 5)[3], 0, "gap at {}", x + 5);
+        }
+        assert_eq!(canvas_data.get_pixel(2, 1)[3], 0);
+    }
+
+    #[test]
+    fn test_dash_gap_color() {
+        let blue = AbsoluteColor::srgb_legacy(0, 0, 255, 1.);
+        let mut canvas_data = new_canvas_data(Size2D::new(20, 4));
+        let mut path = Path::new();
+        path.move_to(0., 2.);
+        path.line_to(20., 2.);
+        canvas_data.stroke_path(
+            &path,
+            red(),
+            LineOptions {
+                width: 2.,
+                dash: vec![4., 4.],
+                dash_gap_color: Some(blue),
+                ..line_options(LineCapStyle::Butt, LineJoinStyle::default())
+            },
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        assert_eq!(canvas_data.get_pixel(1, 2), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(5, 2), [0, 0, 255, 255]);
+        assert_eq!(canvas_data.get_pixel(9, 2), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(13, 2), [0, 0, 255, 255]);
+
+        // The gaps of an uneven, offset pattern start where its dashes end.
+        let (_, gap_line_options) = dash_gap_stroke(&LineOptions {
+            dash: vec![3., 1.],
+            dash_offset: 1.,
+            dash_gap_color: Some(blue),
+            ..line_options(LineCapStyle::Round, LineJoinStyle::default())
+        })
+        .unwrap();
+        assert_eq!(gap_line_options.dash, vec![1., 3.]);
+        assert_eq!(gap_line_options.dash_offset, 2.);
+        assert_eq!(gap_line_options.cap_style, LineCapStyle::Butt);
+
+        // Without dashes, there are no gaps to stroke.
+        assert!(
+            dash_gap_stroke(&LineOptions {
+                dash_gap_color: Some(blue),
+                ..line_options(LineCapStyle::Butt, LineJoinStyle::default())
+            })
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_outside_stroke_alignment() {
+        let mut canvas_data = new_canvas_data(Size2D::new(16, 16));
+        canvas_data.stroke_rect(
+            &Rect::new(Point2D::new(4., 4.), Size2D::new(8., 8.)),
+            red(),
+            LineOptions {
+                width: 2.,
+                alignment: StrokeAlignment::Outside,
+                ..line_options(LineCapStyle::Butt, LineJoinStyle::default())
+            },
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        for y in 4..12 {
+            for x in 4..12 {
+                assert_eq!(canvas_data.get_pixel(x, y)[3], 0, "inside at {x}, {y}");
+            }
+        }
+        assert_eq!(canvas_data.get_pixel(3, 8), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(2, 8), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(1, 8)[3], 0);
+        assert_eq!(canvas_data.get_pixel(12, 12), [255, 0, 0, 255]);
+        assert_eq!(StrokeAlignment::default(), StrokeAlignment::Center);
+    }
+
+    #[test]
+    fn test_device_pixel_line_widths() {
+        let stroke = |device_pixel_line_widths| {
+            let mut canvas_data = new_canvas_data(Size2D::new(20, 20));
+            canvas_data.set_device_pixel_line_widths(device_pixel_line_widths);
+            let transform = Transform2D::scale(2., 2.);
+            let line_options = canvas_data.user_space_line_options(
+                LineOptions {
+                    width: 2.,
+                    ..line_options(LineCapStyle::Butt, LineJoinStyle::default())
+                },
+                &transform,
+            );
+            let mut path = Path::new();
+            path.move_to(0., 5.);
+            path.line_to(10., 5.);
+            canvas_data.stroke_path(
+                &path,
+                red(),
+                line_options,
+                no_shadow(),
+                source_over(),
+                transform,
+            );
+            (0..20)
+                .filter(|y| canvas_data.get_pixel(10, *y)[3] != 0)
+                .collect::<Vec<_>>()
+        };
+        // Scaled by the transform, the line is 4 device pixels thick.
+        assert_eq!(stroke(false), vec![8, 9, 10, 11]);
+        // In device pixels, it keeps its width of 2.
+        assert_eq!(stroke(true), vec![9, 10]);
+    }
+
+    #[test]
+    fn test_sub_pixel_line_widths() {
+        let stroke = |width| {
+            let mut canvas_data = new_canvas_data(Size2D::new(20, 20));
+            let mut path = Path::new();
+            path.move_to(0., 10.5);
+            path.line_to(20., 10.5);
+            canvas_data.stroke_path(
+                &path,
+                red(),
+                LineOptions {
+                    width,
+                    ..line_options(LineCapStyle::Butt, LineJoinStyle::default())
+                },
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            canvas_data.stroke_rect(
+                &Rect::new(Point2D::new(2.5, 2.5), Size2D::new(4., 4.)),
+                red(),
+                LineOptions {
+                    width,
+                    ..line_options(LineCapStyle::Butt, LineJoinStyle::Miter)
+                },
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            (
+                (9..12)
+                    .map(|y| canvas_data.get_pixel(10, y)[3])
+                    .collect::<Vec<_>>(),
+                canvas_data.get_pixel(2, 4)[3],
+            )
+        };
+        for width in [0.25, 0.5] {
+            let expected = width * 255.;
+            let (line, rect_edge) = stroke(width);
+            assert_eq!((line[0], line[2]), (0, 0), "width {width}");
+            assert!((line[1] as f64 - expected).abs() <= 2., "width {width}: {line:?}");
+            assert!((rect_edge as f64 - expected).abs() <= 2., "width {width}: {rect_edge}");
+        }
+
+        // Scaled by a quarter across and four times along, a vertical line 1 wide is a
+        // quarter of a device pixel wide, although the transform keeps its area.
+        let mut canvas_data = new_canvas_data(Size2D::new(20, 20));
+        let mut path = Path::new();
+        path.move_to(42., 0.);
+        path.line_to(42., 5.);
+        canvas_data.stroke_path(
+            &path,
+            red(),
+            LineOptions {
+                width: 1.,
+                ..line_options(LineCapStyle::Butt, LineJoinStyle::default())
+            },
+            no_shadow(),
+            source_over(),
+            Transform2D::scale(0.25, 4.),
+        );
+        let line: Vec<_> = (9..12).map(|x| canvas_data.get_pixel(x, 10)[3]).collect();
+        assert_eq!((line[0], line[2]), (0, 0), "{line:?}");
+        assert!((line[1] as f64 - 0.25 * 255.).abs() <= 2., "{line:?}");
+    }
+
+    #[test]
+    fn test_line_style_defaults() {
+        assert_eq!(LineCapStyle::default(), LineCapStyle::Butt);
+        assert_eq!(LineJoinStyle::default(), LineJoinStyle::Miter);
+        assert!("Butt".parse::<LineCapStyle>().is_ok());
+        assert!("Inward".parse::<LineCapStyle>().is_err());
+    }
+
+    #[test]
+    fn test_line_caps() {
+        // The alpha of three pixels beyond the start of a 4px wide line starting at (5, 10):
+        // one on its axis within half the width of the end, one at the corner of the square
+        // that a square cap adds, and one more than half the width away.
+        let cap_coverage = |cap_style| {
+            let mut canvas_data = new_canvas_data(Size2D::new(20, 20));
+            let mut path = Path::new();
+            path.move_to(5., 10.);
+            path.line_to(15., 10.);
+            canvas_data.stroke_path(
+                &path,
+                red(),
+                line_options(cap_style, LineJoinStyle::default()),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            [(3, 10), (3, 8), (2, 10)].map(|(x, y)| canvas_data.get_pixel(x, y)[3])
+        };
+
+        assert_eq!(cap_coverage(LineCapStyle::Butt), [0, 0, 0]);
+        assert_eq!(cap_coverage(LineCapStyle::Square), [255, 255, 0]);
+        let [axis, corner, beyond] = cap_coverage(LineCapStyle::Round);
+        assert!(axis > 128);
+        assert!(corner > 0 && corner < 128);
+        assert_eq!(beyond, 0);
+    }
+
+    #[test]
+    fn test_line_joins() {
+        // The alpha of the pixel just inside the outer corner of a right angle turning at
+        // (14, 4) in a 4px wide line, where a miter join ends in a point at (16, 2).
+        let join_coverage = |join_style| {
+            let mut canvas_data = new_canvas_data(Size2D::new(20, 20));
+            let mut path = Path::new();
+            path.move_to(4., 4.);
+            path.line_to(14., 4.);
+            path.line_to(14., 14.);
+            canvas_data.stroke_path(
+                &path,
+                red(),
+                line_options(LineCapStyle::default(), join_style),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            [(15, 2), (16, 2)].map(|(x, y)| canvas_data.get_pixel(x, y)[3])
+        };
+
+        assert_eq!(join_coverage(LineJoinStyle::Miter), [255, 0]);
+        // A bevel cuts the corner off along the line from (14, 2) to (16, 4).
+        let [inside, beyond] = join_coverage(LineJoinStyle::Bevel);
+        assert!(inside < 16);
+        assert_eq!(beyond, 0);
+        let [inside, beyond] = join_coverage(LineJoinStyle::Round);
+        assert!(inside > 16 && inside < 255);
+        assert_eq!(beyond, 0);
+    }
+
+    #[test]
+    fn test_draw_focus_ring() {
+        let mut canvas_data = new_canvas_data(Size2D::new(20, 20));
+        canvas_data.set_global_alpha(0.5);
+        let mut path = Path::new();
+        path.rect(5., 5., 10., 10.);
+
+        canvas_data.draw_focus_ring(&path, Transform2D::identity());
+        // The default ring is 2px wide, opaque despite the global alpha, and hollow.
+        assert_eq!(canvas_data.get_pixel(4, 10), [0, 95, 204, 255]);
+        assert_eq!(canvas_data.get_pixel(10, 5), [0, 95, 204, 255]);
+        assert_eq!(canvas_data.get_pixel(3, 10), [0, 0, 0, 0]);
+        assert_eq!(canvas_data.get_pixel(10, 10), [0, 0, 0, 0]);
+
+        canvas_data.set_focus_ring_style(FocusRingStyle {
+            color: AbsoluteColor::srgb_legacy(0, 255, 0, 1.),
+            width: 4.,
+            dash: Vec::new(),
+        });
+        canvas_data.draw_focus_ring(&path, Transform2D::identity());
+        assert_eq!(canvas_data.get_pixel(3, 10), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn test_layers_composite_as_a_group() {
+        let fill = |canvas_data: &mut CanvasData<raqote::DrawTarget>, x, color| {
+            canvas_data.fill_rect(
+                &Rect::new(Point2D::new(x, 0.), Size2D::new(4., 4.)),
+                FillOrStrokeStyle::Color(color),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+        };
+        let half_opaque = CompositionOptions {
+            alpha: 0.5,
+            composition_operation: CompositionOrBlending::default(),
+        };
+        let blue = AbsoluteColor::srgb_legacy(0, 0, 255, 1.);
+        let assert_color = |pixel: [u8; 4], color: [u8; 3], alpha: RangeInclusive<u8>| {
+            assert_eq!(pixel[..3], color, "{pixel:?}");
+            assert!(alpha.contains(&pixel[3]), "{pixel:?}");
+        };
+
+        let mut canvas_data = new_canvas_data(Size2D::new(8, 4));
+        canvas_data.begin_layer(half_opaque);
+        fill(
+            &mut canvas_data,
+            0.,
+            AbsoluteColor::srgb_legacy(255, 0, 0, 1.),
+        );
+        fill(&mut canvas_data, 2., blue);
+        canvas_data.end_layer();
+        // Where the shapes overlap, only the blue one shows, at half opacity.
+        assert_color(canvas_data.get_pixel(1, 1), [255, 0, 0], 127..=128);
+        assert_color(canvas_data.get_pixel(3, 1), [0, 0, 255], 127..=128);
+        assert_color(canvas_data.get_pixel(5, 1), [0, 0, 255], 127..=128);
+        assert_eq!(canvas_data.get_pixel(7, 1), [0, 0, 0, 0]);
+
+        // The opacities of nested layers multiply.
+        let mut canvas_data = new_canvas_data(Size2D::new(8, 4));
+        canvas_data.begin_layer(half_opaque);
+        canvas_data.begin_layer(half_opaque);
+        fill(&mut canvas_data, 0., blue);
+        canvas_data.end_layer();
+        fill(&mut canvas_data, 4., blue);
+        canvas_data.end_layer();
+        assert_color(canvas_data.get_pixel(1, 1), [0, 0, 255], 62..=65);
+        assert_color(canvas_data.get_pixel(5, 1), [0, 0, 255], 127..=128);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(0., 0.), Size2D::new(2., 4.)),
+            FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(255, 64, 0, 1.)),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+
+        // A third of the 9 pixels of the rectangle that are on the canvas are filled.
+        let histogram = canvas_data.histogram(Rect::new(Point2D::new(1, 1), Size2D::new(8, 3)));
+        let bins = |channel: &[u64]| {
+            channel
+                .iter()
+                .enumerate()
+                .filter(|(_, count)| **count > 0)
+                .map(|(value, count)| (value, *count))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(bins(&histogram.red), [(0, 6), (255, 3)]);
+        assert_eq!(bins(&histogram.green), [(0, 6), (64, 3)]);
+        assert_eq!(bins(&histogram.blue), [(0, 9)]);
+        assert_eq!(bins(&histogram.alpha), [(0, 6), (255, 3)]);
+
+        let outside = canvas_data.histogram(Rect::new(Point2D::new(4, 4), Size2D::new(2, 2)));
+        assert_eq!(outside, CanvasHistogram::default());
+    }
+
+    #[test]
+    fn test_read_pixels_as_float() {
+        let mut canvas_data = new_canvas_data(Size2D::new(3, 1));
+        let fill_pixel = |canvas_data: &mut CanvasData<raqote::DrawTarget>, x, color| {
+            canvas_data.fill_rect(
+                &Rect::new(Point2D::new(x, 0.), Size2D::new(1., 1.)),
+                FillOrStrokeStyle::Color(color),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+        };
+        fill_pixel(
+            &mut canvas_data,
+            0.,
+            AbsoluteColor::srgb_legacy(128, 128, 128, 1.),
+        );
+        fill_pixel(
+            &mut canvas_data,
+            1.,
+            AbsoluteColor::srgb_legacy(255, 0, 0, 0.5),
+        );
+
+        let image_data = canvas_data.read_pixels_as_float(None, ColorEncoding::Srgb);
+        assert_eq!(image_data.size, Size2D::new(3, 1));
+        let gray = 128. / 255.;
+        assert_eq!(&image_data.pixels[..4], &[gray, gray, gray, 1.]);
+        // The color of the translucent pixel is unpremultiplied without rounding.
+        assert_eq!(&image_data.pixels[4..7], &[1., 0., 0.]);
+        assert!((image_data.pixels[7] - 0.5).abs() <= 1. / 255.);
+        assert_eq!(&image_data.pixels[8..], &[0.; 4]);
+
+        // Decoding to linear light in floating point keeps the precision that decoding to
+        // 8 bits loses.
+        let image_data = canvas_data.read_pixels_as_float(None, ColorEncoding::Linear);
+        let linear_gray = srgb_to_linear(gray);
+        assert_eq!(
+            &image_data.pixels[..4],
+            &[linear_gray, linear_gray, linear_gray, 1.]
+        );
+        let mut eight_bit_gray = [128, 128, 128, 255];
+        rgba8_srgb_to_linear_inplace(&mut eight_bit_gray);
+        assert_ne!(eight_bit_gray[0] as f32 / 255., linear_gray);
+    }
+
+    #[test]
+    fn test_read_alpha_mask() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        let mut path = Path::new();
+        path.move_to(0., 0.);
+        path.line_to(4., 0.);
+        path.line_to(0., 4.);
+        path.close_path();
+        canvas_data.fill_path(
+            &path,
+            FillRule::Nonzero,
+            FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(0, 0, 255, 0.6)),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+
+        let rect = Rect::new(Point2D::new(0, 0), Size2D::new(4, 3));
+        let alpha_mask = canvas_data.read_alpha_mask(rect);
+        assert_eq!(alpha_mask.size, rect.size);
+        let expected: Vec<_> = (0..3)
+            .flat_map(|y| (0..4).map(move |x| (x, y)))
+            .map(|(x, y)| canvas_data.get_pixel(x, y)[3])
+            .collect();
+        assert_eq!(alpha_mask.alpha, expected);
+        assert_eq!(alpha_mask.alpha[0], 153);
+        assert_eq!(alpha_mask.alpha[11], 0);
+    }
+
+    #[test]
+    fn test_read_pixels_aligned() {
+        let mut canvas_data = new_canvas_data(Size2D::new(3, 2));
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(0., 0.), Size2D::new(1., 1.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+
+        // Rows of RGBA pixels are always a multiple of 4 bytes long, so only an alignment
+        // of 8 pads the 12-byte rows of this odd-width canvas.
+        for (alignment, stride) in [(1, 12), (4, 12), (8, 16), (3, 12)] {
+            let image_data = canvas_data.read_pixels_aligned(None, alignment);
+            assert_eq!(image_data.size, Size2D::new(3, 2));
+            assert_eq!(image_data.stride, stride);
+            assert_eq!(image_data.data.len(), stride * 2);
+            for row in image_data.data.chunks_exact(stride) {
+                assert!(row[12..].iter().all(|byte| *byte == 0));
+            }
+            assert_eq!(image_data.data[..4], [255, 0, 0, 255]);
+            assert_eq!(image_data.data[4..12], [0; 8]);
+            assert_eq!(image_data.data[stride..stride + 12], [0; 12]);
+        }
+    }
+
+    #[test]
+    fn test_save_and_restore_region() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        let fill = |canvas_data: &mut CanvasData<raqote::DrawTarget>, color| {
+            canvas_data.fill_rect(
+                &Rect::new(Point2D::new(0., 0.), Size2D::new(4., 4.)),
+                FillOrStrokeStyle::Color(color),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+        };
+        fill(&mut canvas_data, AbsoluteColor::srgb_legacy(255, 0, 0, 0.5));
+
+        // Regions are clipped to the canvas when saved.
+        let region = Rect::new(Point2D::new(2, 2), Size2D::new(4, 4));
+        let id = canvas_data.save_region(region);
+        assert_eq!(id, SavedRegionId(0));
+        assert_eq!(canvas_data.memory_usage().auxiliary, 2 * 2 * 4);
+
+        fill(&mut canvas_data, AbsoluteColor::srgb_legacy(0, 0, 255, 1.));
+        canvas_data.restore_region(region, id);
+        assert_eq!(canvas_data.get_pixel(2, 2), [255, 0, 0, 128]);
+        assert_eq!(canvas_data.get_pixel(3, 3), [255, 0, 0, 128]);
+        assert_eq!(canvas_data.get_pixel(1, 1), [0, 0, 255, 255]);
+
+        // Restoring elsewhere moves the pixels, and a smaller rectangle restores fewer.
+        canvas_data.restore_region(Rect::new(Point2D::new(0, 0), Size2D::new(1, 4)), id);
+        assert_eq!(canvas_data.get_pixel(0, 1), [255, 0, 0, 128]);
+        assert_eq!(canvas_data.get_pixel(1, 0), [0, 0, 255, 255]);
+
+        canvas_data.release_region(id);
+        assert_eq!(canvas_data.memory_usage().auxiliary, 0);
+        canvas_data.restore_region(region, id);
+        assert_eq!(canvas_data.save_region(region), SavedRegionId(1));
+    }
+
+    #[test]
+    fn test_rollback_transaction() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        let fill = |canvas_data: &mut CanvasData<raqote::DrawTarget>, color| {
+            canvas_data.fill_rect(
+                &Rect::new(Point2D::new(1., 1.), Size2D::new(2., 2.)),
+                FillOrStrokeStyle::Color(color),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+        };
+        fill(&mut canvas_data, AbsoluteColor::srgb_legacy(255, 0, 0, 0.5));
+        let before = canvas_data.read_pixels(None);
+
+        // The pixels are only copied once something is drawn.
+        canvas_data.begin_transaction();
+        assert_eq!(canvas_data.memory_usage().auxiliary, 0);
+        fill(&mut canvas_data, AbsoluteColor::srgb_legacy(0, 0, 255, 1.));
+        assert_eq!(canvas_data.memory_usage().auxiliary, 4 * 4 * 4);
+        canvas_data.begin_layer(source_over());
+        canvas_data.clear_rect(
+            &Rect::new(Point2D::new(0., 0.), Size2D::new(4., 4.)),
+            Transform2D::identity(),
+        );
+        canvas_data.rollback_transaction();
+        assert_eq!(
+            canvas_data.read_pixels(None).as_raw_bytes(),
+            before.as_raw_bytes()
+        );
+        assert_eq!(canvas_data.memory_usage().auxiliary, 0);
+
+        // What is committed stays, and can't be rolled back anymore.
+        canvas_data.begin_transaction();
+        fill(&mut canvas_data, AbsoluteColor::srgb_legacy(0, 0, 255, 1.));
+        canvas_data.commit_transaction();
+        canvas_data.rollback_transaction();
+        assert_eq!(canvas_data.get_pixel(1, 1), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_linear_blending_space() {
+        let blend_white_over_black = |blending_space| {
+            let mut canvas_data = new_canvas_data_with_options(
+                Size2D::new(2, 1),
+                CanvasCreationOptions {
+                    blending_space,
+                    ..Default::default()
+                },
+                mock_compositor_api(),
+            );
+            let black = FillOrStrokeStyle::Color(AbsoluteColor::BLACK);
+            let white = FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(255, 255, 255, 1.));
+            let pixel = Rect::new(Point2D::new(0., 0.), Size2D::new(1., 1.));
+            canvas_data.fill_rect(
+                &pixel,
+                black,
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            canvas_data.fill_rect(
+                &pixel,
+                white,
+                no_shadow(),
+                CompositionOptions {
+                    alpha: 0.5,
+                    composition_operation: CompositionOrBlending::default(),
+                },
+                Transform2D::identity(),
+            );
+            // The pixel that wasn't drawn to is left alone.
+            assert_eq!(canvas_data.get_pixel(1, 0), [0, 0, 0, 0]);
+            canvas_data.get_pixel(0, 0)
+        };
+
+        // Half of the encoded value of white, as browsers blend.
+        let [red, green, blue, alpha] = blend_white_over_black(BlendingSpace::Srgb);
+        assert!((127..=128).contains(&red), "{red}");
+        assert_eq!([green, blue, alpha], [red, red, 255]);
+
+        // Half of the light of white, encoded again, which is much brighter.
+        let [red, green, blue, alpha] = blend_white_over_black(BlendingSpace::Linear);
+        assert!((186..=189).contains(&red), "{red}");
+        assert_eq!([green, blue, alpha], [red, red, 255]);
+    }
+
+    #[test]
+    fn test_is_fully_opaque() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        assert!(!canvas_data.is_fully_opaque());
+
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(0., 0.), Size2D::new(4., 4.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        assert!(canvas_data.is_fully_opaque());
+
+        // Clearing a single pixel makes it transparent.
+        canvas_data.clear_rect(
+            &Rect::new(Point2D::new(3., 3.), Size2D::new(1., 1.)),
+            Transform2D::identity(),
+        );
+        assert!(!canvas_data.is_fully_opaque());
+    }
+
+    #[test]
+    fn test_last_draw_out_of_bounds() {
+        let mut canvas_data = new_canvas_data(Size2D::new(10, 10));
+        assert!(!canvas_data.last_draw_out_of_bounds());
+
+        let rect = Rect::new(Point2D::new(1000., 1000.), Size2D::new(5., 5.));
+        canvas_data.fill_rect(
+            &rect,
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        assert!(canvas_data.last_draw_out_of_bounds());
+
+        // The transform is what counts, not the coordinates passed in.
+        canvas_data.fill_rect(
+            &rect,
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::translation(-998., -998.),
+        );
+        assert!(!canvas_data.last_draw_out_of_bounds());
+    }
+
+    #[test]
+    fn test_clip_bounds() {
+        let mut canvas_data = new_canvas_data(Size2D::new(16, 16));
+        assert_eq!(
+            canvas_data.clip_bounds(),
+            Rect::new(Point2D::new(0., 0.), Size2D::new(16., 16.))
+        );
+
+        let mut path = Path::new();
+        path.rect(1., 1., 4., 4.);
+        let transform = Transform2D::translation(1., 2.).then_scale(2., 2.);
+        canvas_data.clip_path(&path, FillRule::Nonzero, transform);
+        assert_eq!(
+            canvas_data.clip_bounds(),
+            Rect::new(Point2D::new(4., 6.), Size2D::new(8., 8.))
+        );
+
+        // Further clips intersect with it, and nothing extends past the canvas.
+        let mut path = Path::new();
+        path.rect(8., 0., 20., 20.);
+        canvas_data.clip_path(&path, FillRule::Nonzero, Transform2D::identity());
+        assert_eq!(
+            canvas_data.clip_bounds(),
+            Rect::new(Point2D::new(8., 6.), Size2D::new(4., 8.))
+        );
+
+        canvas_data.pop_clips(1);
+        assert_eq!(
+            canvas_data.clip_bounds(),
+            Rect::new(Point2D::new(4., 6.), Size2D::new(8., 8.))
+        );
+    }
+
+    #[test]
+    fn test_set_clip_replaces_clips() {
+        let mut canvas_data = new_canvas_data(Size2D::new(8, 1));
+        let frame = |canvas_data: &mut CanvasData<raqote::DrawTarget>, clip_x, color| {
+            let clip = clip_x.map(|x| {
+                let mut path = Path::new();
+                path.rect(x, 0., 4., 1.);
+                path
+            });
+            canvas_data.set_clip(clip.as_ref(), FillRule::Nonzero, Transform2D::identity());
+            canvas_data.fill_rect(
+                &Rect::from_size(Size2D::new(8., 1.)),
+                FillOrStrokeStyle::Color(color),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+        };
+        let red = AbsoluteColor::srgb_legacy(255, 0, 0, 1.);
+        let blue = AbsoluteColor::srgb_legacy(0, 0, 255, 1.);
+
+        frame(&mut canvas_data, Some(0.), red);
+        assert_eq!(canvas_data.get_pixel(1, 0), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(5, 0), [0, 0, 0, 0]);
+
+        // Intersecting the clips would leave nothing to draw in.
+        frame(&mut canvas_data, Some(4.), blue);
+        assert_eq!(canvas_data.get_pixel(1, 0), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(5, 0), [0, 0, 255, 255]);
+        assert_eq!(
+            canvas_data.clip_bounds(),
+            Rect::new(Point2D::new(4., 0.), Size2D::new(4., 1.))
+        );
+
+        frame(&mut canvas_data, None, red);
+        assert_eq!(canvas_data.get_pixel(5, 0), [255, 0, 0, 255]);
+        assert_eq!(
+            canvas_data.clip_bounds(),
+            Rect::from_size(Size2D::new(8., 1.))
+        );
+    }
+
+    #[test]
+    fn test_pixel_aligned_clip_is_exact() {
+        let fill_clipped = |rect: Rect<f64>, transform: Transform2D<f32>| {
+            let mut canvas_data = new_canvas_data(Size2D::new(8, 8));
+            let mut path = Path::new();
+            path.rect(rect.origin.x, rect.origin.y, rect.size.width, rect.size.height);
+            canvas_data.clip_path(&path, FillRule::Nonzero, transform);
+            canvas_data.fill_rect(
+                &Rect::from_size(Size2D::new(8., 8.)),
+                red(),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            canvas_data
+        };
+        let partly_covered_pixels = |canvas_data: &mut CanvasData<raqote::DrawTarget>| {
+            (0..8)
+                .flat_map(|y| (0..8).map(move |x| (x, y)))
+                .filter(|&(x, y)| ![0, 255].contains(&canvas_data.get_pixel(x, y)[3]))
+                .count()
+        };
+
+        // A rectangle that the transform takes to pixel boundaries, from (2, 2) to (6, 6).
+        let mut canvas_data = fill_clipped(
+            Rect::new(Point2D::new(1., 1.), Size2D::new(2., 2.)),
+            Transform2D::scale(2., 2.),
+        );
+        assert_eq!(partly_covered_pixels(&mut canvas_data), 0);
+        assert_eq!(canvas_data.get_pixel(2, 2), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(5, 5), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(1, 1), [0, 0, 0, 0]);
+        assert_eq!(canvas_data.get_pixel(6, 6), [0, 0, 0, 0]);
+
+        // Fractional rectangles are still antialiased.
+        let mut canvas_data = fill_clipped(
+            Rect::new(Point2D::new(1.5, 1.5), Size2D::new(4., 4.)),
+            Transform2D::identity(),
+        );
+        assert!(partly_covered_pixels(&mut canvas_data) > 0);
+    }
+
+    #[test]
+    fn test_rotated_clip_is_antialiased() {
+        let mut canvas_data = new_canvas_data(Size2D::new(16, 16));
+        let mut path = Path::new();
+        path.rect(-4., -4., 8., 8.);
+        let rotation = Transform2D::rotation(euclid::Angle::degrees(30.));
+        let transform = rotation.then_translate(Vector2D::new(8., 8.));
+        canvas_data.clip_path(&path, FillRule::Nonzero, transform);
+        canvas_data.fill_rect(
+            &Rect::from_size(Size2D::new(16., 16.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+
+        // The pixels along the diagonal edges of the clip are partly covered, and the
+        // coverage fades from the inside of the clip to the outside.
+        let alphas: Vec<u8> = (0..16).map(|x| canvas_data.get_pixel(x, 8)[3]).collect();
+        assert_eq!(alphas[8], 255);
+        assert_eq!(alphas[0], 0);
+        assert!(alphas.iter().any(|alpha| ![0, 255].contains(alpha)));
+        assert!(alphas[..8].windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn test_low_power_clips() {
+        let fill_clipped = |path: &Path, transform: Transform2D<f32>| {
+            let mut canvas_data = new_canvas_data(Size2D::new(16, 16));
+            canvas_data.set_low_power_mode(true);
+            canvas_data.clip_path(path, FillRule::Nonzero, transform);
+            canvas_data.fill_rect(
+                &Rect::from_size(Size2D::new(16., 16.)),
+                red(),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            (0..16)
+                .flat_map(|y| (0..16).map(move |x| (x, y)))
+                .filter(|&(x, y)| ![0, 255].contains(&canvas_data.get_pixel(x, y)[3]))
+                .count()
+        };
+
+        // Axis-aligned rectangles are rounded to whole pixels.
+        let mut path = Path::new();
+        path.rect(1.5, 1.5, 4., 4.);
+        assert_eq!(fill_clipped(&path, Transform2D::identity()), 0);
+
+        // Other clips are still antialiased.
+        let mut path = Path::new();
+        path.rect(-4., -4., 8., 8.);
+        let rotation = Transform2D::rotation(euclid::Angle::degrees(30.));
+        assert!(fill_clipped(&path, rotation.then_translate(Vector2D::new(8., 8.))) > 0);
+    }
+
+    #[test]
+    fn test_copy_is_bounded_by_clip() {
+        let mut canvas_data = new_canvas_data(Size2D::new(8, 8));
+        canvas_data.fill_rect(
+            &Rect::from_size(Size2D::new(8., 8.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        let mut path = Path::new();
+        path.rect(2., 2., 4., 4.);
+        canvas_data.clip_path(&path, FillRule::Nonzero, Transform2D::identity());
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(3., 3.), Size2D::new(1., 1.)),
+            FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(0, 0, 255, 1.)),
+            no_shadow(),
+            CompositionOptions {
+                alpha: 1.,
+                composition_operation: CompositionOrBlending::Composition(CompositionStyle::Copy),
+            },
+            Transform2D::identity(),
+        );
+
+        // `copy` draws the shape and clears the rest of the clip, and nothing outside of it.
+        assert_eq!(canvas_data.get_pixel(3, 3), [0, 0, 255, 255]);
+        assert_eq!(canvas_data.get_pixel(5, 5), [0, 0, 0, 0]);
+        assert_eq!(canvas_data.get_pixel(1, 1), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(6, 3), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_radial_clip_grows_as_a_circle() {
+        let mut canvas_data = new_canvas_data(Size2D::new(20, 20));
+        let frame = |canvas_data: &mut CanvasData<raqote::DrawTarget>, radius| {
+            canvas_data.set_radial_clip(
+                Point2D::new(10., 10.),
+                radius,
+                FillRule::Nonzero,
+                Transform2D::identity(),
+            );
+            canvas_data.fill_rect(
+                &Rect::from_size(Size2D::new(20., 20.)),
+                red(),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+        };
+
+        frame(&mut canvas_data, 0.);
+        assert_eq!(canvas_data.get_pixel(10, 10), [0, 0, 0, 0]);
+
+        frame(&mut canvas_data, 3.);
+        assert_eq!(canvas_data.get_pixel(10, 10), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(15, 10), [0, 0, 0, 0]);
+
+        frame(&mut canvas_data, 7.);
+        assert_eq!(canvas_data.get_pixel(15, 10), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(10, 4), [255, 0, 0, 255]);
+        // The corners of the square around the circle are still hidden.
+        assert_eq!(canvas_data.get_pixel(15, 15), [0, 0, 0, 0]);
+        assert_eq!(canvas_data.get_pixel(4, 4), [0, 0, 0, 0]);
+
+        frame(&mut canvas_data, 100.);
+        assert_eq!(canvas_data.get_pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(
+            canvas_data.clip_bounds(),
+            Rect::from_size(Size2D::new(20., 20.))
+        );
+    }
+
+    #[test]
+    fn test_fill_path_with_holes() {
+        let mut canvas_data = new_canvas_data(Size2D::new(10, 10));
+        // All three rectangles wind the same way, so that none of them would be a hole
+        // under the nonzero rule, and the last two overlap.
+        let mut path = Path::new();
+        path.rect(0., 0., 10., 10.);
+        path.rect(2., 2., 4., 4.);
+        path.rect(4., 4., 4., 4.);
+        canvas_data.fill_path_with_holes(
+            &path,
+            &[1, 2],
+            FillRule::Nonzero,
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+
+        assert_eq!(canvas_data.get_pixel(1, 1), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(8, 2), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(3, 3), [0, 0, 0, 0]);
+        assert_eq!(canvas_data.get_pixel(7, 7), [0, 0, 0, 0]);
+        // Where the holes overlap is cut out too.
+        assert_eq!(canvas_data.get_pixel(5, 5), [0, 0, 0, 0]);
+
+        // The clips that cut out the holes are gone afterwards.
+        assert_eq!(
+            canvas_data.clip_bounds(),
+            Rect::new(Point2D::zero(), Size2D::new(10., 10.))
+        );
     }
 
-    fn build_unshaped_text_runs<'b>(
-        &self,
-        text: &'b str,
-        font_group: &mut FontGroup,
-    ) -> Vec<UnshapedTextRun<'b>> {
-        let mut runs = Vec::new();
-        let mut current_text_run = UnshapedTextRun::default();
-        let mut current_text_run_start_index = 0;
+    #[test]
+    fn test_clip_path_keeps_path() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        let mut path = Path::new();
+        path.rect(1., 1., 2., 2.);
+        let original_path = path.clone();
 
-        for (index, character) in text.char_indices() {
-            // TODO: This should ultimately handle emoji variation selectors, but raqote does not yet
-            // have support for color glyphs.
-            let script = Script::from(character);
-            let font = font_group.find_by_codepoint(&self.font_context, character, None, None);
+        canvas_data.clip_path(&path, FillRule::Nonzero, Transform2D::identity());
+        assert_eq!(path.0.elements(), original_path.0.elements());
 
-            if !current_text_run.script_and_font_compatible(script, &font) {
-                let previous_text_run = mem::replace(
-                    &mut current_text_run,
-                    UnshapedTextRun {
-                        font: font.clone(),
-                        script,
-                        ..Default::default()
-                    },
-                );
-                current_text_run_start_index = index;
-                runs.push(previous_text_run)
-            }
+        // Filling the same path after clipping to it still paints all of it.
+        canvas_data.fill_path(
+            &path,
+            FillRule::Nonzero,
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
 
-            current_text_run.string =
-                &text[current_text_run_start_index..index + character.len_utf8()];
+        let mut snapshot = canvas_data.read_pixels(None);
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            SnapshotPixelFormat::RGBA,
+        );
+        for (index, pixel) in snapshot.as_raw_bytes().chunks(4).enumerate() {
+            let (x, y) = (index % 4, index / 4);
+            let expected = if (1..3).contains(&x) && (1..3).contains(&y) {
+                [255, 0, 0, 255]
+            } else {
+                [0, 0, 0, 0]
+            };
+            assert_eq!(pixel, expected, "Unexpected pixel at ({x}, {y})");
         }
+    }
+
+    #[test]
+    fn test_drop_shadow_filter_shows_through_hole() {
+        let mut canvas_data = new_canvas_data(Size2D::new(6, 6));
+        canvas_data.set_filter(vec![FilterFunction::DropShadow {
+            offset_x: 1.,
+            offset_y: 1.,
+            std_deviation: 0.,
+            color: AbsoluteColor::srgb_legacy(0, 0, 255, 1.),
+        }]);
+
+        // A 4x4 square with a 2x2 hole in the middle.
+        let mut path = Path::new();
+        path.rect(0., 0., 4., 4.);
+        path.rect(1., 1., 2., 2.);
+        canvas_data.fill_path(
+            &path,
+            FillRule::Evenodd,
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
 
-        runs.push(current_text_run);
-        runs
+        let mut snapshot = canvas_data.read_pixels(None);
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            SnapshotPixelFormat::RGBA,
+        );
+        let pixel = |x: usize, y: usize| &snapshot.as_raw_bytes()[(y * 6 + x) * 4..][..4];
+        assert_eq!(pixel(0, 0), [255, 0, 0, 255]);
+        // The shadow of the square shows through its hole...
+        assert_eq!(pixel(1, 1), [0, 0, 255, 255]);
+        // ...apart from where the hole itself casts no shadow.
+        assert_eq!(pixel(2, 2), [0, 0, 0, 0]);
+        assert_eq!(pixel(4, 4), [0, 0, 255, 255]);
+        assert_eq!(pixel(5, 5), [0, 0, 0, 0]);
     }
 
-    /// Find the *anchor_point* for the given parameters of a line of text.
-    /// See <https://html.spec.whatwg.org/multipage/#text-preparation-algorithm>.
-    fn find_anchor_point_for_line_of_text(
-        &self,
-        x: f32,
-        y: f32,
-        metrics: &FontMetrics,
-        width: f32,
-        is_rtl: bool,
-        text_options: &TextOptions,
-    ) -> Point2D<f32> {
-        let text_align = match text_options.align {
-            TextAlign::Start if is_rtl => TextAlign::Right,
-            TextAlign::Start => TextAlign::Left,
-            TextAlign::End if is_rtl => TextAlign::Left,
-            TextAlign::End => TextAlign::Right,
-            text_align => text_align,
+    fn font_style(weight: FontWeight) -> ServoArc<FontStyleStruct> {
+        let mut style = FontStyleStruct::initial_values();
+        style.font_weight = weight;
+        style.compute_font_hash();
+        ServoArc::new(style)
+    }
+
+    #[test]
+    fn test_measure_text_cache() {
+        let shape_count = Cell::new(0);
+        let measure = |text: &str| {
+            shape_count.set(shape_count.get() + 1);
+            TextMeasurements {
+                width: text.len() as f32,
+                bounding_box: Rect::new(Point2D::zero(), Size2D::new(text.len() as f32, 10.)),
+                ascent: 8.,
+                descent: 2.,
+                hanging_baseline: 6.,
+                ideographic_baseline: -2.,
+                alphabetic_baseline: 0.,
+            }
         };
-        let anchor_x = match text_align {
-            TextAlign::Center => -width / 2.,
-            TextAlign::Right => -width,
-            _ => 0.,
+
+        let mut cache = MeasureTextCache::default();
+        let tags = ShapingTags::default();
+        let normal = font_style(FontWeight::NORMAL);
+        let first = cache.get_or_insert_with(&normal, "Hello".to_owned(), false, tags, measure);
+        let second = cache.get_or_insert_with(&normal, "Hello".to_owned(), false, tags, measure);
+        assert_eq!(first, second);
+        assert_eq!(
+            shape_count.get(),
+            1,
+            "Measuring the same string should hit the cache"
+        );
+
+        cache.get_or_insert_with(&normal, "World".to_owned(), false, tags, measure);
+        assert_eq!(shape_count.get(), 2);
+
+        // A different font invalidates every entry.
+        let bold = font_style(FontWeight::BOLD);
+        cache.get_or_insert_with(&bold, "Hello".to_owned(), false, tags, measure);
+        cache.get_or_insert_with(&normal, "Hello".to_owned(), false, tags, measure);
+        assert_eq!(shape_count.get(), 4);
+
+        // So does a different base direction, which can reorder the string.
+        cache.get_or_insert_with(&normal, "Hello".to_owned(), true, tags, measure);
+        assert_eq!(shape_count.get(), 5);
+
+        // And so do different shaping tags, which can select other glyphs.
+        let japanese = ShapingTags {
+            script: None,
+            language: Some(u32::from_be_bytes(*b"JAN ")),
         };
+        cache.get_or_insert_with(&normal, "Hello".to_owned(), false, japanese, measure);
+        assert_eq!(shape_count.get(), 6);
+    }
 
-        let ascent = metrics.ascent.to_f32_px();
-        let descent = metrics.descent.to_f32_px();
-        let anchor_y = match text_options.baseline {
-            TextBaseline::Top => ascent,
-            TextBaseline::Hanging => ascent * HANGING_BASELINE_DEFAULT,
-            TextBaseline::Ideographic => -descent * IDEOGRAPHIC_BASELINE_DEFAULT,
-            TextBaseline::Middle => (ascent - descent) / 2.,
-            TextBaseline::Alphabetic => 0.,
-            TextBaseline::Bottom => -descent,
+    #[test]
+    fn test_trim_glyph_cache() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        let font = font_style(FontWeight::NORMAL);
+        let mut measure = |canvas_data: &mut CanvasData<raqote::DrawTarget>, text: String| {
+            // Fonts can't be loaded here, so the measurements of the font are given.
+            canvas_data
+                .text
+                .measure_text_cache
+                .get_or_insert_with(
+                    &font,
+                    text,
+                    false,
+                    ShapingTags::default(),
+                    |text| TextMeasurements {
+                        width: text.len() as f32,
+                        bounding_box: Rect::new(
+                            Point2D::zero(),
+                            Size2D::new(text.len() as f32, 10.),
+                        ),
+                        ascent: 8.,
+                        descent: 2.,
+                        hanging_baseline: 6.,
+                        ideographic_baseline: -2.,
+                        alphabetic_baseline: 0.,
+                    },
+                );
         };
+        for index in 0..100 {
+            measure(&mut canvas_data, format!("Text {index}"));
+        }
+        assert_eq!(canvas_data.glyph_cache_size(), 100);
 
-        point2(x + anchor_x, y + anchor_y)
+        // Everything was used since the canvas was created.
+        canvas_data.trim_glyph_cache();
+        assert_eq!(canvas_data.glyph_cache_size(), 100);
+
+        measure(&mut canvas_data, "Text 7".to_owned());
+        canvas_data.trim_glyph_cache();
+        assert_eq!(canvas_data.glyph_cache_size(), 1);
+        canvas_data.trim_glyph_cache();
+        assert_eq!(canvas_data.glyph_cache_size(), 0);
     }
 
-    pub(crate) fn fill_rect(
-        &mut self,
-        rect: &Rect<f32>,
-        style: FillOrStrokeStyle,
-        shadow_options: ShadowOptions,
-        composition_options: CompositionOptions,
-        transform: Transform2D<f32>,
-    ) {
-        if style.is_zero_size_gradient() {
-            return; // Paint nothing if gradient size is zero.
-        }
+    #[test]
+    fn test_last_modified() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        assert_eq!(canvas_data.last_modified(), None);
 
-        if shadow_options.need_to_draw_shadow() {
-            self.draw_with_shadow(
-                rect,
-                shadow_options,
-                composition_options,
-                transform,
-                |new_draw_target, transform| {
-                    new_draw_target.fill_rect(rect, style, composition_options, transform);
+        // The time is taken when the image is next uploaded, once for all of the drawing
+        // before it.
+        let before = CrossProcessInstant::now();
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(1., 1.), Size2D::new(2., 2.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        canvas_data.update_image_rendering(false);
+        let after = CrossProcessInstant::now();
+        thread::sleep(Duration::from_millis(10));
+        let modified = canvas_data.last_modified().unwrap();
+        assert!(before <= modified && modified <= after);
+
+        thread::sleep(Duration::from_millis(10));
+        canvas_data.read_pixels(None);
+        canvas_data.get_pixel(1, 1);
+        assert_eq!(canvas_data.last_modified(), Some(modified));
+
+        // Drawing outside of the canvas changes no pixels either.
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(10., 10.), Size2D::new(2., 2.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        assert_eq!(canvas_data.last_modified(), Some(modified));
+
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(0., 0.), Size2D::new(1., 1.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        assert!(canvas_data.last_modified().unwrap() > modified);
+    }
+
+    #[test]
+    fn test_measure_text_ignores_scale() {
+        let font = font_style(FontWeight::NORMAL);
+        let measure_width = |scale_factor| {
+            let mut canvas_data = new_canvas_data_with_options(
+                Size2D::new(10, 10),
+                CanvasCreationOptions {
+                    scale_factor,
+                    ..Default::default()
                 },
+                mock_compositor_api(),
             );
-        } else {
-            self.maybe_bound_shape_with_pattern(
-                style,
-                composition_options,
-                &rect.cast(),
-                transform,
-                |self_, style| {
-                    self_
-                        .drawtarget
-                        .fill_rect(rect, style, composition_options, transform);
+            // Fonts can't be loaded here, so the measurements of the font are given.
+            canvas_data.text.measure_text_cache.get_or_insert_with(
+                &font,
+                "Hello".to_owned(),
+                false,
+                ShapingTags::default(),
+                |_| TextMeasurements {
+                    width: 27.5,
+                    bounding_box: Rect::new(Point2D::new(0., -8.), Size2D::new(27., 10.)),
+                    ascent: 8.,
+                    descent: 2.,
+                    hanging_baseline: 6.,
+                    ideographic_baseline: -2.,
+                    alphabetic_baseline: 0.,
                 },
             );
+            let text_options = TextOptions {
+                font: Some(font.clone()),
+                align: TextAlign::Center,
+                baseline: TextBaseline::Alphabetic,
+                baseline_shift: None,
+                line_height: None,
+                tab_size: None,
+                decoration: None,
+                glyph_range: None,
+                shaping_tags: ShapingTags::default(),
+            };
+            canvas_data.measure_text("Hello".to_owned(), false, text_options)
+        };
+
+        let unscaled = measure_width(1);
+        let scaled = measure_width(3);
+        assert_eq!(unscaled.width, 27.5);
+        assert_eq!(scaled.width, unscaled.width);
+        assert_eq!(
+            scaled.actual_boundingbox_left,
+            unscaled.actual_boundingbox_left
+        );
+    }
+
+    #[test]
+    fn test_line_breaks() {
+        // Each character is 10 units wide.
+        let measure = |line: &str| line.chars().count() as f32 * 10.;
+        let text = "the quick brown fox jumps over the lazy dog";
+        let breaks = line_breaks(text, 100., measure);
+        assert_eq!(breaks, [10, 20, 31, 40]);
+
+        let line_starts = std::iter::once(0).chain(breaks.iter().copied());
+        let line_ends = breaks.iter().copied().chain(std::iter::once(text.len()));
+        for (start, end) in line_starts.zip(line_ends) {
+            assert!(measure(text[start..end].trim_end()) <= 100.);
         }
+
+        // A word that is too wide for any line gets one of its own.
+        assert_eq!(line_breaks("a verylongword b", 50., measure), [2, 15]);
+        assert_eq!(line_breaks("", 50., measure), Vec::<usize>::new());
     }
 
-    pub(crate) fn clear_rect(&mut self, rect: &Rect<f32>, transform: Transform2D<f32>) {
-        self.drawtarget.clear_rect(rect, transform);
+    #[test]
+    fn test_bidi_runs() {
+        let text = "abc \u{5e9}\u{5dc}\u{5d5}\u{5dd} def";
+        let hebrew = "\u{5e9}\u{5dc}\u{5d5}\u{5dd}";
+
+        // In left-to-right text, the Hebrew word is an embedded right-to-left run.
+        assert_eq!(
+            bidi_runs(text, false),
+            vec![
+                ("abc ", Level::ltr()),
+                (hebrew, Level::rtl()),
+                (" def", Level::ltr()),
+            ]
+        );
+
+        // In right-to-left text, the English words are embedded left-to-right runs, and the
+        // first of them is drawn rightmost.
+        let embedded_ltr = Level::new(2).unwrap();
+        assert_eq!(
+            bidi_runs(text, true),
+            vec![
+                ("def", embedded_ltr),
+                (" \u{5e9}\u{5dc}\u{5d5}\u{5dd} ", Level::rtl()),
+                ("abc", embedded_ltr),
+            ]
+        );
+
+        assert!(bidi_runs("", true).is_empty());
     }
 
-    pub(crate) fn stroke_rect(
-        &mut self,
-        rect: &Rect<f32>,
-        style: FillOrStrokeStyle,
-        line_options: LineOptions,
-        shadow_options: ShadowOptions,
-        composition_options: CompositionOptions,
-        transform: Transform2D<f32>,
-    ) {
-        if style.is_zero_size_gradient() {
-            return; // Paint nothing if gradient size is zero.
+    #[test]
+    fn test_frame_pacing() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        canvas_data.set_frame_pacing(true);
+        canvas_data.reset_draw_stats();
+
+        // Draw four frames for each vsync, as a page rendering ahead of the display would.
+        for _ in 0..3 {
+            for _ in 0..4 {
+                canvas_data.fill_rect(
+                    &Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+                    red(),
+                    no_shadow(),
+                    source_over(),
+                    Transform2D::identity(),
+                );
+                canvas_data.update_image_rendering(false);
+            }
+            canvas_data.vsync();
         }
+        assert_eq!(canvas_data.draw_stats().image_uploads, 3);
 
-        if shadow_options.need_to_draw_shadow() {
-            self.draw_with_shadow(
-                rect,
-                shadow_options,
-                composition_options,
-                transform,
-                |new_draw_target, transform| {
-                    new_draw_target.stroke_rect(
-                        rect,
-                        style,
-                        line_options,
-                        composition_options,
-                        transform,
-                    );
+        // Without an update to send, a vsync does nothing.
+        canvas_data.vsync();
+        assert_eq!(canvas_data.draw_stats().image_uploads, 3);
+
+        // Turning pacing off sends the pending update right away, and later ones as they
+        // are requested.
+        canvas_data.update_image_rendering(false);
+        canvas_data.set_frame_pacing(false);
+        assert_eq!(canvas_data.draw_stats().image_uploads, 4);
+        canvas_data.update_image_rendering(false);
+        assert_eq!(canvas_data.draw_stats().image_uploads, 5);
+    }
+
+    #[test]
+    fn test_immediate_image_update() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        canvas_data.set_frame_pacing(true);
+        canvas_data.reset_draw_stats();
+
+        // A paced update waits for the next vsync.
+        canvas_data.update_image_rendering(false);
+        assert_eq!(canvas_data.draw_stats().image_uploads, 0);
+
+        // An immediate one is sent without waiting, and takes the paced one with it.
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        canvas_data.update_image_rendering(true);
+        assert_eq!(canvas_data.draw_stats().image_uploads, 1);
+        canvas_data.vsync();
+        assert_eq!(canvas_data.draw_stats().image_uploads, 1);
+
+        // Later updates are paced again.
+        canvas_data.update_image_rendering(false);
+        assert_eq!(canvas_data.draw_stats().image_uploads, 1);
+        canvas_data.vsync();
+        assert_eq!(canvas_data.draw_stats().image_uploads, 2);
+    }
+
+    #[test]
+    fn test_transparent_fills() {
+        let transparent = || FillOrStrokeStyle::Color(AbsoluteColor::TRANSPARENT_BLACK);
+        let composition = |composition_style| CompositionOptions {
+            alpha: 1.,
+            composition_operation: CompositionOrBlending::Composition(composition_style),
+        };
+        let rect = Rect::new(Point2D::new(0., 0.), Size2D::new(4., 4.));
+        let mut path = Path::new();
+        path.rect(0., 0., 4., 4.);
+
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        canvas_data.fill_rect(
+            &rect,
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        canvas_data.reset_draw_stats();
+
+        // A transparent source-over fill is skipped, as is one with a global alpha of zero.
+        canvas_data.fill_rect(
+            &rect,
+            transparent(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        canvas_data.fill_path(
+            &path,
+            FillRule::Nonzero,
+            transparent(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        canvas_data.fill_rect(
+            &rect,
+            red(),
+            no_shadow(),
+            CompositionOptions {
+                alpha: 0.,
+                ..source_over()
+            },
+            Transform2D::identity(),
+        );
+        assert_eq!(canvas_data.draw_stats().fills, 0);
+        assert_eq!(canvas_data.get_pixel(2, 2), [255, 0, 0, 255]);
+
+        // Under other operations it is drawn: destination-out removes nothing where the
+        // fill is transparent, while copy clears what it covers.
+        canvas_data.fill_path(
+            &path,
+            FillRule::Nonzero,
+            transparent(),
+            no_shadow(),
+            composition(CompositionStyle::DestinationOut),
+            Transform2D::identity(),
+        );
+        assert_eq!(canvas_data.draw_stats().fills, 1);
+        assert_eq!(canvas_data.get_pixel(2, 2), [255, 0, 0, 255]);
+        canvas_data.fill_rect(
+            &rect,
+            transparent(),
+            no_shadow(),
+            composition(CompositionStyle::Copy),
+            Transform2D::identity(),
+        );
+        assert_eq!(canvas_data.draw_stats().fills, 2);
+        assert_eq!(canvas_data.get_pixel(2, 2), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_draw_after_reclaiming_surface() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(0., 0.), Size2D::new(4., 4.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        canvas_data.reclaim_surface();
+        assert!(canvas_data.memory_usage().surface < 4 * 4 * 4);
+
+        canvas_data.restore_reclaimed_surface();
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(0., 0.), Size2D::new(1., 1.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        assert_eq!(canvas_data.memory_usage().surface, 4 * 4 * 4);
+        assert_eq!(canvas_data.get_pixel(0, 0), [255, 0, 0, 255]);
+        // The rest of the surface has the initial color of a canvas, not its old pixels.
+        assert_eq!(canvas_data.get_pixel(1, 1), [0, 0, 0, 0]);
+        assert_eq!(canvas_data.get_pixel(3, 3), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_draw_display_p3_image() {
+        // These are the sRGB colors (200, 100, 50) and (0, 128, 255) in Display P3.
+        let mut image = Snapshot::from_vec(
+            Size2D::new(2, 1),
+            SnapshotPixelFormat::RGBA,
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            vec![187, 105, 62, 255, 55, 126, 247, 255],
+        );
+        image.set_color_space(SnapshotColorSpace::DisplayP3);
+
+        let mut canvas_data = new_canvas_data(Size2D::new(2, 1));
+        canvas_data.draw_image(
+            image,
+            Rect::new(Point2D::new(0., 0.), Size2D::new(2., 1.)),
+            Rect::new(Point2D::new(0., 0.), Size2D::new(2., 1.)),
+            false,
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        assert_eq!(canvas_data.get_pixel(0, 0), [200, 100, 50, 255]);
+        assert_eq!(canvas_data.get_pixel(1, 0), [0, 128, 255, 255]);
+    }
+
+    #[test]
+    fn test_dense_pattern_tile() {
+        // A tile of 2x2 units with a checkerboard of half units, rasterized at `scale`
+        // pixels per unit. At a single pixel per unit the checkerboard blends to gray.
+        let tile = |scale: u32| {
+            let size = 2 * scale;
+            let data = (0..size * size)
+                .flat_map(|index| match (scale, (index % size + index / size) % 2) {
+                    (1, _) => [128, 128, 128, 255],
+                    (_, 0) => [0, 0, 0, 255],
+                    _ => [255, 255, 255, 255],
+                })
+                .collect();
+            let snapshot = Snapshot::from_vec(
+                Size2D::new(size, size),
+                SnapshotPixelFormat::RGBA,
+                SnapshotAlphaMode::Transparent {
+                    premultiplied: false,
                 },
+                data,
             );
-        } else {
-            self.maybe_bound_shape_with_pattern(
+            FillOrStrokeStyle::Surface(SurfaceStyle::new(
+                snapshot.as_ipc(),
+                Size2D::new(2, 2),
+                true,
+                true,
+                Transform2D::identity(),
+            ))
+        };
+        let fill = |style| {
+            let mut canvas_data = new_canvas_data(Size2D::new(4, 1));
+            canvas_data.fill_rect(
+                &Rect::from_size(Size2D::new(2., 0.5)),
                 style,
-                composition_options,
-                &rect.cast(),
-                transform,
-                |self_, style| {
-                    self_.drawtarget.stroke_rect(
-                        rect,
-                        style,
-                        line_options,
-                        composition_options,
-                        transform,
-                    );
-                },
-            )
-        }
+                no_shadow(),
+                source_over(),
+                Transform2D::scale(2., 2.),
+            );
+            (0..4).map(|x| canvas_data.get_pixel(x, 0)).collect::<Vec<_>>()
+        };
+
+        // Under a 2x scale, the tile rasterized at device resolution keeps the checkerboard.
+        let black = [0, 0, 0, 255];
+        let white = [255, 255, 255, 255];
+        assert_eq!(fill(tile(2)), [black, white, black, white]);
+        // The tile rasterized at its own size is only stretched.
+        assert_eq!(fill(tile(1)), [[128, 128, 128, 255]; 4]);
     }
 
-    pub(crate) fn fill_path(
-        &mut self,
-        path: &Path,
-        fill_rule: FillRule,
-        style: FillOrStrokeStyle,
-        _shadow_options: ShadowOptions,
-        composition_options: CompositionOptions,
-        transform: Transform2D<f32>,
-    ) {
-        if style.is_zero_size_gradient() {
-            return; // Paint nothing if gradient size is zero.
+    #[test]
+    fn test_apply_color_matrix() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 1));
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(0., 0.), Size2D::new(2., 1.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        canvas_data.fill_rect(
+            &Rect::new(Point2D::new(2., 0.), Size2D::new(2., 1.)),
+            FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(0, 255, 0, 1.)),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+
+        // Replace the color channels with the luminance, leaving alpha as is.
+        let luminance = [0.2126, 0.7152, 0.0722, 0., 0.];
+        let mut matrix = [0.; 20];
+        for row in 0..3 {
+            matrix[row * 5..][..5].copy_from_slice(&luminance);
         }
+        matrix[18] = 1.;
+        canvas_data.apply_color_matrix(Rect::new(Point2D::new(0, 0), Size2D::new(3, 1)), &matrix);
 
-        self.maybe_bound_shape_with_pattern(
-            style,
-            composition_options,
-            &path.bounding_box(),
-            transform,
-            |self_, style| {
-                self_
-                    .drawtarget
-                    .fill(path, fill_rule, style, composition_options, transform)
-            },
-        )
+        assert_eq!(canvas_data.get_pixel(0, 0), [54, 54, 54, 255]);
+        assert_eq!(canvas_data.get_pixel(2, 0), [182, 182, 182, 255]);
+        // Pixels outside of the rectangle are left alone.
+        assert_eq!(canvas_data.get_pixel(3, 0), [0, 255, 0, 255]);
     }
 
-    pub(crate) fn stroke_path(
-        &mut self,
-        path: &Path,
-        style: FillOrStrokeStyle,
-        line_options: LineOptions,
-        _shadow_options: ShadowOptions,
-        composition_options: CompositionOptions,
-        transform: Transform2D<f32>,
-    ) {
-        if style.is_zero_size_gradient() {
-            return; // Paint nothing if gradient size is zero.
+    #[test]
+    fn test_fade_region() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 1));
+        canvas_data.fill_rect(
+            &Rect::from_size(Size2D::new(4., 1.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        canvas_data.fade_region(Rect::new(Point2D::new(1, 0), Size2D::new(2, 1)), 0.5);
+
+        let mut snapshot = canvas_data.read_pixels(None);
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            SnapshotPixelFormat::RGBA,
+        );
+        let pixels: Vec<_> = snapshot.as_raw_bytes().chunks(4).map(<[u8]>::to_vec).collect();
+        assert_eq!(pixels[1], [255, 0, 0, 128]);
+        assert_eq!(pixels[2], [255, 0, 0, 128]);
+        // Pixels outside of the rectangle are left alone.
+        assert_eq!(pixels[0], [255, 0, 0, 255]);
+        assert_eq!(pixels[3], [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_posterize() {
+        let mut canvas_data = new_canvas_data(Size2D::new(64, 2));
+        let gradient = LinearGradientStyle::new(
+            0.,
+            0.,
+            64.,
+            0.,
+            vec![
+                gradient_stop(0., AbsoluteColor::srgb_legacy(0, 255, 0, 1.)),
+                gradient_stop(1., AbsoluteColor::srgb_legacy(255, 0, 255, 1.)),
+            ],
+            false,
+        );
+        canvas_data.fill_rect(
+            &Rect::from_size(Size2D::new(64., 2.)),
+            FillOrStrokeStyle::LinearGradient(gradient),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        canvas_data.posterize(Rect::from_size(Size2D::new(64, 1)), 4);
+
+        let row = |canvas_data: &mut CanvasData<raqote::DrawTarget>, y| {
+            (0..64)
+                .map(|x| canvas_data.get_pixel(x, y))
+                .collect::<Vec<_>>()
+        };
+        let posterized = row(&mut canvas_data, 0);
+        for channel in 0..3 {
+            let mut values: Vec<_> = posterized.iter().map(|pixel| pixel[channel]).collect();
+            values.sort();
+            values.dedup();
+            assert_eq!(values, [0, 85, 170, 255], "channel {channel}");
         }
+        assert!(posterized.iter().all(|pixel| pixel[3] == 255));
+        // The row outside of the rectangle keeps its smooth gradient.
+        let mut untouched: Vec<_> = row(&mut canvas_data, 1).iter().map(|pixel| pixel[0]).collect();
+        untouched.dedup();
+        assert!(untouched.len() > 4);
 
-        self.maybe_bound_shape_with_pattern(
-            style,
-            composition_options,
-            &path.bounding_box(),
-            transform,
-            |self_, style| {
-                self_
-                    .drawtarget
-                    .stroke(path, style, line_options, composition_options, transform);
-            },
-        )
+        // A single level is taken as two.
+        canvas_data.posterize(Rect::from_size(Size2D::new(64, 1)), 1);
+        assert!(
+            row(&mut canvas_data, 0)
+                .iter()
+                .all(|pixel| pixel[..3].iter().all(|&value| value == 0 || value == 255))
+        );
     }
 
-    pub(crate) fn clip_path(
-        &mut self,
-        path: &Path,
-        fill_rule: FillRule,
-        transform: Transform2D<f32>,
-    ) {
-        self.drawtarget.push_clip(path, fill_rule, transform);
+    #[test]
+    fn test_point_in_stroke_tolerance() {
+        let mut path = Path::new();
+        path.move_to(0., 5.);
+        path.line_to(20., 5.);
+        let line_options = LineOptions {
+            width: 1.,
+            ..line_options(LineCapStyle::Butt, LineJoinStyle::default())
+        };
+        let hit = |x, y, tolerance| {
+            path.is_point_in_stroke(x, y, &line_options, tolerance, Transform2D::identity())
+        };
+        assert!(hit(10., 5.25, 0.));
+        assert!(!hit(10., 7., 0.));
+        // The tolerance widens the line to 6 pixels.
+        assert!(hit(10., 7., 5.));
+        assert!(!hit(10., 8.5, 5.));
+        assert!(!hit(f64::NAN, 5., 5.));
+
+        // The stroke is transformed along with its width.
+        assert!(!hit(20., 10.75, 0.));
+        assert!(path.is_point_in_stroke(20., 10.75, &line_options, 0., Transform2D::scale(2., 2.)));
     }
 
-    /// <https://html.spec.whatwg.org/multipage/#reset-the-rendering-context-to-its-default-state>
-    pub(crate) fn recreate(&mut self, size: Option<Size2D<u64>>) {
-        let size = size
-            .unwrap_or_else(|| self.drawtarget.get_size().to_u64())
-            .max(MIN_WR_IMAGE_SIZE);
+    #[test]
+    fn test_correct_coverage_gamma() {
+        // The antialiased edge of a black glyph, as premultiplied pixels.
+        let glyph_edge: Vec<u8> = [0, 32, 64, 128, 191, 255]
+            .into_iter()
+            .flat_map(|alpha| [0, 0, 0, alpha])
+            .collect();
+        // How much darker than the mid-gray background the glyph makes it, summed over the
+        // pixels, as a measure of its perceived weight.
+        let weight_on_mid_gray = |pixels: &[u8]| -> u32 {
+            pixels
+                .chunks_exact(4)
+                .map(|pixel| 128 - multiply_u8_color(128, 255 - pixel[3]) as u32)
+                .sum()
+        };
+        let corrected = |gamma| {
+            let mut pixels = glyph_edge.clone();
+            correct_coverage_gamma(&mut pixels, gamma);
+            pixels
+        };
 
-        // Step 1. Clear canvas's bitmap to transparent black.
-        self.drawtarget = self
-            .drawtarget
-            .create_similar_draw_target(&Size2D::new(size.width, size.height).cast());
+        let uncorrected_weight = weight_on_mid_gray(&glyph_edge);
+        assert_eq!(corrected(1.), glyph_edge);
+        assert!(weight_on_mid_gray(&corrected(2.2)) > uncorrected_weight);
+        assert!(weight_on_mid_gray(&corrected(1. / 2.2)) < uncorrected_weight);
 
-        self.update_image_rendering();
+        // Uncovered and fully covered pixels are left alone, and the color of the others is
+        // kept.
+        let heavier = corrected(2.2);
+        assert_eq!(&heavier[..4], &[0, 0, 0, 0]);
+        assert_eq!(&heavier[20..], &[0, 0, 0, 255]);
+        let mut red_edge = vec![64, 0, 0, 64];
+        correct_coverage_gamma(&mut red_edge, 2.2);
+        assert!(red_edge[3] > 64);
+        assert_eq!(red_edge[0], red_edge[3]);
+        assert_eq!(&red_edge[1..3], &[0, 0]);
     }
 
-    /// Update image in WebRender
-    pub(crate) fn update_image_rendering(&mut self) {
-        let (descriptor, data) = self.drawtarget.image_descriptor_and_serializable_data();
+    #[test]
+    fn test_shape_gamma() {
+        let fill_circle_on_mid_gray = |gamma| {
+            let mut canvas_data = new_canvas_data(Size2D::new(20, 20));
+            canvas_data.set_shape_gamma(gamma);
+            canvas_data.fill_rect(
+                &Rect::from_size(Size2D::new(20., 20.)),
+                FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(128, 128, 128, 1.)),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            let mut path = Path::new();
+            path.arc(10., 10., 6.3, 0., 2. * PI, false).unwrap();
+            canvas_data.fill_path(
+                &path,
+                FillRule::Nonzero,
+                FillOrStrokeStyle::Color(AbsoluteColor::BLACK),
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            canvas_data
+        };
+        let mut uncorrected = fill_circle_on_mid_gray(1.);
+        let mut corrected = fill_circle_on_mid_gray(2.2);
+
+        // The background and the inside of the circle are the same either way.
+        assert_eq!(corrected.get_pixel(0, 0), [128, 128, 128, 255]);
+        assert_eq!(corrected.get_pixel(0, 0), uncorrected.get_pixel(0, 0));
+        assert_eq!(corrected.get_pixel(10, 10), [0, 0, 0, 255]);
 
-        self.compositor_api
-            .update_image(self.image_key, descriptor, data);
+        // The edge pixels are covered more, so they are darker.
+        let mut darker_edge_pixels = 0;
+        for y in 0..20 {
+            for x in 0..20 {
+                let corrected_red = corrected.get_pixel(x, y)[0];
+                let uncorrected_red = uncorrected.get_pixel(x, y)[0];
+                assert!(corrected_red <= uncorrected_red);
+                if corrected_red < uncorrected_red {
+                    darker_edge_pixels += 1;
+                }
+            }
+        }
+        assert!(darker_edge_pixels > 0);
+
+        // Invalid gammas are ignored.
+        let mut canvas_data = new_canvas_data(Size2D::new(1, 1));
+        canvas_data.set_shape_gamma(0.);
+        canvas_data.set_shape_gamma(f32::NAN);
+        assert_eq!(canvas_data.rendering.shape_gamma, 1.);
     }
 
-    // https://html.spec.whatwg.org/multipage/#dom-context-2d-putimagedata
-    pub(crate) fn put_image_data(&mut self, snapshot: Snapshot, rect: Rect<u32>) {
-        assert_eq!(rect.size, snapshot.size());
-        let source_surface = self
-            .drawtarget
-            .create_source_surface_from_data(snapshot)
-            .unwrap();
-        self.drawtarget.copy_surface(
-            source_surface,
-            Rect::from_size(rect.size.to_i32()),
-            rect.origin.to_i32(),
+    #[test]
+    fn test_draw_image_rounded() {
+        let mut image = new_canvas_data(Size2D::new(20, 20));
+        image.fill_rect(
+            &Rect::from_size(Size2D::new(20., 20.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
         );
+        let draw_rounded = |radius: f64| {
+            let mut canvas_data = new_canvas_data(Size2D::new(24, 24));
+            canvas_data.draw_image_rounded(
+                image.read_pixels(None),
+                Rect::new(Point2D::new(2., 2.), Size2D::new(20., 20.)),
+                [Size2D::new(radius, radius); 4],
+                true,
+                no_shadow(),
+                source_over(),
+                Transform2D::identity(),
+            );
+            canvas_data
+        };
+
+        // The corners are cut off, while the middle and the sides are drawn.
+        let mut canvas_data = draw_rounded(8.);
+        for (x, y) in [(2, 2), (21, 2), (21, 21), (2, 21)] {
+            assert_eq!(canvas_data.get_pixel(x, y), [0, 0, 0, 0]);
+        }
+        for (x, y) in [(12, 12), (12, 2), (21, 12), (12, 21), (2, 12), (5, 5)] {
+            assert_eq!(canvas_data.get_pixel(x, y), [255, 0, 0, 255]);
+        }
+        // The clip is gone afterwards.
+        assert_eq!(canvas_data.clip_bounds.len(), 0);
+
+        // Radii too large for the rectangle are scaled down until the corners meet, which
+        // makes a circle of a square.
+        let mut canvas_data = draw_rounded(30.);
+        assert_eq!(canvas_data.get_pixel(3, 3), [0, 0, 0, 0]);
+        assert_eq!(canvas_data.get_pixel(12, 3), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(12, 12), [255, 0, 0, 255]);
     }
 
-    fn create_draw_target_for_shadow(&self, source_rect: &Rect<f32>) -> DrawTarget {
-        self.drawtarget.create_similar_draw_target(&Size2D::new(
-            source_rect.size.width as i32,
-            source_rect.size.height as i32,
-        ))
+    #[test]
+    fn test_composite_with_mask() {
+        let mut source = new_canvas_data(Size2D::new(20, 20));
+        source.fill_rect(
+            &Rect::from_size(Size2D::new(20., 20.)),
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        let mut mask = new_canvas_data(Size2D::new(20, 20));
+        let mut path = Path::new();
+        path.arc(10., 10., 6.3, 0., 2. * PI, false).unwrap();
+        mask.fill_path(
+            &path,
+            FillRule::Nonzero,
+            FillOrStrokeStyle::Color(AbsoluteColor::BLACK),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+
+        let mut canvas_data = new_canvas_data(Size2D::new(20, 20));
+        canvas_data.composite_with_mask(
+            source.read_pixels(None),
+            mask.read_pixels(None),
+            Rect::from_size(Size2D::new(20., 20.)),
+            source_over(),
+            Transform2D::identity(),
+        );
+
+        // The source shows through the inside of the circle only, and partly at its edge.
+        assert_eq!(canvas_data.get_pixel(10, 10), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(0, 0), [0, 0, 0, 0]);
+        let mut soft_edge_pixels = 0;
+        for y in 0..20 {
+            for x in 0..20 {
+                let pixel = canvas_data.get_pixel(x, y);
+                assert!(pixel[3].abs_diff(mask.get_pixel(x, y)[3]) <= 1);
+                if pixel[3] > 0 && pixel[3] < 255 {
+                    soft_edge_pixels += 1;
+                }
+            }
+        }
+        assert!(soft_edge_pixels > 0);
+
+        // A mask of another size draws nothing.
+        let mut canvas_data = new_canvas_data(Size2D::new(20, 20));
+        canvas_data.composite_with_mask(
+            source.read_pixels(None),
+            new_canvas_data(Size2D::new(10, 10)).read_pixels(None),
+            Rect::from_size(Size2D::new(20., 20.)),
+            source_over(),
+            Transform2D::identity(),
+        );
+        assert_eq!(canvas_data.get_pixel(10, 10), [0, 0, 0, 0]);
     }
 
-    fn draw_with_shadow<F>(
-        &self,
-        rect: &Rect<f32>,
-        shadow_options: ShadowOptions,
-        composition_options: CompositionOptions,
-        transform: Transform2D<f32>,
-        draw_shadow_source: F,
-    ) where
-        F: FnOnce(&mut DrawTarget, Transform2D<f32>),
-    {
-        let shadow_src_rect = transform.outer_transformed_rect(rect);
-        let mut new_draw_target = self.create_draw_target_for_shadow(&shadow_src_rect);
-        let shadow_transform = transform.then(
-            &Transform2D::identity()
-                .pre_translate(-shadow_src_rect.origin.to_vector().cast::<f32>()),
+    #[test]
+    fn test_split_glyph_range() {
+        // Drawing only the first three glyphs of text shaped into runs of two, two and three
+        // glyphs draws both glyphs of the first run, the first glyph of the second, and
+        // nothing of the last.
+        assert_eq!(
+            split_glyph_range(&(0..3), &[2, 2, 3]),
+            vec![0..2, 0..1, 0..0]
         );
-        draw_shadow_source(&mut new_draw_target, shadow_transform);
-        self.drawtarget.draw_surface_with_shadow(
-            new_draw_target.surface(),
-            &Point2D::new(shadow_src_rect.origin.x, shadow_src_rect.origin.y),
-            shadow_options,
-            composition_options,
+        assert_eq!(
+            split_glyph_range(&(3..6), &[2, 2, 3]),
+            vec![2..2, 1..2, 0..2]
+        );
+        // The range is clamped to the glyphs there are.
+        assert_eq!(
+            split_glyph_range(&(5..100), &[2, 2, 3]),
+            vec![2..2, 2..2, 1..3]
+        );
+        assert_eq!(
+            split_glyph_range(&(10..20), &[2, 2, 3]),
+            vec![2..2, 2..2, 3..3]
         );
+        assert_eq!(split_glyph_range(&(2..1), &[2, 2]), vec![2..2, 0..0]);
     }
 
-    /// Push a clip to the draw target to respect the non-repeating bound (either x, y, or both)
-    /// of the given pattern.
-    fn maybe_bound_shape_with_pattern<F>(
-        &mut self,
-        style: FillOrStrokeStyle,
-        composition_options: CompositionOptions,
-        path_bound_box: &Rect<f64>,
-        transform: Transform2D<f32>,
-        draw_shape: F,
-    ) where
-        F: FnOnce(&mut Self, FillOrStrokeStyle),
-    {
-        let x_bound = style.x_bound();
-        let y_bound = style.y_bound();
-        // Clear operations are also unbounded.
-        if matches!(
-            composition_options.composition_operation,
-            CompositionOrBlending::Composition(CompositionStyle::Clear)
-        ) || (x_bound.is_none() && y_bound.is_none())
-        {
-            draw_shape(self, style);
-            return;
-        }
-        let rect = Rect::from_size(Size2D::new(
-            x_bound.unwrap_or(path_bound_box.size.width.ceil() as u32),
-            y_bound.unwrap_or(path_bound_box.size.height.ceil() as u32),
-        ))
-        .cast();
-        let rect = transform.outer_transformed_rect(&rect);
-        self.drawtarget.push_clip_rect(&rect.cast());
-        draw_shape(self, style);
-        self.drawtarget.pop_clip();
+    #[test]
+    fn test_combining_mark_positioning() {
+        // "e\u{301}x": the shaper puts the base and the combining acute accent in one cluster,
+        // and positions the accent over the base with an offset and no advance of its own.
+        let mut glyphs = GlyphStore::new(4, false, false, false, false);
+        glyphs.add_glyphs_for_byte_index(
+            ByteIndex(0),
+            &[
+                GlyphData::new(1, Au::from_px(10), None, true, false),
+                GlyphData::new(
+                    2,
+                    Au::from_px(0),
+                    Some(Point2D::new(Au::from_px(-7), Au::from_px(-3))),
+                    true,
+                    true,
+                ),
+            ],
+        );
+        glyphs.add_glyph_for_byte_index(
+            ByteIndex(3),
+            'x',
+            &GlyphData::new(3, Au::from_px(8), None, true, true),
+        );
+        glyphs.finalize_changes();
+
+        let mut pen = Point2D::new(20., 30.);
+        let positioned: Vec<_> = positioned_glyphs(&glyphs, &mut pen, None).collect();
+        assert_eq!(
+            positioned,
+            vec![
+                (1, Point2D::new(20., 30.)),
+                (2, Point2D::new(23., 27.)),
+                (3, Point2D::new(30., 30.)),
+            ]
+        );
+        // The accent sits over the base rather than pushing the glyphs after it along.
+        assert_eq!(pen, Point2D::new(38., 30.));
     }
 
-    /// It reads image data from the canvas
-    /// canvas_size: The size of the canvas we're reading from
-    /// read_rect: The area of the canvas we want to read from
-    #[allow(unsafe_code)]
-    pub(crate) fn read_pixels(&mut self, read_rect: Option<Rect<u32>>) -> Snapshot {
-        let canvas_size = self.drawtarget.get_size().cast();
+    #[test]
+    fn test_missing_glyph_boxes() {
+        // Three glyphs 10px apart, the middle one of which is `.notdef`.
+        let mut glyphs = GlyphStore::new(3, false, false, false, false);
+        for (index, glyph_id) in [5, 0, 6].into_iter().enumerate() {
+            glyphs.add_glyph_for_byte_index(
+                ByteIndex(index as isize),
+                'a',
+                &GlyphData::new(glyph_id, Au::from_px(10), None, true, true),
+            );
+        }
+        glyphs.finalize_changes();
+        let start = Point2D::new(2., 20.);
 
-        if let Some(read_rect) = read_rect {
-            let canvas_rect = Rect::from_size(canvas_size);
-            if canvas_rect
-                .intersection(&read_rect)
-                .is_none_or(|rect| rect.is_empty())
-            {
-                Snapshot::empty()
-            } else {
-                self.drawtarget.snapshot().get_rect(read_rect)
+        // A box is only drawn for a `.notdef` glyph that is itself drawn.
+        assert!(missing_glyph_boxes([(&glyphs, 0..1)], start, 16.).is_none());
+        assert!(missing_glyph_boxes([(&glyphs, 2..3)], start, 16.).is_none());
+        let boxes = missing_glyph_boxes([(&glyphs, 0..usize::MAX)], start, 16.).unwrap();
+
+        // The box is hollow, and centered in the advance of the glyph above the baseline.
+        let mut canvas_data = new_canvas_data(Size2D::new(40, 40));
+        canvas_data.fill_path(
+            &boxes,
+            FillRule::Evenodd,
+            red(),
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        );
+        assert_eq!(canvas_data.get_pixel(13, 15), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(20, 15), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(17, 19), [255, 0, 0, 255]);
+        assert_eq!(canvas_data.get_pixel(17, 15), [0; 4]);
+        assert_eq!(canvas_data.get_pixel(5, 15), [0; 4]);
+        assert_eq!(canvas_data.get_pixel(25, 15), [0; 4]);
+        assert_eq!(canvas_data.get_pixel(17, 21), [0; 4]);
+    }
+
+    #[test]
+    fn test_coordinate_rounding() {
+        let fill = |rounding, transform| {
+            let mut canvas_data = new_canvas_data(Size2D::new(8, 8));
+            canvas_data.set_coordinate_rounding(rounding);
+            canvas_data.fill_rect(
+                &Rect::new(Point2D::new(1.3, 1.6), Size2D::new(2.4, 2.2)),
+                red(),
+                no_shadow(),
+                source_over(),
+                transform,
+            );
+            canvas_data
+        };
+        let covered_pixels = |canvas_data: &mut CanvasData<raqote::DrawTarget>| {
+            let mut opaque = Vec::new();
+            for y in 0..8 {
+                for x in 0..8 {
+                    match canvas_data.get_pixel(x, y)[3] {
+                        0 => {},
+                        255 => opaque.push((x, y)),
+                        alpha => panic!("pixel ({x}, {y}) is partly covered: {alpha}"),
+                    }
+                }
             }
-        } else {
-            self.drawtarget.snapshot()
-        }
+            opaque
+        };
+        let pixels = |x: std::ops::Range<i32>, y: std::ops::Range<i32>| {
+            y.flat_map(|y| x.clone().map(move |x| (x, y))).collect::<Vec<_>>()
+        };
+
+        // From (1.3, 1.6) to (3.7, 3.8), the edges snap to the pixel grid.
+        let identity = Transform2D::identity();
+        assert_eq!(
+            covered_pixels(&mut fill(CoordinateRounding::Round, identity)),
+            pixels(1..4, 2..4)
+        );
+        assert_eq!(
+            covered_pixels(&mut fill(CoordinateRounding::Floor, identity)),
+            pixels(1..3, 1..3)
+        );
+        assert_eq!(
+            covered_pixels(&mut fill(CoordinateRounding::Ceil, identity)),
+            pixels(2..4, 2..4)
+        );
+
+        // Edges are rounded after the transform, from (2.6, 3.2) to (7.4, 7.6) here.
+        assert_eq!(
+            covered_pixels(&mut fill(CoordinateRounding::Round, identity.then_scale(2., 2.))),
+            pixels(3..7, 3..8)
+        );
+
+        // By default, the edges cover the pixels they cross partly.
+        let mut canvas_data = fill(CoordinateRounding::None, identity);
+        let alpha = canvas_data.get_pixel(1, 2)[3];
+        assert!(alpha > 0 && alpha < 255);
     }
 
-    pub(crate) fn pop_clips(&mut self, clips: usize) {
-        for _ in 0..clips {
-            self.drawtarget.pop_clip();
+    #[test]
+    fn test_pixel_snapped_glyphs() {
+        // A row of glyphs with fractional advances.
+        let mut glyphs = GlyphStore::new(3, false, false, false, false);
+        for (index, character) in "abc".chars().enumerate() {
+            glyphs.add_glyph_for_byte_index(
+                ByteIndex(index as isize),
+                character,
+                &GlyphData::new(index as u32 + 1, Au::from_f32_px(6.3), None, true, true),
+            );
         }
+        glyphs.finalize_changes();
+        let transform = Transform2D::scale(1.5, 1.5).then_translate(Vector2D::new(0.2, 0.4));
+
+        let mut pen = Point2D::new(2.1, 10.3);
+        let device_origins: Vec<_> = positioned_glyphs(&glyphs, &mut pen, Some(&transform))
+            .map(|(_, origin)| transform.transform_point(origin))
+            .collect();
+        assert_eq!(device_origins.len(), 3);
+        for origin in &device_origins {
+            assert!((origin.x - origin.x.round()).abs() < 1e-4);
+            assert!((origin.y - origin.y.round()).abs() < 1e-4);
+        }
+        // The baseline lands on the same device row for every glyph of the run.
+        assert!(device_origins.iter().all(|origin| origin.y.round() == 16.));
+        // Snapping doesn't change how far the pen advances.
+        let mut unsnapped_pen = Point2D::new(2.1, 10.3);
+        positioned_glyphs(&glyphs, &mut unsnapped_pen, None).for_each(drop);
+        assert_eq!(pen, unsnapped_pen);
     }
-}
 
-impl<D: GenericDrawTarget> Drop for CanvasData<D> {
-    fn drop(&mut self) {
-        self.compositor_api.delete_image(self.image_key);
+    #[test]
+    fn test_condensed_font_size() {
+        // Text twice as wide as its maximum width is filled at half the size.
+        assert_eq!(condensed_font_size(200., 100., 20.), Some(10.));
+        // Text that fits keeps its size.
+        assert_eq!(condensed_font_size(80., 100., 20.), None);
+        // Text isn't condensed below 5 pixels, and overflows its maximum width then.
+        assert_eq!(condensed_font_size(1000., 10., 20.), Some(5.));
+        assert_eq!(condensed_font_size(200., 10., 5.), None);
     }
-}
 
-const HANGING_BASELINE_DEFAULT: f32 = 0.8;
-const IDEOGRAPHIC_BASELINE_DEFAULT: f32 = 0.5;
+    #[test]
+    fn test_last_text_truncated() {
+        let mut canvas_data = new_canvas_data(Size2D::new(4, 4));
+        canvas_data.diagnostics.last_text_truncated = true;
+        // Filling text resets the flag, even when there is no font to draw it with.
+        canvas_data.fill_text(
+            "Hello".to_owned(),
+            0.,
+            0.,
+            Some(1.),
+            false,
+            red(),
+            TextOptions {
+                font: None,
+                align: TextAlign::Start,
+                baseline: TextBaseline::Alphabetic,
+                baseline_shift: None,
+                line_height: None,
+                tab_size: None,
+                decoration: None,
+                glyph_range: None,
+                shaping_tags: ShapingTags::default(),
+            },
+            no_shadow(),
+            source_over(),
+            Transform2D::identity(),
+        )
+        .unwrap();
+        assert!(!canvas_data.last_text_truncated());
+    }
 
-/// It writes an image to the destination target
-/// draw_target: the destination target where the image_data will be copied
-/// image_data: Pixel information of the image to be written. It takes RGBA8
-/// image_size: The size of the image to be written
-/// dest_rect: Area of the destination target where the pixels will be copied
-/// smoothing_enabled: It determines if smoothing is applied to the image result
-/// premultiply: Determines whenever the image data should be premultiplied or not
-fn write_image<DrawTarget: GenericDrawTarget>(
-    draw_target: &mut DrawTarget,
-    snapshot: Snapshot,
-    dest_rect: Rect<f64>,
-    smoothing_enabled: bool,
-    composition_options: CompositionOptions,
-    transform: Transform2D<f32>,
-) {
-    if snapshot.size().is_empty() {
-        return;
+    #[test]
+    fn test_unhinted_glyphs_render_reproducibly() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../fonts/tests/support/dejavu-fonts-ttf-2.37/ttf/DejaVuSans.ttf"
+        );
+        let data = Arc::new(std::fs::read(path).unwrap());
+        let font = font_kit::font::Font::from_bytes(data, 0).unwrap();
+        let ids: Vec<u32> = "Hinting"
+            .chars()
+            .map(|character| font.glyph_for_char(character).unwrap())
+            .collect();
+        let positions: Vec<_> = (0..ids.len())
+            .map(|index| Point2D::new(2. + index as f32 * 6.5, 12.))
+            .collect();
+        let black = raqote::Source::Solid(raqote::SolidSource::from_unpremultiplied_argb(
+            255, 0, 0, 0,
+        ));
+
+        // Small text drawn on canvases of different sizes, and the top left 64x16 pixels
+        // that they share.
+        let render = |size: i32, hinting: GlyphHinting| {
+            let mut draw_target = raqote::DrawTarget::new(size, size);
+            draw_glyphs_with_hinting(
+                &mut draw_target,
+                &font,
+                9.,
+                &ids,
+                &positions,
+                hinting,
+                &black,
+                &raqote::DrawOptions::new(),
+            );
+            draw_target
+                .get_data()
+                .chunks(size as usize)
+                .take(16)
+                .map(|row| row[..64].to_vec())
+                .collect::<Vec<_>>()
+        };
+
+        let unhinted = render(64, GlyphHinting::None);
+        assert!(unhinted.iter().flatten().any(|pixel| *pixel != 0));
+        assert_eq!(unhinted, render(128, GlyphHinting::None));
+        // raqote does not hint glyphs by default.
+        assert_eq!(unhinted, render(64, GlyphHinting::Platform));
+        let hinted = render(64, GlyphHinting::Full);
+        assert!(hinted.iter().flatten().any(|pixel| *pixel != 0));
     }
 
-    let image_rect = Rect::new(Point2D::zero(), snapshot.size().cast());
+    #[test]
+    fn test_tab_stops() {
+        let metrics = FontMetrics {
+            space_advance: Au::from_px(5),
+            ..FontMetrics::empty()
+        };
+        assert_eq!(tab_width(TabSize::Pixels(32.), &metrics), 32.);
+        let width = tab_width(TabSize::Spaces(8.), &metrics);
+        assert_eq!(width, 40.);
 
-    // From spec https://html.spec.whatwg.org/multipage/#dom-context-2d-drawimage
-    // When scaling up, if the imageSmoothingEnabled attribute is set to true, the user agent should attempt
-    // to apply a smoothing algorithm to the image data when it is scaled.
-    // Otherwise, the image must be rendered using nearest-neighbor interpolation.
-    let filter = if smoothing_enabled {
-        Filter::Bilinear
-    } else {
-        Filter::Nearest
-    };
+        // Lay out the cells of tab-separated rows as filling them does: every cell after a
+        // tab starts at a tab stop, whatever the width of the cells before it.
+        let columns = |cell_widths: &[f32]| {
+            let mut x = 0.;
+            let mut starts = Vec::new();
+            for (index, cell_width) in cell_widths.iter().enumerate() {
+                if index > 0 {
+                    x += tab_advance(x, width);
+                }
+                starts.push(x);
+                x += cell_width;
+            }
+            starts
+        };
+        assert_eq!(columns(&[13., 22., 7.]), vec![0., 40., 80.]);
+        assert_eq!(columns(&[39., 1., 7.]), vec![0., 40., 80.]);
+        // A cell as wide as a column moves the next one to the stop after, and a wider one
+        // skips the stops it covers.
+        assert_eq!(columns(&[40., 7.]), vec![0., 80.]);
+        assert_eq!(columns(&[95., 7.]), vec![0., 120.]);
+        assert_eq!(columns(&[0., 0.]), vec![0., 40.]);
 
-    let source_surface = draw_target
-        .create_source_surface_from_data(snapshot)
-        .unwrap();
+        assert_eq!(tab_advance(10., 0.), 0.);
+    }
 
-    draw_target.draw_surface(
-        source_surface,
-        dest_rect,
-        image_rect,
-        filter,
-        composition_options,
-        transform,
-    );
-}
+    #[test]
+    fn test_fixed_line_height() {
+        let canvas_data = new_canvas_data(Size2D::new(1, 1));
+        // A short font and one whose ascent and descent exceed the line box.
+        let short = FontMetrics {
+            ascent: Au::from_px(8),
+            descent: Au::from_px(2),
+            ..FontMetrics::empty()
+        };
+        let tall = FontMetrics {
+            ascent: Au::from_px(14),
+            descent: Au::from_px(4),
+            ..FontMetrics::empty()
+        };
+        let line_box = |metrics: &FontMetrics, y, baseline| {
+            let text_options = TextOptions {
+                font: None,
+                align: TextAlign::Start,
+                baseline,
+                baseline_shift: None,
+                line_height: Some(16.),
+                tab_size: None,
+                decoration: None,
+                glyph_range: None,
+                shaping_tags: ShapingTags::default(),
+            };
+            let anchor = canvas_data.find_anchor_point_for_line_of_text(
+                0.,
+                y,
+                metrics,
+                0.,
+                false,
+                &text_options,
+            );
+            let half_leading = (16. - (metrics.ascent + metrics.descent).to_f32_px()) / 2.;
+            (
+                anchor.y - metrics.ascent.to_f32_px() - half_leading,
+                anchor.y + metrics.descent.to_f32_px() + half_leading,
+            )
+        };
 
-pub(crate) trait RectToi32 {
-    fn ceil(&self) -> Rect<f64>;
-}
+        // Rows of either font start where the previous row ends.
+        for (row, metrics) in [&short, &tall, &short, &tall].into_iter().enumerate() {
+            let top = row as f32 * 16.;
+            assert_eq!(line_box(metrics, top, TextBaseline::Top), (top, top + 16.));
+            assert_eq!(
+                line_box(metrics, top + 16., TextBaseline::Bottom),
+                (top, top + 16.)
+            );
+            assert_eq!(
+                line_box(metrics, top + 8., TextBaseline::Middle),
+                (top, top + 16.)
+            );
+        }
 
-impl RectToi32 for Rect<f64> {
-    fn ceil(&self) -> Rect<f64> {
-        Rect::new(
-            Point2D::new(self.origin.x.ceil(), self.origin.y.ceil()),
-            Size2D::new(self.size.width.ceil(), self.size.height.ceil()),
-        )
+        // The baseline sits within the line box as the half-leading places it.
+        let baseline = |metrics| {
+            let text_options = TextOptions {
+                font: None,
+                align: TextAlign::Start,
+                baseline: TextBaseline::Top,
+                baseline_shift: None,
+                line_height: Some(16.),
+                tab_size: None,
+                decoration: None,
+                glyph_range: None,
+                shaping_tags: ShapingTags::default(),
+            };
+            canvas_data
+                .find_anchor_point_for_line_of_text(0., 0., metrics, 0., false, &text_options)
+                .y
+        };
+        assert_eq!(baseline(&short), 11.);
+        assert_eq!(baseline(&tall), 13.);
     }
-}
 
-fn replace_ascii_whitespace(text: String) -> String {
-    text.chars()
-        .map(|c| match c {
-            ' ' | '\t' | '\n' | '\r' | '\x0C' => '\x20',
-            _ => c,
-        })
-        .collect()
+    #[test]
+    fn test_baseline_shift() {
+        let canvas_data = new_canvas_data(Size2D::new(1, 1));
+        let metrics = FontMetrics {
+            ascent: Au::from_px(8),
+            descent: Au::from_px(2),
+            ..FontMetrics::empty()
+        };
+        let anchor = |baseline, baseline_shift| {
+            let text_options = TextOptions {
+                font: None,
+                align: TextAlign::Center,
+                baseline,
+                baseline_shift,
+                line_height: None,
+                tab_size: None,
+                decoration: None,
+                glyph_range: None,
+                shaping_tags: ShapingTags::default(),
+            };
+            canvas_data.find_anchor_point_for_line_of_text(
+                10.,
+                20.,
+                &metrics,
+                6.,
+                false,
+                &text_options,
+            )
+        };
+
+        // A positive shift raises the text like a superscript and a negative one lowers it
+        // like a subscript, on top of whichever baseline is resolved.
+        for baseline in [
+            TextBaseline::Alphabetic,
+            TextBaseline::Top,
+            TextBaseline::Middle,
+            TextBaseline::Bottom,
+        ] {
+            let unshifted = anchor(baseline, None);
+            assert_eq!(anchor(baseline, Some(0.)), unshifted);
+            assert_eq!(anchor(baseline, Some(4.)), unshifted - Vector2D::new(0., 4.));
+            assert_eq!(anchor(baseline, Some(-3.)), unshifted + Vector2D::new(0., 3.));
+        }
+        assert_eq!(anchor(TextBaseline::Alphabetic, Some(4.)), Point2D::new(7., 16.));
+    }
+
+    #[test]
+    fn test_text_decoration_lines() {
+        let metrics = FontMetrics {
+            ascent: Au::from_px(8),
+            underline_offset: Au::from_px(-2),
+            underline_size: Au::from_px(1),
+            strikeout_offset: Au::from_px(3),
+            strikeout_size: Au::from_px(2),
+            ..FontMetrics::empty()
+        };
+        let decoration = TextDecoration {
+            underline: true,
+            overline: true,
+            line_through: true,
+            thickness: None,
+            style: TextDecorationStyle::Solid,
+        };
+        let lines = text_decoration_lines(&decoration, &metrics, Point2D::new(5., 20.), 10.);
+        let positions: Vec<_> = lines
+            .iter()
+            .map(|(path, line_options)| (path.bounding_box(), line_options.width))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                // The underline starts 2px below the baseline.
+                (Rect::new(Point2D::new(5., 22.5), Size2D::new(10., 0.)), 1.),
+                // The overline starts at the ascent.
+                (Rect::new(Point2D::new(5., 12.5), Size2D::new(10., 0.)), 1.),
+                (Rect::new(Point2D::new(5., 18.), Size2D::new(10., 0.)), 2.),
+            ]
+        );
+
+        let dashed = TextDecoration {
+            underline: true,
+            overline: false,
+            line_through: false,
+            thickness: Some(3.),
+            style: TextDecorationStyle::Dashed,
+        };
+        let lines = text_decoration_lines(&dashed, &metrics, Point2D::new(5., 20.), 10.);
+        assert_eq!(lines.len(), 1);
+        let (path, line_options) = &lines[0];
+        assert_eq!(path.bounding_box().origin.y, 23.5);
+        assert_eq!(line_options.width, 3.);
+        assert_eq!(line_options.dash, vec![6., 6.]);
+    }
 }
@@ -6,23 +6,29 @@
 #![allow(unreachable_patterns)]
 
 use std::borrow::ToOwned;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::sync::Arc;
-use std::{f32, thread};
+use std::time::{Duration, Instant};
+use std::{f32, mem, thread};
 
+use base::cross_process_instant::CrossProcessInstant;
 use canvas_traits::ConstellationCanvasMsg;
 use canvas_traits::canvas::*;
 use compositing_traits::CrossProcessCompositorApi;
-use crossbeam_channel::{Sender, select, unbounded};
-use euclid::default::{Rect, Size2D, Transform2D};
+use crossbeam_channel::{Sender, at, never, select, unbounded};
+use devtools_traits::TimelineMarker;
+use euclid::default::{Point2D, Rect, Size2D, Transform2D};
 use fonts::{FontContext, SystemFontServiceProxy};
-use ipc_channel::ipc::{self, IpcSender};
+use ipc_channel::ipc::{self, IpcSender, IpcSharedMemory};
 use ipc_channel::router::ROUTER;
 use log::warn;
 use net_traits::ResourceThreads;
-use pixels::Snapshot;
-use webrender_api::ImageKey;
+use pixels::{EncodedImageType, Snapshot, exif_orientation};
+use style::color::AbsoluteColor;
+use webrender_api::{ImageKey, ImageRendering};
 
+use crate::backend::GenericDrawTarget;
 use crate::canvas_data::*;
 
 pub struct CanvasPaintThread {
@@ -30,13 +36,51 @@ pub struct CanvasPaintThread {
     next_canvas_id: CanvasId,
     compositor_api: CrossProcessCompositorApi,
     font_context: Arc<FontContext>,
+    /// Where to report a [`TimelineMarker`] for each processed [`Canvas2dMsg`], if
+    /// anything is listening.
+    timeline_marker_sender: Option<IpcSender<Option<TimelineMarker>>>,
+    /// The work held back for each paused canvas until it is resumed.
+    paused_canvases: HashMap<CanvasId, PausedCanvas>,
+    /// The number of messages processed for each canvas in a frame of the thread, or `None`
+    /// for no limit.
+    operation_budget: Option<usize>,
+    /// The number of messages processed for each canvas in the current frame.
+    operations: HashMap<CanvasId, usize>,
+    /// When the current frame started, as of which canvases got a fresh operation budget.
+    frame_start: Instant,
+    /// Messages of canvases that went over the operation budget, in the order they were
+    /// received, to be processed in later frames.
+    deferred_messages: HashMap<CanvasId, VecDeque<Canvas2dMsg>>,
+    /// The images decoded for [`Canvas2dMsg::DrawEncodedImage`], shared by every canvas.
+    image_cache: ImageCache,
+    /// The seed that the gradient fills of new canvases are dithered with, if any.
+    render_seed: Option<u64>,
+    /// Where to report the [`CanvasError`]s raised by the messages of each canvas, if
+    /// anything is listening.
+    error_sender: Option<Sender<(CanvasId, CanvasError)>>,
+}
+
+/// How long a frame of the thread lasts when no [`ConstellationCanvasMsg::Vsync`] starts the
+/// next one earlier, as for a display refreshing 60 times a second.
+const FRAME_DURATION: Duration = Duration::from_millis(16);
+
+/// The work held back for a paused canvas, as set with [`CanvasMsg::SetPaused`].
+#[derive(Default)]
+struct PausedCanvas {
+    /// The messages that don't expect a reply, in the order they were received.
+    messages: Vec<Canvas2dMsg>,
+    /// Whether the image of the canvas was updated while it was paused, and if so, whether
+    /// any of the updates asked to bypass frame pacing.
+    image_update: Option<bool>,
 }
 
 impl CanvasPaintThread {
-    fn new(
+    pub(crate) fn new(
         compositor_api: CrossProcessCompositorApi,
         system_font_service: Arc<SystemFontServiceProxy>,
         resource_threads: ResourceThreads,
+        image_cache_capacity: usize,
+        error_sender: Option<Sender<(CanvasId, CanvasError)>>,
     ) -> CanvasPaintThread {
         CanvasPaintThread {
             canvases: HashMap::new(),
@@ -47,37 +91,115 @@ impl CanvasPaintThread {
                 compositor_api,
                 resource_threads,
             )),
+            timeline_marker_sender: None,
+            paused_canvases: HashMap::new(),
+            operation_budget: None,
+            operations: HashMap::new(),
+            frame_start: Instant::now(),
+            deferred_messages: HashMap::new(),
+            image_cache: ImageCache::new(image_cache_capacity),
+            render_seed: None,
+            error_sender,
         }
     }
 
     /// Creates a new `CanvasPaintThread` and returns an `IpcSender` to
-    /// communicate with it.
+    /// communicate with it. Failed operations are reported on `error_sender`, along with
+    /// the id of their canvas, when given, in addition to being logged. Up to
+    /// `image_cache_capacity` decoded images are kept for canvases that draw them again.
     pub fn start(
         compositor_api: CrossProcessCompositorApi,
         system_font_service: Arc<SystemFontServiceProxy>,
         resource_threads: ResourceThreads,
+        image_cache_capacity: usize,
+        error_sender: Option<Sender<(CanvasId, CanvasError)>>,
     ) -> (Sender<ConstellationCanvasMsg>, IpcSender<CanvasMsg>) {
+        let (create_sender, ipc_sender, _) = Self::start_with_local_sender(
+            compositor_api,
+            system_font_service,
+            resource_threads,
+            image_cache_capacity,
+            error_sender,
+        );
+        (create_sender, ipc_sender)
+    }
+
+    /// Like [`CanvasPaintThread::start`], but additionally returns a crossbeam `Sender`
+    /// feeding the same message queue as the `IpcSender`. Callers living in the same
+    /// process as the canvas thread can use it to skip IPC serialization and the router
+    /// hop. Messages sent on either channel are processed identically.
+    pub fn start_with_local_sender(
+        compositor_api: CrossProcessCompositorApi,
+        system_font_service: Arc<SystemFontServiceProxy>,
+        resource_threads: ResourceThreads,
+        image_cache_capacity: usize,
+        error_sender: Option<Sender<(CanvasId, CanvasError)>>,
+    ) -> (
+        Sender<ConstellationCanvasMsg>,
+        IpcSender<CanvasMsg>,
+        Sender<CanvasMsg>,
+    ) {
         let (ipc_sender, ipc_receiver) = ipc::channel::<CanvasMsg>().unwrap();
-        let msg_receiver = ROUTER.route_ipc_receiver_to_new_crossbeam_receiver(ipc_receiver);
+        let (local_sender, msg_receiver) = unbounded();
+        ROUTER.route_ipc_receiver_to_crossbeam_sender(ipc_receiver, local_sender.clone());
         let (create_sender, create_receiver) = unbounded();
         thread::Builder::new()
             .name("Canvas".to_owned())
             .spawn(move || {
                 let mut canvas_paint_thread = CanvasPaintThread::new(
-                    compositor_api, system_font_service, resource_threads);
+                    compositor_api,
+                    system_font_service,
+                    resource_threads,
+                    image_cache_capacity,
+                    error_sender,
+                );
                 loop {
+                    if canvas_paint_thread.frame_ended() {
+                        canvas_paint_thread.begin_frame();
+                    }
+                    // Canvases that went over their budget wait for the next frame, when
+                    // nothing else wakes the thread up before it.
+                    let next_frame = match canvas_paint_thread.next_frame() {
+                        Some(next_frame) => at(next_frame),
+                        None => never(),
+                    };
                     select! {
                         recv(msg_receiver) -> msg => {
                             match msg {
                                 Ok(CanvasMsg::Canvas2d(message, canvas_id)) => {
-                                    canvas_paint_thread.process_canvas_2d_message(message, canvas_id);
+                                    canvas_paint_thread.handle_canvas_2d_message(message, canvas_id);
                                 },
                                 Ok(CanvasMsg::Close(canvas_id)) => {
-                                    canvas_paint_thread.canvases.remove(&canvas_id);
+                                    canvas_paint_thread.close_canvas(canvas_id);
                                 },
                                 Ok(CanvasMsg::Recreate(size, canvas_id)) => {
+                                    // Deferred messages were sent before recreating, and some
+                                    // may expect a reply.
+                                    canvas_paint_thread.process_deferred_messages(canvas_id, usize::MAX);
+                                    // Recreating resets the canvas, so buffered messages would
+                                    // have no visible effect.
+                                    if let Some(paused) =
+                                        canvas_paint_thread.paused_canvases.get_mut(&canvas_id)
+                                    {
+                                        paused.messages.clear();
+                                    }
+                                    let size = size.map(|size| {
+                                        CanvasPaintThread::size_limits().clamp(size)
+                                    });
                                     canvas_paint_thread.canvas(canvas_id).recreate(size);
                                 },
+                                Ok(CanvasMsg::SetPaused(canvas_id, paused)) => {
+                                    canvas_paint_thread.set_paused(canvas_id, paused);
+                                },
+                                Ok(CanvasMsg::BlendSnapshots(base, overlay, composition_operation, alpha, sender)) => {
+                                    let composition_options = CompositionOptions { alpha, composition_operation };
+                                    let Some(blended) = Canvas::blend_snapshots(base.to_owned(), overlay.to_owned(), composition_options) else {
+                                        continue;
+                                    };
+                                    if let Err(error) = sender.send(blended.as_ipc()) {
+                                        warn!("Could not send blended snapshots: {error}");
+                                    }
+                                },
                                 Err(e) => {
                                     warn!("Error on CanvasPaintThread receive ({})", e);
                                 },
@@ -85,8 +207,37 @@ impl CanvasPaintThread {
                         }
                         recv(create_receiver) -> msg => {
                             match msg {
-                                Ok(ConstellationCanvasMsg::Create { sender: creator, size }) => {
-                                    creator.send(canvas_paint_thread.create_canvas(size)).unwrap();
+                                Ok(ConstellationCanvasMsg::Create {
+                                    sender: creator,
+                                    size,
+                                    options,
+                                }) => {
+                                    let result = canvas_paint_thread.create_canvas(size, options);
+                                    creator.send(result).unwrap();
+                                },
+                                Ok(ConstellationCanvasMsg::SetTimelineMarkerSender(sender)) => {
+                                    canvas_paint_thread.timeline_marker_sender = sender;
+                                },
+                                Ok(ConstellationCanvasMsg::SetOperationBudget(budget)) => {
+                                    canvas_paint_thread.set_operation_budget(budget);
+                                },
+                                Ok(ConstellationCanvasMsg::SetRenderSeed(seed)) => {
+                                    canvas_paint_thread.render_seed = seed;
+                                },
+                                Ok(ConstellationCanvasMsg::GetMemoryUsage(sender)) => {
+                                    let usage = canvas_paint_thread
+                                        .canvases
+                                        .values()
+                                        .map(Canvas::memory_usage)
+                                        .sum();
+                                    let _ = sender.send(usage);
+                                },
+                                Ok(ConstellationCanvasMsg::GetImageCacheSize(sender)) => {
+                                    let _ = sender.send(canvas_paint_thread.image_cache.len());
+                                },
+                                Ok(ConstellationCanvasMsg::Vsync) => {
+                                    canvas_paint_thread.begin_frame();
+                                    canvas_paint_thread.canvases.values_mut().for_each(Canvas::vsync);
                                 },
                                 Ok(ConstellationCanvasMsg::Exit(exit_sender)) => {
                                     let _ = exit_sender.send(());
@@ -98,40 +249,264 @@ impl CanvasPaintThread {
                                 },
                             }
                         }
+                        recv(next_frame) -> _ => {},
                     }
                 }
             })
             .expect("Thread spawning failed");
 
-        (create_sender, ipc_sender)
+        (create_sender, ipc_sender, local_sender)
+    }
+
+    /// The largest canvas that the active backend can allocate.
+    pub fn size_limits() -> CanvasSizeLimits {
+        Canvas::size_limits().unwrap_or_default()
     }
 
-    pub fn create_canvas(&mut self, size: Size2D<u64>) -> Option<(CanvasId, ImageKey)> {
+    /// Creates a canvas of `size` CSS pixels, whose backing store has the
+    /// [`CanvasCreationOptions::scale_factor`] device pixels per CSS pixel in each
+    /// direction, reduced by the [`CanvasCreationOptions::render_scale`]. Drawing messages
+    /// are given in CSS pixels whatever the resolution of the backing store. A canvas larger
+    /// than the [`Self::size_limits`] is empty.
+    pub fn create_canvas(
+        &mut self,
+        size: Size2D<u64>,
+        options: CanvasCreationOptions,
+    ) -> Result<(CanvasId, ImageKey), CanvasError> {
+        let mut canvas = Canvas::new(
+            Self::size_limits().clamp(size),
+            options.scale_factor,
+            options.render_scale,
+            self.compositor_api.clone(),
+            self.font_context.clone(),
+            options.y_flipped,
+            options.blending_space,
+        )?;
         let canvas_id = self.next_canvas_id;
         self.next_canvas_id.0 += 1;
-
-        let canvas = Canvas::new(size, self.compositor_api.clone(), self.font_context.clone())?;
+        if let Some(seed) = self.render_seed {
+            canvas.set_gradient_dither(Some(seed), DitherSpace::default());
+        }
         let image_key = canvas.image_key();
         self.canvases.insert(canvas_id, canvas);
 
-        Some((canvas_id, image_key))
+        Ok((canvas_id, image_key))
+    }
+
+    /// Buffers `message` if its canvas is paused and it doesn't expect a reply, otherwise
+    /// processes it, unless the canvas went over its operation budget. A message that
+    /// expects a reply is answered after the messages buffered before it are applied, while
+    /// the upload of the image of a paused canvas waits for it to resume.
+    pub(crate) fn handle_canvas_2d_message(&mut self, message: Canvas2dMsg, canvas_id: CanvasId) {
+        let Some(paused) = self.paused_canvases.get_mut(&canvas_id) else {
+            return self.process_canvas_2d_message_within_budget(message, canvas_id);
+        };
+        if let Canvas2dMsg::UpdateImage(sender, immediate) = message {
+            paused.image_update = Some(paused.image_update.unwrap_or_default() || immediate);
+            if let Err(error) = sender.send(()) {
+                warn!("Could not reply to image update: {error}");
+            }
+            return;
+        }
+        if !message.expects_reply() {
+            paused.messages.push(message);
+            return;
+        }
+        for message in mem::take(&mut paused.messages) {
+            self.process_canvas_2d_message_within_budget(message, canvas_id);
+        }
+        self.process_canvas_2d_message_within_budget(message, canvas_id);
+    }
+
+    /// Forgets about a canvas, once the messages deferred for it are processed, as they were
+    /// sent before it was closed and some may expect a reply.
+    pub(crate) fn close_canvas(&mut self, canvas_id: CanvasId) {
+        self.process_deferred_messages(canvas_id, usize::MAX);
+        self.canvases.remove(&canvas_id);
+        self.paused_canvases.remove(&canvas_id);
+    }
+
+    fn set_paused(&mut self, canvas_id: CanvasId, paused: bool) {
+        if paused {
+            self.paused_canvases.entry(canvas_id).or_default();
+            return;
+        }
+        let Some(paused) = self.paused_canvases.remove(&canvas_id) else {
+            return;
+        };
+        for message in paused.messages {
+            self.process_canvas_2d_message_within_budget(message, canvas_id);
+        }
+        if let Some(immediate) = paused.image_update {
+            self.canvas(canvas_id).update_image_rendering(immediate);
+        }
+    }
+
+    pub(crate) fn set_operation_budget(&mut self, budget: Option<usize>) {
+        self.operation_budget = budget;
+        // Without a budget, nothing waits for later frames.
+        if budget.is_none() {
+            self.begin_frame();
+        }
+    }
+
+    /// Processes `message` if its canvas has operations left in its budget and no earlier
+    /// deferred messages, and defers it otherwise. Drawing one canvas onto another is never
+    /// deferred, but waits for the deferred messages of both canvases to be processed, so
+    /// that it applies after every message that they received before it.
+    fn process_canvas_2d_message_within_budget(
+        &mut self,
+        message: Canvas2dMsg,
+        canvas_id: CanvasId,
+    ) {
+        if let Some(budget) = self.operation_budget {
+            if let Canvas2dMsg::DrawImageInOther(other_canvas_id, ..) = message {
+                self.process_deferred_messages(canvas_id, usize::MAX);
+                self.process_deferred_messages(other_canvas_id, usize::MAX);
+                *self.operations.entry(canvas_id).or_default() += 1;
+                return self.process_canvas_2d_message_with_marker(message, canvas_id);
+            }
+            let operations = self.operations.entry(canvas_id).or_default();
+            if *operations >= budget || self.deferred_messages.contains_key(&canvas_id) {
+                self.deferred_messages
+                    .entry(canvas_id)
+                    .or_default()
+                    .push_back(message);
+                return;
+            }
+            *operations += 1;
+        }
+        self.process_canvas_2d_message_with_marker(message, canvas_id);
+    }
+
+    pub(crate) fn has_deferred_messages(&self) -> bool {
+        !self.deferred_messages.is_empty()
+    }
+
+    /// Whether a canvas may have gone over its operation budget in a frame that lasted long
+    /// enough for a new one to start, for want of a vsync to start it.
+    pub(crate) fn frame_ended(&self) -> bool {
+        self.operation_budget.is_some() && self.frame_start.elapsed() >= FRAME_DURATION
+    }
+
+    /// When the next frame has to start, if canvases wait for it with deferred messages.
+    pub(crate) fn next_frame(&self) -> Option<Instant> {
+        self.has_deferred_messages()
+            .then(|| self.frame_start + FRAME_DURATION)
+    }
+
+    /// Starts a new frame of the thread: every canvas gets a fresh operation budget, which
+    /// goes to its deferred messages first.
+    pub(crate) fn begin_frame(&mut self) {
+        self.frame_start = Instant::now();
+        self.operations.clear();
+        let budget = self.operation_budget.unwrap_or(usize::MAX);
+        let canvas_ids: Vec<_> = self.deferred_messages.keys().copied().collect();
+        for canvas_id in canvas_ids {
+            let processed = self.process_deferred_messages(canvas_id, budget);
+            self.operations.insert(canvas_id, processed);
+        }
+    }
+
+    /// Processes up to `count` of the deferred messages of the canvas, in order, and
+    /// returns how many there were.
+    fn process_deferred_messages(&mut self, canvas_id: CanvasId, count: usize) -> usize {
+        for processed in 0..count {
+            let Some(deferred) = self.deferred_messages.get_mut(&canvas_id) else {
+                return processed;
+            };
+            let Some(message) = deferred.pop_front() else {
+                return processed;
+            };
+            if deferred.is_empty() {
+                self.deferred_messages.remove(&canvas_id);
+            }
+            self.process_canvas_2d_message_with_marker(message, canvas_id);
+        }
+        count
+    }
+
+    /// Processes `message`, adding the time it took to the frame time of its canvas and
+    /// reporting a [`TimelineMarker`] that spans it when a consumer is attached. Without one,
+    /// no marker timestamps are taken.
+    fn process_canvas_2d_message_with_marker(&mut self, message: Canvas2dMsg, canvas_id: CanvasId) {
+        // Messages are only timed while devtools records the timeline.
+        if self.timeline_marker_sender.is_none() {
+            let result = self.process_canvas_2d_message(message, canvas_id);
+            return self.report_result(canvas_id, result);
+        }
+
+        let start = Instant::now();
+        let name: &'static str = (&message).into();
+        let marker = TimelineMarker::start(format!("Canvas2d::{name}"));
+        let result = self.process_canvas_2d_message(message, canvas_id);
+        let marker = marker.end();
+        self.report_result(canvas_id, result);
+        self.canvas(canvas_id).add_frame_time(start);
+        if let Some(sender) = &self.timeline_marker_sender {
+            if sender.send(Some(marker)).is_err() {
+                // The consumer went away; stop paying for markers.
+                self.timeline_marker_sender = None;
+            }
+        }
+    }
+
+    /// Logs the error of a processed message, if any, and reports it to the embedder if it
+    /// asked for errors when starting the canvas thread.
+    fn report_result(&mut self, canvas_id: CanvasId, result: Result<(), CanvasError>) {
+        let Err(error) = result else {
+            return;
+        };
+        warn!("{error:?}");
+        if let Some(sender) = &self.error_sender {
+            if sender.send((canvas_id, error)).is_err() {
+                // The embedder went away; errors are only logged from now on.
+                self.error_sender = None;
+            }
+        }
     }
 
-    fn process_canvas_2d_message(&mut self, message: Canvas2dMsg, canvas_id: CanvasId) {
+    fn process_canvas_2d_message(
+        &mut self,
+        mut message: Canvas2dMsg,
+        canvas_id: CanvasId,
+    ) -> Result<(), CanvasError> {
+        // Queries leave the canvas as it is, and a wrapped message counts when it is
+        // processed itself.
+        if !message.expects_reply() {
+            self.canvas(canvas_id).advance_sequence();
+        }
+        // `DrawImageInOther` draws on the other canvas, so it is that canvas's scale that
+        // applies.
+        let drawing_canvas_id = match message {
+            Canvas2dMsg::DrawImageInOther(other_canvas_id, ..) => other_canvas_id,
+            _ => canvas_id,
+        };
+        if let Some(transform) = message.transform_mut() {
+            *transform = transform.then(&self.canvas(drawing_canvas_id).default_transform());
+        }
+        if message.uses_surface() {
+            self.canvas(canvas_id).restore_reclaimed_surface();
+            if drawing_canvas_id != canvas_id {
+                self.canvas(drawing_canvas_id).restore_reclaimed_surface();
+            }
+        }
+
         match message {
             Canvas2dMsg::FillText(
                 text,
                 x,
                 y,
                 max_width,
-                style,
                 is_rtl,
                 text_options,
                 shadow_options,
-                composition_options,
                 transform,
             ) => {
-                self.canvas(canvas_id).fill_text(
+                let canvas = self.canvas(canvas_id);
+                let style = canvas.fill_style();
+                let composition_options = canvas.composition_options();
+                canvas.fill_text(
                     text,
                     x,
                     y,
@@ -142,47 +517,51 @@ impl CanvasPaintThread {
                     shadow_options,
                     composition_options,
                     transform,
-                );
+                )?;
+            },
+            Canvas2dMsg::FillRect(rect, shadow_options, transform) => {
+                let canvas = self.canvas(canvas_id);
+                let style = canvas.fill_style();
+                let composition_options = canvas.composition_options();
+                canvas.fill_rect(&rect, style, shadow_options, composition_options, transform);
             },
-            Canvas2dMsg::FillRect(rect, style, shadow_options, composition_options, transform) => {
-                self.canvas(canvas_id).fill_rect(
+            Canvas2dMsg::StrokeRect(rect, line_options, shadow_options, transform) => {
+                let canvas = self.canvas(canvas_id);
+                let style = canvas.stroke_style();
+                let composition_options = canvas.composition_options();
+                let line_options = canvas.user_space_line_options(line_options, &transform);
+                canvas.stroke_rect(
                     &rect,
                     style,
+                    line_options,
                     shadow_options,
                     composition_options,
                     transform,
                 );
             },
-            Canvas2dMsg::StrokeRect(
-                rect,
-                style,
-                line_options,
-                shadow_options,
-                composition_options,
-                transform,
-            ) => {
-                self.canvas(canvas_id).stroke_rect(
-                    &rect,
+            Canvas2dMsg::ClearRect(ref rect, transform) => {
+                self.canvas(canvas_id).clear_rect(rect, transform)
+            },
+            Canvas2dMsg::FillPath(path, fill_rule, shadow_options, transform) => {
+                let canvas = self.canvas(canvas_id);
+                let style = canvas.fill_style();
+                let composition_options = canvas.composition_options();
+                canvas.fill_path(
+                    &path,
+                    fill_rule,
                     style,
-                    line_options,
                     shadow_options,
                     composition_options,
                     transform,
                 );
             },
-            Canvas2dMsg::ClearRect(ref rect, transform) => {
-                self.canvas(canvas_id).clear_rect(rect, transform)
-            },
-            Canvas2dMsg::FillPath(
-                style,
-                path,
-                fill_rule,
-                shadow_options,
-                composition_options,
-                transform,
-            ) => {
-                self.canvas(canvas_id).fill_path(
+            Canvas2dMsg::FillPathWithHoles(path, holes, fill_rule, shadow_options, transform) => {
+                let canvas = self.canvas(canvas_id);
+                let style = canvas.fill_style();
+                let composition_options = canvas.composition_options();
+                canvas.fill_path_with_holes(
                     &path,
+                    &holes,
                     fill_rule,
                     style,
                     shadow_options,
@@ -190,15 +569,12 @@ impl CanvasPaintThread {
                     transform,
                 );
             },
-            Canvas2dMsg::StrokePath(
-                path,
-                style,
-                line_options,
-                shadow_options,
-                composition_options,
-                transform,
-            ) => {
-                self.canvas(canvas_id).stroke_path(
+            Canvas2dMsg::StrokePath(path, line_options, shadow_options, transform) => {
+                let canvas = self.canvas(canvas_id);
+                let style = canvas.stroke_style();
+                let composition_options = canvas.composition_options();
+                let line_options = canvas.user_space_line_options(line_options, &transform);
+                canvas.stroke_path(
                     &path,
                     style,
                     line_options,
@@ -211,79 +587,435 @@ impl CanvasPaintThread {
                 self.canvas(canvas_id)
                     .clip_path(&path, fill_rule, transform);
             },
+            Canvas2dMsg::TrimGlyphCache => self.canvas(canvas_id).trim_glyph_cache(),
+            Canvas2dMsg::GetGlyphCacheSize(sender) => {
+                let size = self.canvas(canvas_id).glyph_cache_size();
+                if let Err(error) = sender.send(size) {
+                    warn!("Could not send canvas glyph cache size: {error}");
+                }
+            },
+            Canvas2dMsg::SetViewport(viewport) => self.canvas(canvas_id).set_viewport(viewport),
+            Canvas2dMsg::GetLastModified(sender) => {
+                let last_modified = self.canvas(canvas_id).last_modified();
+                if let Err(error) = sender.send(last_modified) {
+                    warn!("Could not send canvas last modification time: {error}");
+                }
+            },
+            Canvas2dMsg::SetClip(path, fill_rule, transform) => {
+                self.canvas(canvas_id)
+                    .set_clip(path.as_ref(), fill_rule, transform);
+            },
+            Canvas2dMsg::SetRadialClip(center, radius, fill_rule, transform) => {
+                self.canvas(canvas_id)
+                    .set_radial_clip(center, radius, fill_rule, transform);
+            },
             Canvas2dMsg::DrawImage(
                 snapshot,
                 dest_rect,
                 source_rect,
                 smoothing_enabled,
                 shadow_options,
-                composition_options,
                 transform,
-            ) => self.canvas(canvas_id).draw_image(
-                snapshot.to_owned(),
+            ) => {
+                let canvas = self.canvas(canvas_id);
+                let composition_options = canvas.composition_options();
+                canvas.draw_image(
+                    snapshot.to_owned(),
+                    dest_rect,
+                    source_rect,
+                    smoothing_enabled,
+                    shadow_options,
+                    composition_options,
+                    transform,
+                )
+            },
+            Canvas2dMsg::DrawImageRounded(
+                snapshot,
                 dest_rect,
-                source_rect,
+                radii,
                 smoothing_enabled,
                 shadow_options,
-                composition_options,
                 transform,
-            ),
-            Canvas2dMsg::DrawEmptyImage(
-                image_size,
+            ) => {
+                let canvas = self.canvas(canvas_id);
+                let composition_options = canvas.composition_options();
+                canvas.draw_image_rounded(
+                    snapshot.to_owned(),
+                    dest_rect,
+                    radii,
+                    smoothing_enabled,
+                    shadow_options,
+                    composition_options,
+                    transform,
+                )
+            },
+            Canvas2dMsg::CompositeWithMask(source, mask, dest_rect, transform) => {
+                let canvas = self.canvas(canvas_id);
+                let composition_options = canvas.composition_options();
+                canvas.composite_with_mask(
+                    source.to_owned(),
+                    mask.to_owned(),
+                    dest_rect,
+                    composition_options,
+                    transform,
+                )
+            },
+            Canvas2dMsg::DrawEncodedImage(
+                bytes,
+                image_type,
+                orientation,
                 dest_rect,
                 source_rect,
+                smoothing_enabled,
                 shadow_options,
-                composition_options,
                 transform,
-            ) => self.canvas(canvas_id).draw_image(
-                Snapshot::cleared(image_size),
+            ) => {
+                let decoded = self
+                    .image_cache
+                    .get_or_decode(&bytes, image_type, orientation, || {
+                        Snapshot::decode(&bytes, &image_type).map(|mut snapshot| {
+                            if orientation == ImageOrientation::FromImage {
+                                if let Some(orientation) = exif_orientation(&bytes, &image_type) {
+                                    snapshot.orient(orientation);
+                                }
+                            }
+                            snapshot
+                        })
+                    });
+                let snapshot = match decoded {
+                    Ok(snapshot) => snapshot,
+                    Err(error) => {
+                        return Err(CanvasError::OperationFailed(format!(
+                            "Could not decode the image drawn on the canvas: {error}"
+                        )));
+                    },
+                };
+                let canvas = self.canvas(canvas_id);
+                let composition_options = canvas.composition_options();
+                canvas.draw_image(
+                    snapshot,
+                    dest_rect,
+                    source_rect,
+                    smoothing_enabled,
+                    shadow_options,
+                    composition_options,
+                    transform,
+                )
+            },
+            Canvas2dMsg::DrawEmptyImage(
+                image_size,
                 dest_rect,
                 source_rect,
-                false,
                 shadow_options,
-                composition_options,
                 transform,
-            ),
+            ) => {
+                let canvas = self.canvas(canvas_id);
+                let composition_options = canvas.composition_options();
+                canvas.draw_image(
+                    Snapshot::cleared(image_size),
+                    dest_rect,
+                    source_rect,
+                    false,
+                    shadow_options,
+                    composition_options,
+                    transform,
+                )
+            },
             Canvas2dMsg::DrawImageInOther(
                 other_canvas_id,
                 dest_rect,
                 source_rect,
                 smoothing,
                 shadow_options,
-                composition_options,
                 transform,
             ) => {
-                let snapshot = self
-                    .canvas(canvas_id)
-                    .read_pixels(Some(source_rect.to_u32()));
-                self.canvas(other_canvas_id).draw_image(
-                    snapshot,
-                    dest_rect,
-                    source_rect,
-                    smoothing,
-                    shadow_options,
-                    composition_options,
-                    transform,
-                );
+                // The canvas drawn on is the one whose state applies.
+                let composition_options = self.canvas(other_canvas_id).composition_options();
+                if other_canvas_id == canvas_id {
+                    self.canvas(canvas_id).draw_image_from_self(
+                        dest_rect,
+                        source_rect,
+                        smoothing,
+                        shadow_options,
+                        composition_options,
+                        transform,
+                    );
+                } else {
+                    let snapshot = self.canvas(canvas_id).snapshot(Some(source_rect.to_u32()));
+                    self.canvas(other_canvas_id).draw_image(
+                        snapshot,
+                        dest_rect,
+                        source_rect,
+                        smoothing,
+                        shadow_options,
+                        composition_options,
+                        transform,
+                    );
+                }
             },
-            Canvas2dMsg::MeasureText(text, sender, text_options) => {
-                let metrics = self.canvas(canvas_id).measure_text(text, text_options);
+            Canvas2dMsg::MeasureText(text, sender, is_rtl, text_options) => {
+                let metrics = self
+                    .canvas(canvas_id)
+                    .measure_text(text, is_rtl, text_options);
                 sender.send(metrics).unwrap();
             },
-            Canvas2dMsg::GetImageData(dest_rect, sender) => {
-                let snapshot = self.canvas(canvas_id).read_pixels(dest_rect);
+            Canvas2dMsg::WrapText(text, max_width, sender, is_rtl, text_options) => {
+                let line_breaks =
+                    self.canvas(canvas_id)
+                        .wrap_text(text, max_width, is_rtl, text_options);
+                sender.send(line_breaks).unwrap();
+            },
+            Canvas2dMsg::GetImageData(dest_rect, encoding, sender) => {
+                let mut snapshot = self.canvas(canvas_id).read_pixels(dest_rect);
+                if encoding == ColorEncoding::Linear {
+                    snapshot.convert_srgb_to_linear();
+                }
                 sender.send(snapshot.as_ipc()).unwrap();
             },
-            Canvas2dMsg::PutImageData(rect, snapshot) => {
-                self.canvas(canvas_id)
-                    .put_image_data(snapshot.to_owned(), rect);
+            Canvas2dMsg::GetImageDataInTiles(dest_rect, encoding, tile_height, sender) => {
+                self.canvas(canvas_id).read_pixels_in_tiles(
+                    dest_rect,
+                    tile_height,
+                    &mut |image_size, y, mut snapshot| {
+                        if encoding == ColorEncoding::Linear {
+                            snapshot.convert_srgb_to_linear();
+                        }
+                        let tile = ImageDataTile {
+                            image_size,
+                            y,
+                            pixels: snapshot.as_ipc(),
+                        };
+                        sender.send(tile).unwrap();
+                    },
+                )
+            },
+            Canvas2dMsg::GetImageDataInColorSpaces(dest_rect, color_spaces, sender) => {
+                let snapshots = self
+                    .canvas(canvas_id)
+                    .read_pixels_in_color_spaces(dest_rect, &color_spaces);
+                sender
+                    .send(snapshots.into_iter().map(Snapshot::as_ipc).collect())
+                    .unwrap();
+            },
+            Canvas2dMsg::GetPixel(x, y, sender) => {
+                let pixel = self.canvas(canvas_id).get_pixel(x, y);
+                sender.send(pixel).unwrap();
+            },
+            Canvas2dMsg::GetImageDataAsFloat(dest_rect, encoding, sender) => {
+                let image_data = self
+                    .canvas(canvas_id)
+                    .read_pixels_as_float(dest_rect, encoding);
+                if let Err(error) = sender.send(image_data) {
+                    warn!("Could not send canvas pixels as floats: {error}");
+                }
+            },
+            Canvas2dMsg::GetAlphaMask(rect, sender) => {
+                let alpha_mask = self.canvas(canvas_id).read_alpha_mask(rect);
+                if let Err(error) = sender.send(alpha_mask) {
+                    warn!("Could not send canvas alpha mask: {error}");
+                }
+            },
+            Canvas2dMsg::GetImageDataAligned(dest_rect, alignment, sender) => {
+                let image_data = self
+                    .canvas(canvas_id)
+                    .read_pixels_aligned(dest_rect, alignment);
+                if let Err(error) = sender.send(image_data) {
+                    warn!("Could not send aligned canvas pixels: {error}");
+                }
+            },
+            Canvas2dMsg::GetHistogram(rect, sender) => {
+                let histogram = self.canvas(canvas_id).histogram(rect);
+                if let Err(error) = sender.send(histogram) {
+                    warn!("Could not send canvas histogram: {error}");
+                }
+            },
+            Canvas2dMsg::PutImageData(rect, encoding, snapshot) => {
+                let mut snapshot = snapshot.to_owned();
+                if encoding == ColorEncoding::Linear {
+                    snapshot.convert_linear_to_srgb();
+                }
+                self.canvas(canvas_id).put_image_data(snapshot, rect);
+            },
+            Canvas2dMsg::SaveRegion(rect, sender) => {
+                let id = self.canvas(canvas_id).save_region(rect);
+                if let Err(error) = sender.send(id) {
+                    warn!("Could not send saved canvas region: {error}");
+                }
+            },
+            Canvas2dMsg::RestoreRegion(rect, id) => self.canvas(canvas_id).restore_region(rect, id),
+            Canvas2dMsg::ReleaseRegion(id) => self.canvas(canvas_id).release_region(id),
+            Canvas2dMsg::BeginTransaction => self.canvas(canvas_id).begin_transaction(),
+            Canvas2dMsg::CommitTransaction => self.canvas(canvas_id).commit_transaction(),
+            Canvas2dMsg::RollbackTransaction => self.canvas(canvas_id).rollback_transaction(),
+            Canvas2dMsg::PresentFrame(snapshot) => {
+                self.canvas(canvas_id).present_frame(snapshot.to_owned())
             },
-            Canvas2dMsg::UpdateImage(sender) => {
-                self.canvas(canvas_id).update_image_rendering();
+            Canvas2dMsg::UpdateImage(sender, immediate) => {
+                self.canvas(canvas_id).update_image_rendering(immediate);
                 sender.send(()).unwrap();
             },
             Canvas2dMsg::PopClips(clips) => self.canvas(canvas_id).pop_clips(clips),
+            Canvas2dMsg::GetClipBounds(sender) => {
+                let bounds = self.canvas(canvas_id).clip_bounds();
+                if let Err(error) = sender.send(bounds) {
+                    warn!("Could not send canvas clip bounds: {error}");
+                }
+            },
+            Canvas2dMsg::GetWindingNumber(path, x, y, sender) => {
+                if let Err(error) = sender.send(path.winding_number(x, y)) {
+                    warn!("Could not send path winding number: {error}");
+                }
+            },
+            Canvas2dMsg::SetFillStyle(style) => self.canvas(canvas_id).set_fill_style(style),
+            Canvas2dMsg::SetGlobalAlpha(alpha) => self.canvas(canvas_id).set_global_alpha(alpha),
+            Canvas2dMsg::SetGlobalCompositeOperation(operation) => self
+                .canvas(canvas_id)
+                .set_global_composite_operation(operation),
+            Canvas2dMsg::SetStrokeStyle(style) => self.canvas(canvas_id).set_stroke_style(style),
+            Canvas2dMsg::SetFillGradientStopColors(colors) => {
+                self.canvas(canvas_id).set_fill_gradient_stop_colors(colors)
+            },
+            Canvas2dMsg::SetStrokeGradientStopColors(colors) => {
+                self.canvas(canvas_id).set_stroke_gradient_stop_colors(colors)
+            },
+            Canvas2dMsg::DrawFocusRing(path, transform) => {
+                self.canvas(canvas_id).draw_focus_ring(&path, transform)
+            },
+            Canvas2dMsg::SetFocusRingStyle(style) => {
+                self.canvas(canvas_id).set_focus_ring_style(style)
+            },
+            Canvas2dMsg::SetLowPowerMode(low_power) => {
+                self.canvas(canvas_id).set_low_power_mode(low_power)
+            },
+            Canvas2dMsg::SetImageSmoothing(enabled, quality) => {
+                self.canvas(canvas_id).set_image_smoothing(enabled, quality)
+            },
+            Canvas2dMsg::SetGlyphHinting(hinting) => {
+                self.canvas(canvas_id).set_glyph_hinting(hinting)
+            },
+            Canvas2dMsg::SetTextPixelSnapping(snapping) => {
+                self.canvas(canvas_id).set_text_pixel_snapping(snapping)
+            },
+            Canvas2dMsg::SetCoordinateRounding(rounding) => {
+                self.canvas(canvas_id).set_coordinate_rounding(rounding)
+            },
+            Canvas2dMsg::GetRenderingState(sender) => {
+                let state = self.canvas(canvas_id).rendering_state();
+                if let Err(error) = sender.send(state) {
+                    warn!("Could not send canvas rendering state: {error}");
+                }
+            },
+            Canvas2dMsg::SetFallbackFontFamilies(families) => {
+                self.canvas(canvas_id).set_fallback_font_families(families)
+            },
+            Canvas2dMsg::SetTextGamma(gamma) => self.canvas(canvas_id).set_text_gamma(gamma),
+            Canvas2dMsg::SetShapeGamma(gamma) => self.canvas(canvas_id).set_shape_gamma(gamma),
+            Canvas2dMsg::SetGamutMapping(gamut_mapping) => {
+                self.canvas(canvas_id).set_gamut_mapping(gamut_mapping)
+            },
+            Canvas2dMsg::SetGradientInterpolation(interpolation) => {
+                self.canvas(canvas_id).set_gradient_interpolation(interpolation)
+            },
+            Canvas2dMsg::SetMaxShadowBlur(max_shadow_blur) => {
+                self.canvas(canvas_id).set_max_shadow_blur(max_shadow_blur)
+            },
+            Canvas2dMsg::SetDevicePixelLineWidths(device_pixel_line_widths) => self
+                .canvas(canvas_id)
+                .set_device_pixel_line_widths(device_pixel_line_widths),
+            Canvas2dMsg::SetBatchOpaqueFills(batch) => {
+                self.canvas(canvas_id).set_batch_opaque_fills(batch)
+            },
+            Canvas2dMsg::SetGradientDither(seed, space) => {
+                self.canvas(canvas_id).set_gradient_dither(seed, space)
+            },
+            Canvas2dMsg::SetFilter(filter) => self.canvas(canvas_id).set_filter(filter),
+            Canvas2dMsg::BeginLayer(composition_options) => {
+                self.canvas(canvas_id).begin_layer(composition_options)
+            },
+            Canvas2dMsg::EndLayer => self.canvas(canvas_id).end_layer(),
+            Canvas2dMsg::BlurRegion(rect, std_deviation) => {
+                self.canvas(canvas_id).blur_region(rect, std_deviation)
+            },
+            Canvas2dMsg::ApplyColorMatrix(rect, matrix) => {
+                self.canvas(canvas_id).apply_color_matrix(rect, &matrix)
+            },
+            Canvas2dMsg::FadeRegion(rect, factor) => {
+                self.canvas(canvas_id).fade_region(rect, factor)
+            },
+            Canvas2dMsg::Posterize(rect, levels) => self.canvas(canvas_id).posterize(rect, levels),
+            Canvas2dMsg::FlipSurface(axis) => self.canvas(canvas_id).flip_surface(axis),
+            Canvas2dMsg::DebugDump(path) => {
+                if cfg!(debug_assertions) {
+                    self.canvas(canvas_id).debug_dump(&path)?;
+                } else {
+                    warn!("Canvas debug dumps are only written by debug builds");
+                }
+            },
+            Canvas2dMsg::GetMemoryUsage(sender) => {
+                sender.send(self.canvas(canvas_id).memory_usage()).unwrap()
+            },
+            Canvas2dMsg::GetDrawStats(sender) => {
+                let stats = self.canvas(canvas_id).draw_stats();
+                if let Err(error) = sender.send(stats) {
+                    warn!("Could not send canvas draw statistics: {error}");
+                }
+            },
+            Canvas2dMsg::ResetDrawStats => self.canvas(canvas_id).reset_draw_stats(),
+            Canvas2dMsg::GetAcceleration(sender) => {
+                let acceleration = self.canvas(canvas_id).acceleration();
+                if let Err(error) = sender.send(acceleration) {
+                    warn!("Could not send canvas acceleration: {error}");
+                }
+            },
+            Canvas2dMsg::GetLastDrawOutOfBounds(sender) => {
+                let out_of_bounds = self.canvas(canvas_id).last_draw_out_of_bounds();
+                if let Err(error) = sender.send(out_of_bounds) {
+                    warn!("Could not send whether the last canvas draw was out of bounds: {error}");
+                }
+            },
+            Canvas2dMsg::GetLastTextTruncated(sender) => {
+                let truncated = self.canvas(canvas_id).last_text_truncated();
+                if let Err(error) = sender.send(truncated) {
+                    warn!("Could not send whether the last canvas text was truncated: {error}");
+                }
+            },
+            Canvas2dMsg::IsFullyOpaque(sender) => {
+                let opaque = self.canvas(canvas_id).is_fully_opaque();
+                if let Err(error) = sender.send(opaque) {
+                    warn!("Could not send whether the canvas is fully opaque: {error}");
+                }
+            },
+            Canvas2dMsg::SetFramePacing(frame_pacing) => {
+                self.canvas(canvas_id).set_frame_pacing(frame_pacing)
+            },
+            Canvas2dMsg::ReclaimSurface => self.canvas(canvas_id).reclaim_surface(),
+            Canvas2dMsg::SetImageRendering(image_rendering) => {
+                self.canvas(canvas_id).set_image_rendering(image_rendering)
+            },
+            Canvas2dMsg::SetPremultipliedUpload(premultiplied) => self
+                .canvas(canvas_id)
+                .set_premultiplied_upload(premultiplied),
+            Canvas2dMsg::GetSequence(sender) => {
+                let sequence = self.canvas(canvas_id).sequence();
+                if let Err(error) = sender.send(sequence) {
+                    warn!("Could not send canvas sequence number: {error}");
+                }
+            },
+            Canvas2dMsg::GetGeneration(sender) => {
+                let generation = self.canvas(canvas_id).generation();
+                if let Err(error) = sender.send(generation) {
+                    warn!("Could not send canvas generation: {error}");
+                }
+            },
+            Canvas2dMsg::ReportErrors(message, sender) => {
+                let result = self.process_canvas_2d_message(*message, canvas_id);
+                if let Err(error) = sender.send(result.clone()) {
+                    warn!("Could not send canvas operation result: {error}");
+                }
+                return result;
+            },
         }
+        Ok(())
     }
 
     fn canvas(&mut self, canvas_id: CanvasId) -> &mut Canvas {
@@ -291,75 +1023,280 @@ impl CanvasPaintThread {
     }
 }
 
-#[allow(clippy::large_enum_variant)]
-enum Canvas {
-    #[cfg(feature = "raqote")]
-    Raqote(CanvasData<raqote::DrawTarget>),
-    #[cfg(feature = "vello")]
-    Vello(CanvasData<crate::vello_backend::VelloDrawTarget>),
-    #[cfg(feature = "vello_cpu")]
-    VelloCPU(CanvasData<crate::vello_cpu_backend::VelloCPUDrawTarget>),
+/// The number of decoded images that a canvas thread keeps, unless it is started with
+/// another capacity.
+pub const DEFAULT_IMAGE_CACHE_CAPACITY: usize = 32;
+
+/// Images decoded for [`Canvas2dMsg::DrawEncodedImage`], so that drawing the same encoded
+/// image again doesn't decode it again. When the cache is full, the image that was drawn
+/// the least recently is evicted.
+pub(crate) struct ImageCache {
+    capacity: usize,
+    /// The cached images, from the least to the most recently drawn.
+    entries: VecDeque<CachedImage>,
 }
 
-impl Canvas {
-    fn new(
-        size: Size2D<u64>,
-        compositor_api: CrossProcessCompositorApi,
-        font_context: Arc<FontContext>,
-    ) -> Option<Self> {
-        match servo_config::pref!(dom_canvas_backend)
-            .to_lowercase()
-            .as_str()
-        {
-            #[cfg(feature = "raqote")]
-            "" | "auto" | "raqote" => Some(Self::Raqote(CanvasData::new(
-                size,
+struct CachedImage {
+    /// A hash of `bytes`, to compare images without comparing all of their bytes.
+    hash: u64,
+    bytes: IpcSharedMemory,
+    image_type: EncodedImageType,
+    orientation: ImageOrientation,
+    snapshot: Snapshot,
+}
+
+impl ImageCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        ImageCache {
+            capacity,
+            entries: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn position(
+        &self,
+        hash: u64,
+        bytes: &[u8],
+        image_type: EncodedImageType,
+        orientation: ImageOrientation,
+    ) -> Option<usize> {
+        self.entries.iter().position(|entry| {
+            entry.hash == hash &&
+                entry.image_type == image_type &&
+                entry.orientation == orientation &&
+                *entry.bytes == *bytes
+        })
+    }
+
+    pub(crate) fn contains(
+        &self,
+        bytes: &[u8],
+        image_type: EncodedImageType,
+        orientation: ImageOrientation,
+    ) -> bool {
+        self.position(hash_bytes(bytes), bytes, image_type, orientation).is_some()
+    }
+
+    /// Returns the image decoded from `bytes`, running `decode` and caching what it returns
+    /// if it isn't cached yet. Images that fail to decode aren't cached.
+    pub(crate) fn get_or_decode<E>(
+        &mut self,
+        bytes: &IpcSharedMemory,
+        image_type: EncodedImageType,
+        orientation: ImageOrientation,
+        decode: impl FnOnce() -> Result<Snapshot, E>,
+    ) -> Result<Snapshot, E> {
+        let hash = hash_bytes(bytes);
+        if let Some(index) = self.position(hash, bytes, image_type, orientation) {
+            let entry = self.entries.remove(index).expect("Cached image should exist");
+            let snapshot = entry.snapshot.clone();
+            self.entries.push_back(entry);
+            return Ok(snapshot);
+        }
+
+        let snapshot = decode()?;
+        if self.capacity == 0 {
+            return Ok(snapshot);
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(CachedImage {
+            hash,
+            bytes: bytes.clone(),
+            image_type,
+            orientation,
+            snapshot: snapshot.clone(),
+        });
+        Ok(snapshot)
+    }
+}
+
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Canvas {
+    #[cfg(feature = "raqote")]
+    Raqote(CanvasData<raqote::DrawTarget>),
+    #[cfg(feature = "vello")]
+    Vello(CanvasData<crate::vello_backend::VelloDrawTarget>),
+    #[cfg(feature = "vello_cpu")]
+    VelloCPU(CanvasData<crate::vello_cpu_backend::VelloCPUDrawTarget>),
+}
+
+/// Calls a method of the [`CanvasData`] of a [`Canvas`], whichever its backend.
+macro_rules! forward_to_canvas_data {
+    ($canvas:ident.$method:ident($($argument:expr),* $(,)?)) => {
+        match $canvas {
+            #[cfg(feature = "raqote")]
+            Canvas::Raqote(canvas_data) => canvas_data.$method($($argument),*),
+            #[cfg(feature = "vello")]
+            Canvas::Vello(canvas_data) => canvas_data.$method($($argument),*),
+            #[cfg(feature = "vello_cpu")]
+            Canvas::VelloCPU(canvas_data) => canvas_data.$method($($argument),*),
+            _ => unreachable!(),
+        }
+    };
+}
+
+impl Canvas {
+    fn new(
+        size: Size2D<u64>,
+        scale_factor: u32,
+        render_scale: f32,
+        compositor_api: CrossProcessCompositorApi,
+        font_context: Arc<FontContext>,
+        y_flipped: bool,
+        blending_space: BlendingSpace,
+    ) -> Result<Self, CanvasError> {
+        match servo_config::pref!(dom_canvas_backend)
+            .to_lowercase()
+            .as_str()
+        {
+            #[cfg(feature = "raqote")]
+            "" | "auto" | "raqote" => CanvasData::new(
+                size,
+                scale_factor,
+                render_scale,
                 compositor_api,
                 font_context,
-            ))),
+                y_flipped,
+                blending_space,
+            )
+            .map(Self::Raqote),
             #[cfg(feature = "vello")]
-            "" | "auto" | "vello" => Some(Self::Vello(CanvasData::new(
+            "" | "auto" | "vello" => CanvasData::new(
                 size,
+                scale_factor,
+                render_scale,
                 compositor_api,
                 font_context,
-            ))),
+                y_flipped,
+                blending_space,
+            )
+            .map(Self::Vello),
             #[cfg(feature = "vello_cpu")]
-            "" | "auto" | "vello_cpu" => Some(Self::VelloCPU(CanvasData::new(
+            "" | "auto" | "vello_cpu" => CanvasData::new(
                 size,
+                scale_factor,
+                render_scale,
                 compositor_api,
                 font_context,
-            ))),
+                y_flipped,
+                blending_space,
+            )
+            .map(Self::VelloCPU),
             s => {
                 warn!("Unknown 2D canvas backend: `{s}`");
-                None
+                Err(CanvasError::CreationFailed(size))
             },
         }
     }
 
-    fn image_key(&self) -> ImageKey {
-        match self {
+    fn size_limits() -> Option<CanvasSizeLimits> {
+        match servo_config::pref!(dom_canvas_backend)
+            .to_lowercase()
+            .as_str()
+        {
             #[cfg(feature = "raqote")]
-            Canvas::Raqote(canvas_data) => canvas_data.image_key(),
+            "" | "auto" | "raqote" => {
+                Some(<raqote::DrawTarget as GenericDrawTarget>::size_limits())
+            },
             #[cfg(feature = "vello")]
-            Canvas::Vello(canvas_data) => canvas_data.image_key(),
+            "" | "auto" | "vello" => Some(crate::vello_backend::VelloDrawTarget::size_limits()),
             #[cfg(feature = "vello_cpu")]
-            Canvas::VelloCPU(canvas_data) => canvas_data.image_key(),
-            _ => unreachable!(),
+            "" | "auto" | "vello_cpu" => {
+                Some(crate::vello_cpu_backend::VelloCPUDrawTarget::size_limits())
+            },
+            _ => None,
         }
     }
 
-    fn pop_clips(&mut self, clips: usize) {
-        match self {
+    /// Composites `overlay` onto `base` with the active backend, as
+    /// [`CanvasMsg::BlendSnapshots`] asks.
+    fn blend_snapshots(
+        base: Snapshot,
+        overlay: Snapshot,
+        composition_options: CompositionOptions,
+    ) -> Option<Snapshot> {
+        match servo_config::pref!(dom_canvas_backend)
+            .to_lowercase()
+            .as_str()
+        {
             #[cfg(feature = "raqote")]
-            Canvas::Raqote(canvas_data) => canvas_data.pop_clips(clips),
+            "" | "auto" | "raqote" => Some(blend_snapshots::<raqote::DrawTarget>(
+                base,
+                overlay,
+                composition_options,
+            )),
             #[cfg(feature = "vello")]
-            Canvas::Vello(canvas_data) => canvas_data.pop_clips(clips),
+            "" | "auto" | "vello" => Some(blend_snapshots::<
+                crate::vello_backend::VelloDrawTarget,
+            >(base, overlay, composition_options)),
             #[cfg(feature = "vello_cpu")]
-            Canvas::VelloCPU(canvas_data) => canvas_data.pop_clips(clips),
-            _ => unreachable!(),
+            "" | "auto" | "vello_cpu" => Some(blend_snapshots::<
+                crate::vello_cpu_backend::VelloCPUDrawTarget,
+            >(base, overlay, composition_options)),
+            _ => None,
         }
     }
 
+    fn default_transform(&self) -> Transform2D<f32> {
+        forward_to_canvas_data!(self.default_transform())
+    }
+
+    fn image_key(&self) -> ImageKey {
+        forward_to_canvas_data!(self.image_key())
+    }
+
+    fn fill_style(&self) -> FillOrStrokeStyle {
+        forward_to_canvas_data!(self.fill_style())
+    }
+
+    fn set_fill_style(&mut self, style: FillOrStrokeStyle) {
+        forward_to_canvas_data!(self.set_fill_style(style))
+    }
+
+    fn composition_options(&self) -> CompositionOptions {
+        forward_to_canvas_data!(self.composition_options())
+    }
+
+    fn set_global_alpha(&mut self, alpha: f64) {
+        forward_to_canvas_data!(self.set_global_alpha(alpha))
+    }
+
+    fn set_global_composite_operation(&mut self, operation: CompositionOrBlending) {
+        forward_to_canvas_data!(self.set_global_composite_operation(operation))
+    }
+
+    fn stroke_style(&self) -> FillOrStrokeStyle {
+        forward_to_canvas_data!(self.stroke_style())
+    }
+
+    fn set_stroke_style(&mut self, style: FillOrStrokeStyle) {
+        forward_to_canvas_data!(self.set_stroke_style(style))
+    }
+
+    fn set_fill_gradient_stop_colors(&mut self, colors: Vec<AbsoluteColor>) {
+        forward_to_canvas_data!(self.set_fill_gradient_stop_colors(colors))
+    }
+
+    fn set_stroke_gradient_stop_colors(&mut self, colors: Vec<AbsoluteColor>) {
+        forward_to_canvas_data!(self.set_stroke_gradient_stop_colors(colors))
+    }
+
+    fn pop_clips(&mut self, clips: usize) {
+        forward_to_canvas_data!(self.pop_clips(clips))
+    }
+
     fn fill_text(
         &mut self,
         text: String,
@@ -372,49 +1309,19 @@ impl Canvas {
         shadow_options: ShadowOptions,
         composition_options: CompositionOptions,
         transform: Transform2D<f32>,
-    ) {
-        match self {
-            #[cfg(feature = "raqote")]
-            Canvas::Raqote(canvas_data) => canvas_data.fill_text(
-                text,
-                x,
-                y,
-                max_width,
-                is_rtl,
-                style,
-                text_options,
-                shadow_options,
-                composition_options,
-                transform,
-            ),
-            #[cfg(feature = "vello")]
-            Canvas::Vello(canvas_data) => canvas_data.fill_text(
-                text,
-                x,
-                y,
-                max_width,
-                is_rtl,
-                style,
-                text_options,
-                shadow_options,
-                composition_options,
-                transform,
-            ),
-            #[cfg(feature = "vello_cpu")]
-            Canvas::VelloCPU(canvas_data) => canvas_data.fill_text(
-                text,
-                x,
-                y,
-                max_width,
-                is_rtl,
-                style,
-                text_options,
-                shadow_options,
-                composition_options,
-                transform,
-            ),
-            _ => unreachable!(),
-        }
+    ) -> Result<(), CanvasError> {
+        forward_to_canvas_data!(self.fill_text(
+            text,
+            x,
+            y,
+            max_width,
+            is_rtl,
+            style,
+            text_options,
+            shadow_options,
+            composition_options,
+            transform,
+        ))
     }
 
     fn fill_rect(
@@ -425,21 +1332,13 @@ impl Canvas {
         composition_options: CompositionOptions,
         transform: Transform2D<f32>,
     ) {
-        match self {
-            #[cfg(feature = "raqote")]
-            Canvas::Raqote(canvas_data) => {
-                canvas_data.fill_rect(rect, style, shadow_options, composition_options, transform)
-            },
-            #[cfg(feature = "vello")]
-            Canvas::Vello(canvas_data) => {
-                canvas_data.fill_rect(rect, style, shadow_options, composition_options, transform)
-            },
-            #[cfg(feature = "vello_cpu")]
-            Canvas::VelloCPU(canvas_data) => {
-                canvas_data.fill_rect(rect, style, shadow_options, composition_options, transform)
-            },
-            _ => unreachable!(),
-        }
+        forward_to_canvas_data!(self.fill_rect(
+            rect,
+            style,
+            shadow_options,
+            composition_options,
+            transform,
+        ))
     }
 
     fn stroke_rect(
@@ -451,36 +1350,36 @@ impl Canvas {
         composition_options: CompositionOptions,
         transform: Transform2D<f32>,
     ) {
-        match self {
-            #[cfg(feature = "raqote")]
-            Canvas::Raqote(canvas_data) => canvas_data.stroke_rect(
-                rect,
-                style,
-                line_options,
-                shadow_options,
-                composition_options,
-                transform,
-            ),
-            #[cfg(feature = "vello")]
-            Canvas::Vello(canvas_data) => canvas_data.stroke_rect(
-                rect,
-                style,
-                line_options,
-                shadow_options,
-                composition_options,
-                transform,
-            ),
-            #[cfg(feature = "vello_cpu")]
-            Canvas::VelloCPU(canvas_data) => canvas_data.stroke_rect(
-                rect,
-                style,
-                line_options,
-                shadow_options,
-                composition_options,
-                transform,
-            ),
-            _ => unreachable!(),
-        }
+        forward_to_canvas_data!(self.stroke_rect(
+            rect,
+            style,
+            line_options,
+            shadow_options,
+            composition_options,
+            transform,
+        ))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn fill_path_with_holes(
+        &mut self,
+        path: &Path,
+        holes: &[usize],
+        fill_rule: FillRule,
+        style: FillOrStrokeStyle,
+        shadow_options: ShadowOptions,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) {
+        forward_to_canvas_data!(self.fill_path_with_holes(
+            path,
+            holes,
+            fill_rule,
+            style,
+            shadow_options,
+            composition_options,
+            transform,
+        ))
     }
 
     fn fill_path(
@@ -492,36 +1391,14 @@ impl Canvas {
         composition_options: CompositionOptions,
         transform: Transform2D<f32>,
     ) {
-        match self {
-            #[cfg(feature = "raqote")]
-            Canvas::Raqote(canvas_data) => canvas_data.fill_path(
-                path,
-                fill_rule,
-                style,
-                shadow_options,
-                composition_options,
-                transform,
-            ),
-            #[cfg(feature = "vello")]
-            Canvas::Vello(canvas_data) => canvas_data.fill_path(
-                path,
-                fill_rule,
-                style,
-                shadow_options,
-                composition_options,
-                transform,
-            ),
-            #[cfg(feature = "vello_cpu")]
-            Canvas::VelloCPU(canvas_data) => canvas_data.fill_path(
-                path,
-                fill_rule,
-                style,
-                shadow_options,
-                composition_options,
-                transform,
-            ),
-            _ => unreachable!(),
-        }
+        forward_to_canvas_data!(self.fill_path(
+            path,
+            fill_rule,
+            style,
+            shadow_options,
+            composition_options,
+            transform,
+        ))
     }
 
     fn stroke_path(
@@ -533,48 +1410,18 @@ impl Canvas {
         composition_options: CompositionOptions,
         transform: Transform2D<f32>,
     ) {
-        match self {
-            #[cfg(feature = "raqote")]
-            Canvas::Raqote(canvas_data) => canvas_data.stroke_path(
-                path,
-                style,
-                line_options,
-                shadow_options,
-                composition_options,
-                transform,
-            ),
-            #[cfg(feature = "vello")]
-            Canvas::Vello(canvas_data) => canvas_data.stroke_path(
-                path,
-                style,
-                line_options,
-                shadow_options,
-                composition_options,
-                transform,
-            ),
-            #[cfg(feature = "vello_cpu")]
-            Canvas::VelloCPU(canvas_data) => canvas_data.stroke_path(
-                path,
-                style,
-                line_options,
-                shadow_options,
-                composition_options,
-                transform,
-            ),
-            _ => unreachable!(),
-        }
+        forward_to_canvas_data!(self.stroke_path(
+            path,
+            style,
+            line_options,
+            shadow_options,
+            composition_options,
+            transform,
+        ))
     }
 
     fn clear_rect(&mut self, rect: &Rect<f32>, transform: Transform2D<f32>) {
-        match self {
-            #[cfg(feature = "raqote")]
-            Canvas::Raqote(canvas_data) => canvas_data.clear_rect(rect, transform),
-            #[cfg(feature = "vello")]
-            Canvas::Vello(canvas_data) => canvas_data.clear_rect(rect, transform),
-            #[cfg(feature = "vello_cpu")]
-            Canvas::VelloCPU(canvas_data) => canvas_data.clear_rect(rect, transform),
-            _ => unreachable!(),
-        }
+        forward_to_canvas_data!(self.clear_rect(rect, transform))
     }
 
     fn draw_image(
@@ -587,110 +1434,1849 @@ impl Canvas {
         composition_options: CompositionOptions,
         transform: Transform2D<f32>,
     ) {
-        match self {
-            #[cfg(feature = "raqote")]
-            Canvas::Raqote(canvas_data) => canvas_data.draw_image(
-                snapshot,
-                dest_rect,
-                source_rect,
-                smoothing_enabled,
-                shadow_options,
-                composition_options,
-                transform,
-            ),
-            #[cfg(feature = "vello")]
-            Canvas::Vello(canvas_data) => canvas_data.draw_image(
-                snapshot,
-                dest_rect,
-                source_rect,
-                smoothing_enabled,
-                shadow_options,
-                composition_options,
-                transform,
-            ),
-            #[cfg(feature = "vello_cpu")]
-            Canvas::VelloCPU(canvas_data) => canvas_data.draw_image(
-                snapshot,
-                dest_rect,
-                source_rect,
-                smoothing_enabled,
-                shadow_options,
-                composition_options,
-                transform,
-            ),
-            _ => unreachable!(),
-        }
+        forward_to_canvas_data!(self.draw_image(
+            snapshot,
+            dest_rect,
+            source_rect,
+            smoothing_enabled,
+            shadow_options,
+            composition_options,
+            transform,
+        ))
+    }
+
+    fn draw_image_rounded(
+        &mut self,
+        snapshot: Snapshot,
+        dest_rect: Rect<f64>,
+        radii: [Size2D<f64>; 4],
+        smoothing_enabled: bool,
+        shadow_options: ShadowOptions,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) {
+        forward_to_canvas_data!(self.draw_image_rounded(
+            snapshot,
+            dest_rect,
+            radii,
+            smoothing_enabled,
+            shadow_options,
+            composition_options,
+            transform,
+        ))
+    }
+
+    fn composite_with_mask(
+        &mut self,
+        source: Snapshot,
+        mask: Snapshot,
+        dest_rect: Rect<f64>,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) {
+        forward_to_canvas_data!(self.composite_with_mask(
+            source,
+            mask,
+            dest_rect,
+            composition_options,
+            transform,
+        ))
     }
 
     fn read_pixels(&mut self, read_rect: Option<Rect<u32>>) -> Snapshot {
-        match self {
-            #[cfg(feature = "raqote")]
-            Canvas::Raqote(canvas_data) => canvas_data.read_pixels(read_rect),
-            #[cfg(feature = "vello")]
-            Canvas::Vello(canvas_data) => canvas_data.read_pixels(read_rect),
-            #[cfg(feature = "vello_cpu")]
-            Canvas::VelloCPU(canvas_data) => canvas_data.read_pixels(read_rect),
-            _ => unreachable!(),
-        }
+        forward_to_canvas_data!(self.read_pixels(read_rect))
     }
 
-    fn measure_text(&mut self, text: String, text_options: TextOptions) -> TextMetrics {
-        match self {
-            #[cfg(feature = "raqote")]
-            Canvas::Raqote(canvas_data) => canvas_data.measure_text(text, text_options),
-            #[cfg(feature = "vello")]
-            Canvas::Vello(canvas_data) => canvas_data.measure_text(text, text_options),
-            #[cfg(feature = "vello_cpu")]
-            Canvas::VelloCPU(canvas_data) => canvas_data.measure_text(text, text_options),
-            _ => unreachable!(),
-        }
+    fn measure_text(
+        &mut self,
+        text: String,
+        is_rtl: bool,
+        text_options: TextOptions,
+    ) -> TextMetrics {
+        forward_to_canvas_data!(self.measure_text(text, is_rtl, text_options))
+    }
+
+    fn wrap_text(
+        &mut self,
+        text: String,
+        max_width: f64,
+        is_rtl: bool,
+        text_options: TextOptions,
+    ) -> Vec<usize> {
+        forward_to_canvas_data!(self.wrap_text(text, max_width, is_rtl, text_options))
     }
 
     fn clip_path(&mut self, path: &Path, fill_rule: FillRule, transform: Transform2D<f32>) {
-        match self {
-            #[cfg(feature = "raqote")]
-            Canvas::Raqote(canvas_data) => canvas_data.clip_path(path, fill_rule, transform),
-            #[cfg(feature = "vello")]
-            Canvas::Vello(canvas_data) => canvas_data.clip_path(path, fill_rule, transform),
-            #[cfg(feature = "vello_cpu")]
-            Canvas::VelloCPU(canvas_data) => canvas_data.clip_path(path, fill_rule, transform),
-            _ => unreachable!(),
-        }
+        forward_to_canvas_data!(self.clip_path(path, fill_rule, transform))
+    }
+
+    fn trim_glyph_cache(&mut self) {
+        forward_to_canvas_data!(self.trim_glyph_cache())
+    }
+
+    fn glyph_cache_size(&self) -> usize {
+        forward_to_canvas_data!(self.glyph_cache_size())
+    }
+
+    fn last_modified(&mut self) -> Option<CrossProcessInstant> {
+        forward_to_canvas_data!(self.last_modified())
+    }
+
+    fn set_viewport(&mut self, viewport: Option<Rect<f32>>) {
+        forward_to_canvas_data!(self.set_viewport(viewport))
+    }
+
+    fn set_clip(&mut self, path: Option<&Path>, fill_rule: FillRule, transform: Transform2D<f32>) {
+        forward_to_canvas_data!(self.set_clip(path, fill_rule, transform))
+    }
+
+    fn set_radial_clip(
+        &mut self,
+        center: Point2D<f64>,
+        radius: f64,
+        fill_rule: FillRule,
+        transform: Transform2D<f32>,
+    ) {
+        forward_to_canvas_data!(self.set_radial_clip(center, radius, fill_rule, transform))
     }
 
     fn put_image_data(&mut self, snapshot: Snapshot, rect: Rect<u32>) {
-        match self {
-            #[cfg(feature = "raqote")]
-            Canvas::Raqote(canvas_data) => canvas_data.put_image_data(snapshot, rect),
-            #[cfg(feature = "vello")]
-            Canvas::Vello(canvas_data) => canvas_data.put_image_data(snapshot, rect),
-            #[cfg(feature = "vello_cpu")]
-            Canvas::VelloCPU(canvas_data) => canvas_data.put_image_data(snapshot, rect),
-            _ => unreachable!(),
+        forward_to_canvas_data!(self.put_image_data(snapshot, rect))
+    }
+
+    fn present_frame(&mut self, snapshot: Snapshot) {
+        forward_to_canvas_data!(self.present_frame(snapshot))
+    }
+
+    fn memory_usage(&self) -> CanvasMemoryUsage {
+        forward_to_canvas_data!(self.memory_usage())
+    }
+
+    fn update_image_rendering(&mut self, immediate: bool) {
+        forward_to_canvas_data!(self.update_image_rendering(immediate))
+    }
+
+    fn recreate(&mut self, size: Option<Size2D<u64>>) {
+        forward_to_canvas_data!(self.recreate(size))
+    }
+
+    fn set_fallback_font_families(&mut self, families: Vec<String>) {
+        forward_to_canvas_data!(self.set_fallback_font_families(families))
+    }
+
+    fn set_device_pixel_line_widths(&mut self, device_pixel_line_widths: bool) {
+        forward_to_canvas_data!(self.set_device_pixel_line_widths(device_pixel_line_widths))
+    }
+
+    fn user_space_line_options(
+        &self,
+        line_options: LineOptions,
+        transform: &Transform2D<f32>,
+    ) -> LineOptions {
+        forward_to_canvas_data!(self.user_space_line_options(line_options, transform))
+    }
+
+    fn set_max_shadow_blur(&mut self, max_shadow_blur: f64) {
+        forward_to_canvas_data!(self.set_max_shadow_blur(max_shadow_blur))
+    }
+
+    fn set_text_gamma(&mut self, gamma: f32) {
+        forward_to_canvas_data!(self.set_text_gamma(gamma))
+    }
+
+    fn set_shape_gamma(&mut self, gamma: f32) {
+        forward_to_canvas_data!(self.set_shape_gamma(gamma))
+    }
+
+    fn set_gamut_mapping(&mut self, gamut_mapping: GamutMapping) {
+        forward_to_canvas_data!(self.set_gamut_mapping(gamut_mapping))
+    }
+
+    fn set_gradient_interpolation(&mut self, interpolation: GradientInterpolation) {
+        forward_to_canvas_data!(self.set_gradient_interpolation(interpolation))
+    }
+
+    fn set_low_power_mode(&mut self, low_power: bool) {
+        forward_to_canvas_data!(self.set_low_power_mode(low_power))
+    }
+
+    fn begin_layer(&mut self, composition_options: CompositionOptions) {
+        forward_to_canvas_data!(self.begin_layer(composition_options))
+    }
+
+    fn end_layer(&mut self) {
+        forward_to_canvas_data!(self.end_layer())
+    }
+
+    fn draw_focus_ring(&mut self, path: &Path, transform: Transform2D<f32>) {
+        forward_to_canvas_data!(self.draw_focus_ring(path, transform))
+    }
+
+    fn set_focus_ring_style(&mut self, style: FocusRingStyle) {
+        forward_to_canvas_data!(self.set_focus_ring_style(style))
+    }
+
+    fn set_gradient_dither(&mut self, seed: Option<u64>, space: DitherSpace) {
+        forward_to_canvas_data!(self.set_gradient_dither(seed, space))
+    }
+
+    fn set_batch_opaque_fills(&mut self, batch: bool) {
+        forward_to_canvas_data!(self.set_batch_opaque_fills(batch))
+    }
+
+    fn read_pixels_in_tiles(
+        &mut self,
+        read_rect: Option<Rect<u32>>,
+        tile_height: u32,
+        tile: &mut dyn FnMut(Size2D<u32>, u32, Snapshot),
+    ) {
+        forward_to_canvas_data!(self.read_pixels_in_tiles(read_rect, tile_height, tile))
+    }
+
+    fn read_pixels_in_color_spaces(
+        &mut self,
+        read_rect: Option<Rect<u32>>,
+        color_spaces: &[PredefinedColorSpace],
+    ) -> Vec<Snapshot> {
+        forward_to_canvas_data!(self.read_pixels_in_color_spaces(read_rect, color_spaces))
+    }
+
+    fn get_pixel(&mut self, x: i32, y: i32) -> [u8; 4] {
+        forward_to_canvas_data!(self.get_pixel(x, y))
+    }
+
+    fn read_pixels_as_float(
+        &mut self,
+        read_rect: Option<Rect<u32>>,
+        encoding: ColorEncoding,
+    ) -> FloatImageData {
+        forward_to_canvas_data!(self.read_pixels_as_float(read_rect, encoding))
+    }
+
+    fn read_pixels_aligned(
+        &mut self,
+        read_rect: Option<Rect<u32>>,
+        alignment: u32,
+    ) -> AlignedImageData {
+        forward_to_canvas_data!(self.read_pixels_aligned(read_rect, alignment))
+    }
+
+    fn read_alpha_mask(&mut self, rect: Rect<u32>) -> AlphaMask {
+        forward_to_canvas_data!(self.read_alpha_mask(rect))
+    }
+
+    fn histogram(&mut self, rect: Rect<u32>) -> CanvasHistogram {
+        forward_to_canvas_data!(self.histogram(rect))
+    }
+
+    fn set_filter(&mut self, filter: Vec<FilterFunction>) {
+        forward_to_canvas_data!(self.set_filter(filter))
+    }
+
+    fn blur_region(&mut self, rect: Rect<u32>, std_deviation: f64) {
+        forward_to_canvas_data!(self.blur_region(rect, std_deviation))
+    }
+
+    fn apply_color_matrix(&mut self, rect: Rect<u32>, matrix: &[f32; 20]) {
+        forward_to_canvas_data!(self.apply_color_matrix(rect, matrix))
+    }
+
+    fn fade_region(&mut self, rect: Rect<u32>, factor: f64) {
+        forward_to_canvas_data!(self.fade_region(rect, factor))
+    }
+
+    fn posterize(&mut self, rect: Rect<u32>, levels: u32) {
+        forward_to_canvas_data!(self.posterize(rect, levels))
+    }
+
+    fn debug_dump(&mut self, path: &std::path::Path) -> Result<(), CanvasError> {
+        forward_to_canvas_data!(self.debug_dump(path))
+    }
+
+    fn flip_surface(&mut self, axis: FlipAxis) {
+        forward_to_canvas_data!(self.flip_surface(axis))
+    }
+
+    fn save_region(&mut self, rect: Rect<u32>) -> SavedRegionId {
+        forward_to_canvas_data!(self.save_region(rect))
+    }
+
+    fn restore_region(&mut self, rect: Rect<u32>, id: SavedRegionId) {
+        forward_to_canvas_data!(self.restore_region(rect, id))
+    }
+
+    fn release_region(&mut self, id: SavedRegionId) {
+        forward_to_canvas_data!(self.release_region(id))
+    }
+
+    fn begin_transaction(&mut self) {
+        forward_to_canvas_data!(self.begin_transaction())
+    }
+
+    fn commit_transaction(&mut self) {
+        forward_to_canvas_data!(self.commit_transaction())
+    }
+
+    fn rollback_transaction(&mut self) {
+        forward_to_canvas_data!(self.rollback_transaction())
+    }
+
+    fn clip_bounds(&self) -> Rect<f32> {
+        forward_to_canvas_data!(self.clip_bounds())
+    }
+
+    fn last_text_truncated(&self) -> bool {
+        forward_to_canvas_data!(self.last_text_truncated())
+    }
+
+    fn is_fully_opaque(&mut self) -> bool {
+        forward_to_canvas_data!(self.is_fully_opaque())
+    }
+
+    fn last_draw_out_of_bounds(&self) -> bool {
+        forward_to_canvas_data!(self.last_draw_out_of_bounds())
+    }
+
+    fn set_image_smoothing(&mut self, enabled: bool, quality: ImageSmoothingQuality) {
+        forward_to_canvas_data!(self.set_image_smoothing(enabled, quality))
+    }
+
+    fn set_glyph_hinting(&mut self, hinting: GlyphHinting) {
+        forward_to_canvas_data!(self.set_glyph_hinting(hinting))
+    }
+
+    fn set_text_pixel_snapping(&mut self, snapping: bool) {
+        forward_to_canvas_data!(self.set_text_pixel_snapping(snapping))
+    }
+
+    fn set_coordinate_rounding(&mut self, rounding: CoordinateRounding) {
+        forward_to_canvas_data!(self.set_coordinate_rounding(rounding))
+    }
+
+    fn rendering_state(&self) -> RenderingState {
+        forward_to_canvas_data!(self.rendering_state())
+    }
+
+    fn draw_stats(&self) -> CanvasDrawStats {
+        forward_to_canvas_data!(self.draw_stats())
+    }
+
+    fn acceleration(&self) -> CanvasAcceleration {
+        forward_to_canvas_data!(self.acceleration())
+    }
+
+    fn reset_draw_stats(&mut self) {
+        forward_to_canvas_data!(self.reset_draw_stats())
+    }
+
+    fn set_frame_pacing(&mut self, frame_pacing: bool) {
+        forward_to_canvas_data!(self.set_frame_pacing(frame_pacing))
+    }
+
+    fn set_premultiplied_upload(&mut self, premultiplied: bool) {
+        forward_to_canvas_data!(self.set_premultiplied_upload(premultiplied))
+    }
+
+    fn set_image_rendering(&mut self, image_rendering: ImageRendering) {
+        forward_to_canvas_data!(self.set_image_rendering(image_rendering))
+    }
+
+    fn reclaim_surface(&mut self) {
+        forward_to_canvas_data!(self.reclaim_surface())
+    }
+
+    fn restore_reclaimed_surface(&mut self) {
+        forward_to_canvas_data!(self.restore_reclaimed_surface())
+    }
+
+    fn advance_sequence(&mut self) {
+        forward_to_canvas_data!(self.advance_sequence())
+    }
+
+    fn generation(&self) -> u64 {
+        forward_to_canvas_data!(self.generation())
+    }
+
+    fn sequence(&self) -> u64 {
+        forward_to_canvas_data!(self.sequence())
+    }
+
+    fn add_frame_time(&mut self, start: Instant) {
+        forward_to_canvas_data!(self.add_frame_time(start))
+    }
+
+    fn vsync(&mut self) {
+        forward_to_canvas_data!(self.vsync())
+    }
+
+    fn snapshot(&mut self, read_rect: Option<Rect<u32>>) -> Snapshot {
+        forward_to_canvas_data!(self.snapshot(read_rect))
+    }
+
+    fn draw_image_from_self(
+        &mut self,
+        dest_rect: Rect<f64>,
+        source_rect: Rect<f64>,
+        smoothing_enabled: bool,
+        shadow_options: ShadowOptions,
+        composition_options: CompositionOptions,
+        transform: Transform2D<f32>,
+    ) {
+        forward_to_canvas_data!(self.draw_image_from_self(
+            dest_rect,
+            source_rect,
+            smoothing_enabled,
+            shadow_options,
+            composition_options,
+            transform,
+        ))
+    }
+}
+
+#[cfg(all(test, feature = "raqote"))]
+mod test {
+    use std::cell::Cell;
+
+    use canvas_traits::ConstellationCanvasMsg;
+    use canvas_traits::canvas::{
+        Acceleration, BlendingStyle, Canvas2dMsg, CanvasAcceleration, CanvasCreationOptions,
+        CanvasError, CanvasId, CanvasMsg, ColorEncoding, CompositionOrBlending, FillOrStrokeStyle,
+        GlyphHinting, ImageOrientation, ImageSmoothingQuality, LineCapStyle, LineJoinStyle, Path,
+        RenderingState,
+    };
+    use compositing_traits::ImageUpdate;
+    use crossbeam_channel::Sender;
+    use euclid::default::{Point2D, Rect, Size2D, Transform2D};
+    use ipc_channel::ipc::{self, IpcSender, IpcSharedMemory};
+    use pixels::{EncodedImageType, Snapshot, SnapshotAlphaMode, SnapshotPixelFormat};
+    use style::color::AbsoluteColor;
+
+    use super::{CanvasPaintThread, DEFAULT_IMAGE_CACHE_CAPACITY, ImageCache};
+    use crate::canvas_data::test::{
+        disconnected_services, line_options, mock_compositor_api, no_shadow,
+        recording_compositor_api, red, shallow_gray_gradient,
+    };
+
+    /// Starts a canvas paint thread and creates a canvas of `size` on it.
+    fn start_canvas_paint_thread(
+        size: Size2D<u64>,
+    ) -> (
+        Sender<ConstellationCanvasMsg>,
+        IpcSender<CanvasMsg>,
+        CanvasId,
+    ) {
+        start_canvas_paint_thread_with_image_cache(size, DEFAULT_IMAGE_CACHE_CAPACITY)
+    }
+
+    /// Like [`start_canvas_paint_thread`], with a thread that keeps up to
+    /// `image_cache_capacity` decoded images.
+    fn start_canvas_paint_thread_with_image_cache(
+        size: Size2D<u64>,
+        image_cache_capacity: usize,
+    ) -> (
+        Sender<ConstellationCanvasMsg>,
+        IpcSender<CanvasMsg>,
+        CanvasId,
+    ) {
+        let (system_font_service, resource_threads) = disconnected_services();
+        let (create_sender, canvas_sender) = CanvasPaintThread::start(
+            mock_compositor_api(),
+            system_font_service,
+            resource_threads,
+            image_cache_capacity,
+            None,
+        );
+        let canvas_id = create_canvas(&create_sender, size);
+        (create_sender, canvas_sender, canvas_id)
+    }
+
+    /// Creates another canvas of `size` on a running canvas paint thread.
+    fn create_canvas(
+        create_sender: &Sender<ConstellationCanvasMsg>,
+        size: Size2D<u64>,
+    ) -> CanvasId {
+        create_canvas_with_options(create_sender, size, CanvasCreationOptions::default())
+    }
+
+    fn create_canvas_with_options(
+        create_sender: &Sender<ConstellationCanvasMsg>,
+        size: Size2D<u64>,
+        options: CanvasCreationOptions,
+    ) -> CanvasId {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        create_sender
+            .send(ConstellationCanvasMsg::Create {
+                sender,
+                size,
+                options,
+            })
+            .unwrap();
+        receiver.recv().unwrap().unwrap().0
+    }
+
+    fn exit_canvas_paint_thread(create_sender: Sender<ConstellationCanvasMsg>) {
+        let (exit_sender, exit_receiver) = crossbeam_channel::unbounded();
+        create_sender
+            .send(ConstellationCanvasMsg::Exit(exit_sender))
+            .unwrap();
+        exit_receiver.recv().unwrap();
+    }
+
+    /// Reads back the pixels of a canvas on a running canvas paint thread, as
+    /// non-premultiplied RGBA.
+    fn get_image_data(canvas_sender: &IpcSender<CanvasMsg>, canvas_id: CanvasId) -> Vec<u8> {
+        let (sender, receiver) = ipc::channel().unwrap();
+        let message = Canvas2dMsg::GetImageData(None, ColorEncoding::Srgb, sender);
+        canvas_sender
+            .send(CanvasMsg::Canvas2d(message, canvas_id))
+            .unwrap();
+        let mut snapshot = receiver.recv().unwrap().to_owned();
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            SnapshotPixelFormat::RGBA,
+        );
+        snapshot.as_raw_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_acceleration() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(4, 4));
+        let get_acceleration = || {
+            let (sender, receiver) = ipc::channel().unwrap();
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(
+                    Canvas2dMsg::GetAcceleration(sender),
+                    canvas_id,
+                ))
+                .unwrap();
+            receiver.recv().unwrap()
+        };
+        // Raqote draws everything on the CPU, whether it drew yet or not.
+        let software = CanvasAcceleration {
+            fills: Acceleration::Software,
+            strokes: Acceleration::Software,
+            images: Acceleration::Software,
+            texts: Acceleration::Software,
+            shadows: Acceleration::Software,
+            filters: Acceleration::Software,
+        };
+        assert_eq!(get_acceleration(), software);
+
+        let rect = Rect::new(Point2D::new(1., 1.), Size2D::new(2., 2.));
+        for message in [
+            Canvas2dMsg::FillRect(rect, no_shadow(), Transform2D::identity()),
+            Canvas2dMsg::StrokeRect(
+                rect,
+                line_options(LineCapStyle::Butt, LineJoinStyle::Miter),
+                no_shadow(),
+                Transform2D::identity(),
+            ),
+        ] {
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap();
         }
+        assert_eq!(get_acceleration(), software);
+
+        exit_canvas_paint_thread(create_sender);
     }
 
-    fn update_image_rendering(&mut self) {
-        match self {
-            #[cfg(feature = "raqote")]
-            Canvas::Raqote(canvas_data) => canvas_data.update_image_rendering(),
-            #[cfg(feature = "vello")]
-            Canvas::Vello(canvas_data) => canvas_data.update_image_rendering(),
-            #[cfg(feature = "vello_cpu")]
-            Canvas::VelloCPU(canvas_data) => canvas_data.update_image_rendering(),
-            _ => unreachable!(),
+    #[test]
+    fn test_vsync_sends_paced_image_update() {
+        let (compositor_api, updates) = recording_compositor_api();
+        let (system_font_service, resource_threads) = disconnected_services();
+        let (create_sender, canvas_sender) = CanvasPaintThread::start(
+            compositor_api,
+            system_font_service,
+            resource_threads,
+            DEFAULT_IMAGE_CACHE_CAPACITY,
+            None,
+        );
+        let size = Size2D::new(4, 4);
+        let canvas_id = create_canvas(&create_sender, size);
+        let send = |message| {
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap()
+        };
+        // Creating a canvas waits for the compositor, which has received every update sent
+        // before it by then.
+        let image_uploads = || {
+            create_canvas(&create_sender, size);
+            updates
+                .try_iter()
+                .filter(|update| matches!(update, ImageUpdate::UpdateImagePartially(..)))
+                .count()
+        };
+
+        send(Canvas2dMsg::SetFramePacing(true));
+        for _ in 0..3 {
+            send(Canvas2dMsg::FillRect(
+                Rect::from_size(Size2D::new(2., 2.)),
+                no_shadow(),
+                Transform2D::identity(),
+            ));
+            let (sender, receiver) = ipc::channel().unwrap();
+            send(Canvas2dMsg::UpdateImage(sender, false));
+            receiver.recv().unwrap();
         }
+        assert_eq!(image_uploads(), 0, "Paced updates should wait for a vsync");
+
+        create_sender.send(ConstellationCanvasMsg::Vsync).unwrap();
+        assert_eq!(
+            image_uploads(),
+            1,
+            "The updates requested before a vsync should be sent as one"
+        );
+        create_sender.send(ConstellationCanvasMsg::Vsync).unwrap();
+        assert_eq!(image_uploads(), 0);
+
+        exit_canvas_paint_thread(create_sender);
     }
 
-    fn recreate(&mut self, size: Option<Size2D<u64>>) {
-        match self {
-            #[cfg(feature = "raqote")]
-            Canvas::Raqote(canvas_data) => canvas_data.recreate(size),
-            #[cfg(feature = "vello")]
-            Canvas::Vello(canvas_data) => canvas_data.recreate(size),
-            #[cfg(feature = "vello_cpu")]
-            Canvas::VelloCPU(canvas_data) => canvas_data.recreate(size),
-            _ => unreachable!(),
+    #[test]
+    fn test_blend_snapshots() {
+        let (create_sender, canvas_sender, _) = start_canvas_paint_thread(Size2D::new(4, 4));
+        let solid = |size: Size2D<u32>, color: [u8; 4]| {
+            Snapshot::from_vec(
+                size,
+                SnapshotPixelFormat::RGBA,
+                SnapshotAlphaMode::Transparent {
+                    premultiplied: false,
+                },
+                color.repeat(size.area() as usize),
+            )
+            .as_ipc()
+        };
+
+        // The overlay only covers the left half of the base.
+        let (sender, receiver) = ipc::channel().unwrap();
+        canvas_sender
+            .send(CanvasMsg::BlendSnapshots(
+                solid(Size2D::new(4, 2), [0, 0, 255, 255]),
+                solid(Size2D::new(2, 2), [255, 0, 0, 255]),
+                CompositionOrBlending::Blending(BlendingStyle::Multiply),
+                1.,
+                sender,
+            ))
+            .unwrap();
+        let mut blended = receiver.recv().unwrap().to_owned();
+        blended.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            SnapshotPixelFormat::RGBA,
+        );
+        assert_eq!(blended.size(), Size2D::new(4, 2));
+        let pixels: Vec<_> = blended.as_raw_bytes().chunks(4).collect();
+        // Multiplying red by blue leaves no channel.
+        assert_eq!(pixels[0], [0, 0, 0, 255]);
+        assert_eq!(pixels[5], [0, 0, 0, 255]);
+        assert_eq!(pixels[3], [0, 0, 255, 255]);
+        assert_eq!(pixels[7], [0, 0, 255, 255]);
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_persistent_styles() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(8, 4));
+        let send = |message| {
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap()
+        };
+        let blue = FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(0, 0, 255, 1.));
+        let green = FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(0, 255, 0, 1.));
+
+        let fill_columns = |x| {
+            send(Canvas2dMsg::FillRect(
+                Rect::new(Point2D::new(x, 0.), Size2D::new(2., 4.)),
+                no_shadow(),
+                Transform2D::identity(),
+            ))
+        };
+
+        // The styles are set once, and used by every draw until they are set again.
+        send(Canvas2dMsg::SetFillStyle(red()));
+        send(Canvas2dMsg::SetStrokeStyle(blue));
+        fill_columns(0.);
+        fill_columns(2.);
+        send(Canvas2dMsg::StrokeRect(
+            Rect::new(Point2D::new(6., 1.), Size2D::new(2., 2.)),
+            line_options(LineCapStyle::Butt, LineJoinStyle::Miter),
+            no_shadow(),
+            Transform2D::identity(),
+        ));
+        send(Canvas2dMsg::SetFillStyle(green));
+        fill_columns(0.);
+
+        let pixels = get_image_data(&canvas_sender, canvas_id);
+        let pixel = |x: usize, y: usize| &pixels[(y * 8 + x) * 4..][..4];
+        for x in 0..2 {
+            assert_eq!(pixel(x, 2), [0, 255, 0, 255]);
+        }
+        for x in 2..4 {
+            assert_eq!(pixel(x, 2), [255, 0, 0, 255]);
+        }
+        assert_eq!(pixel(5, 2), [0, 0, 255, 255]);
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_paused_canvas() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(4, 4));
+        let send = |message| {
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap()
+        };
+        let fill = |color| {
+            send(Canvas2dMsg::SetFillStyle(FillOrStrokeStyle::Color(color)));
+            send(Canvas2dMsg::FillRect(
+                Rect::from_size(Size2D::new(4., 4.)),
+                no_shadow(),
+                Transform2D::identity(),
+            ))
+        };
+        let update_image = || {
+            let (sender, receiver) = ipc::channel().unwrap();
+            send(Canvas2dMsg::UpdateImage(sender, false));
+            receiver.recv().unwrap();
+        };
+        let image_uploads = || {
+            let (sender, receiver) = ipc::channel().unwrap();
+            send(Canvas2dMsg::GetDrawStats(sender));
+            receiver.recv().unwrap().image_uploads
+        };
+        let uploads = image_uploads();
+
+        canvas_sender
+            .send(CanvasMsg::SetPaused(canvas_id, true))
+            .unwrap();
+        fill(AbsoluteColor::srgb_legacy(255, 0, 0, 1.));
+        fill(AbsoluteColor::srgb_legacy(0, 0, 255, 1.));
+        // The update is answered, but the upload waits for the canvas to resume.
+        update_image();
+        update_image();
+        assert_eq!(image_uploads(), uploads);
+
+        canvas_sender
+            .send(CanvasMsg::SetPaused(canvas_id, false))
+            .unwrap();
+        assert_eq!(image_uploads(), uploads + 1);
+        // The buffered draws were applied in order.
+        assert_eq!(
+            get_image_data(&canvas_sender, canvas_id)[..4],
+            [0, 0, 255, 255]
+        );
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_report_errors_on_paused_canvas() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(4, 4));
+        canvas_sender
+            .send(CanvasMsg::SetPaused(canvas_id, true))
+            .unwrap();
+        canvas_sender
+            .send(CanvasMsg::Canvas2d(
+                Canvas2dMsg::SetFillStyle(red()),
+                canvas_id,
+            ))
+            .unwrap();
+
+        // The wrapped draw is answered without waiting for the canvas to resume, after the
+        // messages buffered before it.
+        let (sender, receiver) = ipc::channel().unwrap();
+        let fill = Canvas2dMsg::FillRect(
+            Rect::from_size(Size2D::new(4., 4.)),
+            no_shadow(),
+            Transform2D::identity(),
+        );
+        assert!(!fill.expects_reply());
+        let report = Canvas2dMsg::ReportErrors(Box::new(fill), sender);
+        assert!(report.expects_reply());
+        canvas_sender
+            .send(CanvasMsg::Canvas2d(report, canvas_id))
+            .unwrap();
+        assert_eq!(receiver.recv().unwrap(), Ok(()));
+        assert_eq!(
+            get_image_data(&canvas_sender, canvas_id)[..4],
+            [255, 0, 0, 255]
+        );
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_timeline_markers() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(4, 4));
+        let (marker_sender, marker_receiver) = ipc::channel().unwrap();
+        create_sender
+            .send(ConstellationCanvasMsg::SetTimelineMarkerSender(Some(marker_sender)))
+            .unwrap();
+        // Wait for the consumer to be attached before drawing on the other channel.
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        create_sender
+            .send(ConstellationCanvasMsg::GetImageCacheSize(sender))
+            .unwrap();
+        receiver.recv().unwrap();
+
+        for _ in 0..3 {
+            let message = Canvas2dMsg::FillRect(
+                Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+                no_shadow(),
+                Transform2D::identity(),
+            );
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap();
+        }
+        get_image_data(&canvas_sender, canvas_id);
+
+        // A marker spans the processing of each message, in order.
+        for name in ["FillRect", "FillRect", "FillRect", "GetImageData"] {
+            let marker = marker_receiver.recv().unwrap().unwrap();
+            assert_eq!(marker.name, format!("Canvas2d::{name}"));
+            assert!(marker.start_time <= marker.end_time);
+        }
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_local_sender() {
+        let size = Size2D::new(4, 4);
+        let (system_font_service, resource_threads) = disconnected_services();
+        let (create_sender, ipc_sender, local_sender) =
+            CanvasPaintThread::start_with_local_sender(
+                mock_compositor_api(),
+                system_font_service,
+                resource_threads,
+                DEFAULT_IMAGE_CACHE_CAPACITY,
+                None,
+            );
+        let ipc_canvas_id = create_canvas(&create_sender, size);
+        let local_canvas_id = create_canvas(&create_sender, size);
+
+        let draw = |canvas_id| {
+            let messages = [
+                Canvas2dMsg::SetFillStyle(red()),
+                Canvas2dMsg::FillRect(
+                    Rect::new(Point2D::new(1., 1.), Size2D::new(2., 2.)),
+                    no_shadow(),
+                    Transform2D::identity(),
+                ),
+            ];
+            messages.map(|message| CanvasMsg::Canvas2d(message, canvas_id))
+        };
+        let read = |canvas_id| {
+            let (sender, receiver) = ipc::channel().unwrap();
+            let message = Canvas2dMsg::GetImageData(None, ColorEncoding::Srgb, sender);
+            (CanvasMsg::Canvas2d(message, canvas_id), receiver)
+        };
+
+        for message in draw(ipc_canvas_id) {
+            ipc_sender.send(message).unwrap();
+        }
+        let (message, ipc_receiver) = read(ipc_canvas_id);
+        ipc_sender.send(message).unwrap();
+        for message in draw(local_canvas_id) {
+            local_sender.send(message).unwrap();
+        }
+        let (message, local_receiver) = read(local_canvas_id);
+        local_sender.send(message).unwrap();
+
+        let through_ipc = ipc_receiver.recv().unwrap().to_owned();
+        let mut through_local = local_receiver.recv().unwrap().to_owned();
+        assert_eq!(through_local.as_raw_bytes(), through_ipc.as_raw_bytes());
+        through_local.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            SnapshotPixelFormat::RGBA,
+        );
+        // The pixel at (1, 1) is red.
+        assert_eq!(through_local.as_raw_bytes()[20..24], [255, 0, 0, 255]);
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_render_seed() {
+        let size = Size2D::new(256, 16);
+        // Renders a gradient on a canvas created before the seed is set and on one created
+        // after it, returning the pixels of both.
+        let render = |seed| {
+            let (create_sender, canvas_sender, unseeded_id) = start_canvas_paint_thread(size);
+            create_sender
+                .send(ConstellationCanvasMsg::SetRenderSeed(seed))
+                .unwrap();
+            let seeded_id = create_canvas(&create_sender, size);
+
+            let gradient = shallow_gray_gradient(size.width as f64);
+            let pixels = [unseeded_id, seeded_id].map(|canvas_id| {
+                let style = FillOrStrokeStyle::LinearGradient(gradient.clone());
+                let fill = Canvas2dMsg::FillRect(
+                    Rect::from_size(size.to_f32()),
+                    no_shadow(),
+                    Transform2D::identity(),
+                );
+                for message in [Canvas2dMsg::SetFillStyle(style), fill] {
+                    canvas_sender
+                        .send(CanvasMsg::Canvas2d(message, canvas_id))
+                        .unwrap();
+                }
+                get_image_data(&canvas_sender, canvas_id)
+            });
+            exit_canvas_paint_thread(create_sender);
+            pixels
+        };
+
+        let [unseeded, seeded] = render(Some(7));
+        let [_, seeded_again] = render(Some(7));
+        assert_eq!(seeded, seeded_again, "Seeded rendering should be reproducible");
+        // The seed dithers the gradient of the canvas created after it was set only.
+        assert_ne!(seeded, unseeded);
+        let [_, unseeded_again] = render(None);
+        assert_eq!(unseeded, unseeded_again);
+    }
+
+    #[test]
+    fn test_generation_counts_recreations() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(4, 4));
+
+        let get_generation = || {
+            let (sender, receiver) = ipc::channel().unwrap();
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(
+                    Canvas2dMsg::GetGeneration(sender),
+                    canvas_id,
+                ))
+                .unwrap();
+            receiver.recv().unwrap()
+        };
+        assert_eq!(get_generation(), 0);
+
+        // The canvas keeps its id across recreations.
+        canvas_sender
+            .send(CanvasMsg::Recreate(Some(Size2D::new(8, 8)), canvas_id))
+            .unwrap();
+        assert_eq!(get_generation(), 1);
+        canvas_sender
+            .send(CanvasMsg::Recreate(None, canvas_id))
+            .unwrap();
+        assert_eq!(get_generation(), 2);
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_sequence_counts_processed_messages() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(4, 4));
+
+        let get_sequence = || {
+            let (sender, receiver) = ipc::channel().unwrap();
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(
+                    Canvas2dMsg::GetSequence(sender),
+                    canvas_id,
+                ))
+                .unwrap();
+            receiver.recv().unwrap()
+        };
+        assert_eq!(get_sequence(), 0);
+
+        for _ in 0..5 {
+            let message = Canvas2dMsg::FillRect(
+                Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+                no_shadow(),
+                Transform2D::identity(),
+            );
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap();
+        }
+        // Asking for the sequence, or any other query, doesn't advance it.
+        assert_eq!(get_sequence(), 5);
+        assert_eq!(get_sequence(), 5);
+        get_image_data(&canvas_sender, canvas_id);
+        assert_eq!(get_sequence(), 5);
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_canvas_size_limits() {
+        let limits = CanvasPaintThread::size_limits();
+        assert!(limits.max_width > 0 && limits.max_height > 0 && limits.max_area > 0);
+
+        let (create_sender, canvas_sender, _) = start_canvas_paint_thread(Size2D::new(1, 1));
+        let send = |message, canvas_id| {
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap()
+        };
+        let surface_after_fill = |canvas_id| {
+            send(
+                Canvas2dMsg::FillRect(
+                    Rect::from_size(Size2D::new(1., 1.)),
+                    no_shadow(),
+                    Transform2D::identity(),
+                ),
+                canvas_id,
+            );
+            let (sender, receiver) = ipc::channel().unwrap();
+            send(Canvas2dMsg::GetMemoryUsage(sender), canvas_id);
+            receiver.recv().unwrap().surface
+        };
+        let empty_surface = surface_after_fill(create_canvas(&create_sender, Size2D::zero()));
+
+        let widest = create_canvas(&create_sender, Size2D::new(limits.max_width, 1));
+        assert_eq!(surface_after_fill(widest), limits.max_width as usize * 4);
+        let too_wide = create_canvas(&create_sender, Size2D::new(limits.max_width + 1, 1));
+        assert_eq!(surface_after_fill(too_wide), empty_surface);
+
+        // Resizing past the limits empties the canvas as well.
+        canvas_sender
+            .send(CanvasMsg::Recreate(
+                Some(Size2D::new(limits.max_width + 1, 1)),
+                widest,
+            ))
+            .unwrap();
+        assert_eq!(surface_after_fill(widest), empty_surface);
+
+        // The backing store of a scaled canvas has to fit within the limits too, however
+        // small its size in CSS pixels.
+        let scaled = |scale_factor| CanvasCreationOptions {
+            scale_factor,
+            ..Default::default()
+        };
+        let half_width = limits.max_width / 2 + 1;
+        let scaled_too_wide =
+            create_canvas_with_options(&create_sender, Size2D::new(half_width, 1), scaled(2));
+        assert_eq!(surface_after_fill(scaled_too_wide), empty_surface);
+        let scaled_canvas =
+            create_canvas_with_options(&create_sender, Size2D::new(2, 1), scaled(2));
+        assert_eq!(surface_after_fill(scaled_canvas), 4 * 2 * 4);
+        canvas_sender
+            .send(CanvasMsg::Recreate(
+                Some(Size2D::new(half_width, 1)),
+                scaled_canvas,
+            ))
+            .unwrap();
+        assert_eq!(surface_after_fill(scaled_canvas), empty_surface);
+
+        // A canvas without device pixels can't be created.
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        create_sender
+            .send(ConstellationCanvasMsg::Create {
+                sender,
+                size: Size2D::new(2, 1),
+                options: scaled(0),
+            })
+            .unwrap();
+        assert_eq!(
+            receiver.recv().unwrap(),
+            Err(CanvasError::CreationFailed(Size2D::new(2, 1)))
+        );
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_y_flipped_canvas() {
+        let (create_sender, canvas_sender, _) = start_canvas_paint_thread(Size2D::new(1, 1));
+        let flipped = CanvasCreationOptions {
+            y_flipped: true,
+            ..Default::default()
+        };
+        let canvas_id = create_canvas_with_options(&create_sender, Size2D::new(4, 4), flipped);
+
+        // A marker drawn in the top-left corner is read back bottom-up, in the bottom-left.
+        let marker = Canvas2dMsg::FillRect(
+            Rect::from_size(Size2D::new(1., 1.)),
+            no_shadow(),
+            Transform2D::identity(),
+        );
+        for message in [Canvas2dMsg::SetFillStyle(red()), marker] {
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap();
         }
+        let pixels = get_image_data(&canvas_sender, canvas_id);
+        let pixel = |x: usize, y: usize| &pixels[(y * 4 + x) * 4..][..4];
+        assert_eq!(pixel(0, 3), [255, 0, 0, 255]);
+        assert_eq!(pixel(0, 0), [0; 4]);
+        assert_eq!(pixels.chunks_exact(4).filter(|pixel| pixel[3] != 0).count(), 1);
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_readbacks_interleaved_with_draws() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(4, 4));
+
+        // Each readback sees the fill sent just before it, and none of those sent after it,
+        // even when the next fill is queued before the reply is received.
+        let fill = |red| {
+            let style = FillOrStrokeStyle::Color(AbsoluteColor::srgb_legacy(red, 0, 255, 1.));
+            let fill = Canvas2dMsg::FillRect(
+                Rect::from_size(Size2D::new(4., 4.)),
+                no_shadow(),
+                Transform2D::identity(),
+            );
+            for message in [Canvas2dMsg::SetFillStyle(style), fill] {
+                canvas_sender
+                    .send(CanvasMsg::Canvas2d(message, canvas_id))
+                    .unwrap();
+            }
+        };
+        let mut receivers = Vec::new();
+        for red in 0..64 {
+            fill(red);
+            let (sender, receiver) = ipc::channel().unwrap();
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(
+                    Canvas2dMsg::GetImageData(None, ColorEncoding::Srgb, sender),
+                    canvas_id,
+                ))
+                .unwrap();
+            receivers.push(receiver);
+        }
+        fill(255);
+
+        for (red, receiver) in receivers.into_iter().enumerate() {
+            let mut snapshot = receiver.recv().unwrap().to_owned();
+            snapshot.transform(
+                SnapshotAlphaMode::Transparent {
+                    premultiplied: false,
+                },
+                SnapshotPixelFormat::RGBA,
+            );
+            assert!(
+                snapshot
+                    .as_raw_bytes()
+                    .chunks_exact(4)
+                    .all(|pixel| pixel == [red as u8, 0, 255, 255])
+            );
+        }
+        assert_eq!(get_image_data(&canvas_sender, canvas_id)[..4], [255, 0, 255, 255]);
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_surface_allocated_on_first_draw() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(4, 4));
+
+        let get_memory_usage = || {
+            let (sender, receiver) = ipc::channel().unwrap();
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(
+                    Canvas2dMsg::GetMemoryUsage(sender),
+                    canvas_id,
+                ))
+                .unwrap();
+            receiver.recv().unwrap()
+        };
+        assert!(get_memory_usage().surface < 4 * 4 * 4);
+
+        // Resizing the canvas doesn't allocate it either.
+        canvas_sender
+            .send(CanvasMsg::Recreate(Some(Size2D::new(8, 8)), canvas_id))
+            .unwrap();
+        assert!(get_memory_usage().surface < 8 * 8 * 4);
+
+        // Neither does setting the drawing state, or querying it.
+        let (sender, _receiver) = ipc::channel().unwrap();
+        for message in [
+            Canvas2dMsg::SetFillStyle(red()),
+            Canvas2dMsg::SetStrokeStyle(red()),
+            Canvas2dMsg::SetGlobalAlpha(0.5),
+            Canvas2dMsg::SetGlobalCompositeOperation(CompositionOrBlending::default()),
+            Canvas2dMsg::SetImageSmoothing(false, ImageSmoothingQuality::High),
+            Canvas2dMsg::SetFilter(vec![]),
+            Canvas2dMsg::GetClipBounds(sender),
+        ] {
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap();
+        }
+        assert!(get_memory_usage().surface < 8 * 8 * 4);
+
+        let message = Canvas2dMsg::FillRect(
+            Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+            no_shadow(),
+            Transform2D::identity(),
+        );
+        canvas_sender
+            .send(CanvasMsg::Canvas2d(message, canvas_id))
+            .unwrap();
+        assert_eq!(get_memory_usage().surface, 8 * 8 * 4);
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_reset_canvas_releases_surface() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(4, 4));
+        let send = |message| {
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap()
+        };
+        let get_memory_usage = || {
+            let (sender, receiver) = ipc::channel().unwrap();
+            send(Canvas2dMsg::GetMemoryUsage(sender));
+            receiver.recv().unwrap()
+        };
+        send(Canvas2dMsg::FillRect(
+            Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+            no_shadow(),
+            Transform2D::identity(),
+        ));
+        assert_eq!(get_memory_usage().surface, 4 * 4 * 4);
+
+        // Script reclaims the surface of a canvas after resetting it, and presenting the
+        // canvas doesn't allocate it again.
+        canvas_sender
+            .send(CanvasMsg::Recreate(Some(Size2D::new(8, 8)), canvas_id))
+            .unwrap();
+        send(Canvas2dMsg::ReclaimSurface);
+        let (sender, receiver) = ipc::channel().unwrap();
+        send(Canvas2dMsg::UpdateImage(sender, false));
+        receiver.recv().unwrap();
+        assert!(get_memory_usage().surface < 8 * 8 * 4);
+
+        // Reading the pixels back does.
+        assert_eq!(get_image_data(&canvas_sender, canvas_id), vec![0; 8 * 8 * 4]);
+        assert_eq!(get_memory_usage().surface, 8 * 8 * 4);
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_winding_number() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(4, 4));
+
+        let get_winding_number = |path: &Path, x, y| {
+            let (sender, receiver) = ipc::channel().unwrap();
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(
+                    Canvas2dMsg::GetWindingNumber(path.clone(), x, y, sender),
+                    canvas_id,
+                ))
+                .unwrap();
+            receiver.recv().unwrap()
+        };
+
+        // Two rectangles wound the same way, overlapping between x = 4 and x = 6.
+        let mut path = Path::new();
+        path.rect(0., 0., 6., 6.);
+        path.rect(4., 0., 6., 6.);
+        assert_eq!(get_winding_number(&path, 5., 3.), 2);
+        assert_eq!(get_winding_number(&path, 2., 3.), 1);
+        assert_eq!(get_winding_number(&path, 8., 3.), 1);
+        assert_eq!(get_winding_number(&path, 12., 3.), 0);
+        assert_eq!(get_winding_number(&path, f64::NAN, 3.), 0);
+
+        // Winding the second rectangle the other way cancels out the overlap.
+        let mut path = Path::new();
+        path.rect(0., 0., 6., 6.);
+        path.move_to(4., 0.);
+        path.line_to(4., 6.);
+        path.line_to(10., 6.);
+        path.line_to(10., 0.);
+        path.close_path();
+        assert_eq!(get_winding_number(&path, 5., 3.), 0);
+        assert_eq!(get_winding_number(&path, 8., 3.), -1);
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_rendering_state() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(4, 4));
+        let send = |message| {
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap();
+        };
+        let get_rendering_state = || {
+            let (sender, receiver) = ipc::channel().unwrap();
+            send(Canvas2dMsg::GetRenderingState(sender));
+            receiver.recv().unwrap()
+        };
+
+        assert_eq!(
+            get_rendering_state(),
+            RenderingState {
+                image_smoothing_enabled: true,
+                image_smoothing_quality: ImageSmoothingQuality::Low,
+                glyph_hinting: GlyphHinting::Platform,
+            }
+        );
+
+        send(Canvas2dMsg::SetImageSmoothing(
+            false,
+            ImageSmoothingQuality::High,
+        ));
+        send(Canvas2dMsg::SetGlyphHinting(GlyphHinting::None));
+        assert_eq!(
+            get_rendering_state(),
+            RenderingState {
+                image_smoothing_enabled: false,
+                image_smoothing_quality: ImageSmoothingQuality::High,
+                glyph_hinting: GlyphHinting::None,
+            }
+        );
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_operation_budget() {
+        let (system_font_service, resource_threads) = disconnected_services();
+        let mut canvas_paint_thread = CanvasPaintThread::new(
+            mock_compositor_api(),
+            system_font_service,
+            resource_threads,
+            DEFAULT_IMAGE_CACHE_CAPACITY,
+            None,
+        );
+        let mut create_canvas = || {
+            canvas_paint_thread
+                .create_canvas(Size2D::new(4, 4), CanvasCreationOptions::default())
+                .unwrap()
+                .0
+        };
+        let heavy = create_canvas();
+        let light = create_canvas();
+        canvas_paint_thread.set_operation_budget(Some(2));
+
+        let fill = || {
+            Canvas2dMsg::FillRect(
+                Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+                no_shadow(),
+                Transform2D::identity(),
+            )
+        };
+        let get_draw_stats = |canvas_paint_thread: &mut CanvasPaintThread, canvas_id| {
+            let (sender, receiver) = ipc::channel().unwrap();
+            canvas_paint_thread
+                .handle_canvas_2d_message(Canvas2dMsg::GetDrawStats(sender), canvas_id);
+            receiver
+        };
+
+        // The heavy canvas floods the thread, then the light one sends a little work.
+        for _ in 0..10 {
+            canvas_paint_thread.handle_canvas_2d_message(fill(), heavy);
+        }
+        let heavy_stats = get_draw_stats(&mut canvas_paint_thread, heavy);
+        canvas_paint_thread.handle_canvas_2d_message(fill(), light);
+        let light_stats = get_draw_stats(&mut canvas_paint_thread, light);
+
+        // The light canvas is within its budget, so its messages are processed right away,
+        // while those of the heavy canvas beyond its budget are deferred.
+        assert_eq!(light_stats.try_recv().unwrap().fills, 1);
+        assert!(heavy_stats.try_recv().is_err());
+        assert!(canvas_paint_thread.has_deferred_messages());
+
+        // Each frame processes two more deferred messages, and the light canvas keeps making
+        // progress in between.
+        for frame in 1..=4 {
+            canvas_paint_thread.begin_frame();
+            canvas_paint_thread.handle_canvas_2d_message(fill(), light);
+            let light_stats = get_draw_stats(&mut canvas_paint_thread, light);
+            assert_eq!(light_stats.try_recv().unwrap().fills, 1 + frame);
+            assert!(heavy_stats.try_recv().is_err());
+        }
+        canvas_paint_thread.begin_frame();
+        assert_eq!(heavy_stats.try_recv().unwrap().fills, 10);
+        assert!(!canvas_paint_thread.has_deferred_messages());
+    }
+
+    #[test]
+    fn test_operation_budget_keeps_order_across_canvases() {
+        let (system_font_service, resource_threads) = disconnected_services();
+        let mut canvas_paint_thread = CanvasPaintThread::new(
+            mock_compositor_api(),
+            system_font_service,
+            resource_threads,
+            DEFAULT_IMAGE_CACHE_CAPACITY,
+            None,
+        );
+        let mut create_canvas = || {
+            canvas_paint_thread
+                .create_canvas(Size2D::new(4, 4), CanvasCreationOptions::default())
+                .unwrap()
+                .0
+        };
+        let source = create_canvas();
+        let destination = create_canvas();
+        canvas_paint_thread.set_operation_budget(Some(1));
+
+        // The fill of the source goes over its budget, but has to be drawn before the source
+        // is drawn onto the destination.
+        canvas_paint_thread.handle_canvas_2d_message(Canvas2dMsg::SetFillStyle(red()), source);
+        canvas_paint_thread.handle_canvas_2d_message(
+            Canvas2dMsg::FillRect(
+                Rect::from_size(Size2D::new(4., 4.)),
+                no_shadow(),
+                Transform2D::identity(),
+            ),
+            source,
+        );
+        assert!(canvas_paint_thread.has_deferred_messages());
+        canvas_paint_thread.handle_canvas_2d_message(
+            Canvas2dMsg::DrawImageInOther(
+                destination,
+                Rect::from_size(Size2D::new(4., 4.)),
+                Rect::from_size(Size2D::new(4., 4.)),
+                false,
+                no_shadow(),
+                Transform2D::identity(),
+            ),
+            source,
+        );
+        assert!(!canvas_paint_thread.has_deferred_messages());
+
+        let (sender, receiver) = ipc::channel().unwrap();
+        canvas_paint_thread.handle_canvas_2d_message(
+            Canvas2dMsg::GetImageData(None, ColorEncoding::Srgb, sender),
+            destination,
+        );
+        let mut snapshot = receiver.try_recv().unwrap().to_owned();
+        snapshot.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            SnapshotPixelFormat::RGBA,
+        );
+        assert_eq!(snapshot.as_raw_bytes()[..4], [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_closing_canvas_answers_deferred_messages() {
+        let (system_font_service, resource_threads) = disconnected_services();
+        let mut canvas_paint_thread = CanvasPaintThread::new(
+            mock_compositor_api(),
+            system_font_service,
+            resource_threads,
+            DEFAULT_IMAGE_CACHE_CAPACITY,
+            None,
+        );
+        let (canvas_id, _) = canvas_paint_thread
+            .create_canvas(Size2D::new(4, 4), CanvasCreationOptions::default())
+            .unwrap();
+        canvas_paint_thread.set_operation_budget(Some(1));
+
+        canvas_paint_thread.handle_canvas_2d_message(Canvas2dMsg::SetFillStyle(red()), canvas_id);
+        let (sender, receiver) = ipc::channel().unwrap();
+        canvas_paint_thread.handle_canvas_2d_message(Canvas2dMsg::GetSequence(sender), canvas_id);
+        assert!(receiver.try_recv().is_err());
+
+        canvas_paint_thread.close_canvas(canvas_id);
+        assert_eq!(receiver.try_recv().unwrap(), 1);
+        assert!(!canvas_paint_thread.has_deferred_messages());
+    }
+
+    #[test]
+    fn test_repeated_clears_are_coalesced() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(4, 4));
+        let send = |message| {
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap()
+        };
+        let clear = || {
+            Canvas2dMsg::ClearRect(
+                Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+                Transform2D::identity(),
+            )
+        };
+        let clears = || {
+            let (sender, receiver) = ipc::channel().unwrap();
+            send(Canvas2dMsg::GetDrawStats(sender));
+            receiver.recv().unwrap().clears
+        };
+
+        send(clear());
+        send(clear());
+        send(clear());
+        assert_eq!(clears(), 1);
+
+        // Drawing in between makes the next clear matter again.
+        send(Canvas2dMsg::FillRect(
+            Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+            no_shadow(),
+            Transform2D::identity(),
+        ));
+        send(clear());
+        assert_eq!(clears(), 2);
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_draw_encoded_image_from_image_orientation() {
+        // The 2x1 PNG of `test_draw_encoded_image`, with an eXIf chunk recording that it
+        // is meant to be displayed rotated 90 degrees clockwise.
+        const PNG: [u8; 109] = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+            0x44, 0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00,
+            0x00, 0xf4, 0x22, 0x7f, 0x8a, 0x00, 0x00, 0x00, 0x1a, 0x65, 0x58, 0x49, 0x66, 0x4d,
+            0x4d, 0x00, 0x2a, 0x00, 0x00, 0x00, 0x08, 0x00, 0x01, 0x01, 0x12, 0x00, 0x03, 0x00,
+            0x00, 0x00, 0x01, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xd6, 0x67, 0x4b,
+            0x69, 0x00, 0x00, 0x00, 0x0e, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9c, 0x63, 0xf8, 0xcf,
+            0xc0, 0x00, 0x42, 0xff, 0x01, 0x0f, 0xf9, 0x03, 0xfd, 0x85, 0x11, 0x99, 0x76, 0x00,
+            0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+        ];
+
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(2, 2));
+        let draw = |orientation, x: f64, height: f64| {
+            let message = Canvas2dMsg::DrawEncodedImage(
+                IpcSharedMemory::from_bytes(&PNG),
+                EncodedImageType::Png,
+                orientation,
+                Rect::new(Point2D::new(x, 0.), Size2D::new(1., height)),
+                Rect::new(Point2D::new(0., 0.), Size2D::new(1., height)),
+                false,
+                no_shadow(),
+                Transform2D::identity(),
+            );
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap();
+        };
+        // Rotated, the image is 1x2, red above blue.
+        draw(ImageOrientation::FromImage, 0., 2.);
+        // Unrotated, the image is 2x1 and its first column is only its red pixel.
+        draw(ImageOrientation::None, 1., 1.);
+
+        let (sender, receiver) = ipc::channel().unwrap();
+        let message = Canvas2dMsg::GetImageData(None, ColorEncoding::Srgb, sender);
+        canvas_sender
+            .send(CanvasMsg::Canvas2d(message, canvas_id))
+            .unwrap();
+        let mut snapshot = receiver.recv().unwrap().to_owned();
+        let (pixels, ..) = snapshot.as_bytes(
+            Some(SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            }),
+            Some(SnapshotPixelFormat::RGBA),
+        );
+        assert_eq!(
+            pixels.to_vec(),
+            vec![255, 0, 0, 255, 255, 0, 0, 255, 0, 0, 255, 255, 0, 0, 0, 0]
+        );
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_image_cache_size() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread_with_image_cache(Size2D::new(1, 1), 2);
+        let draw = |red| {
+            let mut bytes = Vec::new();
+            Snapshot::from_vec(
+                Size2D::new(1, 1),
+                SnapshotPixelFormat::RGBA,
+                SnapshotAlphaMode::Transparent {
+                    premultiplied: false,
+                },
+                vec![red, 0, 0, 255],
+            )
+            .encode_for_mime_type(&EncodedImageType::Png, None, &mut bytes)
+            .unwrap();
+            let message = Canvas2dMsg::DrawEncodedImage(
+                IpcSharedMemory::from_bytes(&bytes),
+                EncodedImageType::Png,
+                ImageOrientation::None,
+                Rect::from_size(Size2D::new(1., 1.)),
+                Rect::from_size(Size2D::new(1., 1.)),
+                false,
+                no_shadow(),
+                Transform2D::identity(),
+            );
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap();
+        };
+        let image_cache_size = || {
+            // Wait for the drawing to be done, as the messages of the constellation can be
+            // handled before those sent to the canvas earlier.
+            let (sender, receiver) = ipc::channel().unwrap();
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(Canvas2dMsg::GetSequence(sender), canvas_id))
+                .unwrap();
+            receiver.recv().unwrap();
+
+            let (sender, receiver) = crossbeam_channel::unbounded();
+            create_sender
+                .send(ConstellationCanvasMsg::GetImageCacheSize(sender))
+                .unwrap();
+            receiver.recv().unwrap()
+        };
+
+        draw(0);
+        assert_eq!(image_cache_size(), 1);
+        draw(0);
+        assert_eq!(image_cache_size(), 1);
+        draw(128);
+        draw(255);
+        assert_eq!(image_cache_size(), 2);
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_draw_encoded_image() {
+        // A 2x1 PNG with a red and a blue pixel.
+        const PNG: [u8; 71] = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+            0x44, 0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00,
+            0x00, 0xf4, 0x22, 0x7f, 0x8a, 0x00, 0x00, 0x00, 0x0e, 0x49, 0x44, 0x41, 0x54, 0x78,
+            0x9c, 0x63, 0xf8, 0xcf, 0xc0, 0x00, 0x42, 0xff, 0x01, 0x0f, 0xf9, 0x03, 0xfd, 0x85,
+            0x11, 0x99, 0x76, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60,
+            0x82,
+        ];
+
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(2, 2));
+        let draw = |bytes: &[u8], y: f64| {
+            let message = Canvas2dMsg::DrawEncodedImage(
+                IpcSharedMemory::from_bytes(bytes),
+                EncodedImageType::Png,
+                ImageOrientation::None,
+                Rect::new(Point2D::new(0., y), Size2D::new(2., 1.)),
+                Rect::new(Point2D::new(0., 0.), Size2D::new(2., 1.)),
+                false,
+                no_shadow(),
+                Transform2D::identity(),
+            );
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap();
+        };
+        draw(&PNG, 0.);
+        // An image that fails to decode draws nothing.
+        draw(&PNG[..40], 1.);
+
+        let (sender, receiver) = ipc::channel().unwrap();
+        let message = Canvas2dMsg::GetImageData(None, ColorEncoding::Srgb, sender);
+        canvas_sender
+            .send(CanvasMsg::Canvas2d(message, canvas_id))
+            .unwrap();
+        let mut snapshot = receiver.recv().unwrap().to_owned();
+        let (pixels, ..) = snapshot.as_bytes(
+            Some(SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            }),
+            Some(SnapshotPixelFormat::RGBA),
+        );
+        assert_eq!(
+            pixels.to_vec(),
+            vec![255, 0, 0, 255, 0, 0, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_composite_operation_persists_across_batches() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(1, 1));
+        let send = |message| {
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap()
+        };
+        let fill = |color| {
+            send(Canvas2dMsg::SetFillStyle(FillOrStrokeStyle::Color(color)));
+            send(Canvas2dMsg::FillRect(
+                Rect::new(Point2D::new(0., 0.), Size2D::new(1., 1.)),
+                no_shadow(),
+                Transform2D::identity(),
+            ))
+        };
+        let get_pixel = || {
+            let (sender, receiver) = ipc::channel().unwrap();
+            send(Canvas2dMsg::GetPixel(0, 0, sender));
+            receiver.recv().unwrap()
+        };
+
+        fill(AbsoluteColor::srgb_legacy(255, 255, 0, 1.));
+        send(Canvas2dMsg::SetGlobalCompositeOperation(
+            CompositionOrBlending::Blending(BlendingStyle::Multiply),
+        ));
+        // Waiting for a reply ends the batch of messages, and updating the image ends a frame.
+        assert_eq!(get_pixel(), [255, 255, 0, 255]);
+        let (sender, receiver) = ipc::channel().unwrap();
+        send(Canvas2dMsg::UpdateImage(sender, false));
+        receiver.recv().unwrap();
+
+        // Yellow multiplied by cyan is green.
+        fill(AbsoluteColor::srgb_legacy(0, 255, 255, 1.));
+        assert_eq!(get_pixel(), [0, 255, 0, 255]);
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_report_operation_errors() {
+        let (create_sender, canvas_sender, canvas_id) =
+            start_canvas_paint_thread(Size2D::new(2, 2));
+        let report_errors = |message| {
+            let (sender, receiver) = ipc::channel().unwrap();
+            let message = Canvas2dMsg::ReportErrors(Box::new(message), sender);
+            canvas_sender
+                .send(CanvasMsg::Canvas2d(message, canvas_id))
+                .unwrap();
+            receiver.recv().unwrap()
+        };
+
+        let fill = Canvas2dMsg::FillRect(
+            Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+            no_shadow(),
+            Transform2D::identity(),
+        );
+        assert_eq!(report_errors(fill), Ok(()));
+
+        let undecodable = Canvas2dMsg::DrawEncodedImage(
+            IpcSharedMemory::from_bytes(b"not an image"),
+            EncodedImageType::Png,
+            ImageOrientation::None,
+            Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+            Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+            false,
+            no_shadow(),
+            Transform2D::identity(),
+        );
+        assert!(matches!(
+            report_errors(undecodable),
+            Err(CanvasError::OperationFailed(_))
+        ));
+
+        // Each wrapped message counts once.
+        let (sender, receiver) = ipc::channel().unwrap();
+        canvas_sender
+            .send(CanvasMsg::Canvas2d(
+                Canvas2dMsg::GetSequence(sender),
+                canvas_id,
+            ))
+            .unwrap();
+        assert_eq!(receiver.recv().unwrap(), 2);
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_error_sender() {
+        let (error_sender, error_receiver) = crossbeam_channel::unbounded();
+        let (system_font_service, resource_threads) = disconnected_services();
+        let (create_sender, canvas_sender) = CanvasPaintThread::start(
+            mock_compositor_api(),
+            system_font_service,
+            resource_threads,
+            DEFAULT_IMAGE_CACHE_CAPACITY,
+            Some(error_sender),
+        );
+        let canvas_id = create_canvas(&create_sender, Size2D::new(2, 2));
+
+        let undecodable = Canvas2dMsg::DrawEncodedImage(
+            IpcSharedMemory::from_bytes(b"not an image"),
+            EncodedImageType::Png,
+            ImageOrientation::None,
+            Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+            Rect::new(Point2D::new(0., 0.), Size2D::new(2., 2.)),
+            false,
+            no_shadow(),
+            Transform2D::identity(),
+        );
+        canvas_sender
+            .send(CanvasMsg::Canvas2d(undecodable, canvas_id))
+            .unwrap();
+        let (error_canvas_id, error) = error_receiver.recv().unwrap();
+        assert_eq!(error_canvas_id, canvas_id);
+        assert!(matches!(error, CanvasError::OperationFailed(_)));
+
+        // A canvas that can't be created is reported to the creator instead.
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        create_sender
+            .send(ConstellationCanvasMsg::Create {
+                sender,
+                size: Size2D::new(1, 1),
+                options: CanvasCreationOptions {
+                    render_scale: 0.,
+                    ..Default::default()
+                },
+            })
+            .unwrap();
+        assert_eq!(
+            receiver.recv().unwrap(),
+            Err(CanvasError::CreationFailed(Size2D::new(1, 1)))
+        );
+        assert!(error_receiver.is_empty());
+
+        exit_canvas_paint_thread(create_sender);
+    }
+
+    #[test]
+    fn test_image_cache_evicts_least_recently_drawn() {
+        let mut cache = ImageCache::new(2);
+        let decodes = Cell::new(0);
+        let mut get = |bytes: &[u8]| {
+            cache
+                .get_or_decode(
+                    &IpcSharedMemory::from_bytes(bytes),
+                    EncodedImageType::Png,
+                    ImageOrientation::None,
+                    || {
+                        decodes.set(decodes.get() + 1);
+                        Ok::<_, ()>(Snapshot::cleared(Size2D::new(1, 1)))
+                    },
+                )
+                .unwrap();
+        };
+
+        get(b"a");
+        get(b"b");
+        // Drawing the first image again makes the second the least recently drawn.
+        get(b"a");
+        assert_eq!(decodes.get(), 2);
+        get(b"c");
+        assert_eq!(decodes.get(), 3);
+
+        assert_eq!(cache.len(), 2);
+        let contains = |bytes: &[u8]| {
+            cache.contains(bytes, EncodedImageType::Png, ImageOrientation::None)
+        };
+        assert!(contains(b"a"));
+        assert!(!contains(b"b"));
+        assert!(contains(b"c"));
+        // Images are cached by orientation too.
+        assert!(!cache.contains(b"a", EncodedImageType::Png, ImageOrientation::FromImage));
     }
 }
@@ -102,6 +102,10 @@ impl Convert<kurbo::Stroke> for LineOptions {
             miter_limit,
             dash,
             dash_offset,
+            // The gaps are stroked separately, by `CanvasData`.
+            dash_gap_color: _,
+            // Strokes that aren't centered are clipped by `CanvasData`.
+            alignment: _,
         } = self;
         kurbo::Stroke {
             width,
@@ -125,6 +129,9 @@ impl Convert<peniko::Brush> for FillOrStrokeStyle {
                 let end = kurbo::Point::new(style.x1, style.y1);
                 let mut gradient = peniko::Gradient::new_linear(start, end);
                 gradient.stops = style.stops.convert();
+                if style.repeating {
+                    gradient.extend = peniko::Extend::Repeat;
+                }
                 peniko::Brush::Gradient(gradient)
             },
             RadialGradient(style) => {
@@ -140,6 +147,7 @@ impl Convert<peniko::Brush> for FillOrStrokeStyle {
                 peniko::Brush::Gradient(gradient)
             },
             Surface(surface_style) => {
+                let size = surface_style.surface_data.size();
                 let data = surface_style
                     .surface_data
                     .to_owned()
@@ -153,8 +161,8 @@ impl Convert<peniko::Brush> for FillOrStrokeStyle {
                 peniko::Brush::Image(peniko::Image {
                     data: peniko::Blob::from(data),
                     format: peniko::ImageFormat::Rgba8,
-                    width: surface_style.surface_size.width,
-                    height: surface_style.surface_size.height,
+                    width: size.width,
+                    height: size.height,
                     x_extend: if surface_style.repeat_x {
                         peniko::Extend::Repeat
                     } else {
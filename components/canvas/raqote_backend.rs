@@ -9,18 +9,22 @@ use canvas_traits::canvas::*;
 use compositing_traits::SerializableImageData;
 use cssparser::color::clamp_unit_f32;
 use euclid::default::{Point2D, Rect, Size2D, Transform2D};
+use font_kit::canvas::{Canvas, Format, RasterizationOptions};
 use font_kit::font::Font;
-use fonts::{ByteIndex, FontIdentifier, FontTemplateRefMethods};
+use font_kit::hinting::HintingOptions;
+use fonts::{FontIdentifier, FontTemplateRefMethods};
 use ipc_channel::ipc::IpcSharedMemory;
-use log::warn;
-use pixels::{Snapshot, SnapshotAlphaMode, SnapshotPixelFormat};
-use range::Range;
+use pathfinder_geometry::transform2d::Transform2F;
+use pathfinder_geometry::vector::vec2f;
+use pixels::{
+    Snapshot, SnapshotAlphaMode, SnapshotPixelFormat, rgba8_get_rect, unmultiply_inplace,
+};
 use raqote::{DrawOptions, PathBuilder, StrokeStyle};
 use style::color::AbsoluteColor;
 use webrender_api::{ImageDescriptor, ImageDescriptorFlags, ImageFormat};
 
 use crate::backend::GenericDrawTarget;
-use crate::canvas_data::{Filter, TextRun};
+use crate::canvas_data::{Filter, TextRun, positioned_glyphs};
 
 thread_local! {
     /// The shared font cache used by all canvases that render on a thread. It would be nicer
@@ -44,14 +48,25 @@ pub struct LinearGradientPattern {
     gradient: raqote::Gradient,
     start: Point2D<f32>,
     end: Point2D<f32>,
+    spread: raqote::Spread,
 }
 
 impl LinearGradientPattern {
-    fn new(start: Point2D<f32>, end: Point2D<f32>, stops: Vec<raqote::GradientStop>) -> Self {
+    fn new(
+        start: Point2D<f32>,
+        end: Point2D<f32>,
+        stops: Vec<raqote::GradientStop>,
+        repeating: bool,
+    ) -> Self {
         LinearGradientPattern {
             gradient: raqote::Gradient { stops },
             start,
             end,
+            spread: if repeating {
+                raqote::Spread::Repeat
+            } else {
+                raqote::Spread::Pad
+            },
         }
     }
 }
@@ -144,7 +159,7 @@ pub fn source(pattern: &Pattern) -> raqote::Source {
             pattern.gradient.clone(),
             pattern.start,
             pattern.end,
-            raqote::Spread::Pad,
+            pattern.spread,
         ),
         Pattern::RadialGradient(pattern) => raqote::Source::new_two_circle_radial_gradient(
             pattern.gradient.clone(),
@@ -265,15 +280,6 @@ impl GenericDrawTarget for raqote::DrawTarget {
             pb.finish(),
         );
     }
-    fn draw_surface_with_shadow(
-        &self,
-        _surface: Self::SourceSurface,
-        _dest: &Point2D<f32>,
-        _shadow_options: ShadowOptions,
-        _composition_options: CompositionOptions,
-    ) {
-        warn!("no support for drawing shadows");
-    }
     fn fill(
         &mut self,
         path: &canvas_traits::canvas::Path,
@@ -298,33 +304,29 @@ impl GenericDrawTarget for raqote::DrawTarget {
         text_runs: Vec<TextRun>,
         start: Point2D<f32>,
         style: FillOrStrokeStyle,
+        hinting: GlyphHinting,
         composition_options: CompositionOptions,
         transform: Transform2D<f32>,
     ) {
         self.set_transform(&transform);
         let draw_options = draw_options(composition_options);
         let pattern = style.to_raqote_pattern();
-        let mut advance = 0.;
+        let mut pen = start;
         for run in text_runs.iter() {
-            let mut positions = Vec::new();
-            let glyphs = &run.glyphs;
-            let ids: Vec<_> = glyphs
-                .iter_glyphs_for_byte_range(&Range::new(ByteIndex(0), glyphs.len()))
-                .map(|glyph| {
-                    let glyph_offset = glyph.offset().unwrap_or(Point2D::zero());
-                    positions.push(Point2D::new(
-                        advance + start.x + glyph_offset.x.to_f32_px(),
-                        start.y + glyph_offset.y.to_f32_px(),
-                    ));
-                    advance += glyph.advance().to_f32_px();
-                    glyph.id()
-                })
-                .collect();
+            let snapping_transform = run.pixel_snapped.then_some(&transform);
+            let (ids, positions): (Vec<_>, Vec<_>) =
+                positioned_glyphs(&run.glyphs, &mut pen, snapping_transform)
+                    .enumerate()
+                    .filter_map(|(index, glyph)| {
+                        run.visible_glyphs.contains(&index).then_some(glyph)
+                    })
+                    .unzip();
 
             // TODO: raqote uses font-kit to rasterize glyphs, but font-kit fails an assertion when
             // using color bitmap fonts in the FreeType backend. For now, simply do not render these
-            // type of fonts.
-            if run.font.has_color_bitmap_or_colr_table() {
+            // type of fonts. Fonts with a `COLR` table are drawn as layers of outline glyphs.
+            let color_glyphs = run.font.color_glyphs();
+            if run.font.has_color_bitmap_or_colr_table() && color_glyphs.is_none() {
                 continue;
             }
 
@@ -345,14 +347,59 @@ impl GenericDrawTarget for raqote::DrawTarget {
                     return;
                 };
 
-                self.draw_glyphs(
+                let size = run.font.descriptor.pt_size.to_f32_px();
+                let Some(color_glyphs) = color_glyphs else {
+                    let source = source(&pattern);
+                    draw_glyphs_with_hinting(
+                        self,
+                        font,
+                        size,
+                        &ids,
+                        &positions,
+                        hinting,
+                        &source,
+                        &draw_options,
+                    );
+                    return;
+                };
+
+                // Glyphs without layers are drawn in the fill style, and the layers of color
+                // glyphs in the colors of their palette, from bottom to top.
+                let (plain_ids, plain_positions): (Vec<_>, Vec<_>) = ids
+                    .iter()
+                    .zip(positions.iter())
+                    .filter(|(id, _)| color_glyphs.layers(**id).is_none())
+                    .unzip();
+                draw_glyphs_with_hinting(
+                    self,
                     font,
-                    run.font.descriptor.pt_size.to_f32_px(),
-                    &ids,
-                    &positions,
+                    size,
+                    &plain_ids,
+                    &plain_positions,
+                    hinting,
                     &source(&pattern),
                     &draw_options,
                 );
+                for (id, position) in ids.iter().zip(positions.iter()) {
+                    for layer in color_glyphs.layers(*id).unwrap_or_default() {
+                        let layer_source = match layer.color {
+                            Some([r, g, b, a]) => raqote::Source::Solid(
+                                raqote::SolidSource::from_unpremultiplied_argb(a, r, g, b),
+                            ),
+                            None => source(&pattern),
+                        };
+                        draw_glyphs_with_hinting(
+                            self,
+                            font,
+                            size,
+                            &[layer.glyph_id],
+                            &[*position],
+                            hinting,
+                            &layer_source,
+                            &draw_options,
+                        );
+                    }
+                }
             })
         }
     }
@@ -480,6 +527,26 @@ impl GenericDrawTarget for raqote::DrawTarget {
             self.get_data_u8().to_vec(),
         )
     }
+
+    fn snapshot_rect(&mut self, rect: Rect<u32>) -> Snapshot {
+        let data = rgba8_get_rect(self.get_data_u8(), self.get_size().cast(), rect);
+        Snapshot::from_vec(
+            rect.size,
+            SnapshotPixelFormat::BGRA,
+            SnapshotAlphaMode::Transparent {
+                premultiplied: true,
+            },
+            data.into_owned(),
+        )
+    }
+
+    fn get_pixel(&mut self, point: Point2D<u32>) -> [u8; 4] {
+        let index = (point.y as usize * self.width() as usize + point.x as usize) * 4;
+        let mut pixel: [u8; 4] = self.get_data_u8()[index..index + 4].try_into().unwrap();
+        // raqote stores premultiplied BGRA.
+        unmultiply_inplace::<true>(&mut pixel);
+        pixel
+    }
 }
 
 fn fill_draw_target(
@@ -490,7 +557,10 @@ fn fill_draw_target(
 ) {
     match draw_options.blend_mode {
         raqote::BlendMode::Src => {
-            draw_target.clear(raqote::SolidSource::from_unpremultiplied_argb(0, 0, 0, 0));
+            // `copy` replaces everything outside of the shape with transparent black, but
+            // only within the current clip. `DrawTarget::clear` ignores the clip stack, so
+            // clear by filling the whole surface, which respects it.
+            clear_draw_target_within_clip(draw_target);
             draw_target.fill(&path, source, &draw_options);
         },
         raqote::BlendMode::Clear |
@@ -502,20 +572,134 @@ fn fill_draw_target(
         raqote::BlendMode::SrcOver => {
             draw_target.fill(&path, source, &draw_options);
         },
-        raqote::BlendMode::SrcIn |
-        raqote::BlendMode::SrcOut |
-        raqote::BlendMode::DstIn |
-        raqote::BlendMode::DstAtop => {
+        // `SrcIn`, `SrcOut`, `DstIn` and `DstAtop` affect the pixels outside of the shape.
+        // They and the remaining modes are drawn through a layer, which raqote can composite
+        // with any blend mode.
+        _ => {
             let mut options = draw_options;
             draw_target.push_layer_with_blend(1., options.blend_mode);
             options.blend_mode = raqote::BlendMode::SrcOver;
             draw_target.fill(&path, source, &options);
             draw_target.pop_layer();
         },
-        _ => warn!("unrecognized blend mode: {:?}", draw_options.blend_mode),
     }
 }
 
+/// Draw glyphs like [`raqote::DrawTarget::draw_glyphs`], which never hints them, fitting
+/// their outlines to the pixel grid as `hinting` says.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn draw_glyphs_with_hinting(
+    draw_target: &mut raqote::DrawTarget,
+    font: &Font,
+    size: f32,
+    ids: &[u32],
+    positions: &[Point2D<f32>],
+    hinting: GlyphHinting,
+    source: &raqote::Source,
+    draw_options: &DrawOptions,
+) {
+    let hinting_options = match hinting {
+        GlyphHinting::Platform | GlyphHinting::None => {
+            draw_target.draw_glyphs(font, size, ids, positions, source, draw_options);
+            return;
+        },
+        GlyphHinting::Light => HintingOptions::Vertical(size),
+        GlyphHinting::Full => HintingOptions::Full(size),
+    };
+
+    // Hinting fits outlines to the device pixel grid, so the glyphs are rasterized in device
+    // space into a single coverage mask.
+    let transform = *draw_target.get_transform();
+    let linear = Transform2F::row_major(
+        transform.m11,
+        transform.m21,
+        0.,
+        transform.m12,
+        transform.m22,
+        0.,
+    );
+    let glyph_transforms: Vec<_> = ids
+        .iter()
+        .zip(positions)
+        .map(|(id, position)| {
+            let position = transform.transform_point(*position);
+            let translation = Transform2F::from_translation(vec2f(position.x, position.y));
+            (*id, translation * linear)
+        })
+        .collect();
+    let Some(bounds) = glyph_transforms
+        .iter()
+        .filter_map(|(id, glyph_transform)| {
+            font.raster_bounds(
+                *id,
+                size,
+                *glyph_transform,
+                hinting_options,
+                RasterizationOptions::GrayscaleAa,
+            )
+            .ok()
+        })
+        .reduce(|bounds, glyph_bounds| bounds.union_rect(glyph_bounds))
+    else {
+        return;
+    };
+    if bounds.width() <= 0 || bounds.height() <= 0 {
+        return;
+    }
+
+    let mut canvas = Canvas::new(bounds.size(), Format::A8);
+    let offset = Transform2F::from_translation(-bounds.origin().to_f32());
+    for (id, glyph_transform) in glyph_transforms {
+        let _ = font.rasterize_glyph(
+            &mut canvas,
+            id,
+            size,
+            offset * glyph_transform,
+            hinting_options,
+            RasterizationOptions::GrayscaleAa,
+        );
+    }
+    let mask = raqote::Mask {
+        width: bounds.width(),
+        height: bounds.height(),
+        data: canvas.pixels,
+    };
+
+    // `DrawTarget::mask` always draws over the target at full opacity.
+    let layered =
+        draw_options.blend_mode != raqote::BlendMode::SrcOver || draw_options.alpha != 1.;
+    if layered {
+        draw_target.push_layer_with_blend(draw_options.alpha, draw_options.blend_mode);
+    }
+    draw_target.mask(source, bounds.origin_x(), bounds.origin_y(), &mask);
+    if layered {
+        draw_target.pop_layer();
+    }
+}
+
+/// Clear the entire surface of the given draw target to transparent black, leaving the
+/// pixels outside of the active clip untouched.
+fn clear_draw_target_within_clip(draw_target: &mut raqote::DrawTarget) {
+    let transform = *draw_target.get_transform();
+    draw_target.set_transform(&raqote::Transform::identity());
+    let mut pb = raqote::PathBuilder::new();
+    pb.rect(
+        0.,
+        0.,
+        draw_target.width() as f32,
+        draw_target.height() as f32,
+    );
+    draw_target.fill(
+        &pb.finish(),
+        &raqote::Source::Solid(raqote::SolidSource::from_unpremultiplied_argb(0, 0, 0, 0)),
+        &DrawOptions {
+            blend_mode: raqote::BlendMode::Clear,
+            ..Default::default()
+        },
+    );
+    draw_target.set_transform(&transform);
+}
+
 impl Filter {
     fn to_raqote(self) -> raqote::FilterMode {
         match self {
@@ -565,6 +749,10 @@ impl ToRaqoteStyle for LineOptions {
             miter_limit,
             dash,
             dash_offset,
+            // The gaps are stroked separately, by `CanvasData`.
+            dash_gap_color: _,
+            // Strokes that aren't centered are clipped by `CanvasData`.
+            alignment: _,
         } = self;
         StrokeStyle {
             width: width as f32,
@@ -641,7 +829,12 @@ impl ToRaqotePattern for FillOrStrokeStyle {
                 let start = Point2D::new(style.x0 as f32, style.y0 as f32);
                 let end = Point2D::new(style.x1 as f32, style.y1 as f32);
                 let stops = create_gradient_stops(style.stops);
-                Pattern::LinearGradient(LinearGradientPattern::new(start, end, stops))
+                Pattern::LinearGradient(LinearGradientPattern::new(
+                    start,
+                    end,
+                    stops,
+                    style.repeating,
+                ))
             },
             RadialGradient(style) => {
                 let center1 = Point2D::new(style.x0 as f32, style.y0 as f32);
@@ -657,6 +850,8 @@ impl ToRaqotePattern for FillOrStrokeStyle {
             },
             Surface(style) => {
                 let repeat = Repetition::from_xy(style.repeat_x, style.repeat_y);
+                // raqote's image transforms map user space to image space.
+                let transform = style.transform.then(&style.surface_data_scale());
                 let mut snapshot = style.surface_data.to_owned();
                 snapshot.transform(
                     SnapshotAlphaMode::Transparent {
@@ -668,7 +863,7 @@ impl ToRaqotePattern for FillOrStrokeStyle {
                     snapshot,
                     raqote::FilterMode::Nearest,
                     repeat,
-                    style.transform,
+                    transform,
                 ))
             },
         }
@@ -17,16 +17,15 @@ use std::num::NonZeroUsize;
 use std::rc::Rc;
 
 use canvas_traits::canvas::{
-    CompositionOptions, CompositionOrBlending, CompositionStyle, FillOrStrokeStyle, FillRule,
-    LineOptions, Path, ShadowOptions,
+    Acceleration, CanvasSizeLimits, CompositionOptions, CompositionOrBlending, CompositionStyle,
+    FillOrStrokeStyle, FillRule, GlyphHinting, LineOptions, Path,
 };
 use compositing_traits::SerializableImageData;
 use euclid::default::{Point2D, Rect, Size2D, Transform2D};
-use fonts::{ByteIndex, FontIdentifier, FontTemplateRefMethods as _};
+use fonts::{FontIdentifier, FontTemplateRefMethods as _};
 use ipc_channel::ipc::IpcSharedMemory;
 use kurbo::Shape as _;
 use pixels::{Snapshot, SnapshotAlphaMode, SnapshotPixelFormat};
-use range::Range;
 use vello::wgpu::{
     BackendOptions, Backends, Buffer, BufferDescriptor, BufferUsages, COPY_BYTES_PER_ROW_ALIGNMENT,
     CommandEncoderDescriptor, Device, Extent3d, Instance, InstanceDescriptor, InstanceFlags,
@@ -38,7 +37,7 @@ use vello::{kurbo, peniko};
 use webrender_api::{ImageDescriptor, ImageDescriptorFlags};
 
 use crate::backend::{Convert as _, GenericDrawTarget};
-use crate::canvas_data::{Filter, TextRun};
+use crate::canvas_data::{Filter, TextRun, positioned_glyphs};
 
 thread_local! {
     /// The shared font cache used by all canvases that render on a thread. It would be nicer
@@ -146,6 +145,8 @@ impl VelloDrawTarget {
             f(self);
             return;
         }
+        // The layer is nested in the clip layers, so operations like `copy` that affect
+        // pixels outside of the drawn shape are still bounded by the active clip.
         self.scene.push_layer(
             composition_operation.convert(),
             1.0,
@@ -239,6 +240,21 @@ impl GenericDrawTarget for VelloDrawTarget {
         Self::new_with_renderer(device, queue, Rc::new(RefCell::new(renderer)), size)
     }
 
+    fn acceleration() -> Acceleration {
+        Acceleration::Hardware
+    }
+
+    fn size_limits() -> CanvasSizeLimits {
+        // The render target is a texture of a device requested with the default limits.
+        let max_dimension = vello::wgpu::Limits::default().max_texture_dimension_2d as u64;
+        let limits = CanvasSizeLimits::default();
+        CanvasSizeLimits {
+            max_width: limits.max_width.min(max_dimension),
+            max_height: limits.max_height.min(max_dimension),
+            max_area: limits.max_area.min(max_dimension * max_dimension + 1),
+        }
+    }
+
     fn clear_rect(&mut self, rect: &Rect<f32>, transform: Transform2D<f32>) {
         // vello scene only ever grows,
         // so we use every opportunity to shrink it
@@ -345,22 +361,6 @@ impl GenericDrawTarget for VelloDrawTarget {
         })
     }
 
-    fn draw_surface_with_shadow(
-        &self,
-        _surface: Vec<u8>,
-        _dest: &Point2D<f32>,
-        _shadow_options: ShadowOptions,
-        _composition_options: CompositionOptions,
-    ) {
-        log::warn!("no support for drawing shadows");
-        /*
-        We will need to do some changes to support drawing shadows with vello, as current abstraction is made for azure.
-        In vello we do not need new draw target (we will use layers) and we need to pass whole rect.
-        offsets will be applied to rect directly. shadow blur will be passed directly to let backend do transforms.
-        */
-        //self_.scene.draw_blurred_rounded_rect(self_.transform, rect, color, 0.0, sigma);
-    }
-
     fn fill(
         &mut self,
         path: &Path,
@@ -370,12 +370,13 @@ impl GenericDrawTarget for VelloDrawTarget {
         transform: Transform2D<f32>,
     ) {
         self.ensure_drawing();
+        let brush_transform = brush_transform(&style);
         self.with_composition(composition_options.composition_operation, |self_| {
             self_.scene.fill(
                 fill_rule.convert(),
                 transform.cast().into(),
                 &convert_to_brush(style, composition_options),
-                None,
+                brush_transform,
                 &path.0,
             );
         })
@@ -386,17 +387,22 @@ impl GenericDrawTarget for VelloDrawTarget {
         text_runs: Vec<TextRun>,
         start: Point2D<f32>,
         style: FillOrStrokeStyle,
+        hinting: GlyphHinting,
         composition_options: CompositionOptions,
         transform: Transform2D<f32>,
     ) {
         self.ensure_drawing();
+        // vello does not hint glyphs unless asked to.
+        let hint = match hinting {
+            GlyphHinting::Platform => false,
+            GlyphHinting::None => false,
+            GlyphHinting::Light | GlyphHinting::Full => true,
+        };
         let pattern = convert_to_brush(style, composition_options);
-        let transform = transform.cast().into();
+        let affine = transform.cast().into();
         self.with_composition(composition_options.composition_operation, |self_| {
-            let mut advance = 0.;
+            let mut pen = start;
             for run in text_runs.iter() {
-                let glyphs = &run.glyphs;
-
                 let template = &run.font.template;
 
                 SHARED_FONT_CACHE.with(|font_cache| {
@@ -419,23 +425,24 @@ impl GenericDrawTarget for VelloDrawTarget {
                     self_
                         .scene
                         .draw_glyphs(font)
-                        .transform(transform)
+                        .transform(affine)
                         .brush(&pattern)
                         .font_size(run.font.descriptor.pt_size.to_f32_px())
+                        .hint(hint)
                         .draw(
                             peniko::Fill::NonZero,
-                            glyphs
-                                .iter_glyphs_for_byte_range(&Range::new(ByteIndex(0), glyphs.len()))
-                                .map(|glyph| {
-                                    let glyph_offset = glyph.offset().unwrap_or(Point2D::zero());
-                                    let x = advance + start.x + glyph_offset.x.to_f32_px();
-                                    let y = start.y + glyph_offset.y.to_f32_px();
-                                    advance += glyph.advance().to_f32_px();
-                                    vello::Glyph {
-                                        id: glyph.id(),
-                                        x,
-                                        y,
-                                    }
+                            positioned_glyphs(
+                                &run.glyphs,
+                                &mut pen,
+                                run.pixel_snapped.then_some(&transform),
+                            )
+                                .enumerate()
+                                .filter_map(|(index, (id, position))| {
+                                    run.visible_glyphs.contains(&index).then_some(vello::Glyph {
+                                        id,
+                                        x: position.x,
+                                        y: position.y,
+                                    })
                                 }),
                         );
                 });
@@ -451,13 +458,18 @@ impl GenericDrawTarget for VelloDrawTarget {
         transform: Transform2D<f32>,
     ) {
         self.ensure_drawing();
+        let brush_transform = brush_transform(&style);
         let pattern = convert_to_brush(style, composition_options);
         let transform = transform.cast().into();
         let rect: kurbo::Rect = rect.cast().into();
         self.with_composition(composition_options.composition_operation, |self_| {
-            self_
-                .scene
-                .fill(peniko::Fill::NonZero, transform, &pattern, None, &rect);
+            self_.scene.fill(
+                peniko::Fill::NonZero,
+                transform,
+                &pattern,
+                brush_transform,
+                &rect,
+            );
         })
     }
 
@@ -500,12 +512,13 @@ impl GenericDrawTarget for VelloDrawTarget {
         transform: Transform2D<f32>,
     ) {
         self.ensure_drawing();
+        let brush_transform = brush_transform(&style);
         self.with_composition(composition_options.composition_operation, |self_| {
             self_.scene.stroke(
                 &line_options.convert(),
                 transform.cast().into(),
                 &convert_to_brush(style, composition_options),
-                None,
+                brush_transform,
                 &path.0,
             );
         })
@@ -521,12 +534,13 @@ impl GenericDrawTarget for VelloDrawTarget {
     ) {
         self.ensure_drawing();
         let rect: kurbo::Rect = rect.cast().into();
+        let brush_transform = brush_transform(&style);
         self.with_composition(composition_options.composition_operation, |self_| {
             self_.scene.stroke(
                 &line_options.convert(),
                 transform.cast().into(),
                 &convert_to_brush(style, composition_options),
-                None,
+                brush_transform,
                 &rect,
             );
         })
@@ -615,6 +629,17 @@ fn convert_to_brush(
     brush.multiply_alpha(composition_options.alpha as f32)
 }
 
+/// The brush transform that scales a pattern tile denser than its size in pattern space
+/// back to that size.
+fn brush_transform(style: &FillOrStrokeStyle) -> Option<kurbo::Affine> {
+    let FillOrStrokeStyle::Surface(surface_style) = style else {
+        return None;
+    };
+    let scale = surface_style.surface_data_scale();
+    (scale != Transform2D::identity())
+        .then(|| kurbo::Affine::scale_non_uniform(1. / scale.m11 as f64, 1. / scale.m22 as f64))
+}
+
 impl VelloDrawTarget {
     fn render_to_texture(&mut self) {
         if matches!(
@@ -8,20 +8,19 @@ use std::sync::Arc;
 
 use canvas_traits::canvas::{
     CompositionOptions, CompositionOrBlending, CompositionStyle, FillOrStrokeStyle, FillRule,
-    LineOptions, Path, ShadowOptions,
+    GlyphHinting, LineOptions, Path,
 };
 use compositing_traits::SerializableImageData;
 use euclid::default::{Point2D, Rect, Size2D, Transform2D};
-use fonts::{ByteIndex, FontIdentifier, FontTemplateRefMethods as _};
+use fonts::{FontIdentifier, FontTemplateRefMethods as _};
 use ipc_channel::ipc::IpcSharedMemory;
 use kurbo::Shape;
-use pixels::{Snapshot, SnapshotAlphaMode, SnapshotPixelFormat};
-use range::Range;
+use pixels::{Snapshot, SnapshotAlphaMode, SnapshotPixelFormat, unmultiply_inplace};
 use vello_cpu::{kurbo, peniko};
 use webrender_api::{ImageDescriptor, ImageDescriptorFlags};
 
 use crate::backend::{Convert, GenericDrawTarget};
-use crate::canvas_data::{Filter, TextRun};
+use crate::canvas_data::{Filter, TextRun, positioned_glyphs};
 
 thread_local! {
     /// The shared font cache used by all canvases that render on a thread. It would be nicer
@@ -57,6 +56,22 @@ pub(crate) struct VelloCPUDrawTarget {
 }
 
 impl VelloCPUDrawTarget {
+    /// Sets `style` as the paint. A pattern tile denser than its size in pattern space also
+    /// sets a paint transform, which the caller must reset once done drawing.
+    fn set_paint(&mut self, style: FillOrStrokeStyle, alpha: f64) {
+        if let FillOrStrokeStyle::Surface(surface_style) = &style {
+            let scale = surface_style.surface_data_scale();
+            if scale != Transform2D::identity() {
+                self.ctx
+                    .set_paint_transform(kurbo::Affine::scale_non_uniform(
+                        1. / scale.m11 as f64,
+                        1. / scale.m22 as f64,
+                    ));
+            }
+        }
+        self.ctx.set_paint(paint(style, alpha));
+    }
+
     fn with_composition(
         &mut self,
         composition_operation: CompositionOrBlending,
@@ -68,6 +83,8 @@ impl VelloCPUDrawTarget {
             f(self);
             return;
         }
+        // The layer is nested in the clip layers, so operations like `copy` that affect
+        // pixels outside of the drawn shape are still bounded by the active clip.
         self.ctx.push_blend_layer(composition_operation.convert());
         f(self);
         self.ctx.pop_layer();
@@ -250,22 +267,6 @@ impl GenericDrawTarget for VelloCPUDrawTarget {
         })
     }
 
-    fn draw_surface_with_shadow(
-        &self,
-        _surface: Self::SourceSurface,
-        _dest: &Point2D<f32>,
-        _shadow_options: ShadowOptions,
-        _composition_options: CompositionOptions,
-    ) {
-        log::warn!("no support for drawing shadows");
-        /*
-        We will need to do some changes to support drawing shadows with vello, as current abstraction is made for azure.
-        In vello we do not need new draw target (we will use layers) and we need to pass whole rect.
-        offsets will be applied to rect directly. shadow blur will be passed directly to let backend do transforms.
-        */
-        //self_.scene.draw_blurred_rounded_rect(self_.transform, rect, color, 0.0, sigma);
-    }
-
     fn fill(
         &mut self,
         path: &Path,
@@ -278,8 +279,9 @@ impl GenericDrawTarget for VelloCPUDrawTarget {
         self.with_composition(composition_options.composition_operation, |self_| {
             self_.ctx.set_transform(transform.cast().into());
             self_.ctx.set_fill_rule(fill_rule.convert());
-            self_.ctx.set_paint(paint(style, composition_options.alpha));
+            self_.set_paint(style, composition_options.alpha);
             self_.ctx.fill_path(&path.0);
+            self_.ctx.reset_paint_transform();
         });
         self.ctx.set_fill_rule(peniko::Fill::NonZero);
     }
@@ -289,17 +291,22 @@ impl GenericDrawTarget for VelloCPUDrawTarget {
         text_runs: Vec<TextRun>,
         start: Point2D<f32>,
         style: FillOrStrokeStyle,
+        hinting: GlyphHinting,
         composition_options: CompositionOptions,
         transform: Transform2D<f32>,
     ) {
         self.ensure_drawing();
+        // vello_cpu hints glyphs unless asked not to.
+        let hint = match hinting {
+            GlyphHinting::Platform => true,
+            GlyphHinting::None => false,
+            GlyphHinting::Light | GlyphHinting::Full => true,
+        };
         self.ctx.set_paint(paint(style, composition_options.alpha));
         self.ctx.set_transform(transform.cast().into());
         self.with_composition(composition_options.composition_operation, |self_| {
-            let mut advance = 0.;
+            let mut pen = start;
             for run in text_runs.iter() {
-                let glyphs = &run.glyphs;
-
                 let template = &run.font.template;
 
                 SHARED_FONT_CACHE.with(|font_cache| {
@@ -323,19 +330,22 @@ impl GenericDrawTarget for VelloCPUDrawTarget {
                         .ctx
                         .glyph_run(font)
                         .font_size(run.font.descriptor.pt_size.to_f32_px())
+                        .hint(hint)
                         .fill_glyphs(
-                            glyphs
-                                .iter_glyphs_for_byte_range(&Range::new(ByteIndex(0), glyphs.len()))
-                                .map(|glyph| {
-                                    let glyph_offset = glyph.offset().unwrap_or(Point2D::zero());
-                                    let x = advance + start.x + glyph_offset.x.to_f32_px();
-                                    let y = start.y + glyph_offset.y.to_f32_px();
-                                    advance += glyph.advance().to_f32_px();
-                                    vello_cpu::Glyph {
-                                        id: glyph.id(),
-                                        x,
-                                        y,
-                                    }
+                            positioned_glyphs(
+                                &run.glyphs,
+                                &mut pen,
+                                run.pixel_snapped.then_some(&transform),
+                            )
+                                .enumerate()
+                                .filter_map(|(index, (id, position))| {
+                                    run.visible_glyphs.contains(&index).then_some(
+                                        vello_cpu::Glyph {
+                                            id,
+                                            x: position.x,
+                                            y: position.y,
+                                        },
+                                    )
                                 }),
                         );
                 });
@@ -353,8 +363,9 @@ impl GenericDrawTarget for VelloCPUDrawTarget {
         self.ensure_drawing();
         self.with_composition(composition_options.composition_operation, |self_| {
             self_.ctx.set_transform(transform.cast().into());
-            self_.ctx.set_paint(paint(style, composition_options.alpha));
+            self_.set_paint(style, composition_options.alpha);
             self_.ctx.fill_rect(&rect.cast().into());
+            self_.ctx.reset_paint_transform();
         })
     }
 
@@ -401,9 +412,10 @@ impl GenericDrawTarget for VelloCPUDrawTarget {
         self.ensure_drawing();
         self.with_composition(composition_options.composition_operation, |self_| {
             self_.ctx.set_transform(transform.cast().into());
-            self_.ctx.set_paint(paint(style, composition_options.alpha));
+            self_.set_paint(style, composition_options.alpha);
             self_.ctx.set_stroke(line_options.convert());
             self_.ctx.stroke_path(&path.0);
+            self_.ctx.reset_paint_transform();
         })
     }
 
@@ -418,9 +430,10 @@ impl GenericDrawTarget for VelloCPUDrawTarget {
         self.ensure_drawing();
         self.with_composition(composition_options.composition_operation, |self_| {
             self_.ctx.set_transform(transform.cast().into());
-            self_.ctx.set_paint(paint(style, composition_options.alpha));
+            self_.set_paint(style, composition_options.alpha);
             self_.ctx.set_stroke(line_options.convert());
             self_.ctx.stroke_rect(&rect.cast().into());
+            self_.ctx.reset_paint_transform();
         })
     }
 
@@ -449,6 +462,13 @@ impl GenericDrawTarget for VelloCPUDrawTarget {
         )
     }
 
+    fn get_pixel(&mut self, point: Point2D<u32>) -> [u8; 4] {
+        let index = (point.y as usize * self.size().width as usize + point.x as usize) * 4;
+        let mut pixel: [u8; 4] = self.pixmap()[index..index + 4].try_into().unwrap();
+        unmultiply_inplace::<false>(&mut pixel);
+        pixel
+    }
+
     fn surface(&mut self) -> Self::SourceSurface {
         self.pixmap(); // sync pixmap
         Arc::new(vello_cpu::Pixmap::from_parts(
@@ -488,6 +508,9 @@ impl Convert<vello_cpu::PaintType> for FillOrStrokeStyle {
                 let end = kurbo::Point::new(style.x1, style.y1);
                 let mut gradient = peniko::Gradient::new_linear(start, end);
                 gradient.stops = style.stops.convert();
+                if style.repeating {
+                    gradient.extend = peniko::Extend::Repeat;
+                }
                 vello_cpu::PaintType::Gradient(gradient)
             },
             RadialGradient(style) => {
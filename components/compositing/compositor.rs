@@ -25,7 +25,7 @@ use constellation_traits::{EmbedderToConstellationMessage, PaintMetricEvent};
 use crossbeam_channel::{Receiver, Sender};
 use dpi::PhysicalSize;
 use embedder_traits::{CompositorHitTestResult, InputEvent, ShutdownState, ViewportDetails};
-use euclid::{Point2D, Rect, Scale, Size2D, Transform3D};
+use euclid::{Box2D, Point2D, Rect, Scale, Size2D, Transform3D};
 use ipc_channel::ipc::{self, IpcSharedMemory};
 use log::{debug, info, trace, warn};
 use pixels::{CorsStatus, ImageFrame, ImageMetadata, PixelFormat, RasterImage};
@@ -715,6 +715,23 @@ impl IOCompositor {
                         ImageUpdate::UpdateImage(key, desc, data) => {
                             txn.update_image(key, desc, data.into(), &DirtyRect::All)
                         },
+                        ImageUpdate::UpdateImagePartially(key, desc, data, damage) => {
+                            let dirty_rect = damage
+                                .iter()
+                                .map(|rect| Box2D::from_untyped(&rect.to_box2d().to_i32()))
+                                .fold(Box2D::zero(), |dirty_rect, rect| dirty_rect.union(&rect));
+                            txn.update_image(
+                                key,
+                                desc,
+                                data.into(),
+                                &DirtyRect::Partial(dirty_rect),
+                            )
+                        },
+                        // WebRender filters an image as the display items that draw it ask,
+                        // and layout builds those from the `image-rendering` of the element.
+                        ImageUpdate::SetImageRendering(..) => {},
+                        // Presentation is not paced per image yet.
+                        ImageUpdate::SetFrameTime(..) => {},
                     }
                 }
                 self.global.borrow_mut().send_transaction(txn);
@@ -84,6 +84,20 @@ pub struct Preferences {
     /// - vello
     /// - vello_cpu
     pub dom_canvas_backend: String,
+    /// Blend the results of 2D canvas drawing operations in linear light rather than in
+    /// the sRGB encoding that canvases store their pixels in.
+    pub dom_canvas_linear_blending_enabled: bool,
+    /// The percentage of their resolution that the backing stores of 2D canvases are
+    /// allocated at, trading quality for speed.
+    pub dom_canvas_render_scale_percent: i64,
+    /// The number of messages that the canvas paint thread processes for each canvas in a
+    /// frame, deferring the others to later frames, so that one canvas flooding it with work
+    /// can't starve the others. Zero means no limit.
+    pub dom_canvas_operation_budget: i64,
+    /// Hold back the uploads of the images of 2D canvases until the compositor starts a
+    /// frame, so that canvases drawing faster than the display refreshes don't upload images
+    /// that are never shown.
+    pub dom_canvas_frame_pacing_enabled: bool,
     pub dom_clipboardevent_enabled: bool,
     pub dom_composition_event_enabled: bool,
     pub dom_cookiestore_enabled: bool,
@@ -264,6 +278,10 @@ impl Preferences {
             dom_canvas_capture_enabled: false,
             dom_canvas_text_enabled: true,
             dom_canvas_backend: String::new(),
+            dom_canvas_linear_blending_enabled: false,
+            dom_canvas_render_scale_percent: 100,
+            dom_canvas_operation_budget: 0,
+            dom_canvas_frame_pacing_enabled: false,
             dom_clipboardevent_enabled: true,
             dom_composition_event_enabled: false,
             dom_cookiestore_enabled: false,
@@ -93,6 +93,7 @@ use std::mem::replace;
 use std::rc::{Rc, Weak};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::Duration;
 use std::{process, thread};
 
 use background_hang_monitor::HangMonitorRegister;
@@ -106,9 +107,9 @@ use base::id::{
 };
 #[cfg(feature = "bluetooth")]
 use bluetooth_traits::BluetoothRequest;
-use canvas::canvas_paint_thread::CanvasPaintThread;
+use canvas::canvas_paint_thread::{CanvasPaintThread, DEFAULT_IMAGE_CACHE_CAPACITY};
 use canvas_traits::ConstellationCanvasMsg;
-use canvas_traits::canvas::{CanvasId, CanvasMsg};
+use canvas_traits::canvas::{Canvas2dMsg, CanvasCreationOptions, CanvasError, CanvasId, CanvasMsg};
 use canvas_traits::webgl::WebGLThreads;
 use compositing_traits::{
     CompositorMsg, CompositorProxy, PipelineExitSource, SendableFrameTree,
@@ -124,8 +125,9 @@ use constellation_traits::{
 };
 use crossbeam_channel::{Receiver, Select, Sender, unbounded};
 use devtools_traits::{
-    ChromeToDevtoolsControlMsg, DevtoolsControlMsg, DevtoolsPageInfo, NavigationState,
-    ScriptToDevtoolsControlMsg,
+    ChromeToDevtoolsControlMsg, ConsoleMessageArgument, ConsoleMessageBuilder,
+    DevtoolsControlMsg, DevtoolsPageInfo, LogLevel, NavigationState, ScriptToDevtoolsControlMsg,
+    TimelineMarker,
 };
 use embedder_traits::resources::{self, Resource};
 use embedder_traits::user_content_manager::UserContentManager;
@@ -153,6 +155,7 @@ use net_traits::{
     self, AsyncRuntime, IpcSend, ReferrerPolicy, ResourceThreads, exit_fetch_thread,
     start_fetch_thread,
 };
+use pixels::EncodedImageType;
 use profile_traits::mem::ProfilerMsg;
 use profile_traits::{mem, time};
 use script_traits::{
@@ -436,6 +439,23 @@ pub struct Constellation<STF, SWF> {
     /// Lazily initialized channels for canvas paint thread.
     canvas: OnceCell<(Sender<ConstellationCanvasMsg>, IpcSender<CanvasMsg>)>,
 
+    /// The seed of any randomness that affects rendering, if the embedder set one.
+    render_seed: Option<u64>,
+
+    /// Where the canvas paint thread reports the processing of its messages, while devtools
+    /// records a timeline.
+    canvas_timeline_marker_sender: Option<IpcSender<Option<TimelineMarker>>>,
+
+    /// A channel for the canvas paint thread to report the errors raised by the messages of
+    /// each canvas.
+    canvas_error_sender: Sender<(CanvasId, CanvasError)>,
+
+    /// The receiving end of `canvas_error_sender`.
+    canvas_error_receiver: Receiver<(CanvasId, CanvasError)>,
+
+    /// The pipeline that created each canvas, whose console its errors are reported to.
+    canvas_pipelines: HashMap<CanvasId, PipelineId>,
+
     /// Navigation requests from script awaiting approval from the embedder.
     pending_approval_navigations: PendingApprovalNavigations,
 
@@ -654,6 +674,8 @@ where
 
                 let rippy_data = resources::read_bytes(Resource::RippyPNG);
 
+                let (canvas_error_sender, canvas_error_receiver) = unbounded();
+
                 let mut constellation: Constellation<STF, SWF> = Constellation {
                     namespace_receiver,
                     namespace_ipc_sender,
@@ -709,6 +731,11 @@ where
                     webgl_threads: state.webgl_threads,
                     webxr_registry: state.webxr_registry,
                     canvas: OnceCell::new(),
+                    render_seed: None,
+                    canvas_timeline_marker_sender: None,
+                    canvas_error_sender,
+                    canvas_error_receiver,
+                    canvas_pipelines: HashMap::new(),
                     pending_approval_navigations: HashMap::new(),
                     pressed_mouse_buttons: 0,
                     active_keyboard_modifiers: Modifiers::empty(),
@@ -1164,6 +1191,7 @@ where
             BackgroundHangMonitor(HangMonitorAlert),
             Compositor(EmbedderToConstellationMessage),
             FromSWManager(SWManagerMsg),
+            CanvasError((CanvasId, CanvasError)),
             RemoveProcess(usize),
         }
         // Get one incoming request.
@@ -1183,6 +1211,7 @@ where
         sel.recv(&self.background_hang_monitor_receiver);
         sel.recv(&self.compositor_receiver);
         sel.recv(&self.swmanager_receiver);
+        sel.recv(&self.canvas_error_receiver);
 
         self.process_manager.register(&mut sel);
 
@@ -1214,9 +1243,13 @@ where
                     .recv(&self.swmanager_receiver)
                     .expect("Unexpected SW channel panic in constellation")
                     .map(Request::FromSWManager),
+                5 => Ok(Request::CanvasError(
+                    oper.recv(&self.canvas_error_receiver)
+                        .expect("Unexpected canvas error channel panic in constellation"),
+                )),
                 _ => {
                     // This can only be a error reading on a closed lifeline receiver.
-                    let process_index = index - 5;
+                    let process_index = index - 6;
                     let _ = oper.recv(self.process_manager.receiver_at(process_index));
                     Ok(Request::RemoveProcess(process_index))
                 },
@@ -1242,6 +1275,11 @@ where
             Request::FromSWManager(message) => {
                 self.handle_request_from_swmanager(message);
             },
+            Request::CanvasError((canvas_id, error)) => {
+                if let Some(pipeline_id) = self.canvas_pipelines.get(&canvas_id) {
+                    self.report_canvas_error(*pipeline_id, error);
+                }
+            },
             Request::RemoveProcess(index) => self.process_manager.remove(index),
         }
     }
@@ -1431,6 +1469,12 @@ where
             EmbedderToConstellationMessage::ThemeChange(webview_id, theme) => {
                 self.handle_theme_change(webview_id, theme);
             },
+            EmbedderToConstellationMessage::PinAnimationTimeline(webview_id, time) => {
+                self.handle_pin_animation_timeline(webview_id, time);
+            },
+            EmbedderToConstellationMessage::SetRenderSeed(seed) => {
+                self.handle_set_render_seed(seed);
+            },
             EmbedderToConstellationMessage::TickAnimation(webview_ids) => {
                 self.handle_tick_animation(webview_ids)
             },
@@ -1762,8 +1806,73 @@ where
                     warn!("Error replying to remove iframe ({})", e);
                 }
             },
-            ScriptToConstellationMessage::CreateCanvasPaintThread(size, response_sender) => {
-                self.handle_create_canvas_paint_thread_msg(size, response_sender)
+            ScriptToConstellationMessage::CreateCanvasPaintThread(
+                size,
+                options,
+                response_sender,
+            ) => self.handle_create_canvas_paint_thread_msg(
+                source_pipeline_id,
+                size,
+                options,
+                response_sender,
+            ),
+            ScriptToConstellationMessage::GetMaxCanvasSize(response_sender) => {
+                if let Err(e) = response_sender.send(CanvasPaintThread::size_limits()) {
+                    warn!("Max canvas size response failed ({})", e);
+                }
+            },
+            ScriptToConstellationMessage::GetSupportedEncodeFormats(response_sender) => {
+                if let Err(e) = response_sender.send(EncodedImageType::supported()) {
+                    warn!("Supported encode formats response failed ({})", e);
+                }
+            },
+            ScriptToConstellationMessage::ListAvailableFonts(response_sender) => {
+                let families = self.system_font_service.available_families();
+                if let Err(e) = response_sender.send(families) {
+                    warn!("Available fonts response failed ({})", e);
+                }
+            },
+            ScriptToConstellationMessage::SetCanvasTimelineMarkerSender(sender) => {
+                self.handle_set_canvas_timeline_marker_sender(sender)
+            },
+            ScriptToConstellationMessage::GetCanvasDrawStats(canvas_id, response_sender) => self
+                .handle_canvas_query_msg(
+                    source_pipeline_id,
+                    canvas_id,
+                    Canvas2dMsg::GetDrawStats(response_sender),
+                ),
+            ScriptToConstellationMessage::ResetCanvasDrawStats(canvas_id) => self
+                .handle_canvas_query_msg(
+                    source_pipeline_id,
+                    canvas_id,
+                    Canvas2dMsg::ResetDrawStats,
+                ),
+            ScriptToConstellationMessage::GetCanvasMemoryUsage(canvas_id, response_sender) => self
+                .handle_canvas_query_msg(
+                    source_pipeline_id,
+                    canvas_id,
+                    Canvas2dMsg::GetMemoryUsage(response_sender),
+                ),
+            ScriptToConstellationMessage::BlendSnapshots(
+                base,
+                overlay,
+                composition_operation,
+                alpha,
+                response_sender,
+            ) => {
+                let (_, canvas_ipc_sender) = self
+                    .canvas
+                    .get_or_init(|| self.create_canvas_paint_thread());
+                let message = CanvasMsg::BlendSnapshots(
+                    base,
+                    overlay,
+                    composition_operation,
+                    alpha,
+                    response_sender,
+                );
+                if let Err(error) = canvas_ipc_sender.send(message) {
+                    warn!("Failed to send snapshots to blend to canvas paint thread ({error:?}).");
+                }
             },
             ScriptToConstellationMessage::SetDocumentState(state) => {
                 self.document_states.insert(source_pipeline_id, state);
@@ -2699,6 +2808,8 @@ where
         let Some(pipeline) = self.pipelines.remove(&pipeline_id) else {
             return;
         };
+        self.canvas_pipelines
+            .retain(|_, canvas_pipeline_id| *canvas_pipeline_id != pipeline_id);
 
         // Now that the Script and Constellation parts of Servo no longer have a reference to
         // this pipeline, tell the compositor that it has shut down. This is delayed until the
@@ -3491,6 +3602,17 @@ where
             // some other message.
             let _ = event_loop.send(ScriptThreadMessage::TickAllAnimations(webview_ids.clone()));
         }
+
+        // Canvases that pace the updates of their images send them on this frame tick. They
+        // all do when frame pacing is enabled, so there is nothing to tick without canvases.
+        if !pref!(dom_canvas_frame_pacing_enabled) || self.canvas_pipelines.is_empty() {
+            return;
+        }
+        if let Some((canvas_sender, _)) = self.canvas.get() {
+            if let Err(error) = canvas_sender.send(ConstellationCanvasMsg::Vsync) {
+                warn!("Failed to send vsync to canvas paint thread ({error:?}).");
+            }
+        }
     }
 
     /// Schedule a navigation(via load_url).
@@ -4457,7 +4579,9 @@ where
     #[servo_tracing::instrument(skip_all)]
     fn handle_create_canvas_paint_thread_msg(
         &mut self,
+        source_pipeline_id: PipelineId,
         size: UntypedSize2D<u64>,
+        options: CanvasCreationOptions,
         response_sender: IpcSender<Option<(IpcSender<CanvasMsg>, CanvasId, ImageKey)>>,
     ) {
         let (canvas_data_sender, canvas_data_receiver) = unbounded();
@@ -4468,15 +4592,20 @@ where
         let response = if let Err(e) = canvas_sender.send(ConstellationCanvasMsg::Create {
             sender: canvas_data_sender,
             size,
+            options,
         }) {
             warn!("Create canvas paint thread failed ({})", e);
             None
         } else {
             match canvas_data_receiver.recv() {
-                Ok(Some((canvas_id, image_key))) => {
+                Ok(Ok((canvas_id, image_key))) => {
+                    self.canvas_pipelines.insert(canvas_id, source_pipeline_id);
                     Some((canvas_ipc_sender.clone(), canvas_id, image_key))
                 },
-                Ok(None) => None,
+                Ok(Err(error)) => {
+                    self.report_canvas_error(source_pipeline_id, error);
+                    None
+                },
                 Err(e) => {
                     warn!("Create canvas paint thread id response failed ({})", e);
                     None
@@ -5230,6 +5359,88 @@ where
         }
     }
 
+    /// Forward a request to pin the animation timeline to all `ScriptThread`s with a pipeline
+    /// in the given `WebView`.
+    #[servo_tracing::instrument(skip_all)]
+    fn handle_pin_animation_timeline(&mut self, webview_id: WebViewId, time: Duration) {
+        for pipeline in self.pipelines.values() {
+            if pipeline.webview_id != webview_id {
+                continue;
+            }
+            if let Err(error) = pipeline
+                .event_loop
+                .send(ScriptThreadMessage::PinAnimationTimeline(pipeline.id, time))
+            {
+                warn!(
+                    "{}: Failed to send animation timeline pin to pipeline ({error:?}).",
+                    pipeline.id,
+                );
+            }
+        }
+    }
+
+    /// Remember the seed of the randomness that affects rendering, and forward it to the
+    /// canvas paint thread if it was already started.
+    #[servo_tracing::instrument(skip_all)]
+    fn handle_set_render_seed(&mut self, seed: Option<u64>) {
+        self.render_seed = seed;
+        if let Some((canvas_sender, _)) = self.canvas.get() {
+            if let Err(error) = canvas_sender.send(ConstellationCanvasMsg::SetRenderSeed(seed)) {
+                warn!("Failed to send render seed to canvas paint thread ({error:?}).");
+            }
+        }
+    }
+
+    /// Reports an error of a canvas created by `pipeline_id` to the console of the pipeline,
+    /// where devtools shows it.
+    fn report_canvas_error(&self, pipeline_id: PipelineId, error: CanvasError) {
+        let Some(devtools_sender) = &self.devtools_sender else {
+            return;
+        };
+        let mut message = ConsoleMessageBuilder::new(LogLevel::Warn, String::new(), 0, 0);
+        message.add_argument(ConsoleMessageArgument::String(error.to_string()));
+        let _ = devtools_sender.send(DevtoolsControlMsg::FromScript(
+            ScriptToDevtoolsControlMsg::ConsoleAPI(pipeline_id, message.finish(), None),
+        ));
+    }
+
+    /// Forward `message` to a canvas, unless it wasn't created by `source_pipeline_id`, in
+    /// which case the sender of any reply is dropped.
+    #[servo_tracing::instrument(skip_all)]
+    fn handle_canvas_query_msg(
+        &self,
+        source_pipeline_id: PipelineId,
+        canvas_id: CanvasId,
+        message: Canvas2dMsg,
+    ) {
+        if self.canvas_pipelines.get(&canvas_id) != Some(&source_pipeline_id) {
+            return warn!("{source_pipeline_id}: Query of canvas {canvas_id:?} it didn't create");
+        }
+        let Some((_, canvas_ipc_sender)) = self.canvas.get() else {
+            return;
+        };
+        if let Err(error) = canvas_ipc_sender.send(CanvasMsg::Canvas2d(message, canvas_id)) {
+            warn!("Failed to send query to canvas paint thread ({error:?}).");
+        }
+    }
+
+    /// Remember where devtools wants the timeline markers of the canvas paint thread, and
+    /// forward it to the thread if it was already started.
+    #[servo_tracing::instrument(skip_all)]
+    fn handle_set_canvas_timeline_marker_sender(
+        &mut self,
+        sender: Option<IpcSender<Option<TimelineMarker>>>,
+    ) {
+        self.canvas_timeline_marker_sender = sender.clone();
+        if let Some((canvas_sender, _)) = self.canvas.get() {
+            if let Err(error) =
+                canvas_sender.send(ConstellationCanvasMsg::SetTimelineMarkerSender(sender))
+            {
+                warn!("Failed to send timeline marker sender to canvas paint thread ({error:?}).");
+            }
+        }
+    }
+
     // Handle switching from fullscreen mode
     #[servo_tracing::instrument(skip_all)]
     fn switch_fullscreen_mode(&mut self, browsing_context_id: BrowsingContextId) {
@@ -5607,10 +5818,35 @@ where
     }
 
     fn create_canvas_paint_thread(&self) -> (Sender<ConstellationCanvasMsg>, IpcSender<CanvasMsg>) {
-        CanvasPaintThread::start(
+        let (canvas_sender, canvas_ipc_sender) = CanvasPaintThread::start(
             self.compositor_proxy.cross_process_compositor_api.clone(),
             self.system_font_service.clone(),
             self.public_resource_threads.clone(),
-        )
+            DEFAULT_IMAGE_CACHE_CAPACITY,
+            Some(self.canvas_error_sender.clone()),
+        );
+        if self.render_seed.is_some() {
+            if let Err(error) =
+                canvas_sender.send(ConstellationCanvasMsg::SetRenderSeed(self.render_seed))
+            {
+                warn!("Failed to send render seed to canvas paint thread ({error:?}).");
+            }
+        }
+        if let Some(sender) = self.canvas_timeline_marker_sender.clone() {
+            if let Err(error) =
+                canvas_sender.send(ConstellationCanvasMsg::SetTimelineMarkerSender(Some(sender)))
+            {
+                warn!("Failed to send timeline marker sender to canvas paint thread ({error:?}).");
+            }
+        }
+        let operation_budget = pref!(dom_canvas_operation_budget);
+        if operation_budget > 0 {
+            if let Err(error) = canvas_sender.send(ConstellationCanvasMsg::SetOperationBudget(
+                Some(operation_budget as usize),
+            )) {
+                warn!("Failed to send operation budget to canvas paint thread ({error:?}).");
+            }
+        }
+        (canvas_sender, canvas_ipc_sender)
     }
 }
@@ -57,6 +57,8 @@ mod from_compositor {
                 Self::TraverseHistory(..) => target!("TraverseHistory"),
                 Self::ChangeViewportDetails(..) => target!("ChangeViewportDetails"),
                 Self::ThemeChange(..) => target!("ThemeChange"),
+                Self::PinAnimationTimeline(..) => target!("PinAnimationTimeline"),
+                Self::SetRenderSeed(..) => target!("SetRenderSeed"),
                 Self::TickAnimation(..) => target!("TickAnimation"),
                 Self::WebDriverCommand(..) => target!("WebDriverCommand"),
                 Self::Reload(..) => target!("Reload"),
@@ -141,6 +143,16 @@ mod from_script {
                 Self::BroadcastStorageEvent(..) => target!("BroadcastStorageEvent"),
                 Self::ChangeRunningAnimationsState(..) => target!("ChangeRunningAnimationsState"),
                 Self::CreateCanvasPaintThread(..) => target!("CreateCanvasPaintThread"),
+                Self::GetMaxCanvasSize(..) => target!("GetMaxCanvasSize"),
+                Self::GetSupportedEncodeFormats(..) => target!("GetSupportedEncodeFormats"),
+                Self::ListAvailableFonts(..) => target!("ListAvailableFonts"),
+                Self::SetCanvasTimelineMarkerSender(..) => {
+                    target!("SetCanvasTimelineMarkerSender")
+                },
+                Self::GetCanvasDrawStats(..) => target!("GetCanvasDrawStats"),
+                Self::ResetCanvasDrawStats(..) => target!("ResetCanvasDrawStats"),
+                Self::GetCanvasMemoryUsage(..) => target!("GetCanvasMemoryUsage"),
+                Self::BlendSnapshots(..) => target!("BlendSnapshots"),
                 Self::Focus(..) => target!("Focus"),
                 Self::FocusRemoteDocument(..) => target!("FocusRemoteDocument"),
                 Self::GetTopForBrowsingContext(..) => target!("GetTopForBrowsingContext"),
@@ -134,7 +134,11 @@ impl TimelineActor {
         pipeline_id: PipelineId,
         script_sender: IpcSender<DevtoolScriptControlMsg>,
     ) -> TimelineActor {
-        let marker_types = vec![TimelineMarkerType::Reflow, TimelineMarkerType::DOMEvent];
+        let marker_types = vec![
+            TimelineMarkerType::Reflow,
+            TimelineMarkerType::DOMEvent,
+            TimelineMarkerType::Canvas,
+        ];
 
         TimelineActor {
             name,
@@ -4,6 +4,7 @@
 
 use std::borrow::ToOwned;
 use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, OnceLock};
 use std::time::Instant;
@@ -48,6 +49,7 @@ pub const KERN: u32 = ot_tag!('k', 'e', 'r', 'n');
 pub const SBIX: u32 = ot_tag!('s', 'b', 'i', 'x');
 pub const CBDT: u32 = ot_tag!('C', 'B', 'D', 'T');
 pub const COLR: u32 = ot_tag!('C', 'O', 'L', 'R');
+pub const CPAL: u32 = ot_tag!('C', 'P', 'A', 'L');
 pub const BASE: u32 = ot_tag!('B', 'A', 'S', 'E');
 
 pub const LAST_RESORT_GLYPH_ADVANCE: FractionalPixel = 10.0;
@@ -227,6 +229,99 @@ impl malloc_size_of::MallocSizeOf for CachedShapeData {
     }
 }
 
+/// A layer of a color glyph, which is filled in a single color.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorGlyphLayer {
+    /// The glyph whose outline is filled.
+    pub glyph_id: GlyphId,
+    /// The non-premultiplied RGBA color of the layer, from the first palette of the font, or
+    /// `None` if the layer is filled with the color of the text.
+    pub color: Option<[u8; 4]>,
+}
+
+/// The color glyphs of version 0 of a `COLR` table, which are drawn as layers of other
+/// glyphs in the colors of the first palette of the `CPAL` table.
+/// <https://learn.microsoft.com/en-us/typography/opentype/spec/colr>
+#[derive(Debug, Default)]
+pub struct ColorGlyphs {
+    /// The glyphs that have layers, in ascending order, with the range of their layers in
+    /// `layers`.
+    base_glyphs: Vec<(GlyphId, Range<usize>)>,
+    layers: Vec<ColorGlyphLayer>,
+}
+
+impl ColorGlyphs {
+    /// The palette index of layers that are filled with the color of the text.
+    const FOREGROUND_PALETTE_INDEX: u16 = 0xFFFF;
+
+    /// Reads the color glyphs of the `COLR` and `CPAL` tables, returning `None` if they are
+    /// truncated or have no color glyphs.
+    pub fn parse(colr: &[u8], cpal: &[u8]) -> Option<ColorGlyphs> {
+        let u16_at = |table: &[u8], offset: usize| {
+            Some(u16::from_be_bytes(table.get(offset..offset + 2)?.try_into().ok()?))
+        };
+        let u32_at = |table: &[u8], offset: usize| {
+            Some(u32::from_be_bytes(table.get(offset..offset + 4)?.try_into().ok()?) as usize)
+        };
+
+        let num_palette_entries = u16_at(cpal, 2)? as usize;
+        let color_records = u32_at(cpal, 8)?;
+        // The index of the first color of the first palette.
+        let first_color = u16_at(cpal, 12)? as usize;
+        let palette_color = |index: u16| -> Option<Option<[u8; 4]>> {
+            if index == Self::FOREGROUND_PALETTE_INDEX {
+                return Some(None);
+            }
+            if index as usize >= num_palette_entries {
+                return None;
+            }
+            let record = color_records + (first_color + index as usize) * 4;
+            let [blue, green, red, alpha] = cpal.get(record..record + 4)?.try_into().ok()?;
+            Some(Some([red, green, blue, alpha]))
+        };
+
+        let num_base_glyphs = u16_at(colr, 2)? as usize;
+        let base_glyph_records = u32_at(colr, 4)?;
+        let layer_records = u32_at(colr, 8)?;
+        let num_layers = u16_at(colr, 12)? as usize;
+        let layers = (0..num_layers)
+            .map(|index| {
+                let record = layer_records + index * 4;
+                Some(ColorGlyphLayer {
+                    glyph_id: u16_at(colr, record)? as GlyphId,
+                    color: palette_color(u16_at(colr, record + 2)?)?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let base_glyphs = (0..num_base_glyphs)
+            .map(|index| {
+                let record = base_glyph_records + index * 6;
+                let first_layer = u16_at(colr, record + 2)? as usize;
+                let layer_range = first_layer..first_layer + u16_at(colr, record + 4)? as usize;
+                (layer_range.end <= layers.len())
+                    .then_some((u16_at(colr, record)? as GlyphId, layer_range))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        if base_glyphs.is_empty() {
+            return None;
+        }
+        Some(ColorGlyphs {
+            base_glyphs,
+            layers,
+        })
+    }
+
+    /// The layers of the glyph, from bottom to top, if it is a color glyph.
+    pub fn layers(&self, glyph_id: GlyphId) -> Option<&[ColorGlyphLayer]> {
+        let index = self
+            .base_glyphs
+            .binary_search_by_key(&glyph_id, |(base_glyph, _)| *base_glyph)
+            .ok()?;
+        Some(&self.layers[self.base_glyphs[index].1.clone()])
+    }
+}
+
 pub struct Font {
     pub handle: PlatformFont,
     pub template: FontTemplateRef,
@@ -238,6 +333,8 @@ pub struct Font {
 
     shaper: OnceLock<Shaper>,
     cached_shape_data: RwLock<CachedShapeData>,
+    /// The number of times that text was shaped, rather than found in the shape cache.
+    shaping_count: AtomicUsize,
     pub font_instance_key: OnceLock<FontInstanceKey>,
 
     /// If this is a synthesized small caps font, then this font reference is for
@@ -250,6 +347,10 @@ pub struct Font {
     /// This is cached, because getting table data is expensive.
     has_color_bitmap_or_colr_table: OnceLock<bool>,
 
+    /// The layers of the color glyphs of this font, if it has any. This is cached for the
+    /// same reason.
+    color_glyphs: OnceLock<Option<ColorGlyphs>>,
+
     /// Whether or not this font can do fast shaping, ie whether or not it has
     /// a kern table, but no GSUB and GPOS tables. When this is true, Servo will
     /// shape Latin horizontal left-to-right text without using Harfbuzz.
@@ -291,9 +392,11 @@ impl Font {
             data: data.map(OnceLock::from).unwrap_or_default(),
             shaper: OnceLock::new(),
             cached_shape_data: Default::default(),
+            shaping_count: AtomicUsize::new(0),
             font_instance_key: Default::default(),
             synthesized_small_caps,
             has_color_bitmap_or_colr_table: OnceLock::new(),
+            color_glyphs: OnceLock::new(),
             can_do_fast_shaping: OnceLock::new(),
         })
     }
@@ -315,6 +418,18 @@ impl Font {
         })
     }
 
+    /// The color glyphs that the `COLR` table of this font layers from its other glyphs, if
+    /// it has any that are well formed.
+    pub fn color_glyphs(&self) -> Option<&ColorGlyphs> {
+        self.color_glyphs
+            .get_or_init(|| {
+                let colr = self.table_for_tag(COLR)?;
+                let cpal = self.table_for_tag(CPAL)?;
+                ColorGlyphs::parse(colr.buffer(), cpal.buffer())
+            })
+            .as_ref()
+    }
+
     pub fn key(&self, font_context: &FontContext) -> FontInstanceKey {
         *self
             .font_instance_key
@@ -365,6 +480,12 @@ pub struct ShapingOptions {
     pub word_spacing: Au,
     /// The Unicode script property of the characters in this run.
     pub script: Script,
+    /// The OpenType script tag that the run is shaped with instead of the one for `script`.
+    pub script_tag: Option<FontTableTag>,
+    /// The OpenType language system tag that the run is shaped with, which selects the
+    /// glyphs for a language in fonts that have several. Otherwise, the default language
+    /// system of the script is used.
+    pub language_tag: Option<FontTableTag>,
     /// Various flags.
     pub flags: ShapingFlags,
 }
@@ -389,6 +510,7 @@ impl Font {
             }
         }
 
+        self.shaping_count.fetch_add(1, Ordering::Relaxed);
         let is_single_preserved_newline = text.len() == 1 && text.starts_with('\n');
         let start_time = Instant::now();
         let mut glyphs = GlyphStore::new(
@@ -513,6 +635,24 @@ impl Font {
         glyph_index
     }
 
+    /// Drops the shaped text and the glyph indices and advances that the font has cached.
+    /// They are looked up again the next time they are needed.
+    pub fn clear_shape_cache(&self) {
+        *self.cached_shape_data.write() = CachedShapeData::default();
+    }
+
+    /// The number of entries in the caches dropped by [`Self::clear_shape_cache`].
+    pub fn shape_cache_len(&self) -> usize {
+        let cache = self.cached_shape_data.read();
+        cache.glyph_advances.len() + cache.glyph_indices.len() + cache.shaped_text.len()
+    }
+
+    /// The number of times that [`Self::shape_text`] shaped text rather than finding it in
+    /// the shape cache, so that text measured and then drawn is known to be shaped once.
+    pub fn shaping_count(&self) -> usize {
+        self.shaping_count.load(Ordering::Relaxed)
+    }
+
     pub fn has_glyph_for(&self, codepoint: char) -> bool {
         self.glyph_index(codepoint).is_some()
     }
@@ -587,6 +727,26 @@ impl FontGroup {
         codepoint: char,
         next_codepoint: Option<char>,
         first_fallback: Option<FontRef>,
+    ) -> Option<FontRef> {
+        self.find_by_codepoint_with_fallback_families(
+            font_context,
+            codepoint,
+            next_codepoint,
+            first_fallback,
+            &[],
+        )
+    }
+
+    /// Like [`Self::find_by_codepoint`], but tries each of `fallback_families`, in order,
+    /// before the fallback fonts of the platform. This lets callers give text fallback fonts
+    /// of their own, such as an emoji font for emoji-heavy content.
+    pub fn find_by_codepoint_with_fallback_families(
+        &mut self,
+        font_context: &FontContext,
+        codepoint: char,
+        next_codepoint: Option<char>,
+        first_fallback: Option<FontRef>,
+        fallback_families: &[SingleFontFamily],
     ) -> Option<FontRef> {
         // Tab characters are converted into spaces when rendering.
         // TODO: We should not render a tab character. Instead they should be converted into tab stops
@@ -640,6 +800,17 @@ impl FontGroup {
             }
         }
 
+        if let Some(font) = fallback_families.iter().find_map(|family| {
+            FontGroupFamily::new(family).find(
+                &self.descriptor,
+                font_context,
+                &char_in_template,
+                &font_has_glyph_and_presentation,
+            )
+        }) {
+            return font_or_synthesized_small_caps(font);
+        }
+
         if let Some(font) = self.find_fallback(
             font_context,
             options,
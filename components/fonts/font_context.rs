@@ -128,6 +128,40 @@ impl FontContext {
         self.web_fonts.read().number_of_fonts_still_loading()
     }
 
+    /// Drops the shape caches of every font loaded by this context, keeping the fonts.
+    pub fn clear_shape_caches(&self) {
+        for font in self.fonts.read().values().flatten() {
+            font.clear_shape_cache();
+        }
+    }
+
+    /// The number of entries in the shape caches of the fonts loaded by this context.
+    pub fn shape_cache_len(&self) -> usize {
+        self.fonts
+            .read()
+            .values()
+            .flatten()
+            .map(|font| font.shape_cache_len())
+            .sum()
+    }
+
+    /// The number of times that the fonts loaded by this context shaped text, see
+    /// [`Font::shaping_count`].
+    pub fn shaping_count(&self) -> usize {
+        self.fonts
+            .read()
+            .values()
+            .flatten()
+            .map(|font| font.shaping_count())
+            .sum()
+    }
+
+    /// The names of the local font families that this context can resolve fonts from, not
+    /// counting web fonts, so that content can pick one rather than silently falling back.
+    pub fn available_font_families(&self) -> Vec<String> {
+        self.system_font_service_proxy.available_families()
+    }
+
     fn get_font_data(&self, identifier: &FontIdentifier) -> Option<FontData> {
         match identifier {
             FontIdentifier::Web(_) => self.font_data.read().get(identifier).cloned(),
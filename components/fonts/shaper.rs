@@ -18,12 +18,12 @@ use harfbuzz_sys::{
     HB_OT_LAYOUT_BASELINE_TAG_IDEO_EMBOX_BOTTOM_OR_LEFT, HB_OT_LAYOUT_BASELINE_TAG_ROMAN,
     hb_blob_create, hb_blob_t, hb_bool_t, hb_buffer_add_utf8, hb_buffer_create, hb_buffer_destroy,
     hb_buffer_get_glyph_infos, hb_buffer_get_glyph_positions, hb_buffer_get_length,
-    hb_buffer_set_direction, hb_buffer_set_script, hb_buffer_t, hb_codepoint_t,
-    hb_face_create_for_tables, hb_face_destroy, hb_face_t, hb_feature_t, hb_font_create,
-    hb_font_destroy, hb_font_funcs_create, hb_font_funcs_set_glyph_h_advance_func,
+    hb_buffer_set_direction, hb_buffer_set_language, hb_buffer_set_script, hb_buffer_t,
+    hb_codepoint_t, hb_face_create_for_tables, hb_face_destroy, hb_face_t, hb_feature_t,
+    hb_font_create, hb_font_destroy, hb_font_funcs_create, hb_font_funcs_set_glyph_h_advance_func,
     hb_font_funcs_set_nominal_glyph_func, hb_font_funcs_t, hb_font_set_funcs, hb_font_set_ppem,
     hb_font_set_scale, hb_font_t, hb_glyph_info_t, hb_glyph_position_t, hb_ot_layout_get_baseline,
-    hb_position_t, hb_shape, hb_tag_t,
+    hb_ot_tag_to_language, hb_ot_tag_to_script, hb_position_t, hb_shape, hb_tag_t,
 };
 use log::debug;
 use num_traits::Zero;
@@ -380,7 +380,14 @@ impl Shaper {
                 },
             );
 
-            hb_buffer_set_script(hb_buffer, unicode_to_hb_script(options.script));
+            let script = match options.script_tag {
+                Some(script_tag) => hb_ot_tag_to_script(script_tag),
+                None => unicode_to_hb_script(options.script),
+            };
+            hb_buffer_set_script(hb_buffer, script);
+            if let Some(language_tag) = options.language_tag {
+                hb_buffer_set_language(hb_buffer, hb_ot_tag_to_language(language_tag));
+            }
 
             hb_buffer_add_utf8(
                 hb_buffer,
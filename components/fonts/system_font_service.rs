@@ -69,6 +69,7 @@ pub enum SystemFontServiceMessage {
     ),
     GetFontKey(IpcSender<FontKey>),
     GetFontInstanceKey(IpcSender<FontInstanceKey>),
+    GetAvailableFamilies(IpcSender<Vec<String>>),
     CollectMemoryReport(ReportsChan),
     Exit(IpcSender<()>),
     Ping,
@@ -187,6 +188,9 @@ impl SystemFontService {
                     self.fetch_new_keys();
                     let _ = result_sender.send(self.free_font_instance_keys.pop().unwrap());
                 },
+                SystemFontServiceMessage::GetAvailableFamilies(result_sender) => {
+                    let _ = result_sender.send(self.available_families());
+                },
                 SystemFontServiceMessage::CollectMemoryReport(report_sender) => {
                     self.collect_memory_report(report_sender);
                 },
@@ -240,6 +244,17 @@ impl SystemFontService {
             .collect()
     }
 
+    /// The names of the local font families, as the platform spells them, sorted and
+    /// without duplicates.
+    #[servo_tracing::instrument(skip_all)]
+    fn available_families(&self) -> Vec<String> {
+        let mut families = Vec::new();
+        for_each_available_family(|family_name| families.push(family_name));
+        families.sort_unstable();
+        families.dedup();
+        families
+    }
+
     #[servo_tracing::instrument(skip_all)]
     fn refresh_local_families(&mut self) {
         self.local_families.clear();
@@ -548,6 +563,21 @@ impl SystemFontServiceProxy {
         templates
     }
 
+    /// The names of the local font families, sorted and without duplicates.
+    pub fn available_families(&self) -> Vec<String> {
+        let (result_sender, result_receiver) =
+            ipc::channel().expect("failed to create IPC channel");
+        self.sender
+            .lock()
+            .send(SystemFontServiceMessage::GetAvailableFamilies(
+                result_sender,
+            ))
+            .expect("failed to send message to system font service");
+        result_receiver
+            .recv()
+            .expect("Failed to communicate with system font service.")
+    }
+
     pub(crate) fn generate_font_key(&self) -> FontKey {
         let (result_sender, result_receiver) =
             ipc::channel().expect("failed to create IPC channel");
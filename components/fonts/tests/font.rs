@@ -10,9 +10,10 @@ use app_units::Au;
 use euclid::num::Zero;
 use fonts::platform::font::PlatformFont;
 use fonts::{
-    Font, FontData, FontDescriptor, FontIdentifier, FontTemplate, FontTemplateRef,
-    PlatformFontMethods, ShapingFlags, ShapingOptions,
+    ByteIndex, ColorGlyphLayer, ColorGlyphs, Font, FontData, FontDescriptor, FontIdentifier,
+    FontTemplate, FontTemplateRef, PlatformFontMethods, ShapingFlags, ShapingOptions, ot_tag,
 };
+use range::Range;
 use servo_url::ServoUrl;
 use style::properties::longhands::font_variant_caps::computed_value::T as FontVariantCaps;
 use style::values::computed::{FontStretch, FontStyle, FontWeight};
@@ -77,6 +78,8 @@ fn test_font_can_do_fast_shaping() {
         letter_spacing: None,
         word_spacing: Au::zero(),
         script: Script::Latin,
+        script_tag: None,
+        language_tag: None,
         flags: ShapingFlags::empty(),
     };
     assert!(!dejavu_sans.can_do_fast_shaping("WAVE", &shaping_options));
@@ -87,6 +90,8 @@ fn test_font_can_do_fast_shaping() {
         letter_spacing: None,
         word_spacing: Au::zero(),
         script: Script::Cherokee,
+        script_tag: None,
+        language_tag: None,
         flags: ShapingFlags::empty(),
     };
     assert!(!dejavu_sans.can_do_fast_shaping("WAVE", &shaping_options));
@@ -97,8 +102,155 @@ fn test_font_can_do_fast_shaping() {
         letter_spacing: None,
         word_spacing: Au::zero(),
         script: Script::Latin,
+        script_tag: None,
+        language_tag: None,
         flags: ShapingFlags::RTL_FLAG,
     };
     assert!(!dejavu_sans.can_do_fast_shaping("WAVE", &shaping_options));
     assert!(!dejavu_sans_fast_shapeable.can_do_fast_shaping("WAVE", &shaping_options));
 }
+
+#[test]
+fn test_color_glyph_layers() {
+    let be_bytes = |values: &[u32], widths: &[usize]| -> Vec<u8> {
+        values
+            .iter()
+            .zip(widths)
+            .flat_map(|(value, width)| value.to_be_bytes()[4 - width..].to_vec())
+            .collect()
+    };
+
+    // Glyph 5 is red with a translucent green layer on top, and glyph 9 is drawn in the color
+    // of the text.
+    let mut colr = be_bytes(&[0, 2, 14, 26, 3], &[2, 2, 4, 4, 2]);
+    colr.extend(be_bytes(&[5, 0, 2, 9, 2, 1], &[2; 6]));
+    colr.extend(be_bytes(&[20, 0, 21, 1, 22, 0xFFFF], &[2; 6]));
+    let mut cpal = be_bytes(&[0, 2, 1, 2, 14, 0], &[2, 2, 2, 2, 4, 2]);
+    cpal.extend([0, 0, 255, 255, 0, 255, 0, 128]);
+
+    let color_glyphs = ColorGlyphs::parse(&colr, &cpal).unwrap();
+    assert_eq!(
+        color_glyphs.layers(5),
+        Some(
+            &[
+                ColorGlyphLayer {
+                    glyph_id: 20,
+                    color: Some([255, 0, 0, 255]),
+                },
+                ColorGlyphLayer {
+                    glyph_id: 21,
+                    color: Some([0, 255, 0, 128]),
+                },
+            ][..]
+        )
+    );
+    assert_eq!(
+        color_glyphs.layers(9),
+        Some(
+            &[ColorGlyphLayer {
+                glyph_id: 22,
+                color: None,
+            }][..]
+        )
+    );
+    assert_eq!(color_glyphs.layers(20), None);
+
+    // Tables that are cut short have no color glyphs.
+    assert!(ColorGlyphs::parse(&colr[..colr.len() - 1], &cpal).is_none());
+    assert!(ColorGlyphs::parse(&colr, &cpal[..cpal.len() - 1]).is_none());
+
+    // Fonts without a `COLR` table have no color glyphs.
+    let path: PathBuf = [
+        env!("CARGO_MANIFEST_DIR"),
+        "tests",
+        "support",
+        "dejavu-fonts-ttf-2.37",
+        "ttf",
+        "DejaVuSans.ttf",
+    ]
+    .iter()
+    .collect();
+    assert!(make_font(path).color_glyphs().is_none());
+}
+
+#[test]
+fn test_shape_with_language_tag() {
+    let dejavu_sans = make_font(
+        [
+            env!("CARGO_MANIFEST_DIR"),
+            "tests",
+            "support",
+            "dejavu-fonts-ttf-2.37",
+            "ttf",
+            "DejaVuSans.ttf",
+        ]
+        .iter()
+        .collect(),
+    );
+    let glyph_id = |language_tag| {
+        let shaping_options = ShapingOptions {
+            letter_spacing: None,
+            word_spacing: Au::zero(),
+            script: Script::Cyrillic,
+            script_tag: None,
+            language_tag,
+            flags: ShapingFlags::empty(),
+        };
+        let glyphs = dejavu_sans.shape_text("б", &shaping_options);
+        glyphs
+            .iter_glyphs_for_byte_range(&Range::new(ByteIndex(0), glyphs.len()))
+            .next()
+            .unwrap()
+            .id()
+    };
+
+    // The font has a Serbian form of the letter, which only that language system selects.
+    let default = glyph_id(None);
+    assert_eq!(default, dejavu_sans.glyph_index('б').unwrap());
+    assert_ne!(glyph_id(Some(ot_tag!('S', 'R', 'B', ' '))), default);
+    assert_eq!(glyph_id(Some(ot_tag!('R', 'U', 'S', ' '))), default);
+}
+
+#[test]
+fn test_shape_text_once_when_measured_then_drawn() {
+    let dejavu_sans = make_font(
+        [
+            env!("CARGO_MANIFEST_DIR"),
+            "tests",
+            "support",
+            "dejavu-fonts-ttf-2.37",
+            "ttf",
+            "DejaVuSans.ttf",
+        ]
+        .iter()
+        .collect(),
+    );
+    let shaping_options = ShapingOptions {
+        letter_spacing: None,
+        word_spacing: Au::zero(),
+        script: Script::Latin,
+        script_tag: None,
+        language_tag: None,
+        flags: ShapingFlags::empty(),
+    };
+
+    // Measuring and then drawing text shapes it with the same options.
+    let measured = dejavu_sans.shape_text("Shaped once", &shaping_options);
+    let drawn = dejavu_sans.shape_text("Shaped once", &shaping_options);
+    assert!(std::sync::Arc::ptr_eq(&measured, &drawn));
+    assert_eq!(dejavu_sans.shaping_count(), 1);
+
+    // Other text, or the same text shaped differently, is shaped again.
+    dejavu_sans.shape_text("Shaped twice", &shaping_options);
+    let rtl_options = ShapingOptions {
+        flags: ShapingFlags::RTL_FLAG,
+        ..shaping_options
+    };
+    dejavu_sans.shape_text("Shaped once", &rtl_options);
+    assert_eq!(dejavu_sans.shaping_count(), 3);
+
+    // Clearing the shape cache doesn't reset the count.
+    dejavu_sans.clear_shape_cache();
+    dejavu_sans.shape_text("Shaped once", &shaping_options);
+    assert_eq!(dejavu_sans.shaping_count(), 4);
+}
@@ -38,6 +38,9 @@ mod font_context {
 
     static INIT: Once = Once::new();
 
+    const CSSTEST_DIRECTORY: &str = "CSSTest";
+    const DEJAVU_DIRECTORY: &str = "dejavu-fonts-ttf-2.37/ttf";
+
     struct TestContext {
         context: FontContext,
         system_font_service: Arc<MockSystemFontService>,
@@ -143,6 +146,9 @@ mod font_context {
                         let _ = result_sender.send(());
                         break;
                     },
+                    SystemFontServiceMessage::GetAvailableFamilies(result_sender) => {
+                        let _ = result_sender.send(self.families.lock().keys().cloned().collect());
+                    },
                     SystemFontServiceMessage::Ping => {},
                     SystemFontServiceMessage::CollectMemoryReport(..) => {},
                 }
@@ -156,18 +162,27 @@ mod font_context {
             };
 
             let mut csstest_ascii = FontTemplates::default();
-            proxy.add_face(&mut csstest_ascii, "csstest-ascii");
+            proxy.add_face(&mut csstest_ascii, CSSTEST_DIRECTORY, "csstest-ascii");
 
             let mut csstest_basic = FontTemplates::default();
-            proxy.add_face(&mut csstest_basic, "csstest-basic-regular");
+            proxy.add_face(&mut csstest_basic, CSSTEST_DIRECTORY, "csstest-basic-regular");
+
+            let mut csstest_basic_bold = FontTemplates::default();
+            proxy.add_face(&mut csstest_basic_bold, CSSTEST_DIRECTORY, "csstest-basic-bold");
+
+            let mut dejavu_sans = FontTemplates::default();
+            proxy.add_face(&mut dejavu_sans, DEJAVU_DIRECTORY, "DejaVuSans");
+            proxy.add_face(&mut dejavu_sans, DEJAVU_DIRECTORY, "DejaVuSansCondensed");
 
             let mut fallback = FontTemplates::default();
-            proxy.add_face(&mut fallback, "csstest-basic-regular");
+            proxy.add_face(&mut fallback, CSSTEST_DIRECTORY, "csstest-basic-regular");
 
             {
                 let mut families = proxy.families.lock();
                 families.insert("CSSTest ASCII".to_owned(), csstest_ascii);
                 families.insert("CSSTest Basic".to_owned(), csstest_basic);
+                families.insert("CSSTest Basic Bold".to_owned(), csstest_basic_bold);
+                families.insert("DejaVu Sans".to_owned(), dejavu_sans);
                 families.insert(
                     fallback_font_families(FallbackFontSelectionOptions::default())[0].to_owned(),
                     fallback,
@@ -177,8 +192,8 @@ mod font_context {
             proxy
         }
 
-        fn add_face(&self, family: &mut FontTemplates, name: &str) {
-            let mut path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "support", "CSSTest"]
+        fn add_face(&self, family: &mut FontTemplates, directory: &str, name: &str) {
+            let mut path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "support", directory]
                 .iter()
                 .collect();
             path.push(format!("{}.ttf", name));
@@ -342,6 +357,122 @@ mod font_context {
         );
     }
 
+    #[test]
+    fn test_font_fallback_families() {
+        let context = TestContext::new();
+
+        let mut style = style();
+        style.set_font_family(font_family(vec!["CSSTest ASCII"]));
+
+        let group = context.context.font_group(ServoArc::new(style));
+        let fallback_families = font_family(vec!["CSSTest Basic Bold"]).families;
+        let fallback_families: Vec<_> = fallback_families.iter().cloned().collect();
+        let find = |codepoint| {
+            group
+                .write()
+                .find_by_codepoint_with_fallback_families(
+                    &context.context,
+                    codepoint,
+                    None,
+                    None,
+                    &fallback_families,
+                )
+                .unwrap()
+        };
+
+        assert_eq!(
+            &font_face_name(&find('a').identifier()),
+            "csstest-ascii",
+            "a family in the group should be used before the fallback families"
+        );
+        assert_eq!(
+            &font_face_name(&find('á').identifier()),
+            "csstest-basic-bold",
+            "a fallback family should be used before the fallback fonts of the platform"
+        );
+    }
+
+    #[test]
+    fn test_font_group_selects_condensed_face() {
+        let mut context = TestContext::new();
+
+        let mut normal_style = style();
+        normal_style.set_font_family(font_family(vec!["DejaVu Sans"]));
+        let mut condensed_style = normal_style.clone();
+        condensed_style.set_font_stretch(FontStretch::CONDENSED);
+
+        let normal_font = context
+            .context
+            .font_group(ServoArc::new(normal_style))
+            .write()
+            .find_by_codepoint(&mut context.context, 'a', None, None)
+            .unwrap();
+        let condensed_font = context
+            .context
+            .font_group(ServoArc::new(condensed_style))
+            .write()
+            .find_by_codepoint(&mut context.context, 'a', None, None)
+            .unwrap();
+        assert_eq!(&font_face_name(&normal_font.identifier()), "DejaVuSans");
+        assert_eq!(
+            &font_face_name(&condensed_font.identifier()),
+            "DejaVuSansCondensed"
+        );
+
+        let normal_advance = normal_font.glyph_h_advance(normal_font.glyph_index('a').unwrap());
+        let condensed_advance =
+            condensed_font.glyph_h_advance(condensed_font.glyph_index('a').unwrap());
+        assert!(
+            condensed_advance < normal_advance,
+            "a condensed stretch should narrow the advances"
+        );
+    }
+
+    #[test]
+    fn test_font_group_synthesizes_small_caps() {
+        let mut context = TestContext::new();
+
+        let mut style = style();
+        style.set_font_family(font_family(vec!["DejaVu Sans"]));
+        style.set_font_variant_caps(FontVariantCaps::SmallCaps);
+
+        let group = context.context.font_group(ServoArc::new(style));
+        let lowercase_font = group
+            .write()
+            .find_by_codepoint(&mut context.context, 'a', None, None)
+            .unwrap();
+        let uppercase_font = group
+            .write()
+            .find_by_codepoint(&mut context.context, 'A', None, None)
+            .unwrap();
+
+        assert!(
+            lowercase_font.descriptor.pt_size < uppercase_font.descriptor.pt_size,
+            "lowercase letters should use the scaled down small caps font"
+        );
+        assert!(uppercase_font.glyph_index('A').is_some());
+        assert_eq!(
+            lowercase_font.glyph_index('a'),
+            uppercase_font.glyph_index('A'),
+            "lowercase letters should render as capitals"
+        );
+    }
+
+    #[test]
+    fn test_available_font_families() {
+        let context = TestContext::new();
+        let families = context.context.available_font_families();
+        assert!(!families.is_empty());
+        assert!(families.iter().any(|family| family == "CSSTest ASCII"));
+        assert!(!families.iter().any(|family| family == "No Such Family"));
+
+        // The constellation answers script from the proxy of the service directly.
+        assert_eq!(
+            context.system_font_service_proxy.available_families(),
+            families
+        );
+    }
+
     #[test]
     fn test_font_template_is_cached() {
         let context = TestContext::new();
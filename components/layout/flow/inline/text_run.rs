@@ -422,6 +422,8 @@ impl TextRun {
                     letter_spacing,
                     word_spacing,
                     script: segment.script,
+                    script_tag: None,
+                    language_tag: None,
                     flags,
                 };
 
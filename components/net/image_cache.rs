@@ -274,6 +274,48 @@ struct VectorImageData {
     cors_status: CorsStatus,
 }
 
+impl VectorImageData {
+    /// Rasterizes the image at `requested_size`, or at its natural size if that is empty.
+    fn rasterize(&self, requested_size: DeviceIntSize) -> RasterImage {
+        let natural_size = self.svg_tree.size().to_int_size();
+        let tinyskia_requested_size = {
+            let width = requested_size.width.try_into().unwrap_or(0);
+            let height = requested_size.height.try_into().unwrap_or(0);
+            tiny_skia::IntSize::from_wh(width, height).unwrap_or(natural_size)
+        };
+        let transform = tiny_skia::Transform::from_scale(
+            tinyskia_requested_size.width() as f32 / natural_size.width() as f32,
+            tinyskia_requested_size.height() as f32 / natural_size.height() as f32,
+        );
+        let mut pixmap = tiny_skia::Pixmap::new(
+            tinyskia_requested_size.width(),
+            tinyskia_requested_size.height(),
+        )
+        .unwrap();
+        resvg::render(&self.svg_tree, transform, &mut pixmap.as_mut());
+
+        let bytes = pixmap.take();
+        let frame = ImageFrame {
+            delay: None,
+            byte_range: 0..bytes.len(),
+            width: tinyskia_requested_size.width(),
+            height: tinyskia_requested_size.height(),
+        };
+
+        RasterImage {
+            metadata: ImageMetadata {
+                width: tinyskia_requested_size.width(),
+                height: tinyskia_requested_size.height(),
+            },
+            format: PixelFormat::RGBA8,
+            frames: vec![frame],
+            bytes: IpcSharedMemory::from_bytes(&bytes),
+            id: None,
+            cors_status: self.cors_status,
+        }
+    }
+}
+
 impl std::fmt::Debug for VectorImageData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("VectorImageData").finish()
@@ -905,42 +947,7 @@ impl ImageCache for ImageCacheImpl {
 
         let store = self.store.clone();
         self.thread_pool.spawn(move || {
-            let natural_size = vector_image.svg_tree.size().to_int_size();
-            let tinyskia_requested_size = {
-                let width = requested_size.width.try_into().unwrap_or(0);
-                let height = requested_size.height.try_into().unwrap_or(0);
-                tiny_skia::IntSize::from_wh(width, height).unwrap_or(natural_size)
-            };
-            let transform = tiny_skia::Transform::from_scale(
-                tinyskia_requested_size.width() as f32 / natural_size.width() as f32,
-                tinyskia_requested_size.height() as f32 / natural_size.height() as f32,
-            );
-            let mut pixmap = tiny_skia::Pixmap::new(
-                tinyskia_requested_size.width(),
-                tinyskia_requested_size.height(),
-            )
-            .unwrap();
-            resvg::render(&vector_image.svg_tree, transform, &mut pixmap.as_mut());
-
-            let bytes = pixmap.take();
-            let frame = ImageFrame {
-                delay: None,
-                byte_range: 0..bytes.len(),
-                width: tinyskia_requested_size.width(),
-                height: tinyskia_requested_size.height(),
-            };
-
-            let rasterized_image = RasterImage {
-                metadata: ImageMetadata {
-                    width: tinyskia_requested_size.width(),
-                    height: tinyskia_requested_size.height(),
-                },
-                format: PixelFormat::RGBA8,
-                frames: vec![frame],
-                bytes: IpcSharedMemory::from_bytes(&bytes),
-                id: None,
-                cors_status: vector_image.cors_status,
-            };
+            let rasterized_image = vector_image.rasterize(requested_size);
 
             let mut store = store.lock().unwrap();
             store.load_image_with_keycache(PendingKey::Svg((
@@ -953,6 +960,41 @@ impl ImageCache for ImageCacheImpl {
         None
     }
 
+    fn rasterize_vector_image_sync(
+        &self,
+        image_id: PendingImageId,
+        requested_size: DeviceIntSize,
+    ) -> Option<RasterImage> {
+        let vector_image = {
+            let mut store = self.store.lock().unwrap();
+            let Some(vector_image) = store.vector_images.get(&image_id).cloned() else {
+                warn!("Unknown image id {image_id:?} requested for rasterization");
+                return None;
+            };
+            let entry = store
+                .rasterized_vector_images
+                .entry((image_id, requested_size))
+                .or_default();
+            if let Some(result) = entry.result.as_ref() {
+                return Some(result.clone());
+            }
+            vector_image
+        };
+
+        // The store isn't locked while rasterizing, so that other threads can keep using
+        // the cache. The result is stored as if it had been rasterized asynchronously.
+        let rasterized_image = vector_image.rasterize(requested_size);
+        self.store
+            .lock()
+            .unwrap()
+            .load_image_with_keycache(PendingKey::Svg((
+                image_id,
+                rasterized_image.clone(),
+                requested_size,
+            )));
+        Some(rasterized_image)
+    }
+
     /// Add a new listener for the given pending image id. If the image is already present,
     /// the responder will still receive the expected response.
     fn add_listener(&self, listener: ImageLoadListener) {
@@ -5,12 +5,13 @@
 mod snapshot;
 
 use std::borrow::Cow;
-use std::io::Cursor;
+use std::io::{self, Cursor};
 use std::ops::Range;
+use std::sync::LazyLock;
 use std::time::Duration;
 use std::{cmp, fmt, vec};
 
-use euclid::default::{Point2D, Rect, Size2D};
+use euclid::default::{Point2D, Rect, Size2D, Vector2D};
 use image::codecs::{bmp, gif, ico, jpeg, png, webp};
 use image::error::ImageFormatHint;
 use image::imageops::{self, FilterType};
@@ -149,6 +150,23 @@ pub fn flip_y_rgba8_image_inplace(size: Size2D<u32>, pixels: &mut [u8]) {
     }
 }
 
+/// Flips the source image horizontally in place.
+pub fn flip_x_rgba8_image_inplace(size: Size2D<u32>, pixels: &mut [u8]) {
+    assert_eq!(pixels.len() % 4, 0);
+
+    let row_length = size.width as usize * 4;
+    if row_length == 0 {
+        return;
+    }
+    for row in pixels.chunks_exact_mut(row_length) {
+        let width = row.len() / 4;
+        for i in 0..width / 2 {
+            let (left, right) = row.split_at_mut((width - i - 1) * 4);
+            left[i * 4..][..4].swap_with_slice(&mut right[..4]);
+        }
+    }
+}
+
 pub fn rgba8_get_rect(pixels: &[u8], size: Size2D<u32>, rect: Rect<u32>) -> Cow<[u8]> {
     assert!(!rect.is_empty());
     assert!(Rect::from_size(size).contains_rect(&rect));
@@ -172,6 +190,131 @@ pub fn rgba8_get_rect(pixels: &[u8], size: Size2D<u32>, rect: Rect<u32>) -> Cow<
     data.into()
 }
 
+/// Returns the pixels of an image of `size` laid out as an image with the EXIF
+/// `orientation` (1 to 8) is meant to be displayed, along with the size of the result.
+/// Orientations 5 to 8 transpose the image, swapping its width and height.
+pub fn rgba8_orient(pixels: &[u8], size: Size2D<u32>, orientation: u16) -> (Vec<u8>, Size2D<u32>) {
+    assert_eq!(size.area() as usize, pixels.len() / 4);
+    let (width, height) = (size.width as usize, size.height as usize);
+    let oriented_size = if (5..=8).contains(&orientation) {
+        Size2D::new(size.height, size.width)
+    } else {
+        size
+    };
+    let (oriented_width, oriented_height) =
+        (oriented_size.width as usize, oriented_size.height as usize);
+    let mut data = Vec::with_capacity(pixels.len());
+    for y in 0..oriented_height {
+        for x in 0..oriented_width {
+            let (source_x, source_y) = match orientation {
+                2 => (width - 1 - x, y),
+                3 => (width - 1 - x, height - 1 - y),
+                4 => (x, height - 1 - y),
+                5 => (y, x),
+                6 => (y, height - 1 - x),
+                7 => (width - 1 - y, height - 1 - x),
+                8 => (width - 1 - y, x),
+                _ => (x, y),
+            };
+            let start = (source_y * width + source_x) * 4;
+            data.extend_from_slice(&pixels[start..start + 4]);
+        }
+    }
+    (data, oriented_size)
+}
+
+/// Returns the EXIF orientation (1 to 8) recorded in an encoded image, if it has one.
+pub fn exif_orientation(buffer: &[u8], image_type: &EncodedImageType) -> Option<u16> {
+    let exif = match image_type {
+        EncodedImageType::Png => png_exif(buffer),
+        EncodedImageType::Jpeg => jpeg_exif(buffer),
+        EncodedImageType::Webp => webp_exif(buffer),
+        EncodedImageType::Gif => None,
+    }?;
+    tiff_orientation(exif).filter(|orientation| (1..=8).contains(orientation))
+}
+
+/// Returns the TIFF data of the `eXIf` chunk of a PNG.
+fn png_exif(buffer: &[u8]) -> Option<&[u8]> {
+    let mut chunks = buffer.get(8..)?;
+    while chunks.len() >= 12 {
+        let length = u32::from_be_bytes(chunks[..4].try_into().ok()?) as usize;
+        let data = chunks.get(8..8 + length)?;
+        match &chunks[4..8] {
+            b"eXIf" => return Some(data),
+            b"IDAT" | b"IEND" => return None,
+            _ => chunks = chunks.get(12 + length..)?,
+        }
+    }
+    None
+}
+
+/// Returns the TIFF data of the `APP1` Exif segment of a JPEG.
+fn jpeg_exif(buffer: &[u8]) -> Option<&[u8]> {
+    let mut segments = buffer.get(2..)?;
+    while segments.len() >= 4 && segments[0] == 0xff {
+        let marker = segments[1];
+        // Start of scan: the metadata segments are all before it.
+        if marker == 0xda {
+            return None;
+        }
+        let length = u16::from_be_bytes([segments[2], segments[3]]) as usize;
+        let data = segments.get(4..2 + length)?;
+        if marker == 0xe1 && data.starts_with(b"Exif\0\0") {
+            return Some(&data[6..]);
+        }
+        segments = &segments[2 + length..];
+    }
+    None
+}
+
+/// Returns the TIFF data of the `EXIF` chunk of a WebP.
+fn webp_exif(buffer: &[u8]) -> Option<&[u8]> {
+    let mut chunks = buffer.get(12..)?;
+    while chunks.len() >= 8 {
+        let length = u32::from_le_bytes(chunks[4..8].try_into().ok()?) as usize;
+        let data = chunks.get(8..8 + length)?;
+        if &chunks[..4] == b"EXIF" {
+            return Some(data.strip_prefix(b"Exif\0\0").unwrap_or(data));
+        }
+        // Chunks are padded to an even length.
+        chunks = chunks.get(8 + length + length % 2..)?;
+    }
+    None
+}
+
+/// Returns the value of the orientation tag in the first image file directory of
+/// TIFF data.
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(..4)? {
+        b"MM\0*" => true,
+        b"II*\0" => false,
+        _ => return None,
+    };
+    let read_u16 = |offset: usize| {
+        let bytes = tiff.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let read_u32 = |offset: usize| {
+        let bytes = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+    let directory = read_u32(4)? as usize;
+    let entries = read_u16(directory)? as usize;
+    (0..entries)
+        .map(|index| directory + 2 + index * 12)
+        .find(|&entry| read_u16(entry) == Some(0x0112))
+        .and_then(|entry| read_u16(entry + 8))
+}
+
 // TODO(pcwalton): Speed up with SIMD, or better yet, find some way to not do this.
 pub fn rgba8_byte_swap_colors_inplace(pixels: &mut [u8]) {
     assert!(pixels.len() % 4 == 0);
@@ -190,6 +333,259 @@ pub fn rgba8_byte_swap_and_premultiply_inplace(pixels: &mut [u8]) {
     }
 }
 
+/// Composites premultiplied pixels, in either channel order, over a checkerboard of white
+/// and light gray squares of `cell_size` pixels, the way image previews show transparency.
+/// The pixels are opaque afterwards.
+pub fn rgba8_over_checkerboard_inplace(size: Size2D<u32>, pixels: &mut [u8], cell_size: u32) {
+    assert_eq!(pixels.len(), size.area() as usize * 4);
+    let cell_size = cell_size.max(1);
+    for (index, pixel) in pixels.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (index as u32 % size.width, index as u32 / size.width);
+        let background = if (x / cell_size + y / cell_size) % 2 == 0 {
+            255
+        } else {
+            204
+        };
+        let coverage = 255 - pixel[3];
+        for channel in &mut pixel[..3] {
+            *channel = channel.saturating_add(multiply_u8_color(background, coverage));
+        }
+        pixel[3] = 255;
+    }
+}
+
+/// The bits of each channel that [`rgba8_quantize`] keeps when it counts colors, so that
+/// median cut splits a histogram of at most 32768 cells rather than every distinct color.
+const QUANTIZE_HISTOGRAM_BITS: u32 = 5;
+/// The bits of each channel that index the cells of the color cube in which
+/// [`NearestColorLookup`] searches the palette.
+const QUANTIZE_LOOKUP_BITS: u32 = 3;
+
+/// The index of the cell of a grid over the color cube, `bits` per channel, that `color` is in.
+fn color_cell_index(color: [u8; 3], bits: u32) -> usize {
+    color
+        .iter()
+        .fold(0, |index, &value| (index << bits) | (value >> (8 - bits)) as usize)
+}
+
+/// The pixels of a cell of the histogram of [`rgba8_quantize`].
+struct HistogramCell {
+    /// The average color of the pixels.
+    color: [u8; 3],
+    count: u64,
+    sum: [u64; 3],
+}
+
+/// The average of `count` colors whose channels add up to `sum`.
+fn average_color(count: u64, sum: [u64; 3]) -> [u8; 3] {
+    sum.map(|sum| ((sum + count / 2) / count) as u8)
+}
+
+/// The channel along which the colors of `cells` range the most, and that range.
+fn widest_channel(cells: &[HistogramCell]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let values = cells.iter().map(|cell| cell.color[channel]);
+            let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+            (channel, range)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+/// Finds the entry of a palette nearest to a color. Only the entries that can be nearest to
+/// some color of the cell of the color cube that the color is in are searched, and those are
+/// found on first use of the cell.
+struct NearestColorLookup<'a> {
+    palette: &'a [[u8; 4]],
+    candidates: Vec<Option<Vec<u8>>>,
+}
+
+impl<'a> NearestColorLookup<'a> {
+    fn new(palette: &'a [[u8; 4]]) -> Self {
+        Self {
+            palette,
+            candidates: vec![None; 1 << (3 * QUANTIZE_LOOKUP_BITS)],
+        }
+    }
+
+    fn nearest(&mut self, color: [u8; 3]) -> u8 {
+        let palette = self.palette;
+        let squared_distance = |entry: &[u8; 4], color: [i32; 3]| -> i32 {
+            (0..3)
+                .map(|channel| (entry[channel] as i32 - color[channel]).pow(2))
+                .sum()
+        };
+        let cell = color_cell_index(color, QUANTIZE_LOOKUP_BITS);
+        let candidates = self.candidates[cell].get_or_insert_with(|| {
+            let shift = 8 - QUANTIZE_LOOKUP_BITS;
+            let low = color.map(|value| (value >> shift << shift) as i32);
+            let high = low.map(|value| value + (1 << shift) - 1);
+            // The squared distance from an entry to the nearest or farthest color of the cell.
+            let distance_to_cell = |entry: &[u8; 4], farthest: bool| -> i32 {
+                (0..3)
+                    .map(|channel| {
+                        let value = entry[channel] as i32;
+                        let (low, high) = (low[channel], high[channel]);
+                        let distance = if farthest {
+                            (value - low).max(high - value)
+                        } else {
+                            (low - value).max(value - high).max(0)
+                        };
+                        distance * distance
+                    })
+                    .sum()
+            };
+            // Every color of the cell is at most `reach` from some entry, so an entry that is
+            // farther than that from the whole cell is never the nearest.
+            let reach = palette
+                .iter()
+                .map(|entry| distance_to_cell(entry, true))
+                .min()
+                .unwrap_or(0);
+            (0..palette.len())
+                .filter(|&index| distance_to_cell(&palette[index], false) <= reach)
+                .map(|index| index as u8)
+                .collect()
+        });
+        candidates
+            .iter()
+            .copied()
+            .min_by_key(|&index| squared_distance(&palette[index as usize], color.map(i32::from)))
+            .unwrap_or(0)
+    }
+}
+
+/// Reduces the colors of non-premultiplied RGBA pixels to a palette of at most `max_colors`,
+/// which can be 256 at most, with median cut over a histogram of the colors. Returns the
+/// palette and the index into it of each pixel. Pixels are made either opaque or, when their
+/// alpha is zero, transparent, and the transparent ones share one entry of the palette. With
+/// `dither`, the error of each opaque pixel is diffused to its neighbors (Floyd-Steinberg),
+/// trading banding in gradients for noise.
+pub fn rgba8_quantize(
+    size: Size2D<u32>,
+    pixels: &[u8],
+    max_colors: usize,
+    dither: bool,
+) -> (Vec<[u8; 4]>, Vec<u8>) {
+    assert_eq!(pixels.len(), size.area() as usize * 4);
+    let mut histogram = vec![(0, [0; 3]); 1 << (3 * QUANTIZE_HISTOGRAM_BITS)];
+    let mut has_transparent = false;
+    for pixel in pixels.chunks_exact(4) {
+        if pixel[3] == 0 {
+            has_transparent = true;
+            continue;
+        }
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let (count, sum) = &mut histogram[color_cell_index(color, QUANTIZE_HISTOGRAM_BITS)];
+        *count += 1;
+        for (sum, value) in sum.iter_mut().zip(color) {
+            *sum += value as u64;
+        }
+    }
+    let mut cells: Vec<HistogramCell> = histogram
+        .into_iter()
+        .filter(|&(count, _)| count > 0)
+        .map(|(count, sum)| HistogramCell {
+            color: average_color(count, sum),
+            count,
+            sum,
+        })
+        .collect();
+
+    // Split the box of cells with the widest range of a channel at the median of the pixels
+    // along it, until there are enough boxes or no box can be split. Each box is a range of
+    // `cells`, which is sorted in place when it is split.
+    let max_opaque_colors = max_colors.saturating_sub(has_transparent as usize).max(1);
+    let mut boxes = vec![(0..cells.len(), widest_channel(&cells))];
+    while boxes.len() < max_opaque_colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, (_, range)))| *range > 0)
+            .max_by_key(|(_, (_, (_, range)))| *range);
+        let Some((index, (colors, (channel, _)))) = widest else {
+            break;
+        };
+        let (colors, channel) = (colors.clone(), *channel);
+        let sorted = &mut cells[colors.clone()];
+        sorted.sort_unstable_by_key(|cell| cell.color[channel]);
+        let total: u64 = sorted.iter().map(|cell| cell.count).sum();
+        let mut below = 0;
+        let median = sorted
+            .iter()
+            .position(|cell| {
+                below += cell.count;
+                below * 2 >= total
+            })
+            .unwrap_or(0);
+        let split = colors.start + (median + 1).min(sorted.len() - 1);
+        boxes[index] = (colors.start..split, widest_channel(&cells[colors.start..split]));
+        boxes.push((split..colors.end, widest_channel(&cells[split..colors.end])));
+    }
+
+    let mut palette: Vec<[u8; 4]> = boxes
+        .iter()
+        .filter(|(colors, _)| !colors.is_empty())
+        .map(|(colors, _)| {
+            let cells = &cells[colors.clone()];
+            let count = cells.iter().map(|cell| cell.count).sum();
+            let sum = cells.iter().fold([0; 3], |sum, cell| {
+                std::array::from_fn(|channel| sum[channel] + cell.sum[channel])
+            });
+            let [red, green, blue] = average_color(count, sum);
+            [red, green, blue, 255]
+        })
+        .collect();
+    // There are fewer opaque colors than `max_colors` when there are transparent pixels.
+    let opaque_count = palette.len();
+    let transparent_index = opaque_count as u8;
+    if has_transparent {
+        palette.push([0; 4]);
+    }
+    let opaque_colors = &palette[..opaque_count];
+    let mut lookup = NearestColorLookup::new(opaque_colors);
+
+    let width = size.width as usize;
+    let mut indices = Vec::with_capacity(pixels.len() / 4);
+    // The errors diffused to the pixels of the current row and of the next one.
+    let mut errors = vec![[0f32; 3]; width + 2];
+    let mut next_errors = vec![[0f32; 3]; width + 2];
+    for row in pixels.chunks_exact(width.max(1) * 4) {
+        for (x, pixel) in row.chunks_exact(4).enumerate() {
+            if pixel[3] == 0 {
+                indices.push(transparent_index);
+                continue;
+            }
+            let mut color = [pixel[0], pixel[1], pixel[2]];
+            if dither {
+                for (value, error) in color.iter_mut().zip(errors[x + 1]) {
+                    *value = (*value as f32 + error).round().clamp(0., 255.) as u8;
+                }
+            }
+            let index = lookup.nearest(color);
+            indices.push(index);
+            if dither {
+                let entry = opaque_colors[index as usize];
+                let error: [f32; 3] =
+                    std::array::from_fn(|channel| color[channel] as f32 - entry[channel] as f32);
+                let diffuse = |target: &mut [f32; 3], weight: f32| {
+                    for (target, error) in target.iter_mut().zip(error) {
+                        *target += error * weight / 16.;
+                    }
+                };
+                diffuse(&mut errors[x + 2], 7.);
+                diffuse(&mut next_errors[x], 3.);
+                diffuse(&mut next_errors[x + 1], 5.);
+                diffuse(&mut next_errors[x + 2], 1.);
+            }
+        }
+        std::mem::swap(&mut errors, &mut next_errors);
+        next_errors.fill([0.; 3]);
+    }
+    (palette, indices)
+}
+
 /// Returns true if the pixels were found to be completely opaque.
 pub fn rgba8_premultiply_inplace(pixels: &mut [u8]) -> bool {
     assert!(pixels.len() % 4 == 0);
@@ -203,6 +599,422 @@ pub fn rgba8_premultiply_inplace(pixels: &mut [u8]) -> bool {
     is_opaque
 }
 
+/// Decodes a normalized sRGB-encoded channel value to linear light.
+/// <https://www.w3.org/TR/css-color-4/#color-conversion-code>
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a normalized linear-light channel value with the sRGB transfer function.
+/// <https://www.w3.org/TR/css-color-4/#color-conversion-code>
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn apply_channel_lookup_table(pixels: &mut [u8], transfer: impl Fn(f32) -> f32) {
+    assert!(pixels.len() % 4 == 0);
+    let table: Vec<u8> = (0..=u8::MAX)
+        .map(|value| {
+            (transfer(value as f32 / 255.) * 255.)
+                .round()
+                .clamp(0., 255.) as u8
+        })
+        .collect();
+    for rgba in pixels.chunks_mut(4) {
+        rgba[0] = table[rgba[0] as usize];
+        rgba[1] = table[rgba[1] as usize];
+        rgba[2] = table[rgba[2] as usize];
+    }
+}
+
+/// Converts the color channels of non-premultiplied RGBA8 (or BGRA8) pixels from the sRGB
+/// transfer function to linear light. Alpha is left untouched.
+pub fn rgba8_srgb_to_linear_inplace(pixels: &mut [u8]) {
+    apply_channel_lookup_table(pixels, srgb_to_linear);
+}
+
+/// Converts the color channels of non-premultiplied RGBA8 (or BGRA8) pixels from linear
+/// light to the sRGB transfer function. Alpha is left untouched.
+pub fn rgba8_linear_to_srgb_inplace(pixels: &mut [u8]) {
+    apply_channel_lookup_table(pixels, linear_to_srgb);
+}
+
+/// The matrix converting linear-light sRGB to linear-light Display P3.
+/// <https://www.w3.org/TR/css-color-4/#color-conversion-code>
+const LINEAR_SRGB_TO_LINEAR_DISPLAY_P3: [[f32; 3]; 3] = [
+    [0.822_462_1, 0.177_538, 0.],
+    [0.033_194_2, 0.966_805_8, 0.],
+    [0.017_082_7, 0.072_397_4, 0.910_519_9],
+];
+
+/// The matrix converting linear-light Display P3 to linear-light sRGB, the inverse of
+/// [`LINEAR_SRGB_TO_LINEAR_DISPLAY_P3`].
+const LINEAR_DISPLAY_P3_TO_LINEAR_SRGB: [[f32; 3]; 3] = [
+    [1.224_940_1, -0.224_940_1, 0.],
+    [-0.042_056_9, 1.042_057, 0.],
+    [-0.019_637_6, -0.078_636, 1.098_273_6],
+];
+
+/// Converts the color channels of non-premultiplied RGBA8 pixels from sRGB to Display P3,
+/// which shares the sRGB transfer function but has wider primaries. Alpha is left
+/// untouched.
+pub fn rgba8_srgb_to_display_p3_inplace(pixels: &mut [u8]) {
+    rgba8_convert_primaries_inplace(pixels, &LINEAR_SRGB_TO_LINEAR_DISPLAY_P3);
+}
+
+/// Converts the color channels of non-premultiplied RGBA8 pixels from Display P3 to sRGB.
+/// Colors outside of the sRGB gamut are clipped to it. Alpha is left untouched.
+pub fn rgba8_display_p3_to_srgb_inplace(pixels: &mut [u8]) {
+    rgba8_convert_primaries_inplace(pixels, &LINEAR_DISPLAY_P3_TO_LINEAR_SRGB);
+}
+
+/// Applies `matrix` to the color channels of non-premultiplied RGBA8 pixels in linear
+/// light, for color spaces that use the sRGB transfer function.
+fn rgba8_convert_primaries_inplace(pixels: &mut [u8], matrix: &[[f32; 3]; 3]) {
+    assert!(pixels.len() % 4 == 0);
+    let linear: Vec<f32> = (0..=u8::MAX)
+        .map(|value| srgb_to_linear(value as f32 / 255.))
+        .collect();
+    for rgba in pixels.chunks_mut(4) {
+        let source = [
+            linear[rgba[0] as usize],
+            linear[rgba[1] as usize],
+            linear[rgba[2] as usize],
+        ];
+        for (channel, row) in matrix.iter().enumerate() {
+            let value = row[0] * source[0] + row[1] * source[1] + row[2] * source[2];
+            rgba[channel] = (linear_to_srgb(value) * 255.).round().clamp(0., 255.) as u8;
+        }
+    }
+}
+
+/// Returns the normalized weights of a one-dimensional Gaussian kernel with the given
+/// standard deviation. The kernel extends three standard deviations on each side.
+pub fn gaussian_kernel(std_deviation: f64) -> Vec<f32> {
+    let radius = (std_deviation * 3.).ceil() as usize;
+    let two_variance = 2. * std_deviation * std_deviation;
+    let mut kernel: Vec<f32> = (0..=2 * radius)
+        .map(|index| {
+            let distance = index as f64 - radius as f64;
+            (-(distance * distance) / two_variance).exp() as f32
+        })
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    kernel.iter_mut().for_each(|weight| *weight /= sum);
+    kernel
+}
+
+/// Applies a Gaussian blur with the given standard deviation to premultiplied RGBA8 (or
+/// BGRA8) pixels in place, as two separable passes. Samples that fall outside of the image
+/// are clamped to the nearest edge pixel, so edges do not darken.
+pub fn gaussian_blur_rgba8_inplace(size: Size2D<u32>, pixels: &mut [u8], std_deviation: f64) {
+    assert_eq!(size.area() as usize * 4, pixels.len());
+    if std_deviation <= 0. || size.is_empty() {
+        return;
+    }
+
+    let kernel = gaussian_kernel(std_deviation);
+    let radius = (kernel.len() / 2) as isize;
+    let width = size.width as usize;
+    let height = size.height as usize;
+    let clamped = |value: isize, length: usize| value.clamp(0, length as isize - 1) as usize;
+
+    let mut horizontal = vec![0f32; pixels.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0f32; 4];
+            for (offset, weight) in kernel.iter().enumerate() {
+                let sample_x = clamped(x as isize + offset as isize - radius, width);
+                let sample = &pixels[(y * width + sample_x) * 4..][..4];
+                for channel in 0..4 {
+                    sum[channel] += sample[channel] as f32 * weight;
+                }
+            }
+            horizontal[(y * width + x) * 4..][..4].copy_from_slice(&sum);
+        }
+    }
+
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0f32; 4];
+            for (offset, weight) in kernel.iter().enumerate() {
+                let sample_y = clamped(y as isize + offset as isize - radius, height);
+                let sample = &horizontal[(sample_y * width + x) * 4..][..4];
+                for channel in 0..4 {
+                    sum[channel] += sample[channel] * weight;
+                }
+            }
+            let pixel = &mut pixels[(y * width + x) * 4..][..4];
+            for channel in 0..4 {
+                pixel[channel] = sum[channel].round().clamp(0., 255.) as u8;
+            }
+        }
+    }
+}
+
+/// Approximates [`gaussian_blur_rgba8_inplace`] with a box blur of the same variance, whose
+/// cost does not grow with the standard deviation. Samples that fall outside of the image
+/// are clamped to the nearest edge pixel, as for the Gaussian blur.
+pub fn box_blur_rgba8_inplace(size: Size2D<u32>, pixels: &mut [u8], std_deviation: f64) {
+    assert_eq!(size.area() as usize * 4, pixels.len());
+    if std_deviation <= 0. || size.is_empty() {
+        return;
+    }
+
+    // A box `2 * radius + 1` pixels wide has a variance of `radius * (radius + 1) / 3`.
+    let variance = std_deviation * std_deviation;
+    let radius = (((12. * variance + 1.).sqrt() - 1.) / 2.).round().max(1.) as isize;
+    let weight = 1. / (2 * radius + 1) as f32;
+    let width = size.width as usize;
+    let height = size.height as usize;
+
+    let mut horizontal = vec![0f32; pixels.len()];
+    for y in 0..height {
+        box_blur_line(
+            width,
+            radius,
+            |x| std::array::from_fn(|channel| pixels[(y * width + x) * 4 + channel] as f32),
+            |x, sum| {
+                let pixel = &mut horizontal[(y * width + x) * 4..][..4];
+                for channel in 0..4 {
+                    pixel[channel] = sum[channel] * weight;
+                }
+            },
+        );
+    }
+
+    for x in 0..width {
+        box_blur_line(
+            height,
+            radius,
+            |y| std::array::from_fn(|channel| horizontal[(y * width + x) * 4 + channel]),
+            |y, sum| {
+                let pixel = &mut pixels[(y * width + x) * 4..][..4];
+                for channel in 0..4 {
+                    pixel[channel] = (sum[channel] * weight).round().clamp(0., 255.) as u8;
+                }
+            },
+        );
+    }
+}
+
+/// Passes the sums of the samples of a line of `length` pixels within `radius` of each of
+/// them to `output`, sliding the window along the line rather than summing it anew.
+fn box_blur_line(
+    length: usize,
+    radius: isize,
+    sample: impl Fn(usize) -> [f32; 4],
+    mut output: impl FnMut(usize, [f32; 4]),
+) {
+    let clamped = |index: isize| index.clamp(0, length as isize - 1) as usize;
+    let mut sum = [0f32; 4];
+    for offset in -radius..=radius {
+        let value = sample(clamped(offset));
+        for channel in 0..4 {
+            sum[channel] += value[channel];
+        }
+    }
+    for index in 0..length {
+        output(index, sum);
+        let entering = sample(clamped(index as isize + radius + 1));
+        let leaving = sample(clamped(index as isize - radius));
+        for channel in 0..4 {
+            sum[channel] += entering[channel] - leaving[channel];
+        }
+    }
+}
+
+/// Applies a 4x5 color matrix, given row by row as for SVG `feColorMatrix`, to
+/// non-premultiplied RGBA8 pixels in place. Each output channel is a weighted sum of the
+/// input channels in the range [0, 1] plus the last entry of its row, clamped to that range.
+/// <https://drafts.fxtf.org/filter-effects/#feColorMatrixElement>
+pub fn color_matrix_rgba8_inplace(pixels: &mut [u8], matrix: &[f32; 20]) {
+    assert!(pixels.len() % 4 == 0);
+    for pixel in pixels.chunks_exact_mut(4) {
+        let source: [f32; 4] = std::array::from_fn(|channel| pixel[channel] as f32 / 255.);
+        for (channel, row) in matrix.chunks_exact(5).enumerate() {
+            let value = row[0] * source[0] +
+                row[1] * source[1] +
+                row[2] * source[2] +
+                row[3] * source[3] +
+                row[4];
+            pixel[channel] = (value.clamp(0., 1.) * 255.).round() as u8;
+        }
+    }
+}
+
+/// Grows the shape of premultiplied RGBA8 (or BGRA8) pixels by `spread` pixels in every
+/// direction, or shrinks it if `spread` is negative, as for the spread of a shadow. Each
+/// channel of each pixel becomes the largest, or smallest, of its values in the square of
+/// pixels within that distance, where pixels outside of the image are transparent.
+pub fn spread_rgba8_inplace(size: Size2D<u32>, pixels: &mut [u8], spread: f64) {
+    assert_eq!(size.area() as usize * 4, pixels.len());
+    let radius = spread.abs().round() as usize;
+    if radius == 0 || size.is_empty() {
+        return;
+    }
+
+    let grow = spread > 0.;
+    let width = size.width as usize;
+    let height = size.height as usize;
+    let mut horizontal = vec![0; pixels.len()];
+    spread_pass(pixels, &mut horizontal, width, height, radius, grow, 4, width * 4);
+    spread_pass(&horizontal, pixels, height, width, radius, grow, width * 4, 4);
+}
+
+/// Spreads each of the `lines` of `source` of `length` pixels into `destination`, where
+/// consecutive pixels of a line are `pixel_stride` bytes apart and lines are `line_stride`
+/// bytes apart.
+#[allow(clippy::too_many_arguments)]
+fn spread_pass(
+    source: &[u8],
+    destination: &mut [u8],
+    length: usize,
+    lines: usize,
+    radius: usize,
+    grow: bool,
+    pixel_stride: usize,
+    line_stride: usize,
+) {
+    for line in 0..lines {
+        for index in 0..length {
+            let mut value = if grow { [0; 4] } else { [u8::MAX; 4] };
+            for sample in index as isize - radius as isize..=(index + radius) as isize {
+                let sample: &[u8] = match usize::try_from(sample) {
+                    Ok(sample) if sample < length => {
+                        let offset = line * line_stride + sample * pixel_stride;
+                        &source[offset..offset + 4]
+                    },
+                    _ => &[0; 4],
+                };
+                for channel in 0..4 {
+                    value[channel] = if grow {
+                        value[channel].max(sample[channel])
+                    } else {
+                        value[channel].min(sample[channel])
+                    };
+                }
+            }
+            let offset = line * line_stride + index * pixel_stride;
+            destination[offset..offset + 4].copy_from_slice(&value);
+        }
+    }
+}
+
+/// Reduces each color channel of non-premultiplied RGBA8 (or BGRA8) pixels in place to
+/// `levels` evenly spaced values, including 0 and 255, rounding to the nearest. Alpha is left
+/// as it is. Fewer than two levels are taken as two.
+pub fn posterize_rgba8_inplace(pixels: &mut [u8], levels: u32) {
+    assert!(pixels.len() % 4 == 0);
+    let steps = levels.clamp(2, 256) as f32 - 1.;
+    let posterized: Vec<u8> = (0..=u8::MAX)
+        .map(|value| ((value as f32 / 255. * steps).round() / steps * 255.).round() as u8)
+        .collect();
+    for pixel in pixels.chunks_exact_mut(4) {
+        for channel in &mut pixel[..3] {
+            *channel = posterized[*channel as usize];
+        }
+    }
+}
+
+/// Multiplies premultiplied RGBA8 (or BGRA8) `pixels` in place by the alpha of the pixels at
+/// the same places of `mask`, which must be as many.
+pub fn mask_rgba8_inplace(pixels: &mut [u8], mask: &[u8]) {
+    assert!(pixels.len() % 4 == 0);
+    assert_eq!(pixels.len(), mask.len());
+    for (pixel, mask_pixel) in pixels.chunks_exact_mut(4).zip(mask.chunks_exact(4)) {
+        let alpha = mask_pixel[3];
+        for channel in pixel.iter_mut() {
+            *channel = multiply_u8_color(*channel, alpha);
+        }
+    }
+}
+
+/// Replaces premultiplied RGBA8 (or BGRA8) pixels with their shadow: their alpha, tinted
+/// with `color` and blurred with a Gaussian of the given standard deviation. `color` is
+/// premultiplied and in the same channel order as the pixels.
+pub fn shadow_rgba8_inplace(
+    size: Size2D<u32>,
+    pixels: &mut [u8],
+    std_deviation: f64,
+    color: [u8; 4],
+) {
+    tint_alpha_rgba8_inplace(pixels, color);
+    gaussian_blur_rgba8_inplace(size, pixels, std_deviation);
+}
+
+/// Like [`shadow_rgba8_inplace`], but blurs the shadow with [`box_blur_rgba8_inplace`],
+/// which is cheaper and coarser.
+pub fn approximate_shadow_rgba8_inplace(
+    size: Size2D<u32>,
+    pixels: &mut [u8],
+    std_deviation: f64,
+    color: [u8; 4],
+) {
+    tint_alpha_rgba8_inplace(pixels, color);
+    box_blur_rgba8_inplace(size, pixels, std_deviation);
+}
+
+/// Replaces premultiplied pixels with `color`, scaled by their alpha.
+fn tint_alpha_rgba8_inplace(pixels: &mut [u8], color: [u8; 4]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3];
+        for channel in 0..4 {
+            pixel[channel] = multiply_u8_color(color[channel], alpha);
+        }
+    }
+}
+
+/// Applies a `drop-shadow()` filter to premultiplied RGBA8 (or BGRA8) pixels in place: the
+/// alpha of the pixels, offset by `offset`, blurred and tinted with `color`, is drawn
+/// underneath them. `color` is premultiplied and in the same channel order as the pixels.
+/// Since the shadow is derived from the composited pixels, it shows through any of their
+/// transparent parts.
+pub fn drop_shadow_rgba8_inplace(
+    size: Size2D<u32>,
+    pixels: &mut [u8],
+    offset: Vector2D<i32>,
+    std_deviation: f64,
+    color: [u8; 4],
+) {
+    assert_eq!(size.area() as usize * 4, pixels.len());
+    let width = size.width as i64;
+    let height = size.height as i64;
+
+    let mut shadow = vec![0u8; pixels.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let source_x = x - offset.x as i64;
+            let source_y = y - offset.y as i64;
+            if !(0..width).contains(&source_x) || !(0..height).contains(&source_y) {
+                continue;
+            }
+            let alpha = pixels[((source_y * width + source_x) * 4 + 3) as usize];
+            let shadow_pixel = &mut shadow[((y * width + x) * 4) as usize..][..4];
+            for channel in 0..4 {
+                shadow_pixel[channel] = multiply_u8_color(color[channel], alpha);
+            }
+        }
+    }
+    gaussian_blur_rgba8_inplace(size, &mut shadow, std_deviation);
+
+    for (pixel, shadow_pixel) in pixels.chunks_exact_mut(4).zip(shadow.chunks_exact(4)) {
+        let inverse_alpha = 255 - pixel[3];
+        for channel in 0..4 {
+            pixel[channel] = pixel[channel]
+                .saturating_add(multiply_u8_color(shadow_pixel[channel], inverse_alpha));
+        }
+    }
+}
+
 /// Returns a*b/255, rounding any fractional bits to nearest integer
 /// to reduce the loss of precision after multiple consequence alpha
 /// (un)premultiply operations.
@@ -212,6 +1024,14 @@ pub fn multiply_u8_color(a: u8, b: u8) -> u8 {
     ((c + (c >> 8)) >> 8) as u8
 }
 
+/// Returns c*255/a, rounding to the nearest integer, for a nonzero `a`. Rounding (rather
+/// than truncating) makes premultiplying the result with [`multiply_u8_color`] give back
+/// `c`, so pixels are stable across repeated premultiply/unpremultiply round-trips.
+pub fn unmultiply_u8_color(c: u8, a: u8) -> u8 {
+    let a = a as u32;
+    ((c as u32 * 255 + a / 2) / a).min(255) as u8
+}
+
 pub fn clip(
     mut origin: Point2D<i32>,
     mut size: Size2D<u32>,
@@ -231,11 +1051,13 @@ pub fn clip(
         .filter(|rect| !rect.is_empty())
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum EncodedImageType {
     Png,
     Jpeg,
     Webp,
+    /// GIF, whose colors are quantized to a palette of 256 with dithering.
+    Gif,
 }
 
 impl From<String> for EncodedImageType {
@@ -250,6 +1072,8 @@ impl From<String> for EncodedImageType {
             Self::Jpeg
         } else if mime == "image/webp" {
             Self::Webp
+        } else if mime == "image/gif" {
+            Self::Gif
         } else {
             Self::Png
         }
@@ -262,9 +1086,40 @@ impl EncodedImageType {
             Self::Png => "image/png",
             Self::Jpeg => "image/jpeg",
             Self::Webp => "image/webp",
+            Self::Gif => "image/gif",
         }
         .to_owned()
     }
+
+    /// Every type, with PNG, which every user agent must support, first.
+    const ALL: [Self; 4] = [Self::Png, Self::Jpeg, Self::Webp, Self::Gif];
+
+    /// The types that [`Snapshot::encode_for_mime_type`] can encode images as, with PNG
+    /// first: those whose encoder, as built, encodes a single pixel without an error.
+    pub fn supported() -> Vec<Self> {
+        static SUPPORTED: LazyLock<Vec<EncodedImageType>> = LazyLock::new(|| {
+            EncodedImageType::ALL
+                .into_iter()
+                .filter(|image_type| {
+                    Snapshot::cleared(Size2D::new(1, 1))
+                        .encode_for_mime_type(image_type, None, &mut io::sink())
+                        .is_ok()
+                })
+                .collect()
+        });
+        SUPPORTED.clone()
+    }
+
+    /// The type to encode the pixels of a canvas as for `mime_type`, the type passed to
+    /// `toBlob` or `toDataURL`: the one it names if it is supported, and PNG otherwise.
+    pub fn for_encoding(mime_type: String) -> Self {
+        let image_type = Self::from(mime_type);
+        if Self::supported().contains(&image_type) {
+            image_type
+        } else {
+            Self::Png
+        }
+    }
 }
 
 /// Whether this response passed any CORS checks, and is thus safe to read from
@@ -483,12 +1338,12 @@ pub fn generic_transform_inplace<
                 rgba[2] = multiply_u8_color(rgba[2], a);
             },
             2 => {
-                let a = rgba[3] as u32;
+                let a = rgba[3];
 
                 if a > 0 {
-                    rgba[0] = (rgba[0] as u32 * 255 / a) as u8;
-                    rgba[1] = (rgba[1] as u32 * 255 / a) as u8;
-                    rgba[2] = (rgba[2] as u32 * 255 / a) as u8;
+                    rgba[0] = unmultiply_u8_color(rgba[0], a);
+                    rgba[1] = unmultiply_u8_color(rgba[1], a);
+                    rgba[2] = unmultiply_u8_color(rgba[2], a);
                 }
             },
             _ => {},
@@ -669,7 +1524,35 @@ where
 
 #[cfg(test)]
 mod test {
-    use super::detect_image_format;
+    use std::collections::HashSet;
+    use std::time::{Duration, Instant};
+
+    use euclid::default::Size2D;
+
+    use super::{detect_image_format, rgba8_orient, rgba8_quantize};
+    use crate::{EncodedImageType, Snapshot, SnapshotAlphaMode, SnapshotPixelFormat};
+
+    #[test]
+    fn test_supported_encoded_image_types() {
+        let supported = EncodedImageType::supported();
+        assert!(!supported.is_empty());
+        assert_eq!(supported[0], EncodedImageType::Png);
+
+        assert_eq!(
+            EncodedImageType::for_encoding("image/png".to_owned()),
+            EncodedImageType::Png
+        );
+        assert_eq!(
+            EncodedImageType::for_encoding("image/bmp".to_owned()),
+            EncodedImageType::Png
+        );
+        for image_type in supported {
+            assert_eq!(
+                EncodedImageType::for_encoding(image_type.as_mime_type()),
+                image_type
+            );
+        }
+    }
 
     #[test]
     fn test_supported_images() {
@@ -693,4 +1576,133 @@ mod test {
         assert!(detect_image_format(&ico).is_ok());
         assert!(detect_image_format(&junk_format).is_err());
     }
+
+    #[test]
+    fn test_rgba8_orient() {
+        // A 3x2 image whose pixels are numbered 1 to 6 in reading order.
+        let pixels: Vec<u8> = (1..=6).flat_map(|value| [value; 4]).collect();
+        let orient = |orientation| {
+            let (data, size) = rgba8_orient(&pixels, Size2D::new(3, 2), orientation);
+            let values: Vec<u8> = data.chunks(4).map(|pixel| pixel[0]).collect();
+            (values, size)
+        };
+        assert_eq!(orient(1), (vec![1, 2, 3, 4, 5, 6], Size2D::new(3, 2)));
+        assert_eq!(orient(2), (vec![3, 2, 1, 6, 5, 4], Size2D::new(3, 2)));
+        assert_eq!(orient(3), (vec![6, 5, 4, 3, 2, 1], Size2D::new(3, 2)));
+        assert_eq!(orient(4), (vec![4, 5, 6, 1, 2, 3], Size2D::new(3, 2)));
+        assert_eq!(orient(5), (vec![1, 4, 2, 5, 3, 6], Size2D::new(2, 3)));
+        assert_eq!(orient(6), (vec![4, 1, 5, 2, 6, 3], Size2D::new(2, 3)));
+        assert_eq!(orient(7), (vec![6, 3, 5, 2, 4, 1], Size2D::new(2, 3)));
+        assert_eq!(orient(8), (vec![3, 6, 2, 5, 1, 4], Size2D::new(2, 3)));
+    }
+
+    #[test]
+    fn test_export_over_checkerboard() {
+        // Transparent on the left half, opaque red on the right half.
+        let pixels: Vec<u8> = (0..16)
+            .flat_map(|x| if x < 8 { [0; 4] } else { [255, 0, 0, 255] })
+            .collect();
+        let export = |checkerboard| {
+            let mut snapshot = Snapshot::from_vec(
+                Size2D::new(16, 1),
+                SnapshotPixelFormat::RGBA,
+                SnapshotAlphaMode::Transparent {
+                    premultiplied: false,
+                },
+                pixels.clone(),
+            );
+            if checkerboard {
+                snapshot.composite_over_checkerboard(4);
+            }
+            let mut encoded = Vec::new();
+            snapshot
+                .encode_for_mime_type(&EncodedImageType::Png, None, &mut encoded)
+                .unwrap();
+            let decoded = Snapshot::decode(&encoded, &EncodedImageType::Png).unwrap();
+            decoded
+                .as_raw_bytes()
+                .chunks(4)
+                .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+                .collect::<Vec<_>>()
+        };
+
+        let exported = export(true);
+        assert_eq!(exported[1], [255, 255, 255, 255]);
+        assert_eq!(exported[5], [204, 204, 204, 255]);
+        assert_eq!(exported[9], [255, 0, 0, 255]);
+
+        // Without it, the alpha is kept.
+        assert_eq!(export(false)[1], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_gif_export() {
+        // A gradient of more colors than a GIF palette can hold.
+        let size = Size2D::new(64, 16);
+        let pixels: Vec<u8> = (0..16u8)
+            .flat_map(|y| (0..64u8).flat_map(move |x| [x * 4, y * 16, 255 - x * 4, 255]))
+            .collect();
+
+        let (palette, indices) = rgba8_quantize(size, &pixels, 256, false);
+        assert_eq!(palette.len(), 256);
+        assert_eq!(indices.len(), 64 * 16);
+        for (pixel, &index) in pixels.chunks(4).zip(&indices) {
+            let entry = palette[index as usize];
+            for channel in 0..4 {
+                assert!(pixel[channel].abs_diff(entry[channel]) <= 8);
+            }
+        }
+
+        // Transparent pixels share an entry, which leaves fewer for the opaque colors.
+        let mut transparent = pixels.clone();
+        transparent[3] = 0;
+        let (palette, indices) = rgba8_quantize(size, &transparent, 256, false);
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette[indices[0] as usize], [0, 0, 0, 0]);
+
+        let mut snapshot = Snapshot::from_vec(
+            size,
+            SnapshotPixelFormat::RGBA,
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            pixels.clone(),
+        );
+        let mut encoded = Vec::new();
+        snapshot
+            .encode_for_mime_type(&EncodedImageType::Gif, None, &mut encoded)
+            .unwrap();
+        assert!(encoded.starts_with(b"GIF8"));
+        let decoded = Snapshot::decode(&encoded, &EncodedImageType::Gif).unwrap();
+        assert_eq!(decoded.size(), size);
+        let decoded = decoded.as_raw_bytes();
+        let colors: HashSet<_> = decoded.chunks(4).collect();
+        assert!(colors.len() <= 256);
+        // Dithering trades the error of single pixels for a smaller error on average.
+        let error: u64 = pixels
+            .iter()
+            .zip(decoded)
+            .map(|(&original, &decoded)| original.abs_diff(decoded) as u64)
+            .sum();
+        assert!(error as f64 / pixels.len() as f64 <= 4.);
+    }
+
+    #[test]
+    fn test_quantize_large_image() {
+        // Far more distinct colors than median cut could split one by one.
+        let size = Size2D::new(512, 512);
+        let pixels: Vec<u8> = (0..512u32)
+            .flat_map(|y| {
+                (0..512u32).flat_map(move |x| {
+                    [(x / 2) as u8, (y / 2) as u8, ((x ^ y) >> 1) as u8, 255]
+                })
+            })
+            .collect();
+
+        let start = Instant::now();
+        let (palette, indices) = rgba8_quantize(size, &pixels, 256, true);
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(palette.len(), 256);
+        assert_eq!(indices.len(), 512 * 512);
+    }
 }
@@ -5,15 +5,20 @@
 use std::ops::{Deref, DerefMut};
 
 use euclid::default::{Rect, Size2D};
+use image::codecs::gif::GifEncoder;
 use image::codecs::jpeg::JpegEncoder;
 use image::codecs::png::PngEncoder;
 use image::codecs::webp::WebPEncoder;
-use image::{ColorType, ImageEncoder, ImageError};
+use image::{ColorType, ImageEncoder, ImageError, ImageFormat};
 use ipc_channel::ipc::IpcSharedMemory;
 use malloc_size_of_derive::MallocSizeOf;
 use serde::{Deserialize, Serialize};
 
-use crate::{EncodedImageType, Multiply, rgba8_get_rect, transform_inplace};
+use crate::{
+    EncodedImageType, Multiply, rgba8_display_p3_to_srgb_inplace, rgba8_get_rect,
+    rgba8_linear_to_srgb_inplace, rgba8_orient, rgba8_over_checkerboard_inplace, rgba8_quantize,
+    rgba8_srgb_to_display_p3_inplace, rgba8_srgb_to_linear_inplace, transform_inplace,
+};
 
 #[derive(Clone, Copy, Debug, Default, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
 pub enum SnapshotPixelFormat {
@@ -22,6 +27,14 @@ pub enum SnapshotPixelFormat {
     BGRA,
 }
 
+/// The color space that the color channels of a snapshot are in.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub enum SnapshotColorSpace {
+    #[default]
+    Srgb,
+    DisplayP3,
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
 pub enum Alpha {
     Premultiplied,
@@ -126,6 +139,7 @@ pub struct Snapshot<T = SnapshotData> {
     format: SnapshotPixelFormat,
     /// How to treat alpha channel
     alpha_mode: SnapshotAlphaMode,
+    color_space: SnapshotColorSpace,
 }
 
 impl<T> Snapshot<T> {
@@ -140,6 +154,15 @@ impl<T> Snapshot<T> {
     pub const fn alpha_mode(&self) -> SnapshotAlphaMode {
         self.alpha_mode
     }
+
+    pub const fn color_space(&self) -> SnapshotColorSpace {
+        self.color_space
+    }
+
+    /// Tag the pixels of the snapshot as being in `color_space`, without converting them.
+    pub fn set_color_space(&mut self, color_space: SnapshotColorSpace) {
+        self.color_space = color_space;
+    }
 }
 
 impl Snapshot<SnapshotData> {
@@ -151,6 +174,7 @@ impl Snapshot<SnapshotData> {
             alpha_mode: SnapshotAlphaMode::Transparent {
                 premultiplied: true,
             },
+            color_space: SnapshotColorSpace::Srgb,
         }
     }
 
@@ -163,6 +187,7 @@ impl Snapshot<SnapshotData> {
             alpha_mode: SnapshotAlphaMode::Transparent {
                 premultiplied: true,
             },
+            color_space: SnapshotColorSpace::Srgb,
         }
     }
 
@@ -177,12 +202,15 @@ impl Snapshot<SnapshotData> {
             data: SnapshotData::Owned(data),
             format,
             alpha_mode,
+            color_space: SnapshotColorSpace::Srgb,
         }
     }
 
     pub fn get_rect(&self, rect: Rect<u32>) -> Self {
         let data = rgba8_get_rect(self.as_raw_bytes(), self.size(), rect).to_vec();
-        Self::from_vec(rect.size, self.format, self.alpha_mode, data)
+        let mut snapshot = Self::from_vec(rect.size, self.format, self.alpha_mode, data);
+        snapshot.color_space = self.color_space;
+        snapshot
     }
 
     // TODO: https://github.com/servo/servo/issues/36594
@@ -249,6 +277,58 @@ impl Snapshot<SnapshotData> {
         self.format = target_format;
     }
 
+    /// Convert inner data of snapshot to non-premultiplied alpha, keeping its pixel format.
+    pub fn unpremultiply(&mut self) {
+        let target_alpha_mode = match self.alpha_mode {
+            SnapshotAlphaMode::Opaque => SnapshotAlphaMode::Opaque,
+            SnapshotAlphaMode::AsOpaque { .. } => SnapshotAlphaMode::AsOpaque {
+                premultiplied: false,
+            },
+            SnapshotAlphaMode::Transparent { .. } => SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+        };
+        self.transform(target_alpha_mode, self.format);
+    }
+
+    /// Convert the color channels of the snapshot from the sRGB transfer function to
+    /// linear light. The data is left non-premultiplied.
+    pub fn convert_srgb_to_linear(&mut self) {
+        self.unpremultiply();
+        rgba8_srgb_to_linear_inplace(&mut self.data);
+    }
+
+    /// Convert the color channels of the snapshot from linear light to the sRGB transfer
+    /// function. The data is left non-premultiplied.
+    pub fn convert_linear_to_srgb(&mut self) {
+        self.unpremultiply();
+        rgba8_linear_to_srgb_inplace(&mut self.data);
+    }
+
+    /// Convert the pixels of the snapshot from sRGB to Display P3. The data is left
+    /// non-premultiplied, in RGBA order.
+    pub fn convert_srgb_to_display_p3(&mut self) {
+        self.unpremultiply();
+        self.transform(self.alpha_mode, SnapshotPixelFormat::RGBA);
+        rgba8_srgb_to_display_p3_inplace(&mut self.data);
+        self.color_space = SnapshotColorSpace::DisplayP3;
+    }
+
+    /// Convert the pixels of the snapshot from the color space they are tagged with to
+    /// sRGB, if they are in another one. The data is then left non-premultiplied, in RGBA
+    /// order.
+    pub fn convert_to_srgb(&mut self) {
+        match self.color_space {
+            SnapshotColorSpace::Srgb => {},
+            SnapshotColorSpace::DisplayP3 => {
+                self.unpremultiply();
+                self.transform(self.alpha_mode, SnapshotPixelFormat::RGBA);
+                rgba8_display_p3_to_srgb_inplace(&mut self.data);
+                self.color_space = SnapshotColorSpace::Srgb;
+            },
+        }
+    }
+
     pub fn as_raw_bytes(&self) -> &[u8] {
         &self.data
     }
@@ -286,6 +366,7 @@ impl Snapshot<SnapshotData> {
             data,
             format,
             alpha_mode,
+            color_space,
         } = self;
         let data = match data {
             //Data::IPC(ipc_shared_memory) => ipc_shared_memory,
@@ -296,7 +377,55 @@ impl Snapshot<SnapshotData> {
             data,
             format,
             alpha_mode,
+            color_space,
+        }
+    }
+
+    /// Decode an image of type `image_type` into a snapshot of its first frame.
+    pub fn decode(buffer: &[u8], image_type: &EncodedImageType) -> Result<Self, ImageError> {
+        let format = match image_type {
+            EncodedImageType::Png => ImageFormat::Png,
+            EncodedImageType::Jpeg => ImageFormat::Jpeg,
+            EncodedImageType::Webp => ImageFormat::WebP,
+            EncodedImageType::Gif => ImageFormat::Gif,
+        };
+        let image = image::load_from_memory_with_format(buffer, format)?.into_rgba8();
+        Ok(Self::from_vec(
+            Size2D::new(image.width(), image.height()),
+            SnapshotPixelFormat::RGBA,
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            image.into_raw(),
+        ))
+    }
+
+    /// Lay out the pixels of the snapshot as an image with the EXIF `orientation` (1 to 8)
+    /// is meant to be displayed. See [`crate::exif_orientation`].
+    pub fn orient(&mut self, orientation: u16) {
+        if orientation <= 1 {
+            return;
         }
+        let (data, size) = rgba8_orient(self.as_raw_bytes(), self.size, orientation);
+        self.data = SnapshotData::Owned(data);
+        self.size = size;
+    }
+
+    /// Make the snapshot opaque by compositing it over a checkerboard of `cell_size` pixel
+    /// squares, so that an export meant as a preview shows where it is transparent.
+    /// Exports keep their alpha unless this is done first.
+    pub fn composite_over_checkerboard(&mut self, cell_size: u32) {
+        if self.alpha_mode == SnapshotAlphaMode::Opaque {
+            return;
+        }
+        self.transform(
+            SnapshotAlphaMode::Transparent {
+                premultiplied: true,
+            },
+            self.format,
+        );
+        rgba8_over_checkerboard_inplace(self.size, self.as_raw_bytes_mut(), cell_size);
+        self.alpha_mode = SnapshotAlphaMode::Opaque;
     }
 
     pub fn encode_for_mime_type<W: std::io::Write>(
@@ -354,6 +483,15 @@ impl Snapshot<SnapshotData> {
                     ColorType::Rgba8,
                 )
             },
+            EncodedImageType::Gif => {
+                // Quantizing first leaves the encoder few enough colors to keep them exactly.
+                let (palette, indices) = rgba8_quantize(self.size, data, 256, true);
+                let data: Vec<u8> = indices
+                    .iter()
+                    .flat_map(|&index| palette[index as usize])
+                    .collect();
+                GifEncoder::new(encoder).encode(&data, width, height, ColorType::Rgba8)
+            },
         }
     }
 }
@@ -371,12 +509,14 @@ impl Snapshot<IpcSharedMemory> {
             data,
             format,
             alpha_mode,
+            color_space,
         } = self;
         Snapshot {
             size,
             data: Data::IPC(data),
             format,
             alpha_mode,
+            color_space,
         }
     }
     */
@@ -386,12 +526,14 @@ impl Snapshot<IpcSharedMemory> {
             data,
             format,
             alpha_mode,
+            color_space,
         } = self;
         Snapshot {
             size,
             data: SnapshotData::Owned(data.to_vec()),
             format,
             alpha_mode,
+            color_space,
         }
     }
 
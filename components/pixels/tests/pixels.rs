@@ -3,7 +3,11 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
 use euclid::default::Size2D;
-use pixels::flip_y_rgba8_image_inplace;
+use pixels::{
+    Snapshot, SnapshotAlphaMode, SnapshotPixelFormat, flip_y_rgba8_image_inplace,
+    gaussian_blur_rgba8_inplace, rgba8_linear_to_srgb_inplace, rgba8_srgb_to_linear_inplace,
+    srgb_to_linear,
+};
 
 const RED: [u8; 4] = [255, 0, 0, 255];
 const GREEN: [u8; 4] = [0, 255, 0, 255];
@@ -58,3 +62,87 @@ fn test_flip_y_rgba8_image_inplace() {
         "Expected blue color at [2, 2] (image3x3)"
     );
 }
+
+#[test]
+fn test_srgb_linear_round_trip() {
+    // Mid-grey is roughly 21.6% of full intensity in linear light.
+    let mut pixels = vec![128, 128, 128, 200];
+    rgba8_srgb_to_linear_inplace(&mut pixels);
+    let expected = (srgb_to_linear(128. / 255.) * 255.).round() as u8;
+    assert_eq!(pixels, [expected, expected, expected, 200]);
+    assert_eq!(expected, 55);
+
+    rgba8_linear_to_srgb_inplace(&mut pixels);
+    assert!(pixels[0].abs_diff(128) <= 1, "got {}", pixels[0]);
+    assert_eq!(pixels[3], 200, "alpha must be left untouched");
+
+    let mut extremes = vec![0, 255, 0, 255];
+    rgba8_srgb_to_linear_inplace(&mut extremes);
+    assert_eq!(extremes, [0, 255, 0, 255]);
+}
+
+#[test]
+fn test_gaussian_blur_sharp_edge() {
+    // A 16x1 image whose left half is opaque white and right half is opaque black.
+    let width = 16;
+    let mut image: Vec<u8> = (0..width)
+        .flat_map(|x| {
+            if x < width / 2 {
+                [255, 255, 255, 255]
+            } else {
+                [0, 0, 0, 255]
+            }
+        })
+        .collect();
+
+    gaussian_blur_rgba8_inplace(Size2D::new(width as u32, 1), &mut image, 1.5);
+
+    let reds: Vec<u8> = image.chunks(4).map(|pixel| pixel[0]).collect();
+    assert!(
+        reds.windows(2).all(|pair| pair[0] >= pair[1]),
+        "Expected a monotonic falloff across the edge: {reds:?}"
+    );
+    assert!(reds[width / 2 - 1] < 255 && reds[width / 2] > 0);
+    // Edges are clamped rather than sampling transparent black.
+    assert_eq!(reds[0], 255);
+    assert_eq!(reds[width - 1], 0);
+    assert!(image.chunks(4).all(|pixel| pixel[3] == 255));
+}
+
+#[test]
+fn test_premultiply_round_trip_is_stable() {
+    // Every straight-alpha color and alpha value, as `putImageData` could provide them.
+    let data: Vec<u8> = (0..=255u8)
+        .flat_map(|alpha| (0..=255u8).map(move |color| [color, 255 - color, color / 2, alpha]))
+        .flatten()
+        .collect();
+    let straight = SnapshotAlphaMode::Transparent {
+        premultiplied: false,
+    };
+    let premultiplied = SnapshotAlphaMode::Transparent {
+        premultiplied: true,
+    };
+
+    // Put and read back, as a canvas storing premultiplied BGRA pixels does.
+    let round_trip = |data: Vec<u8>| {
+        let mut snapshot = Snapshot::from_vec(
+            Size2D::new(256, 256),
+            SnapshotPixelFormat::RGBA,
+            straight,
+            data,
+        );
+        snapshot.transform(premultiplied, SnapshotPixelFormat::BGRA);
+        snapshot.transform(straight, SnapshotPixelFormat::RGBA);
+        snapshot.as_raw_bytes().to_vec()
+    };
+
+    let first = round_trip(data.clone());
+    let second = round_trip(first.clone());
+    assert_eq!(first, second, "Pixels drifted across put/get round-trips");
+    for (original, read) in data.chunks(4).zip(first.chunks(4)) {
+        assert_eq!(original[3], read[3]);
+        if original[3] == 255 {
+            assert_eq!(original, read);
+        }
+    }
+}
@@ -16,24 +16,36 @@ use jstraceable_derive::JSTraceable;
 #[derive(Clone, Copy, Debug, JSTraceable, MallocSizeOf)]
 pub(crate) struct AnimationTimeline {
     current_value: f64,
+    /// The value of the timeline when it was created.
+    origin: f64,
+    /// Whether the timeline has been pinned to a fixed value and should no longer
+    /// follow the clock.
+    pinned: bool,
 }
 
 impl AnimationTimeline {
     /// Creates a new "normal" timeline, i.e., a "Current" mode timer.
     #[inline]
     pub(crate) fn new() -> Self {
+        let current_value = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
         Self {
-            current_value: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs_f64(),
+            current_value,
+            origin: current_value,
+            pinned: false,
         }
     }
 
     /// Creates a new "test mode" timeline, with initial time 0.
     #[inline]
     pub(crate) fn new_for_testing() -> Self {
-        Self { current_value: 0. }
+        Self {
+            current_value: 0.,
+            origin: 0.,
+            pinned: false,
+        }
     }
 
     /// Returns the current value of the timeline in seconds.
@@ -41,8 +53,12 @@ impl AnimationTimeline {
         self.current_value
     }
 
-    /// Updates the value of the `AnimationTimeline` to the current clock time.
+    /// Updates the value of the `AnimationTimeline` to the current clock time, unless
+    /// it has been pinned.
     pub(crate) fn update(&mut self) {
+        if self.pinned {
+            return;
+        }
         self.current_value = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
@@ -54,4 +70,12 @@ impl AnimationTimeline {
     pub(crate) fn advance_specific(&mut self, by: f64) {
         self.current_value += by;
     }
+
+    /// Sets the current value of the timeline to `time` seconds after its creation and
+    /// stops it from following the clock, so that animations are sampled at exactly
+    /// that time. This is used for testing.
+    pub(crate) fn pin(&mut self, time: f64) {
+        self.current_value = self.origin + time;
+        self.pinned = true;
+    }
 }
@@ -8,10 +8,11 @@ use std::str::FromStr;
 use std::sync::Arc;
 
 use canvas_traits::canvas::{
-    Canvas2dMsg, CanvasId, CanvasMsg, CompositionOptions, CompositionOrBlending, Direction,
-    FillOrStrokeStyle, FillRule, LineCapStyle, LineJoinStyle, LineOptions, LinearGradientStyle,
-    Path, RadialGradientStyle, RepetitionStyle, ShadowOptions, TextAlign, TextBaseline,
-    TextMetrics as CanvasTextMetrics, TextOptions,
+    BlendingSpace, Canvas2dMsg, CanvasCreationOptions, CanvasId, CanvasMsg, CanvasSizeLimits,
+    ColorEncoding, CompositionOptions, CompositionOrBlending, Direction, FillOrStrokeStyle,
+    FillRule, LineCapStyle, LineJoinStyle, LineOptions, LinearGradientStyle, Path,
+    RadialGradientStyle, RepetitionStyle, ShadowOptions, ShapingTags, StrokeAlignment, TextAlign,
+    TextBaseline, TextMetrics as CanvasTextMetrics, TextOptions,
 };
 use constellation_traits::ScriptToConstellationMessage;
 use cssparser::color::clamp_unit_f32;
@@ -19,12 +20,14 @@ use cssparser::{Parser, ParserInput};
 use euclid::default::{Point2D, Rect, Size2D, Transform2D};
 use euclid::vec2;
 use ipc_channel::ipc::{self, IpcSender};
-use net_traits::image_cache::{ImageCache, ImageResponse};
+use net_traits::image_cache::{Image, ImageCache, ImageResponse};
 use net_traits::request::CorsSettings;
 use pixels::{PixelFormat, Snapshot, SnapshotAlphaMode, SnapshotPixelFormat};
 use profile_traits::ipc as profiled_ipc;
+use servo_config::pref;
 use servo_url::{ImmutableOrigin, ServoUrl};
 use style::color::{AbsoluteColor, ColorFlags, ColorSpace};
+use style::computed_values::font_stretch::T as FontStretch;
 use style::context::QuirksMode;
 use style::parser::ParserContext;
 use style::properties::longhands::font_variant_caps::computed_value::T as FontVariantCaps;
@@ -41,8 +44,8 @@ use crate::canvas_context::{CanvasContext, OffscreenRenderingContext, RenderingC
 use crate::conversions::Convert;
 use crate::dom::bindings::cell::DomRefCell;
 use crate::dom::bindings::codegen::Bindings::CanvasRenderingContext2DBinding::{
-    CanvasDirection, CanvasFillRule, CanvasImageSource, CanvasLineCap, CanvasLineJoin,
-    CanvasTextAlign, CanvasTextBaseline, ImageDataMethods,
+    CanvasDirection, CanvasFillRule, CanvasFontStretch, CanvasFontVariantCaps, CanvasImageSource,
+    CanvasLineCap, CanvasLineJoin, CanvasTextAlign, CanvasTextBaseline, ImageDataMethods,
 };
 use crate::dom::bindings::codegen::Bindings::DOMMatrixBinding::DOMMatrix2DInit;
 use crate::dom::bindings::codegen::UnionTypes::StringOrCanvasGradientOrCanvasPattern;
@@ -78,11 +81,18 @@ pub(crate) enum CanvasFillOrStrokeStyle {
 }
 
 impl CanvasFillOrStrokeStyle {
-    fn to_fill_or_stroke_style(&self) -> FillOrStrokeStyle {
+    /// Converts this style for drawing with the given current `transform`.
+    fn to_fill_or_stroke_style(
+        &self,
+        transform: &Transform2D<f32>,
+        image_cache: &dyn ImageCache,
+    ) -> FillOrStrokeStyle {
         match self {
             CanvasFillOrStrokeStyle::Color(rgba) => FillOrStrokeStyle::Color(*rgba),
             CanvasFillOrStrokeStyle::Gradient(gradient) => gradient.to_fill_or_stroke_style(),
-            CanvasFillOrStrokeStyle::Pattern(pattern) => pattern.to_fill_or_stroke_style(),
+            CanvasFillOrStrokeStyle::Pattern(pattern) => {
+                pattern.to_fill_or_stroke_style_for_transform(transform, image_cache)
+            },
         }
     }
 }
@@ -119,6 +129,8 @@ pub(crate) struct CanvasContextState {
     text_baseline: TextBaseline,
     #[no_trace]
     direction: Direction,
+    font_stretch: CanvasFontStretch,
+    font_variant_caps: CanvasFontVariantCaps,
     /// The number of clips pushed onto the context while in this state.
     /// When restoring old state, same number of clips will be popped to restore state.
     clips_pushed: usize,
@@ -135,8 +147,8 @@ impl CanvasContextState {
             fill_style: CanvasFillOrStrokeStyle::Color(AbsoluteColor::BLACK),
             stroke_style: CanvasFillOrStrokeStyle::Color(AbsoluteColor::BLACK),
             line_width: 1.0,
-            line_cap: LineCapStyle::Butt,
-            line_join: LineJoinStyle::Miter,
+            line_cap: Default::default(),
+            line_join: Default::default(),
             miter_limit: 10.0,
             transform: Transform2D::identity(),
             shadow_offset_x: 0.0,
@@ -147,6 +159,8 @@ impl CanvasContextState {
             text_align: Default::default(),
             text_baseline: Default::default(),
             direction: Default::default(),
+            font_stretch: CanvasFontStretch::Normal,
+            font_variant_caps: CanvasFontVariantCaps::Normal,
             line_dash: Vec::new(),
             line_dash_offset: 0.0,
             clips_pushed: 0,
@@ -155,12 +169,30 @@ impl CanvasContextState {
 
     fn text_options(&self) -> TextOptions {
         TextOptions {
-            font: self
-                .font_style
-                .as_ref()
-                .map(|font| servo_arc::Arc::new(font.clone())),
+            font: self.font_style.as_ref().map(|font| {
+                let mut font = font.clone();
+                font.font_stretch = self.font_stretch.convert();
+                font.font_variant_caps = self.font_variant_caps.convert();
+                font.compute_font_hash();
+                servo_arc::Arc::new(font)
+            }),
             align: self.text_align,
             baseline: self.text_baseline,
+            baseline_shift: None,
+            line_height: None,
+            tab_size: None,
+            decoration: None,
+            glyph_range: None,
+            shaping_tags: ShapingTags::default(),
+        }
+    }
+
+    /// Whether text is laid out with a right-to-left base direction.
+    fn is_rtl(&self) -> bool {
+        match self.direction {
+            Direction::Ltr => false,
+            Direction::Rtl => true,
+            Direction::Inherit => false, // TODO: resolve direction wrt to canvas element
         }
     }
 
@@ -176,6 +208,7 @@ impl CanvasContextState {
             offset_x: self.shadow_offset_x,
             offset_y: self.shadow_offset_y,
             blur: self.shadow_blur,
+            spread: 0.,
             color: self.shadow_color,
         }
     }
@@ -188,6 +221,8 @@ impl CanvasContextState {
             miter_limit: self.miter_limit,
             dash: self.line_dash.iter().map(|x| *x as f32).collect(),
             dash_offset: self.line_dash_offset,
+            dash_gap_color: None,
+            alignment: StrokeAlignment::Center,
         }
     }
 }
@@ -204,6 +239,9 @@ pub(crate) struct CanvasState {
     image_key: ImageKey,
     #[no_trace]
     size: Cell<Size2D<u64>>,
+    /// The largest canvas that the canvas paint thread can allocate.
+    #[no_trace]
+    size_limits: CanvasSizeLimits,
     state: DomRefCell<CanvasContextState>,
     origin_clean: Cell<bool>,
     #[ignore_malloc_size_of = "Arc"]
@@ -222,6 +260,18 @@ pub(crate) struct CanvasState {
     /// <https://html.spec.whatwg.org/multipage/#current-default-path>
     #[no_trace]
     current_default_path: DomRefCell<Path>,
+    /// The fill color last sent to the canvas paint thread with
+    /// `Canvas2dMsg::SetFillStyle`, if it was a color. Gradients and patterns can change
+    /// after they are set, so they are sent again before every fill.
+    #[no_trace]
+    sent_fill_color: Cell<Option<AbsoluteColor>>,
+    /// Like `sent_fill_color`, for the stroke style.
+    #[no_trace]
+    sent_stroke_color: Cell<Option<AbsoluteColor>>,
+    /// The global alpha and compositing operation last sent to the canvas paint thread
+    /// with `Canvas2dMsg::SetGlobalAlpha` and `Canvas2dMsg::SetGlobalCompositeOperation`.
+    #[no_trace]
+    sent_composition_options: Cell<Option<CompositionOptions>>,
 }
 
 impl CanvasState {
@@ -231,10 +281,21 @@ impl CanvasState {
             profiled_ipc::channel(global.time_profiler_chan().clone()).unwrap();
         let script_to_constellation_chan = global.script_to_constellation_chan();
         debug!("Asking constellation to create new canvas thread.");
-        let size = adjust_canvas_size(size);
+        let size_limits = global.canvas_size_limits();
+        let size = size_limits.clamp(size);
+        let blending_space = if pref!(dom_canvas_linear_blending_enabled) {
+            BlendingSpace::Linear
+        } else {
+            BlendingSpace::Srgb
+        };
+        let options = CanvasCreationOptions {
+            render_scale: pref!(dom_canvas_render_scale_percent) as f32 / 100.,
+            blending_space,
+            ..Default::default()
+        };
         script_to_constellation_chan
             .send(ScriptToConstellationMessage::CreateCanvasPaintThread(
-                size, sender,
+                size, options, sender,
             ))
             .unwrap();
         let (ipc_renderer, canvas_id, image_key) = receiver.recv().ok()??;
@@ -247,10 +308,11 @@ impl CanvasState {
         } else {
             global.origin().immutable().clone()
         };
-        Some(CanvasState {
+        let canvas_state = CanvasState {
             ipc_renderer,
             canvas_id,
             size: Cell::new(size),
+            size_limits,
             state: DomRefCell::new(CanvasContextState::new()),
             origin_clean: Cell::new(true),
             image_cache: global.image_cache(),
@@ -260,7 +322,14 @@ impl CanvasState {
             image_key,
             origin,
             current_default_path: DomRefCell::new(Path::new()),
-        })
+            sent_fill_color: Cell::new(None),
+            sent_stroke_color: Cell::new(None),
+            sent_composition_options: Cell::new(None),
+        };
+        if pref!(dom_canvas_frame_pacing_enabled) {
+            canvas_state.send_canvas_2d_msg(Canvas2dMsg::SetFramePacing(true));
+        }
+        Some(canvas_state)
     }
 
     pub(crate) fn image_key(&self) -> ImageKey {
@@ -289,6 +358,15 @@ impl CanvasState {
             .unwrap()
     }
 
+    /// Pause or resume the processing of the drawing of this canvas, as while its document
+    /// isn't fully active. Replies to queries still reflect all of the drawing sent before
+    /// them.
+    pub(crate) fn set_paused(&self, paused: bool) {
+        self.ipc_renderer
+            .send(CanvasMsg::SetPaused(self.canvas_id, paused))
+            .unwrap()
+    }
+
     /// Updates WR image and blocks on completion
     pub(crate) fn update_rendering(&self) {
         if !self.is_paintable() {
@@ -298,7 +376,7 @@ impl CanvasState {
         let (sender, receiver) = ipc::channel().unwrap();
         self.ipc_renderer
             .send(CanvasMsg::Canvas2d(
-                Canvas2dMsg::UpdateImage(sender),
+                Canvas2dMsg::UpdateImage(sender, false),
                 self.canvas_id,
             ))
             .unwrap();
@@ -309,7 +387,7 @@ impl CanvasState {
     pub(crate) fn set_bitmap_dimensions(&self, size: Size2D<u64>) {
         self.reset_to_initial_state();
 
-        self.size.replace(adjust_canvas_size(size));
+        self.size.replace(self.size_limits.clamp(size));
 
         self.ipc_renderer
             .send(CanvasMsg::Recreate(
@@ -317,6 +395,7 @@ impl CanvasState {
                 self.get_canvas_id(),
             ))
             .unwrap();
+        self.reclaim_surface();
     }
 
     pub(crate) fn reset(&self) {
@@ -329,12 +408,24 @@ impl CanvasState {
         self.ipc_renderer
             .send(CanvasMsg::Recreate(None, self.get_canvas_id()))
             .unwrap();
+        self.reclaim_surface();
+    }
+
+    /// The bitmap was just reset to transparent black, so its memory can be released until
+    /// the page draws to it again, which many pages that resize their canvases don't do
+    /// right away.
+    fn reclaim_surface(&self) {
+        self.send_canvas_2d_msg(Canvas2dMsg::ReclaimSurface);
     }
 
     pub(crate) fn reset_to_initial_state(&self) {
         *self.current_default_path.borrow_mut() = Path::new();
         self.saved_states.borrow_mut().clear();
         *self.state.borrow_mut() = CanvasContextState::new();
+        // The canvas paint thread resets its styles along with the bitmap.
+        self.sent_fill_color.set(None);
+        self.sent_stroke_color.set(None);
+        self.sent_composition_options.set(None);
     }
 
     pub(crate) fn reset_bitmap(&self) {
@@ -583,6 +674,7 @@ impl CanvasState {
         }
 
         let smoothing_enabled = self.state.borrow().image_smoothing_enabled;
+        self.send_composition_options();
 
         self.send_canvas_2d_msg(Canvas2dMsg::DrawImage(
             snapshot.as_ipc(),
@@ -590,7 +682,6 @@ impl CanvasState {
             source_rect,
             smoothing_enabled,
             self.state.borrow().shadow_options(),
-            self.state.borrow().composition_options(),
             self.state.borrow().transform,
         ));
 
@@ -632,6 +723,7 @@ impl CanvasState {
         }
 
         let smoothing_enabled = self.state.borrow().image_smoothing_enabled;
+        self.send_composition_options();
 
         self.send_canvas_2d_msg(Canvas2dMsg::DrawImage(
             snapshot.as_ipc(),
@@ -639,7 +731,6 @@ impl CanvasState {
             source_rect,
             smoothing_enabled,
             self.state.borrow().shadow_options(),
-            self.state.borrow().composition_options(),
             self.state.borrow().transform,
         ));
 
@@ -679,6 +770,7 @@ impl CanvasState {
         }
 
         let smoothing_enabled = self.state.borrow().image_smoothing_enabled;
+        self.send_composition_options();
 
         if let Some(context) = canvas.context() {
             match *context {
@@ -689,7 +781,6 @@ impl CanvasState {
                         source_rect,
                         smoothing_enabled,
                         self.state.borrow().shadow_options(),
-                        self.state.borrow().composition_options(),
                         self.state.borrow().transform,
                     ));
                 },
@@ -704,7 +795,6 @@ impl CanvasState {
                         source_rect,
                         smoothing_enabled,
                         self.state.borrow().shadow_options(),
-                        self.state.borrow().composition_options(),
                         self.state.borrow().transform,
                     ));
                 },
@@ -716,7 +806,6 @@ impl CanvasState {
                 dest_rect,
                 source_rect,
                 self.state.borrow().shadow_options(),
-                self.state.borrow().composition_options(),
                 self.state.borrow().transform,
             ));
         }
@@ -758,6 +847,7 @@ impl CanvasState {
         }
 
         let smoothing_enabled = self.state.borrow().image_smoothing_enabled;
+        self.send_composition_options();
 
         if let Some(context) = canvas.context() {
             match *context {
@@ -768,7 +858,6 @@ impl CanvasState {
                         source_rect,
                         smoothing_enabled,
                         self.state.borrow().shadow_options(),
-                        self.state.borrow().composition_options(),
                         self.state.borrow().transform,
                     ));
                 },
@@ -783,7 +872,6 @@ impl CanvasState {
                         source_rect,
                         smoothing_enabled,
                         self.state.borrow().shadow_options(),
-                        self.state.borrow().composition_options(),
                         self.state.borrow().transform,
                     ));
                 },
@@ -799,7 +887,6 @@ impl CanvasState {
                                 source_rect,
                                 smoothing_enabled,
                                 self.state.borrow().shadow_options(),
-                                self.state.borrow().composition_options(),
                                 self.state.borrow().transform,
                             )),
                         OffscreenRenderingContext::BitmapRenderer(ref context) => {
@@ -813,7 +900,6 @@ impl CanvasState {
                                 source_rect,
                                 smoothing_enabled,
                                 self.state.borrow().shadow_options(),
-                                self.state.borrow().composition_options(),
                                 self.state.borrow().transform,
                             ));
                         },
@@ -828,7 +914,6 @@ impl CanvasState {
                 dest_rect,
                 source_rect,
                 self.state.borrow().shadow_options(),
-                self.state.borrow().composition_options(),
                 self.state.borrow().transform,
             ));
         }
@@ -872,13 +957,13 @@ impl CanvasState {
         }
 
         let smoothing_enabled = self.state.borrow().image_smoothing_enabled;
+        self.send_composition_options();
         self.send_canvas_2d_msg(Canvas2dMsg::DrawImage(
             snapshot.as_ipc(),
             dest_rect,
             source_rect,
             smoothing_enabled,
             self.state.borrow().shadow_options(),
-            self.state.borrow().composition_options(),
             self.state.borrow().transform,
         ));
         self.mark_as_dirty(canvas);
@@ -920,6 +1005,7 @@ impl CanvasState {
         }
 
         let smoothing_enabled = self.state.borrow().image_smoothing_enabled;
+        self.send_composition_options();
 
         self.send_canvas_2d_msg(Canvas2dMsg::DrawImage(
             snapshot.as_ipc(),
@@ -927,7 +1013,6 @@ impl CanvasState {
             source_rect,
             smoothing_enabled,
             self.state.borrow().shadow_options(),
-            self.state.borrow().composition_options(),
             self.state.borrow().transform,
         ));
 
@@ -1018,15 +1103,62 @@ impl CanvasState {
         }
     }
 
+    fn current_fill_style(&self) -> FillOrStrokeStyle {
+        let state = self.state.borrow();
+        state
+            .fill_style
+            .to_fill_or_stroke_style(&state.transform, &*self.image_cache)
+    }
+
+    fn current_stroke_style(&self) -> FillOrStrokeStyle {
+        let state = self.state.borrow();
+        state
+            .stroke_style
+            .to_fill_or_stroke_style(&state.transform, &*self.image_cache)
+    }
+
+    /// Sends the current fill style to the canvas paint thread, unless it is the color that
+    /// was sent last, before a fill.
+    fn send_fill_style(&self) {
+        let style = self.current_fill_style();
+        if !update_sent_color(&self.sent_fill_color, &style) {
+            self.send_canvas_2d_msg(Canvas2dMsg::SetFillStyle(style));
+        }
+    }
+
+    /// Like [`Self::send_fill_style`], before a stroke.
+    fn send_stroke_style(&self) {
+        let style = self.current_stroke_style();
+        if !update_sent_color(&self.sent_stroke_color, &style) {
+            self.send_canvas_2d_msg(Canvas2dMsg::SetStrokeStyle(style));
+        }
+    }
+
+    /// Sends the global alpha and compositing operation to the canvas paint thread, each
+    /// unless it is the one that was sent last, before a drawing operation.
+    fn send_composition_options(&self) {
+        let options = self.state.borrow().composition_options();
+        let sent_options = self.sent_composition_options.replace(Some(options));
+        if sent_options.is_none_or(|sent_options| sent_options.alpha != options.alpha) {
+            self.send_canvas_2d_msg(Canvas2dMsg::SetGlobalAlpha(options.alpha));
+        }
+        if sent_options.is_none_or(|sent_options| {
+            sent_options.composition_operation != options.composition_operation
+        }) {
+            self.send_canvas_2d_msg(Canvas2dMsg::SetGlobalCompositeOperation(
+                options.composition_operation,
+            ));
+        }
+    }
+
     // https://html.spec.whatwg.org/multipage/#dom-context-2d-fillrect
     pub(crate) fn fill_rect(&self, x: f64, y: f64, width: f64, height: f64) {
         if let Some(rect) = self.create_drawable_rect(x, y, width, height) {
-            let style = self.state.borrow().fill_style.to_fill_or_stroke_style();
+            self.send_fill_style();
+            self.send_composition_options();
             self.send_canvas_2d_msg(Canvas2dMsg::FillRect(
                 rect,
-                style,
                 self.state.borrow().shadow_options(),
-                self.state.borrow().composition_options(),
                 self.state.borrow().transform,
             ));
         }
@@ -1042,13 +1174,12 @@ impl CanvasState {
     // https://html.spec.whatwg.org/multipage/#dom-context-2d-strokerect
     pub(crate) fn stroke_rect(&self, x: f64, y: f64, width: f64, height: f64) {
         if let Some(rect) = self.create_drawable_rect(x, y, width, height) {
-            let style = self.state.borrow().stroke_style.to_fill_or_stroke_style();
+            self.send_stroke_style();
+            self.send_composition_options();
             self.send_canvas_2d_msg(Canvas2dMsg::StrokeRect(
                 rect,
-                style,
                 self.state.borrow().line_options(),
                 self.state.borrow().shadow_options(),
-                self.state.borrow().composition_options(),
                 self.state.borrow().transform,
             ));
         }
@@ -1205,7 +1336,14 @@ impl CanvasState {
     ) -> DomRoot<CanvasGradient> {
         CanvasGradient::new(
             global,
-            CanvasGradientStyle::Linear(LinearGradientStyle::new(*x0, *y0, *x1, *y1, Vec::new())),
+            CanvasGradientStyle::Linear(LinearGradientStyle::new(
+                *x0,
+                *y0,
+                *x1,
+                *y1,
+                Vec::new(),
+                false,
+            )),
             can_gc,
         )
     }
@@ -1250,6 +1388,7 @@ impl CanvasState {
         mut repetition: DOMString,
         can_gc: CanGc,
     ) -> Fallible<Option<DomRoot<CanvasPattern>>> {
+        let mut vector_image = None;
         let snapshot = match image {
             CanvasImageSource::HTMLImageElement(ref image) => {
                 // <https://html.spec.whatwg.org/multipage/#check-the-usability-of-the-image-argument>
@@ -1257,7 +1396,23 @@ impl CanvasState {
                     return Ok(None);
                 }
 
-                image.get_raster_image_data().ok_or(Error::InvalidState)?
+                match image.image_data() {
+                    Some(Image::Vector(image)) => {
+                        // The tile is rasterized at its natural size right away, so that
+                        // the pattern isn't transparent while a rasterization at device
+                        // resolution is pending.
+                        let size = Size2D::new(image.metadata.width, image.metadata.height);
+                        let snapshot = CanvasPattern::rasterize_vector_image_sync(
+                            &image,
+                            size.to_i32().cast_unit(),
+                            &*self.image_cache,
+                        )
+                        .ok_or(Error::InvalidState)?;
+                        vector_image = Some(image);
+                        snapshot
+                    },
+                    _ => image.get_raster_image_data().ok_or(Error::InvalidState)?,
+                }
             },
             CanvasImageSource::HTMLVideoElement(ref video) => {
                 // <https://html.spec.whatwg.org/multipage/#check-the-usability-of-the-image-argument>
@@ -1307,6 +1462,7 @@ impl CanvasState {
                 global,
                 snapshot,
                 size.cast(),
+                vector_image,
                 rep,
                 self.is_origin_clean(image),
                 can_gc,
@@ -1391,26 +1547,21 @@ impl CanvasState {
             return;
         }
         if self.state.borrow().font_style.is_none() {
-            self.set_font(canvas, CanvasContextState::DEFAULT_FONT_STYLE.into())
+            self.set_default_font(canvas);
         }
 
-        let is_rtl = match self.state.borrow().direction {
-            Direction::Ltr => false,
-            Direction::Rtl => true,
-            Direction::Inherit => false, // TODO: resolve direction wrt to canvas element
-        };
+        let is_rtl = self.state.borrow().is_rtl();
 
-        let style = self.state.borrow().fill_style.to_fill_or_stroke_style();
+        self.send_fill_style();
+        self.send_composition_options();
         self.send_canvas_2d_msg(Canvas2dMsg::FillText(
             text.into(),
             x,
             y,
             max_width,
-            style,
             is_rtl,
             self.state.borrow().text_options(),
             self.state.borrow().shadow_options(),
-            self.state.borrow().composition_options(),
             self.state.borrow().transform,
         ));
     }
@@ -1424,13 +1575,14 @@ impl CanvasState {
         can_gc: CanGc,
     ) -> DomRoot<TextMetrics> {
         if self.state.borrow().font_style.is_none() {
-            self.set_font(canvas, CanvasContextState::DEFAULT_FONT_STYLE.into());
+            self.set_default_font(canvas);
         }
 
         let (sender, receiver) = ipc::channel::<CanvasTextMetrics>().unwrap();
         self.send_canvas_2d_msg(Canvas2dMsg::MeasureText(
             text.into(),
             sender,
+            self.state.borrow().is_rtl(),
             self.state.borrow().text_options(),
         ));
         let metrics = receiver.recv().unwrap();
@@ -1453,19 +1605,36 @@ impl CanvasState {
         )
     }
 
+    fn resolve_font(&self, canvas: Option<&HTMLCanvasElement>, value: DOMString) -> Option<Font> {
+        // An offscreen canvas doesn't have a placeholder canvas.
+        let canvas = canvas?;
+        let node = canvas.upcast::<Node>();
+        let window = canvas.owner_window();
+        let resolved_font_style = window.resolved_font_style_query(node, value.to_string())?;
+        Some((*resolved_font_style).clone())
+    }
+
+    /// Resolves the default font for text operations issued before `font` was ever set,
+    /// keeping any `fontStretch` and `fontVariantCaps` that were set in the meantime.
+    fn set_default_font(&self, canvas: Option<&HTMLCanvasElement>) {
+        if let Some(font) = self.resolve_font(canvas, CanvasContextState::DEFAULT_FONT_STYLE.into())
+        {
+            self.state.borrow_mut().font_style = Some(font);
+        }
+    }
+
     // https://html.spec.whatwg.org/multipage/#dom-context-2d-font
     pub(crate) fn set_font(&self, canvas: Option<&HTMLCanvasElement>, value: DOMString) {
-        let canvas = match canvas {
-            Some(element) => element,
-            None => return, // offscreen canvas doesn't have a placeholder canvas
+        let Some(font) = self.resolve_font(canvas, value) else {
+            return; // syntax error
         };
-        let node = canvas.upcast::<Node>();
-        let window = canvas.owner_window();
-        let resolved_font_style = match window.resolved_font_style_query(node, value.to_string()) {
-            Some(value) => value,
-            None => return, // syntax error
+        let mut state = self.state.borrow_mut();
+        state.font_stretch = font_stretch_keyword(font.font_stretch);
+        state.font_variant_caps = match font.font_variant_caps {
+            FontVariantCaps::SmallCaps => CanvasFontVariantCaps::Small_caps,
+            FontVariantCaps::Normal => CanvasFontVariantCaps::Normal,
         };
-        self.state.borrow_mut().font_style = Some((*resolved_font_style).clone());
+        state.font_style = Some(font);
     }
 
     // https://html.spec.whatwg.org/multipage/#dom-context-2d-font
@@ -1526,6 +1695,26 @@ impl CanvasState {
         self.state.borrow_mut().text_baseline = text_baseline;
     }
 
+    // https://html.spec.whatwg.org/multipage/#dom-context-2d-fontstretch
+    pub(crate) fn font_stretch(&self) -> CanvasFontStretch {
+        self.state.borrow().font_stretch
+    }
+
+    // https://html.spec.whatwg.org/multipage/#dom-context-2d-fontstretch
+    pub(crate) fn set_font_stretch(&self, value: CanvasFontStretch) {
+        self.state.borrow_mut().font_stretch = value;
+    }
+
+    // https://html.spec.whatwg.org/multipage/#dom-context-2d-fontvariantcaps
+    pub(crate) fn font_variant_caps(&self) -> CanvasFontVariantCaps {
+        self.state.borrow().font_variant_caps
+    }
+
+    // https://html.spec.whatwg.org/multipage/#dom-context-2d-fontvariantcaps
+    pub(crate) fn set_font_variant_caps(&self, value: CanvasFontVariantCaps) {
+        self.state.borrow_mut().font_variant_caps = value;
+    }
+
     // https://html.spec.whatwg.org/multipage/#dom-context-2d-direction
     pub(crate) fn direction(&self) -> CanvasDirection {
         match self.state.borrow().direction {
@@ -1716,9 +1905,24 @@ impl CanvasState {
             },
         };
 
-        let data = if self.is_paintable() {
+        let data = if !self.is_paintable() {
+            None
+        } else if size.area() == 1 {
+            // Color pickers read single pixels, which don't need a snapshot.
+            let (sender, receiver) = ipc::channel().unwrap();
+            self.send_canvas_2d_msg(Canvas2dMsg::GetPixel(
+                read_rect.origin.x as i32,
+                read_rect.origin.y as i32,
+                sender,
+            ));
+            Some(receiver.recv().unwrap().to_vec())
+        } else {
             let (sender, receiver) = ipc::channel().unwrap();
-            self.send_canvas_2d_msg(Canvas2dMsg::GetImageData(Some(read_rect), sender));
+            self.send_canvas_2d_msg(Canvas2dMsg::GetImageData(
+                Some(read_rect),
+                ColorEncoding::Srgb,
+                sender,
+            ));
             let snapshot = receiver.recv().unwrap().to_owned();
             Some(
                 snapshot
@@ -1730,8 +1934,6 @@ impl CanvasState {
                     )
                     .0,
             )
-        } else {
-            None
         };
 
         ImageData::new(global, size.width, size.height, data, can_gc)
@@ -1810,7 +2012,11 @@ impl CanvasState {
 
         // Step 7.
         let snapshot = imagedata.get_snapshot_rect(Rect::new(src_rect.origin, dst_rect.size));
-        self.send_canvas_2d_msg(Canvas2dMsg::PutImageData(dst_rect, snapshot.as_ipc()));
+        self.send_canvas_2d_msg(Canvas2dMsg::PutImageData(
+            dst_rect,
+            ColorEncoding::Srgb,
+            snapshot.as_ipc(),
+        ));
     }
 
     // https://html.spec.whatwg.org/multipage/#dom-context-2d-drawimage
@@ -1910,13 +2116,12 @@ impl CanvasState {
 
     // https://html.spec.whatwg.org/multipage/#dom-context-2d-fill
     pub(crate) fn fill_(&self, path: Path, fill_rule: CanvasFillRule) {
-        let style = self.state.borrow().fill_style.to_fill_or_stroke_style();
+        self.send_fill_style();
+        self.send_composition_options();
         self.send_canvas_2d_msg(Canvas2dMsg::FillPath(
-            style,
             path,
             fill_rule.convert(),
             self.state.borrow().shadow_options(),
-            self.state.borrow().composition_options(),
             self.state.borrow().transform,
         ));
     }
@@ -1928,13 +2133,12 @@ impl CanvasState {
     }
 
     pub(crate) fn stroke_(&self, path: Path) {
-        let style = self.state.borrow().stroke_style.to_fill_or_stroke_style();
+        self.send_stroke_style();
+        self.send_composition_options();
         self.send_canvas_2d_msg(Canvas2dMsg::StrokePath(
             path,
-            style,
             self.state.borrow().line_options(),
             self.state.borrow().shadow_options(),
-            self.state.borrow().composition_options(),
             self.state.borrow().transform,
         ));
     }
@@ -2233,6 +2437,17 @@ pub(crate) fn parse_color(
     }
 }
 
+/// Records `style` as the last one sent in `sent_color`, and returns whether it is the same
+/// color as the one that was sent before it.
+fn update_sent_color(sent_color: &Cell<Option<AbsoluteColor>>, style: &FillOrStrokeStyle) -> bool {
+    let color = match style {
+        FillOrStrokeStyle::Color(color) => Some(*color),
+        _ => None,
+    };
+    let previous_color = sent_color.replace(color);
+    color.is_some() && previous_color == color
+}
+
 // Used by drawImage to determine if a source or destination rectangle is valid
 // Origin coordinates and size cannot be negative. Size has to be greater than zero
 pub(crate) fn is_rect_valid(rect: Rect<f64>) -> bool {
@@ -2306,23 +2521,53 @@ where
     )
 }
 
-fn adjust_canvas_size(size: Size2D<u64>) -> Size2D<u64> {
-    // Firefox limits width/height to 32767 pixels and Chromium to 65535 pixels,
-    // but slows down dramatically before it reaches that limit.
-    // We limit by area instead, giving us larger maximum dimensions,
-    // in exchange for a smaller maximum canvas size.
-    const MAX_CANVAS_AREA: u64 = 32768 * 8192;
-    // Max width/height to 65535 in CSS pixels.
-    const MAX_CANVAS_SIZE: u64 = 65535;
-
-    if !size.is_empty() &&
-        size.greater_than(Size2D::new(MAX_CANVAS_SIZE, MAX_CANVAS_SIZE))
-            .none() &&
-        size.area() < MAX_CANVAS_AREA
-    {
-        size
-    } else {
-        Size2D::zero()
+/// Returns the `fontStretch` keyword for a font's stretch, falling back to `normal` for
+/// percentages that no keyword names.
+fn font_stretch_keyword(stretch: FontStretch) -> CanvasFontStretch {
+    [
+        CanvasFontStretch::Ultra_condensed,
+        CanvasFontStretch::Extra_condensed,
+        CanvasFontStretch::Condensed,
+        CanvasFontStretch::Semi_condensed,
+        CanvasFontStretch::Semi_expanded,
+        CanvasFontStretch::Expanded,
+        CanvasFontStretch::Extra_expanded,
+        CanvasFontStretch::Ultra_expanded,
+    ]
+    .into_iter()
+    .find(|keyword| keyword.convert() == stretch)
+    .unwrap_or(CanvasFontStretch::Normal)
+}
+
+impl Convert<FontStretch> for CanvasFontStretch {
+    fn convert(self) -> FontStretch {
+        match self {
+            CanvasFontStretch::Ultra_condensed => FontStretch::ULTRA_CONDENSED,
+            CanvasFontStretch::Extra_condensed => FontStretch::EXTRA_CONDENSED,
+            CanvasFontStretch::Condensed => FontStretch::CONDENSED,
+            CanvasFontStretch::Semi_condensed => FontStretch::SEMI_CONDENSED,
+            CanvasFontStretch::Normal => FontStretch::NORMAL,
+            CanvasFontStretch::Semi_expanded => FontStretch::SEMI_EXPANDED,
+            CanvasFontStretch::Expanded => FontStretch::EXPANDED,
+            CanvasFontStretch::Extra_expanded => FontStretch::EXTRA_EXPANDED,
+            CanvasFontStretch::Ultra_expanded => FontStretch::ULTRA_EXPANDED,
+        }
+    }
+}
+
+impl Convert<FontVariantCaps> for CanvasFontVariantCaps {
+    fn convert(self) -> FontVariantCaps {
+        // Small capitals are the only variant the font system can synthesize, so every
+        // flavour of small capitals renders with them; the remaining values render normally.
+        match self {
+            CanvasFontVariantCaps::Small_caps |
+            CanvasFontVariantCaps::All_small_caps |
+            CanvasFontVariantCaps::Petite_caps |
+            CanvasFontVariantCaps::All_petite_caps => FontVariantCaps::SmallCaps,
+            CanvasFontVariantCaps::Normal |
+            CanvasFontVariantCaps::Unicase |
+            CanvasFontVariantCaps::Titling_caps => FontVariantCaps::Normal,
+        }
     }
 }
 
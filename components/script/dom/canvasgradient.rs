@@ -90,6 +90,7 @@ impl ToFillOrStrokeStyle for &CanvasGradient {
                     gradient.x1,
                     gradient.y1,
                     gradient_stops,
+                    gradient.repeating,
                 ))
             },
             CanvasGradientStyle::Radial(ref gradient) => {
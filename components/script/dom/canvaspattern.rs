@@ -5,7 +5,11 @@
 use canvas_traits::canvas::{FillOrStrokeStyle, RepetitionStyle, SurfaceStyle};
 use dom_struct::dom_struct;
 use euclid::default::{Size2D, Transform2D};
-use pixels::{IpcSnapshot, Snapshot};
+use net_traits::image_cache::{ImageCache, VectorImage};
+use pixels::{
+    IpcSnapshot, PixelFormat, RasterImage, Snapshot, SnapshotAlphaMode, SnapshotPixelFormat,
+};
+use webrender_api::units::DeviceIntSize;
 
 use crate::dom::bindings::cell::DomRefCell;
 use crate::dom::bindings::codegen::Bindings::CanvasRenderingContext2DBinding::CanvasPatternMethods;
@@ -18,6 +22,10 @@ use crate::dom::dommatrixreadonly::dommatrix2dinit_to_matrix;
 use crate::dom::globalscope::GlobalScope;
 use crate::script_runtime::CanGc;
 
+/// The largest width or height, in device pixels, at which the tile of a pattern created
+/// from a vector image is rasterized.
+const MAX_VECTOR_TILE_SIZE: i32 = 4096;
+
 /// <https://html.spec.whatwg.org/multipage/#canvaspattern>
 #[dom_struct]
 pub(crate) struct CanvasPattern {
@@ -26,6 +34,10 @@ pub(crate) struct CanvasPattern {
     surface_data: IpcSnapshot,
     #[no_trace]
     surface_size: Size2D<u32>,
+    /// The vector image this pattern was created from, if any. Its tile is rasterized
+    /// again at device resolution when drawn under a scale.
+    #[no_trace]
+    vector_image: Option<VectorImage>,
     repeat_x: bool,
     repeat_y: bool,
     #[no_trace]
@@ -37,6 +49,7 @@ impl CanvasPattern {
     fn new_inherited(
         surface_data: Snapshot,
         surface_size: Size2D<u32>,
+        vector_image: Option<VectorImage>,
         repeat: RepetitionStyle,
         origin_clean: bool,
     ) -> CanvasPattern {
@@ -51,6 +64,7 @@ impl CanvasPattern {
             reflector_: Reflector::new(),
             surface_data: surface_data.as_ipc(),
             surface_size,
+            vector_image,
             repeat_x: x,
             repeat_y: y,
             transform: DomRefCell::new(Transform2D::identity()),
@@ -61,6 +75,7 @@ impl CanvasPattern {
         global: &GlobalScope,
         surface_data: Snapshot,
         surface_size: Size2D<u32>,
+        vector_image: Option<VectorImage>,
         repeat: RepetitionStyle,
         origin_clean: bool,
         can_gc: CanGc,
@@ -69,6 +84,7 @@ impl CanvasPattern {
             Box::new(CanvasPattern::new_inherited(
                 surface_data,
                 surface_size,
+                vector_image,
                 repeat,
                 origin_clean,
             )),
@@ -79,6 +95,87 @@ impl CanvasPattern {
     pub(crate) fn origin_is_clean(&self) -> bool {
         self.origin_clean
     }
+
+    /// Returns `image` rasterized at `size`, if the image cache has it. Otherwise the
+    /// rasterization is started and `None` is returned.
+    pub(crate) fn rasterize_vector_image(
+        image: &VectorImage,
+        size: DeviceIntSize,
+        image_cache: &dyn ImageCache,
+    ) -> Option<Snapshot> {
+        image_cache
+            .rasterize_vector_image(image.id, size)
+            .map(Self::snapshot_from_raster_image)
+    }
+
+    /// Returns `image` rasterized at `size`, rasterizing it on this thread if the image
+    /// cache doesn't have it yet.
+    pub(crate) fn rasterize_vector_image_sync(
+        image: &VectorImage,
+        size: DeviceIntSize,
+        image_cache: &dyn ImageCache,
+    ) -> Option<Snapshot> {
+        image_cache
+            .rasterize_vector_image_sync(image.id, size)
+            .map(Self::snapshot_from_raster_image)
+    }
+
+    fn snapshot_from_raster_image(raster_image: RasterImage) -> Snapshot {
+        let format = match raster_image.format {
+            PixelFormat::BGRA8 => SnapshotPixelFormat::BGRA,
+            PixelFormat::RGBA8 => SnapshotPixelFormat::RGBA,
+            pixel_format => unimplemented!("unsupported pixel format ({:?})", pixel_format),
+        };
+        Snapshot::from_vec(
+            Size2D::new(raster_image.metadata.width, raster_image.metadata.height),
+            format,
+            SnapshotAlphaMode::Transparent {
+                premultiplied: false,
+            },
+            raster_image.first_frame().bytes.to_vec(),
+        )
+    }
+
+    /// Like [`ToFillOrStrokeStyle::to_fill_or_stroke_style`], but for drawing with the
+    /// given current `transform`. The tile of a pattern created from a vector image is
+    /// rasterized at its size in device pixels, so it stays crisp when tiled under scale.
+    /// The tile keeps its size in pattern space; only its pixel density changes.
+    pub(crate) fn to_fill_or_stroke_style_for_transform(
+        &self,
+        transform: &Transform2D<f32>,
+        image_cache: &dyn ImageCache,
+    ) -> FillOrStrokeStyle {
+        let pattern_transform = *self.transform.borrow();
+        let Some(vector_image) = self.vector_image.as_ref() else {
+            return self.to_fill_or_stroke_style();
+        };
+
+        let device_transform = pattern_transform.then(transform);
+        let scale_x = device_transform.m11.hypot(device_transform.m12);
+        let scale_y = device_transform.m21.hypot(device_transform.m22);
+        let device_size = DeviceIntSize::new(
+            (self.surface_size.width as f32 * scale_x).ceil() as i32,
+            (self.surface_size.height as f32 * scale_y).ceil() as i32,
+        )
+        .min(DeviceIntSize::splat(MAX_VECTOR_TILE_SIZE));
+        if device_size.is_empty() {
+            return self.to_fill_or_stroke_style();
+        }
+
+        // Until the device resolution rasterization is ready, keep using the tile
+        // captured when the pattern was created.
+        let surface_data = Self::rasterize_vector_image(vector_image, device_size, image_cache)
+            .map(Snapshot::as_ipc)
+            .unwrap_or_else(|| self.surface_data.clone());
+
+        FillOrStrokeStyle::Surface(SurfaceStyle::new(
+            surface_data,
+            self.surface_size,
+            self.repeat_x,
+            self.repeat_y,
+            pattern_transform,
+        ))
+    }
 }
 
 impl CanvasPatternMethods<crate::DomTypeHolder> for CanvasPattern {
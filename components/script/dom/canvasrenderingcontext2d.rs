@@ -2,7 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
-use canvas_traits::canvas::{Canvas2dMsg, CanvasId};
+use canvas_traits::canvas::{Canvas2dMsg, CanvasId, ColorEncoding};
 use dom_struct::dom_struct;
 use euclid::default::Size2D;
 use ipc_channel::ipc;
@@ -14,8 +14,9 @@ use webrender_api::ImageKey;
 use crate::canvas_context::{CanvasContext, CanvasHelpers, LayoutCanvasRenderingContextHelpers};
 use crate::canvas_state::CanvasState;
 use crate::dom::bindings::codegen::Bindings::CanvasRenderingContext2DBinding::{
-    CanvasDirection, CanvasFillRule, CanvasImageSource, CanvasLineCap, CanvasLineJoin,
-    CanvasRenderingContext2DMethods, CanvasTextAlign, CanvasTextBaseline,
+    CanvasDirection, CanvasFillRule, CanvasFontStretch, CanvasFontVariantCaps, CanvasImageSource,
+    CanvasLineCap, CanvasLineJoin, CanvasRenderingContext2DMethods, CanvasTextAlign,
+    CanvasTextBaseline,
 };
 use crate::dom::bindings::codegen::Bindings::DOMMatrixBinding::DOMMatrix2DInit;
 use crate::dom::bindings::codegen::UnionTypes::{
@@ -97,6 +98,10 @@ impl CanvasRenderingContext2D {
     pub(crate) fn send_canvas_2d_msg(&self, msg: Canvas2dMsg) {
         self.canvas_state.send_canvas_2d_msg(msg)
     }
+
+    pub(crate) fn set_paused(&self, paused: bool) {
+        self.canvas_state.set_paused(paused)
+    }
 }
 
 impl LayoutCanvasRenderingContextHelpers for LayoutDom<'_, CanvasRenderingContext2D> {
@@ -141,7 +146,7 @@ impl CanvasContext for CanvasRenderingContext2D {
 
         let (sender, receiver) = ipc::channel().unwrap();
         self.canvas_state
-            .send_canvas_2d_msg(Canvas2dMsg::GetImageData(None, sender));
+            .send_canvas_2d_msg(Canvas2dMsg::GetImageData(None, ColorEncoding::Srgb, sender));
         Some(receiver.recv().unwrap().to_owned())
     }
 
@@ -374,6 +379,26 @@ impl CanvasRenderingContext2DMethods<crate::DomTypeHolder> for CanvasRenderingCo
         self.canvas_state.set_text_baseline(value)
     }
 
+    // https://html.spec.whatwg.org/multipage/#dom-context-2d-fontstretch
+    fn FontStretch(&self) -> CanvasFontStretch {
+        self.canvas_state.font_stretch()
+    }
+
+    // https://html.spec.whatwg.org/multipage/#dom-context-2d-fontstretch
+    fn SetFontStretch(&self, value: CanvasFontStretch) {
+        self.canvas_state.set_font_stretch(value)
+    }
+
+    // https://html.spec.whatwg.org/multipage/#dom-context-2d-fontvariantcaps
+    fn FontVariantCaps(&self) -> CanvasFontVariantCaps {
+        self.canvas_state.font_variant_caps()
+    }
+
+    // https://html.spec.whatwg.org/multipage/#dom-context-2d-fontvariantcaps
+    fn SetFontVariantCaps(&self, value: CanvasFontVariantCaps) {
+        self.canvas_state.set_font_variant_caps(value)
+    }
+
     // https://html.spec.whatwg.org/multipage/#dom-context-2d-direction
     fn Direction(&self) -> CanvasDirection {
         self.canvas_state.direction()
@@ -84,7 +84,7 @@ use webrender_api::units::DeviceIntRect;
 
 use crate::animation_timeline::AnimationTimeline;
 use crate::animations::Animations;
-use crate::canvas_context::CanvasContext as _;
+use crate::canvas_context::{CanvasContext as _, RenderingContext};
 use crate::document_loader::{DocumentLoader, LoadType};
 use crate::dom::attr::Attr;
 use crate::dom::beforeunloadevent::BeforeUnloadEvent;
@@ -160,6 +160,7 @@ use crate::dom::hashchangeevent::HashChangeEvent;
 use crate::dom::htmlanchorelement::HTMLAnchorElement;
 use crate::dom::htmlareaelement::HTMLAreaElement;
 use crate::dom::htmlbaseelement::HTMLBaseElement;
+use crate::dom::htmlcanvaselement::HTMLCanvasElement;
 use crate::dom::htmlcollection::{CollectionFilter, HTMLCollection};
 use crate::dom::htmlelement::HTMLElement;
 use crate::dom::htmlembedelement::HTMLEmbedElement;
@@ -763,9 +764,12 @@ impl Document {
         let client_context_id =
             ClientContextId::build(pipeline_id.namespace_id.0, pipeline_id.index.0.get());
 
+        // The 2D canvases of a document that isn't fully active aren't presented, so their
+        // drawing is held back until it is again.
         if activity != DocumentActivity::FullyActive {
             self.window().suspend(can_gc);
             media.suspend(&client_context_id);
+            self.set_2d_canvases_paused(true);
             return;
         }
 
@@ -773,6 +777,7 @@ impl Document {
         self.dirty_all_nodes();
         self.window().resume(can_gc);
         media.resume(&client_context_id);
+        self.set_2d_canvases_paused(false);
 
         if self.ready_state.get() != DocumentReadyState::Complete {
             return;
@@ -5245,6 +5250,17 @@ impl Document {
             .update_for_new_timeline_value(&self.window, current_timeline_value);
     }
 
+    /// Pins the animation timeline `time` seconds after its creation, so that this document
+    /// is rendered as it would be at that moment until the timeline is pinned again.
+    pub(crate) fn pin_animation_timeline(&self, time: f64) {
+        self.animation_timeline.borrow_mut().pin(time);
+        let current_timeline_value = self.current_animation_timeline_value();
+        self.animations
+            .borrow()
+            .update_for_new_timeline_value(&self.window, current_timeline_value);
+        self.maybe_mark_animating_nodes_as_dirty();
+    }
+
     pub(crate) fn maybe_mark_animating_nodes_as_dirty(&self) {
         let current_timeline_value = self.current_animation_timeline_value();
         self.animations
@@ -5474,6 +5490,21 @@ impl Document {
             .fire_bubbling_event(atom!("visibilitychange"), can_gc);
     }
 
+    /// Pause or resume the processing of the drawing of the 2D canvases in this document.
+    fn set_2d_canvases_paused(&self, paused: bool) {
+        for canvas in self
+            .upcast::<Node>()
+            .traverse_preorder(ShadowIncluding::Yes)
+            .filter_map(DomRoot::downcast::<HTMLCanvasElement>)
+        {
+            if let Some(context) = canvas.context() {
+                if let RenderingContext::Context2d(ref context) = *context {
+                    context.set_paused(paused);
+                }
+            }
+        }
+    }
+
     /// <https://html.spec.whatwg.org/multipage/#is-initial-about:blank>
     pub(crate) fn is_initial_about_blank(&self) -> bool {
         self.is_initial_about_blank.get()
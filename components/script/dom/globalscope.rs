@@ -18,6 +18,7 @@ use base::id::{
     BlobId, BroadcastChannelRouterId, MessagePortId, MessagePortRouterId, PipelineId,
     ServiceWorkerId, ServiceWorkerRegistrationId, WebViewId,
 };
+use canvas_traits::canvas::CanvasSizeLimits;
 use constellation_traits::{
     BlobData, BlobImpl, BroadcastChannelMsg, FileBlob, MessagePortImpl, MessagePortMsg,
     PortMessageTask, ScriptToConstellationChan, ScriptToConstellationMessage,
@@ -262,6 +263,11 @@ pub(crate) struct GlobalScope {
     /// <https://html.spec.whatwg.org/multipage/#timers>
     timers: OnceCell<OneshotTimers>,
 
+    /// The largest canvas that the active 2D canvas backend can allocate, asked from the
+    /// constellation when the first canvas of this global is created.
+    #[no_trace]
+    canvas_size_limits: OnceCell<CanvasSizeLimits>,
+
     /// The origin of the globalscope
     #[no_trace]
     origin: MutableOrigin,
@@ -760,6 +766,7 @@ impl GlobalScope {
             in_error_reporting_mode: Default::default(),
             resource_threads,
             timers: OnceCell::default(),
+            canvas_size_limits: OnceCell::new(),
             origin,
             creation_url,
             top_level_creation_url,
@@ -811,6 +818,16 @@ impl GlobalScope {
         self.timers.get_or_init(|| OneshotTimers::new(self))
     }
 
+    /// The largest canvas that the active 2D canvas backend can allocate, so that canvases
+    /// can be sized down before they are requested.
+    pub(crate) fn canvas_size_limits(&self) -> CanvasSizeLimits {
+        *self.canvas_size_limits.get_or_init(|| {
+            let (sender, receiver) = ipc::channel().unwrap();
+            self.send_to_constellation(ScriptToConstellationMessage::GetMaxCanvasSize(sender));
+            receiver.recv().unwrap_or_default()
+        })
+    }
+
     /// <https://w3c.github.io/ServiceWorker/#get-the-service-worker-registration-object>
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn get_serviceworker_registration(
@@ -489,7 +489,7 @@ impl HTMLCanvasElementMethods<crate::DomTypeHolder> for HTMLCanvasElement {
             return Ok(USVString("data:,".into()));
         };
 
-        let image_type = EncodedImageType::from(mime_type.to_string());
+        let image_type = EncodedImageType::for_encoding(mime_type.to_string());
 
         let mut url = format!("data:{};base64,", image_type.as_mime_type());
 
@@ -544,7 +544,7 @@ impl HTMLCanvasElementMethods<crate::DomTypeHolder> for HTMLCanvasElement {
             .borrow_mut()
             .insert(callback_id, callback);
         let quality = Self::maybe_quality(quality);
-        let image_type = EncodedImageType::from(mime_type.to_string());
+        let image_type = EncodedImageType::for_encoding(mime_type.to_string());
 
         self.global()
             .task_manager()
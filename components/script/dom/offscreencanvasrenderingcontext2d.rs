@@ -10,8 +10,8 @@ use dom_struct::dom_struct;
 use pixels::Snapshot;
 
 use crate::dom::bindings::codegen::Bindings::CanvasRenderingContext2DBinding::{
-    CanvasDirection, CanvasFillRule, CanvasImageSource, CanvasLineCap, CanvasLineJoin,
-    CanvasTextAlign, CanvasTextBaseline,
+    CanvasDirection, CanvasFillRule, CanvasFontStretch, CanvasFontVariantCaps, CanvasImageSource,
+    CanvasLineCap, CanvasLineJoin, CanvasTextAlign, CanvasTextBaseline,
 };
 use crate::dom::bindings::codegen::Bindings::DOMMatrixBinding::DOMMatrix2DInit;
 use crate::dom::bindings::codegen::Bindings::OffscreenCanvasRenderingContext2DBinding::OffscreenCanvasRenderingContext2DMethods;
@@ -308,6 +308,26 @@ impl OffscreenCanvasRenderingContext2DMethods<crate::DomTypeHolder>
         self.context.SetTextBaseline(value)
     }
 
+    // https://html.spec.whatwg.org/multipage/#dom-context-2d-fontstretch
+    fn FontStretch(&self) -> CanvasFontStretch {
+        self.context.FontStretch()
+    }
+
+    // https://html.spec.whatwg.org/multipage/#dom-context-2d-fontstretch
+    fn SetFontStretch(&self, value: CanvasFontStretch) {
+        self.context.SetFontStretch(value)
+    }
+
+    // https://html.spec.whatwg.org/multipage/#dom-context-2d-fontvariantcaps
+    fn FontVariantCaps(&self) -> CanvasFontVariantCaps {
+        self.context.FontVariantCaps()
+    }
+
+    // https://html.spec.whatwg.org/multipage/#dom-context-2d-fontvariantcaps
+    fn SetFontVariantCaps(&self, value: CanvasFontVariantCaps) {
+        self.context.SetFontVariantCaps(value)
+    }
+
     // https://html.spec.whatwg.org/multipage/#dom-context-2d-direction
     fn Direction(&self) -> CanvasDirection {
         self.context.Direction()
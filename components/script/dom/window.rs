@@ -2872,6 +2872,13 @@ impl Window {
         markers: Vec<TimelineMarkerType>,
         reply: IpcSender<Option<TimelineMarker>>,
     ) {
+        // The canvas paint thread lives outside of script, so it reports its markers
+        // directly.
+        if markers.contains(&TimelineMarkerType::Canvas) {
+            self.send_to_constellation(ScriptToConstellationMessage::SetCanvasTimelineMarkerSender(
+                Some(reply.clone()),
+            ));
+        }
         *self.devtools_marker_sender.borrow_mut() = Some(reply);
         self.devtools_markers.borrow_mut().extend(markers);
     }
@@ -2879,6 +2886,11 @@ impl Window {
     pub(crate) fn drop_devtools_timeline_markers(&self, markers: Vec<TimelineMarkerType>) {
         let mut devtools_markers = self.devtools_markers.borrow_mut();
         for marker in markers {
+            if marker == TimelineMarkerType::Canvas && devtools_markers.contains(&marker) {
+                self.send_to_constellation(
+                    ScriptToConstellationMessage::SetCanvasTimelineMarkerSender(None),
+                );
+            }
             devtools_markers.remove(&marker);
         }
         if devtools_markers.is_empty() {
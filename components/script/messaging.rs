@@ -57,6 +57,7 @@ impl MixedMessage {
                     .or(Some(new_layout_info.new_pipeline_id)),
                 ScriptThreadMessage::Resize(id, ..) => Some(*id),
                 ScriptThreadMessage::ThemeChange(id, ..) => Some(*id),
+                ScriptThreadMessage::PinAnimationTimeline(id, ..) => Some(*id),
                 ScriptThreadMessage::ResizeInactive(id, ..) => Some(*id),
                 ScriptThreadMessage::UnloadDocument(id) => Some(*id),
                 ScriptThreadMessage::ExitPipeline(_webview_id, id, ..) => Some(*id),
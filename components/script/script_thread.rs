@@ -1903,6 +1903,9 @@ impl ScriptThread {
             ScriptThreadMessage::ThemeChange(_, theme) => {
                 self.handle_theme_change_msg(theme);
             },
+            ScriptThreadMessage::PinAnimationTimeline(pipeline_id, time) => {
+                self.handle_pin_animation_timeline_msg(pipeline_id, time);
+            },
             ScriptThreadMessage::GetTitle(pipeline_id) => self.handle_get_title_msg(pipeline_id),
             ScriptThreadMessage::SetDocumentActivity(pipeline_id, activity) => {
                 self.handle_set_document_activity_msg(pipeline_id, activity, can_gc)
@@ -2597,6 +2600,14 @@ impl ScriptThread {
         }
     }
 
+    fn handle_pin_animation_timeline_msg(&self, pipeline_id: PipelineId, time: Duration) {
+        let Some(document) = self.documents.borrow().find_document(pipeline_id) else {
+            warn!("Animation timeline pinned for closed pipeline {pipeline_id}.");
+            return;
+        };
+        document.pin_animation_timeline(time.as_secs_f64());
+    }
+
     // exit_fullscreen creates a new JS promise object, so we need to have entered a realm
     fn handle_exit_fullscreen(&self, id: PipelineId, can_gc: CanGc) {
         let document = self.documents.borrow().find_document(id);
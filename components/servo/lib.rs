@@ -1054,6 +1054,14 @@ impl Servo {
         }
     }
 
+    /// Seed (or, with `None`, stop seeding) any randomness that affects rendering, such as the
+    /// noise that canvas gradients are dithered with, so that rendering the same content twice
+    /// produces identical output. This only affects canvases created afterwards.
+    pub fn set_render_seed(&self, seed: Option<u64>) {
+        self.constellation_proxy
+            .send(EmbedderToConstellationMessage::SetRenderSeed(seed));
+    }
+
     pub fn constellation_sender(&self) -> Sender<EmbedderToConstellationMessage> {
         self.constellation_proxy.sender()
     }
@@ -51,6 +51,7 @@ pub(crate) fn run_test(
 
 pub struct ServoTest {
     servo: Servo,
+    rendering_context: Rc<SoftwareRenderingContext>,
 }
 
 impl Drop for ServoTest {
@@ -90,13 +91,23 @@ impl ServoTest {
         let servo = ServoBuilder::new(rendering_context.clone())
             .event_loop_waker(Box::new(EventLoopWakerImpl(user_event_triggered)))
             .build();
-        Self { servo }
+        Self {
+            servo,
+            rendering_context,
+        }
     }
 
     pub fn servo(&self) -> &Servo {
         &self.servo
     }
 
+    // The dead code exception here is because not all test suites that use `common` also
+    // read back what was painted.
+    #[allow(dead_code)]
+    pub fn rendering_context(&self) -> &Rc<SoftwareRenderingContext> {
+        &self.rendering_context
+    }
+
     /// Spin the Servo event loop until one of:
     ///  - The given callback returns `Ok(false)`.
     ///  - The given callback returns an `Error`, in which case the `Error` will be returned.
@@ -13,22 +13,28 @@ mod common;
 
 use std::cell::{Cell, RefCell};
 use std::rc::Rc;
+use std::time::Duration;
 
-use anyhow::ensure;
+use anyhow::{anyhow, ensure};
 use common::{ServoTest, run_api_tests};
+use servo::config::prefs;
+use servo::webrender_api::units::DeviceIntRect;
 use servo::{
-    JSValue, JavaScriptEvaluationError, LoadStatus, Theme, WebView, WebViewBuilder, WebViewDelegate,
+    JSValue, JavaScriptEvaluationError, LoadStatus, RenderingContext, Theme, WebView,
+    WebViewBuilder, WebViewDelegate,
 };
 use url::Url;
 
 #[derive(Default)]
 struct WebViewDelegateImpl {
     url_changed: Cell<bool>,
+    new_frame_ready: Cell<bool>,
 }
 
 impl WebViewDelegateImpl {
     pub(crate) fn reset(&self) {
         self.url_changed.set(false);
+        self.new_frame_ready.set(false);
     }
 }
 
@@ -36,6 +42,10 @@ impl WebViewDelegate for WebViewDelegateImpl {
     fn notify_url_changed(&self, _webview: servo::WebView, _url: url::Url) {
         self.url_changed.set(true);
     }
+
+    fn notify_new_frame_ready(&self, _webview: servo::WebView) {
+        self.new_frame_ready.set(true);
+    }
 }
 
 fn test_create_webview(servo_test: &ServoTest) -> Result<(), anyhow::Error> {
@@ -164,11 +174,162 @@ fn test_theme_change(servo_test: &ServoTest) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+fn test_theme_set_before_first_load(servo_test: &ServoTest) -> Result<(), anyhow::Error> {
+    let delegate = Rc::new(WebViewDelegateImpl::default());
+    let webview = WebViewBuilder::new(servo_test.servo())
+        .delegate(delegate.clone())
+        .url(
+            Url::parse(
+                "data:text/html,<style>@media (prefers-color-scheme: dark) \
+                 { body { background: black } }</style>",
+            )
+            .unwrap(),
+        )
+        .build();
+    // Embedders that override the theme, like servoshell's headless windows, set it right
+    // after creating the webview, before its first page has loaded.
+    webview.notify_theme_change(Theme::Dark);
+    let load_webview = webview.clone();
+    servo_test.spin(move || Ok(load_webview.load_status() != LoadStatus::Complete))?;
+
+    let is_dark_theme_script = "window.matchMedia('(prefers-color-scheme: dark)').matches";
+    let result = evaluate_javascript(servo_test, webview.clone(), is_dark_theme_script);
+    ensure!(result == Ok(JSValue::Boolean(true)));
+
+    let pixels = capture_webview_pixels(servo_test, &webview, delegate, &[(10, 10)])?;
+    ensure!(pixels == [[0, 0, 0, 255]]);
+
+    Ok(())
+}
+
+fn test_pin_animation_timeline(servo_test: &ServoTest) -> Result<(), anyhow::Error> {
+    // A bar that grows by 100px every second after the animation starts, shortly after the
+    // document is created.
+    let delegate = Rc::new(WebViewDelegateImpl::default());
+    let webview = WebViewBuilder::new(servo_test.servo())
+        .delegate(delegate.clone())
+        .url(
+            Url::parse(
+                "data:text/html,<style>@keyframes grow { from { width: 0 } to { width: 400px } \
+                 } div { height: 20px; background: red; animation: grow 4s steps(4, end) }\
+                 </style><body style=margin:0><div>",
+            )
+            .unwrap(),
+        )
+        .build();
+    let load_webview = webview.clone();
+    servo_test.spin(move || Ok(load_webview.load_status() != LoadStatus::Complete))?;
+
+    let width_script = "getComputedStyle(document.querySelector('div')).width";
+    webview.pin_animation_timeline(Duration::from_millis(2500));
+    let result = evaluate_javascript(servo_test, webview.clone(), width_script);
+    ensure!(result == Ok(JSValue::String("200px".into())));
+    let points = [(150, 10), (250, 10)];
+    let pixels = capture_webview_pixels(servo_test, &webview, delegate.clone(), &points)?;
+    ensure!(pixels == [[255, 0, 0, 255], [255, 255, 255, 255]]);
+
+    // Pinning again samples the animations at the new time after the creation of the
+    // document, not after the previous pin.
+    webview.pin_animation_timeline(Duration::from_millis(3500));
+    let result = evaluate_javascript(servo_test, webview.clone(), width_script);
+    ensure!(result == Ok(JSValue::String("300px".into())));
+
+    // The pinned timeline doesn't follow the clock.
+    std::thread::sleep(Duration::from_millis(600));
+    let result = evaluate_javascript(servo_test, webview.clone(), width_script);
+    ensure!(result == Ok(JSValue::String("300px".into())));
+
+    Ok(())
+}
+
+/// Shows `webview` in place of the others and returns the colors of the pixels at `points`
+/// in the first frame painted afterwards.
+fn capture_webview_pixels(
+    servo_test: &ServoTest,
+    webview: &WebView,
+    delegate: Rc<WebViewDelegateImpl>,
+    points: &[(u32, u32)],
+) -> Result<Vec<[u8; 4]>, anyhow::Error> {
+    delegate.reset();
+    webview.show(true);
+    servo_test.spin(move || Ok(!delegate.new_frame_ready.get()))?;
+    ensure!(webview.paint());
+
+    let rendering_context = servo_test.rendering_context();
+    let size = rendering_context.size2d().to_i32();
+    let image = rendering_context
+        .read_to_image(DeviceIntRect::from_size(size))
+        .ok_or_else(|| anyhow!("Could not read the painted frame"))?;
+    Ok(points
+        .iter()
+        .map(|&(x, y)| image.get_pixel(x, y).0)
+        .collect())
+}
+
+fn test_capture_multiple_webviews(servo_test: &ServoTest) -> Result<(), anyhow::Error> {
+    let delegate = Rc::new(WebViewDelegateImpl::default());
+    let red_webview = WebViewBuilder::new(servo_test.servo())
+        .delegate(delegate.clone())
+        .url(Url::parse("data:text/html,<style>body{background:red}</style>").unwrap())
+        .build();
+    let blue_webview = WebViewBuilder::new(servo_test.servo())
+        .delegate(delegate.clone())
+        .url(Url::parse("data:text/html,<style>body{background:blue}</style>").unwrap())
+        .build();
+
+    let (red, blue) = (red_webview.clone(), blue_webview.clone());
+    servo_test.spin(move || {
+        Ok(red.load_status() != LoadStatus::Complete || blue.load_status() != LoadStatus::Complete)
+    })?;
+
+    // Each webview can be captured, whichever was created last.
+    let pixels = capture_webview_pixels(servo_test, &red_webview, delegate.clone(), &[(10, 10)])?;
+    ensure!(pixels == [[255, 0, 0, 255]]);
+    let pixels = capture_webview_pixels(servo_test, &blue_webview, delegate.clone(), &[(10, 10)])?;
+    ensure!(pixels == [[0, 0, 255, 255]]);
+    let pixels = capture_webview_pixels(servo_test, &red_webview, delegate, &[(10, 10)])?;
+    ensure!(pixels == [[255, 0, 0, 255]]);
+
+    Ok(())
+}
+
+fn test_backdrop_fills_viewport_below_content(servo_test: &ServoTest) -> Result<(), anyhow::Error> {
+    let original_preferences = prefs::get().clone();
+    let mut preferences = original_preferences.clone();
+    preferences.shell_background_color_rgba = [0.0, 1.0, 0.0, 1.0];
+    prefs::set(preferences);
+
+    let delegate = Rc::new(WebViewDelegateImpl::default());
+    let webview = WebViewBuilder::new(servo_test.servo())
+        .delegate(delegate.clone())
+        .url(
+            Url::parse(
+                "data:text/html,<body style=margin:0><div style=height:20px;background:red>",
+            )
+            .unwrap(),
+        )
+        .build();
+    let load_webview = webview.clone();
+    servo_test.spin(move || Ok(load_webview.load_status() != LoadStatus::Complete))?;
+
+    let pixels = capture_webview_pixels(servo_test, &webview, delegate, &[(10, 10), (10, 200)]);
+    prefs::set(original_preferences);
+
+    // The page has no background of its own, so the backdrop shows below its content.
+    ensure!(pixels? == [[255, 0, 0, 255], [0, 255, 0, 255]]);
+
+    Ok(())
+}
+
 fn main() {
     run_api_tests!(
         test_create_webview,
         test_evaluate_javascript_basic,
         test_theme_change,
+        test_theme_set_before_first_load,
+        test_pin_animation_timeline,
+        test_capture_multiple_webviews,
+        test_backdrop_fills_viewport_below_content,
         // This test needs to be last, as it tests creating and dropping
         // a WebView right before shutdown.
         test_create_webview_and_immediately_drop_webview_before_shutdown
@@ -417,6 +417,20 @@ impl WebView {
             ))
     }
 
+    /// Pin the animation timelines of the documents currently in this [`WebView`] to `time`
+    /// after their creation. CSS animations and transitions will then be rendered as they
+    /// would be at that moment, regardless of how much real time passes, which allows
+    /// capturing animated content deterministically. Documents loaded afterwards are not
+    /// affected.
+    pub fn pin_animation_timeline(&self, time: Duration) {
+        self.inner()
+            .constellation_proxy
+            .send(EmbedderToConstellationMessage::PinAnimationTimeline(
+                self.id(),
+                time,
+            ))
+    }
+
     pub fn load(&self, url: Url) {
         self.inner()
             .constellation_proxy
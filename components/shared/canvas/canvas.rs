@@ -3,21 +3,27 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
 use std::default::Default;
+use std::f64::consts::TAU;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, Range};
+use std::path::PathBuf;
 use std::str::FromStr;
 
-use euclid::Angle;
+use base::cross_process_instant::CrossProcessInstant;
 use euclid::approxeq::ApproxEq;
 use euclid::default::{Point2D, Rect, Size2D, Transform2D};
-use ipc_channel::ipc::IpcSender;
+use ipc_channel::ipc::{IpcError, IpcReceiver, IpcSender, IpcSharedMemory};
 use kurbo::{BezPath, ParamCurveNearest as _, PathEl, Point, Shape, Triangle};
 use malloc_size_of::MallocSizeOf;
 use malloc_size_of_derive::MallocSizeOf;
-use pixels::IpcSnapshot;
+use pixels::{EncodedImageType, IpcSnapshot, Snapshot};
 use serde::{Deserialize, Serialize};
-use strum::{Display, EnumString};
+use strum::{Display, EnumString, IntoStaticStr};
 use style::color::AbsoluteColor;
 use style::properties::style_structs::Font as FontStyleStruct;
 use style::servo_arc::Arc as ServoArc;
+use webrender_api::ImageRendering;
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Path(pub BezPath);
@@ -280,44 +286,27 @@ impl Path {
             return Err(IndexSizeError);
         }
 
-        let mut start = Angle::radians(start_angle);
-        let mut end = Angle::radians(end_angle);
-
-        // Wrap angles mod 2 * PI if necessary
-        if !counterclockwise && start > end + Angle::two_pi() ||
-            counterclockwise && end > start + Angle::two_pi()
-        {
-            start = start.positive();
-            end = end.positive();
-        }
-
-        // Calculate the total arc we're going to sweep.
-        let sweep = match counterclockwise {
-            true => {
-                if end - start == Angle::two_pi() {
-                    -Angle::two_pi()
-                } else if end > start {
-                    -(Angle::two_pi() - (end - start))
-                } else {
-                    -(start - end)
-                }
-            },
-            false => {
-                if start - end == Angle::two_pi() {
-                    Angle::two_pi()
-                } else if start > end {
-                    Angle::two_pi() - (start - end)
-                } else {
-                    end - start
-                }
-            },
+        // The arc is the whole circumference if it sweeps at least a full turn in its
+        // direction. Otherwise it runs from the start angle to the end angle in its
+        // direction, which is less than a full turn.
+        let sweep = if !counterclockwise && end_angle - start_angle >= TAU {
+            TAU
+        } else if counterclockwise && start_angle - end_angle >= TAU {
+            -TAU
+        } else {
+            let sweep = (end_angle - start_angle).rem_euclid(TAU);
+            if counterclockwise && sweep != 0. {
+                sweep - TAU
+            } else {
+                sweep
+            }
         };
 
         let arc = kurbo::Arc::new(
             (x, y),
             (radius_x, radius_y),
-            start.radians,
-            sweep.radians,
+            start_angle,
+            sweep,
             rotation_angle,
         );
 
@@ -328,7 +317,7 @@ impl Path {
 
         self.line_to(start_point.x, start_point.y);
 
-        if sweep.radians.abs() > 1e-3 {
+        if sweep.abs() > 1e-3 {
             self.0.extend(iter);
         }
 
@@ -357,6 +346,114 @@ impl Path {
         self.0.move_to((x, y));
     }
 
+    /// <https://html.spec.whatwg.org/multipage/#dom-context-2d-roundrect>
+    ///
+    /// The `radii` are the horizontal and vertical radii of the upper left, upper right,
+    /// lower right and lower left corners, in that order. Negative radii, which the
+    /// specification rejects, are treated as zero.
+    pub fn round_rect(&mut self, x: f64, y: f64, w: f64, h: f64, radii: [Size2D<f64>; 4]) {
+        // Step 1. If any of x, y, w, or h are infinite or NaN, then return.
+        if !(x.is_finite() && y.is_finite() && w.is_finite() && h.is_finite()) {
+            return;
+        }
+        if radii
+            .iter()
+            .any(|radius| !radius.width.is_finite() || !radius.height.is_finite())
+        {
+            return;
+        }
+        let [mut upper_left, mut upper_right, mut lower_right, mut lower_left] =
+            radii.map(|radius| radius.max(Size2D::zero()));
+
+        // A rectangle of negative width or height is drawn flipped, with its corners
+        // swapped accordingly.
+        let (mut x, mut y, mut w, mut h) = (x, y, w, h);
+        if w < 0. {
+            x += w;
+            w = -w;
+            std::mem::swap(&mut upper_left, &mut upper_right);
+            std::mem::swap(&mut lower_left, &mut lower_right);
+        }
+        if h < 0. {
+            y += h;
+            h = -h;
+            std::mem::swap(&mut upper_left, &mut lower_left);
+            std::mem::swap(&mut upper_right, &mut lower_right);
+        }
+
+        // Corner curves must not overlap. Scale all radii to prevent this.
+        let top = upper_left.width + upper_right.width;
+        let right = upper_right.height + lower_right.height;
+        let bottom = lower_right.width + lower_left.width;
+        let left = upper_left.height + lower_left.height;
+        let scale = [w / top, h / right, w / bottom, h / left]
+            .into_iter()
+            .fold(1., f64::min);
+        if scale < 1. {
+            for radius in [
+                &mut upper_left,
+                &mut upper_right,
+                &mut lower_right,
+                &mut lower_left,
+            ] {
+                *radius = *radius * scale;
+            }
+        }
+
+        // Create a new subpath made of the sides and the elliptical corners, and mark it as
+        // closed.
+        let quarter_turn = TAU / 4.;
+        self.0.move_to((x + upper_left.width, y));
+        self.0.line_to((x + w - upper_right.width, y));
+        let _ = self.ellipse(
+            x + w - upper_right.width,
+            y + upper_right.height,
+            upper_right.width,
+            upper_right.height,
+            0.,
+            -quarter_turn,
+            0.,
+            false,
+        );
+        self.0.line_to((x + w, y + h - lower_right.height));
+        let _ = self.ellipse(
+            x + w - lower_right.width,
+            y + h - lower_right.height,
+            lower_right.width,
+            lower_right.height,
+            0.,
+            0.,
+            quarter_turn,
+            false,
+        );
+        self.0.line_to((x + lower_left.width, y + h));
+        let _ = self.ellipse(
+            x + lower_left.width,
+            y + h - lower_left.height,
+            lower_left.width,
+            lower_left.height,
+            0.,
+            quarter_turn,
+            2. * quarter_turn,
+            false,
+        );
+        self.0.line_to((x, y + upper_left.height));
+        let _ = self.ellipse(
+            x + upper_left.width,
+            y + upper_left.height,
+            upper_left.width,
+            upper_left.height,
+            0.,
+            2. * quarter_turn,
+            3. * quarter_turn,
+            false,
+        );
+        self.0.close_path();
+
+        // Create a new subpath with the point (x, y) as the only point in the subpath.
+        self.0.move_to((x, y));
+    }
+
     /// <https://html.spec.whatwg.org/multipage/#dom-context-2d-ispointinpath>
     pub fn is_point_in_path(&self, x: f64, y: f64, fill_rule: FillRule) -> bool {
         let p = Point::new(x, y);
@@ -387,6 +484,77 @@ impl Path {
             .any(|seg| seg.nearest(p, 0.00001).distance_sq < 0.00001)
     }
 
+    /// The signed number of times the path winds around the point, with open subpaths
+    /// implicitly closed, or zero if the point is infinite or NaN.
+    pub fn winding_number(&self, x: f64, y: f64) -> i32 {
+        let p = Point::new(x, y);
+        if !p.is_finite() {
+            return 0;
+        }
+        let mut path = self.clone();
+        path.close_path();
+        path.0.winding(p)
+    }
+
+    /// <https://html.spec.whatwg.org/multipage/#dom-context-2d-ispointinstroke>, with
+    /// `tolerance` added to the width of the line, so that thin lines are easier to hit.
+    /// The path is stroked in its own coordinate space, and the stroke then transformed.
+    pub fn is_point_in_stroke(
+        &self,
+        x: f64,
+        y: f64,
+        line_options: &LineOptions,
+        tolerance: f64,
+        transform: Transform2D<f64>,
+    ) -> bool {
+        let cap = match line_options.cap_style {
+            LineCapStyle::Butt => kurbo::Cap::Butt,
+            LineCapStyle::Round => kurbo::Cap::Round,
+            LineCapStyle::Square => kurbo::Cap::Square,
+        };
+        // A stroke that isn't centered is the half of one twice as wide on its side.
+        let width = match line_options.alignment {
+            StrokeAlignment::Center => line_options.width,
+            StrokeAlignment::Inside | StrokeAlignment::Outside => 2. * line_options.width,
+        };
+        let style = kurbo::Stroke {
+            width: width + tolerance.max(0.),
+            join: match line_options.join_style {
+                LineJoinStyle::Round => kurbo::Join::Round,
+                LineJoinStyle::Bevel => kurbo::Join::Bevel,
+                LineJoinStyle::Miter => kurbo::Join::Miter,
+            },
+            miter_limit: line_options.miter_limit,
+            start_cap: cap,
+            end_cap: cap,
+            dash_pattern: line_options
+                .dash
+                .iter()
+                .map(|length| *length as f64)
+                .collect(),
+            dash_offset: line_options.dash_offset,
+        };
+        // Step 2. The point is inside of the path that results from tracing the path with
+        // the line styles, with the nonzero winding rule.
+        let mut stroke = Path(kurbo::stroke(
+            self.0.iter(),
+            &style,
+            &kurbo::StrokeOpts::default(),
+            0.01,
+        ));
+        stroke.transform(transform);
+        if !stroke.is_point_in_path(x, y, FillRule::Nonzero) {
+            return false;
+        }
+        let mut path = self.clone();
+        path.transform(transform);
+        match line_options.alignment {
+            StrokeAlignment::Center => true,
+            StrokeAlignment::Inside => path.is_point_in_path(x, y, FillRule::Nonzero),
+            StrokeAlignment::Outside => !path.is_point_in_path(x, y, FillRule::Nonzero),
+        }
+    }
+
     pub fn bounding_box(&self) -> Rect<f64> {
         self.0.control_box().into()
     }
@@ -401,17 +569,64 @@ pub enum FillRule {
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, MallocSizeOf, PartialEq, Serialize)]
 pub struct CanvasId(pub u64);
 
+/// A handle to pixels of a canvas kept by [`Canvas2dMsg::SaveRegion`], unique within the
+/// canvas.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, MallocSizeOf, PartialEq, Serialize)]
+pub struct SavedRegionId(pub u64);
+
 #[derive(Clone, Copy, Debug, Deserialize, MallocSizeOf, Serialize)]
 pub struct CompositionOptions {
     pub alpha: f64,
     pub composition_operation: CompositionOrBlending,
 }
 
-#[derive(Debug, Deserialize, MallocSizeOf, Serialize)]
+impl CompositionOptions {
+    /// Whether compositing with these options leaves the pixels outside of what is drawn
+    /// as they are. Operations like `copy` or `source-in` clear them instead.
+    pub fn is_bounded(&self) -> bool {
+        match self.composition_operation {
+            CompositionOrBlending::Composition(style) => matches!(
+                style,
+                CompositionStyle::SourceOver |
+                    CompositionStyle::DestinationOver |
+                    CompositionStyle::DestinationOut |
+                    CompositionStyle::SourceAtop |
+                    CompositionStyle::Xor |
+                    CompositionStyle::Lighter
+            ),
+            CompositionOrBlending::Blending(_) => true,
+        }
+    }
+}
+
+/// How [`Canvas2dMsg::DrawFocusRing`] strokes focus rings.
+#[derive(Clone, Debug, Deserialize, MallocSizeOf, Serialize)]
+pub struct FocusRingStyle {
+    pub color: AbsoluteColor,
+    pub width: f64,
+    /// The lengths of the alternating dashes and gaps of the ring, or empty for a solid ring.
+    pub dash: Vec<f32>,
+}
+
+impl Default for FocusRingStyle {
+    /// A solid, high-contrast blue ring, similar to the focus rings of other elements.
+    fn default() -> Self {
+        Self {
+            color: AbsoluteColor::srgb_legacy(0, 95, 204, 1.),
+            width: 2.,
+            dash: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, MallocSizeOf, Serialize)]
 pub struct ShadowOptions {
     pub offset_x: f64,
     pub offset_y: f64,
     pub blur: f64,
+    /// How far the shape is grown, in device pixels, before it is blurred into the shadow,
+    /// or shrunk if it is negative, like the spread of CSS `box-shadow`.
+    pub spread: f64,
     pub color: AbsoluteColor,
 }
 
@@ -423,10 +638,26 @@ impl ShadowOptions {
         // and either the shadowBlur is nonzero, or the shadowOffsetX is nonzero, or the shadowOffsetY is nonzero.
             (self.offset_x != 0.0 ||
                 self.offset_y != 0.0 ||
-                self.blur != 0.0)
+                self.blur != 0.0 ||
+                // A spread shadow shows around the shape even when it is right underneath it.
+                self.spread > 0.0)
     }
 }
 
+/// A function in the filter chain of a canvas context, which applies to the result of
+/// each drawing operation before it is composited onto the canvas.
+/// <https://html.spec.whatwg.org/multipage/#dom-context-2d-filter>
+#[derive(Clone, Debug, Deserialize, MallocSizeOf, Serialize)]
+pub enum FilterFunction {
+    /// <https://drafts.fxtf.org/filter-effects/#funcdef-filter-drop-shadow>
+    DropShadow {
+        offset_x: f64,
+        offset_y: f64,
+        std_deviation: f64,
+        color: AbsoluteColor,
+    },
+}
+
 #[derive(Debug, Deserialize, MallocSizeOf, Serialize)]
 pub struct LineOptions {
     pub width: f64,
@@ -435,6 +666,25 @@ pub struct LineOptions {
     pub miter_limit: f64,
     pub dash: Vec<f32>,
     pub dash_offset: f64,
+    /// The color that the gaps between the dashes are stroked in, as an extension to the
+    /// transparent gaps of the specification. This only has an effect along with `dash`.
+    pub dash_gap_color: Option<AbsoluteColor>,
+    /// Which side of the path the line is stroked on.
+    pub alignment: StrokeAlignment,
+}
+
+/// Where a stroke lies relative to its path, as an extension to the centered strokes of
+/// the specification. The inside of a path is where it would be filled with the nonzero
+/// winding rule, so open subpaths are implicitly closed to tell it apart.
+#[derive(Clone, Copy, Debug, Default, Deserialize, MallocSizeOf, PartialEq, Serialize)]
+pub enum StrokeAlignment {
+    /// The line is centered on the path.
+    #[default]
+    Center,
+    /// The line lies entirely inside of the path.
+    Inside,
+    /// The line lies entirely outside of the path.
+    Outside,
 }
 
 #[derive(Debug, Deserialize, MallocSizeOf, Serialize)]
@@ -443,17 +693,210 @@ pub struct TextOptions {
     pub font: Option<ServoArc<FontStyleStruct>>,
     pub align: TextAlign,
     pub baseline: TextBaseline,
+    /// An extra offset of the baseline in user units, applied when filling text on top of
+    /// the offset resolved from `baseline`. Positive values raise the text, as for a
+    /// superscript.
+    pub baseline_shift: Option<f32>,
+    /// The height in user units of the line box that the `top`, `middle` and `bottom`
+    /// baselines place text in, instead of the ascent and descent of its font. As with the
+    /// half-leading of CSS, the difference is split evenly above and below them, so that
+    /// lines drawn this far apart abut exactly whatever their fonts.
+    pub line_height: Option<f32>,
+    /// The distance between the tab stops of the text, which are measured from its start.
+    /// When given, a tab advances the text after it to the next tab stop, instead of being
+    /// replaced with a space as the specification requires.
+    pub tab_size: Option<TabSize>,
+    /// Lines to draw along filled text, if any.
+    pub decoration: Option<TextDecoration>,
+    /// The glyphs of filled text to draw, as indices into its shaped glyphs in visual
+    /// order, or `None` to draw all of them. The drawn glyphs keep the position they have in
+    /// the whole text, and the range is clamped to the glyphs there are.
+    pub glyph_range: Option<Range<usize>>,
+    /// The OpenType tags that filled and measured text is shaped with.
+    pub shaping_tags: ShapingTags,
+}
+
+/// OpenType tags that text is shaped with instead of those detected from its characters.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, MallocSizeOf, PartialEq, Serialize)]
+pub struct ShapingTags {
+    /// The script tag, such as `kana` or `hani`.
+    pub script: Option<u32>,
+    /// The language system tag, such as `JAN` or `ZHS`, which selects among the forms that
+    /// fonts have for several languages. By default, the default language system of the
+    /// script is used.
+    pub language: Option<u32>,
 }
 
+/// The distance between tab stops, like CSS `tab-size`.
+#[derive(Clone, Copy, Debug, Deserialize, MallocSizeOf, PartialEq, Serialize)]
+pub enum TabSize {
+    /// A number of advances of the space of the first font of the text.
+    Spaces(f32),
+    /// A distance in user units.
+    Pixels(f32),
+}
+
+/// Lines drawn along a line of text, like those of CSS `text-decoration`. They are placed
+/// according to the metrics of the first font of the text.
+#[derive(Clone, Copy, Debug, Deserialize, MallocSizeOf, PartialEq, Serialize)]
+pub struct TextDecoration {
+    pub underline: bool,
+    pub overline: bool,
+    pub line_through: bool,
+    /// The thickness of the lines in user units, or `None` to use the thickness that the
+    /// font gives for each kind of line.
+    pub thickness: Option<f64>,
+    pub style: TextDecorationStyle,
+}
+
+/// The direction in which [`Canvas2dMsg::FlipSurface`] mirrors the canvas.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub enum FlipAxis {
+    /// Swap the left and the right of the canvas.
+    Horizontal,
+    /// Swap the top and the bottom of the canvas.
+    Vertical,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub enum TextDecorationStyle {
+    #[default]
+    Solid,
+    /// Dashes and gaps that are each twice as long as the line is thick.
+    Dashed,
+}
+
+/// The largest canvas that the active 2D canvas backend can allocate.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub struct CanvasSizeLimits {
+    pub max_width: u64,
+    pub max_height: u64,
+    /// The area of a canvas must be strictly below this.
+    pub max_area: u64,
+}
+
+impl Default for CanvasSizeLimits {
+    fn default() -> Self {
+        // Firefox limits width/height to 32767 pixels and Chromium to 65535 pixels,
+        // but slows down dramatically before it reaches that limit.
+        // We limit by area instead, giving us larger maximum dimensions,
+        // in exchange for a smaller maximum canvas size.
+        Self {
+            max_width: 65535,
+            max_height: 65535,
+            max_area: 32768 * 8192,
+        }
+    }
+}
+
+impl CanvasSizeLimits {
+    /// Whether a canvas of the given size can be allocated.
+    pub fn allows(&self, size: Size2D<u64>) -> bool {
+        !size.is_empty() &&
+            size.width <= self.max_width &&
+            size.height <= self.max_height &&
+            size.area() < self.max_area
+    }
+
+    /// Returns `size` if a canvas of that size can be allocated, and an empty size, which
+    /// nothing is drawn to, otherwise.
+    pub fn clamp(&self, size: Size2D<u64>) -> Size2D<u64> {
+        if self.allows(size) {
+            size
+        } else {
+            Size2D::zero()
+        }
+    }
+}
+
+/// How the backing store of a new canvas is allocated and drawn to.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CanvasCreationOptions {
+    /// The number of device pixels per CSS pixel in each direction of the canvas's backing
+    /// store.
+    pub scale_factor: u32,
+    /// The fraction of the resolution given by `scale_factor` that the backing store is
+    /// actually allocated at, trading quality for speed. WebRender upscales the canvas back
+    /// to its size when presenting it.
+    pub render_scale: f32,
+    /// Whether the canvas produces its pixels bottom-up, as GL textures expect.
+    pub y_flipped: bool,
+    /// How the results of drawing operations are blended with the pixels of the canvas.
+    pub blending_space: BlendingSpace,
+}
+
+impl Default for CanvasCreationOptions {
+    fn default() -> Self {
+        Self {
+            scale_factor: 1,
+            render_scale: 1.,
+            y_flipped: false,
+            blending_space: BlendingSpace::default(),
+        }
+    }
+}
+
+/// A problem encountered by the canvas paint thread. It is returned by the failed
+/// operation, and reported to embedders that asked for errors when starting the thread.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum CanvasError {
+    /// The active backend does not support the described operation, so it was skipped.
+    UnsupportedOperation(String),
+    /// A canvas of the given size could not be created.
+    CreationFailed(Size2D<u64>),
+    /// The described operation failed and had no effect.
+    OperationFailed(String),
+}
+
+impl fmt::Display for CanvasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CanvasError::UnsupportedOperation(operation) => {
+                write!(f, "Unsupported canvas operation: {operation}")
+            },
+            CanvasError::CreationFailed(size) => write!(
+                f,
+                "Could not create a canvas of {}x{} pixels",
+                size.width, size.height
+            ),
+            CanvasError::OperationFailed(description) => f.write_str(description),
+        }
+    }
+}
+
+/// Messages to the canvas paint thread. The messages for a canvas that were sent on one
+/// sender are processed one at a time, in the order they were sent, so that a reply always
+/// reflects exactly the messages sent before it. Messages sent on different senders, for
+/// instance an `IpcSender` and a local `Sender`, are not ordered with respect to each other.
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Deserialize, Serialize)]
 pub enum CanvasMsg {
     Canvas2d(Canvas2dMsg, CanvasId),
     Recreate(Option<Size2D<u64>>, CanvasId),
     Close(CanvasId),
+    /// Pause or resume processing of the canvas's [`Canvas2dMsg`]s. While paused, messages
+    /// that don't expect a reply are buffered and applied in order on resume, or before the
+    /// next message that expects one. [`Canvas2dMsg::UpdateImage`] is answered right away,
+    /// but the image is only uploaded on resume.
+    SetPaused(CanvasId, bool),
+    /// Composite the overlay onto the base with the operation and global alpha, without
+    /// drawing to any canvas, and reply with the result, which has the size of the base. The
+    /// overlay is placed at the origin of the base. Script asks for this through the
+    /// constellation, which starts the thread if no canvas has yet.
+    BlendSnapshots(
+        IpcSnapshot,
+        IpcSnapshot,
+        CompositionOrBlending,
+        f64,
+        IpcSender<IpcSnapshot>,
+    ),
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Messages understood by a 2D canvas. Drawing messages fill and stroke with the styles
+/// last set with [`Canvas2dMsg::SetFillStyle`] and [`Canvas2dMsg::SetStrokeStyle`], and
+/// composite with the global alpha and compositing operation last set with
+/// [`Canvas2dMsg::SetGlobalAlpha`] and [`Canvas2dMsg::SetGlobalCompositeOperation`].
+#[derive(Debug, Deserialize, IntoStaticStr, Serialize)]
 pub enum Canvas2dMsg {
     DrawImage(
         IpcSnapshot,
@@ -461,7 +904,30 @@ pub enum Canvas2dMsg {
         Rect<f64>,
         bool,
         ShadowOptions,
-        CompositionOptions,
+        Transform2D<f32>,
+    ),
+    /// Like [`Canvas2dMsg::DrawImage`], but drawing the whole image into the destination
+    /// rectangle with its corners rounded by the given radii, as `roundRect()` rounds them,
+    /// without changing the clip of the canvas.
+    DrawImageRounded(
+        IpcSnapshot,
+        Rect<f64>,
+        [Size2D<f64>; 4],
+        bool,
+        ShadowOptions,
+        Transform2D<f32>,
+    ),
+    /// Like [`Canvas2dMsg::DrawImage`], but with the image still encoded, so that it is
+    /// decoded on the canvas thread instead of being sent as pixels. Images that fail to
+    /// decode are not drawn.
+    DrawEncodedImage(
+        IpcSharedMemory,
+        EncodedImageType,
+        ImageOrientation,
+        Rect<f64>,
+        Rect<f64>,
+        bool,
+        ShadowOptions,
         Transform2D<f32>,
     ),
     DrawEmptyImage(
@@ -469,7 +935,6 @@ pub enum Canvas2dMsg {
         Rect<f64>,
         Rect<f64>,
         ShadowOptions,
-        CompositionOptions,
         Transform2D<f32>,
     ),
     DrawImageInOther(
@@ -478,59 +943,819 @@ pub enum Canvas2dMsg {
         Rect<f64>,
         bool,
         ShadowOptions,
-        CompositionOptions,
         Transform2D<f32>,
     ),
+    /// Draw the first snapshot into a rectangle of the canvas, with each of its pixels made
+    /// as opaque as the pixel at the same place of the second, for stamping with soft edges.
+    /// Nothing is drawn if the snapshots aren't the same size.
+    CompositeWithMask(IpcSnapshot, IpcSnapshot, Rect<f64>, Transform2D<f32>),
     ClearRect(Rect<f32>, Transform2D<f32>),
     ClipPath(Path, FillRule, Transform2D<f32>),
     PopClips(usize),
-    FillPath(
-        FillOrStrokeStyle,
-        Path,
-        FillRule,
-        ShadowOptions,
-        CompositionOptions,
-        Transform2D<f32>,
-    ),
+    /// Drop the measurements of strings that haven't been measured since the previous
+    /// trim, and the text that the fonts of the paint thread have shaped, to bound the
+    /// memory of canvases that render a lot of different text.
+    TrimGlyphCache,
+    /// Reply with the number of entries in the caches that
+    /// [`Canvas2dMsg::TrimGlyphCache`] trims.
+    GetGlyphCacheSize(IpcSender<usize>),
+    /// Make the draw target show the given region of a larger logical canvas, in CSS
+    /// pixels, or the canvas as it is if there is none: drawing operations are offset by
+    /// the origin of the region and clipped to its size, so that a huge canvas can be
+    /// rendered one tile at a time. The clips in effect, which were in the coordinates of
+    /// the previous region, are removed. This is ignored while layers are open.
+    SetViewport(Option<Rect<f32>>),
+    /// Reply with when the pixels of the canvas last changed, or with nothing if they
+    /// haven't since it was created. Reading the pixels or measuring text doesn't count.
+    /// The time is taken once for all the changes before an update of the image of the
+    /// canvas, or before this message, whichever comes first.
+    GetLastModified(IpcSender<Option<CrossProcessInstant>>),
+    /// Replace the clips in effect with the path, or remove them all if there is none,
+    /// rather than intersect them with it like [`Canvas2dMsg::ClipPath`]. The clips of the
+    /// draw targets of layers that are open below the innermost are kept.
+    SetClip(Option<Path>, FillRule, Transform2D<f32>),
+    /// Replace the clips in effect with a circle of a center and radius in user space, as
+    /// [`Canvas2dMsg::SetClip`] would with its path, for wipes that reveal a canvas from a
+    /// point. A radius of zero clips everything out, and a radius large enough for the
+    /// circle to cover the whole canvas removes the clips.
+    SetRadialClip(Point2D<f64>, f64, FillRule, Transform2D<f32>),
+    /// Reply with the bounding box, in device pixels, of the intersection of the clips in
+    /// effect, or with the bounds of the canvas if there are none.
+    GetClipBounds(IpcSender<Rect<f32>>),
+    /// Reply with the signed winding number of the path around a point in user space, as
+    /// used by the nonzero fill rule. This is a diagnostic for how a path will be filled.
+    GetWindingNumber(Path, f64, f64, IpcSender<i32>),
+    FillPath(Path, FillRule, ShadowOptions, Transform2D<f32>),
+    /// Like [`Canvas2dMsg::FillPath`], but the subpaths of the path with the given indices,
+    /// counted from zero, are holes: whatever the fill rule and their winding, they are cut
+    /// out of the fill of the others. Each hole is itself filled with the even-odd rule.
+    FillPathWithHoles(Path, Vec<usize>, FillRule, ShadowOptions, Transform2D<f32>),
     FillText(
         String,
         f64,
         f64,
         Option<f64>,
-        FillOrStrokeStyle,
         bool,
         TextOptions,
         ShadowOptions,
-        CompositionOptions,
         Transform2D<f32>,
     ),
-    FillRect(
-        Rect<f32>,
-        FillOrStrokeStyle,
-        ShadowOptions,
-        CompositionOptions,
-        Transform2D<f32>,
-    ),
-    GetImageData(Option<Rect<u32>>, IpcSender<IpcSnapshot>),
-    MeasureText(String, IpcSender<TextMetrics>, TextOptions),
-    PutImageData(Rect<u32>, IpcSnapshot),
-    StrokeRect(
-        Rect<f32>,
-        FillOrStrokeStyle,
-        LineOptions,
-        ShadowOptions,
-        CompositionOptions,
-        Transform2D<f32>,
+    FillRect(Rect<f32>, ShadowOptions, Transform2D<f32>),
+    /// Read back the pixels of the canvas. They reflect all of the drawing messages sent
+    /// before this one and none sent after it.
+    GetImageData(Option<Rect<u32>>, ColorEncoding, IpcSender<IpcSnapshot>),
+    /// Like [`Canvas2dMsg::GetImageData`], but sends the pixels as horizontal
+    /// [`ImageDataTile`]s of at most the given number of rows, from top to bottom, so that
+    /// neither side has to hold more than one tile besides the result.
+    GetImageDataInTiles(
+        Option<Rect<u32>>,
+        ColorEncoding,
+        u32,
+        IpcSender<ImageDataTile>,
     ),
-    StrokePath(
-        Path,
-        FillOrStrokeStyle,
-        LineOptions,
-        ShadowOptions,
-        CompositionOptions,
-        Transform2D<f32>,
+    /// Like [`Canvas2dMsg::GetImageData`], but reply with the pixels converted to each of
+    /// the given color spaces, in order, from a single read of the canvas.
+    GetImageDataInColorSpaces(
+        Option<Rect<u32>>,
+        Vec<PredefinedColorSpace>,
+        IpcSender<Vec<IpcSnapshot>>,
     ),
-    UpdateImage(IpcSender<()>),
+    /// Like [`Canvas2dMsg::GetImageData`], but reply with the pixels as floats, for
+    /// consumers that compute with them. Colors are unpremultiplied and decoded to
+    /// `ColorEncoding` in floating point, without rounding to 8 bits in between.
+    GetImageDataAsFloat(Option<Rect<u32>>, ColorEncoding, IpcSender<FloatImageData>),
+    /// Like [`Canvas2dMsg::GetImageData`], but reply with only the alpha channel of the
+    /// pixels within the rectangle, for consumers that extract masks. This sends a quarter
+    /// of the bytes of the full pixels.
+    GetAlphaMask(Rect<u32>, IpcSender<AlphaMask>),
+    /// Like [`Canvas2dMsg::GetImageData`], but reply with non-premultiplied RGBA rows that
+    /// are each padded to a multiple of the given alignment in bytes, as `readPixels()`
+    /// packs them with `GL_PACK_ALIGNMENT`, so that WebGL can upload them as they are.
+    /// The alignment is 1, 2, 4 or 8; any other is taken as 1.
+    GetImageDataAligned(Option<Rect<u32>>, u32, IpcSender<AlignedImageData>),
+    /// Read back the non-premultiplied RGBA color of a single pixel, which is transparent
+    /// black outside of the canvas. This is cheaper than [`Canvas2dMsg::GetImageData`].
+    GetPixel(i32, i32, IpcSender<[u8; 4]>),
+    /// Reply with the histogram of the pixels of the canvas within the rectangle, clipped to
+    /// the canvas. This spares sending the pixels just to count them.
+    GetHistogram(Rect<u32>, IpcSender<CanvasHistogram>),
+    /// Reply with the metrics of text in user units, which the transform of the context and
+    /// the resolution of the canvas have no effect on. The message carries no transform.
+    MeasureText(String, IpcSender<TextMetrics>, bool, TextOptions),
+    /// Break text into lines no wider than a maximum width, breaking after spaces, and
+    /// reply with the byte offset at which each line after the first starts. A word that
+    /// is wider than the maximum width on its own gets a line of its own.
+    WrapText(String, f64, IpcSender<Vec<usize>>, bool, TextOptions),
+    /// Set the font families, in order, that text falls back to for characters missing from
+    /// the fonts of its own families, before the fallback fonts of the platform.
+    SetFallbackFontFamilies(Vec<String>),
+    /// Set the gamma with which the antialiased coverage of filled text is corrected: the
+    /// alpha of the glyphs, as rasterized, is raised to the power `1 / gamma` before they
+    /// are composited. Values above 1 make text heavier, values below 1 lighter. The
+    /// default, 1, leaves the coverage untouched. Values that aren't finite and positive
+    /// are ignored.
+    SetTextGamma(f32),
+    /// Like [`Canvas2dMsg::SetTextGamma`], for the edges of filled and stroked paths and
+    /// rectangles. Shapes drawn with a shadow are left uncorrected, as correcting their
+    /// coverage would distort the shadow.
+    SetShapeGamma(f32),
+    /// Set how colors outside of the sRGB gamut of the canvas are brought into it, for the
+    /// styles of the messages processed from then on.
+    SetGamutMapping(GamutMapping),
+    /// Set whether the colors of gradients are interpolated with their alpha premultiplied,
+    /// for the messages processed from then on.
+    SetGradientInterpolation(GradientInterpolation),
+    /// Set the largest shadow blur that is applied, in the units of the `shadowBlur`
+    /// attribute. Larger blurs are clamped to it with a warning, as the time that blurring
+    /// takes grows with the blur. The default, 256, leaves the blurs of ordinary drawing
+    /// alone. Negative values are ignored.
+    SetMaxShadowBlur(f64),
+    /// Set whether the widths of the lines of [`Canvas2dMsg::StrokeRect`] and
+    /// [`Canvas2dMsg::StrokePath`] are in device pixels, unscaled by their transform, so
+    /// that lines keep their thickness under zoom. By default, as in the specification,
+    /// they are in user units.
+    SetDevicePixelLineWidths(bool),
+    PutImageData(Rect<u32>, ColorEncoding, IpcSnapshot),
+    /// Copy the pixels of the canvas within the rectangle, clipped to the canvas, and keep
+    /// them in the paint thread, replying with a handle to them. Unlike
+    /// [`Canvas2dMsg::GetImageData`], this neither converts the pixels nor sends them.
+    SaveRegion(Rect<u32>, IpcSender<SavedRegionId>),
+    /// Copy the pixels kept by [`Canvas2dMsg::SaveRegion`] back with their top left corner
+    /// at the origin of the rectangle, and no further than its size. They replace the
+    /// pixels there whatever the drawing state, without compositing or shadows.
+    RestoreRegion(Rect<u32>, SavedRegionId),
+    /// Discard the pixels kept by [`Canvas2dMsg::SaveRegion`].
+    ReleaseRegion(SavedRegionId),
+    /// Start recording what the following messages draw, so that it can be undone with
+    /// [`Canvas2dMsg::RollbackTransaction`]. The pixels of the canvas are only copied once
+    /// something changes them. A transaction that is already open is kept.
+    BeginTransaction,
+    /// Keep what was drawn since [`Canvas2dMsg::BeginTransaction`] and end the transaction.
+    CommitTransaction,
+    /// Restore the pixels of the canvas to what they were at
+    /// [`Canvas2dMsg::BeginTransaction`], dropping the layers begun since, and end the
+    /// transaction. Drawing state such as the clip is left as it is.
+    RollbackTransaction,
+    /// Replace the whole contents of the canvas with a frame from an external producer,
+    /// such as a video decoder, scaled to the size of the canvas, and present it to the
+    /// compositor right away.
+    PresentFrame(IpcSnapshot),
+    /// Set how the image of the canvas should be filtered when the compositor presents it
+    /// at a size other than that of its backing store, such as nearest-neighbor scaling for
+    /// pixel art.
+    SetImageRendering(ImageRendering),
+    /// Set whether the pixels of the image that the compositor presents are premultiplied by
+    /// their alpha, as WebRender expects and as they are by default, or not, for compositors
+    /// that premultiply them themselves. This applies from the next update of the image.
+    SetPremultipliedUpload(bool),
+    SetFillStyle(FillOrStrokeStyle),
+    SetStrokeStyle(FillOrStrokeStyle),
+    /// Replace the colors of the stops of the gradient last set with
+    /// [`Canvas2dMsg::SetFillStyle`], in order, keeping its geometry and the offsets of its
+    /// stops. This spares animations that only cycle the colors of a gradient from sending
+    /// the whole gradient every frame. It is ignored if the fill style is not a gradient
+    /// with as many stops as there are colors.
+    SetFillGradientStopColors(Vec<AbsoluteColor>),
+    /// Like [`Canvas2dMsg::SetFillGradientStopColors`], for the stroke style.
+    SetStrokeGradientStopColors(Vec<AbsoluteColor>),
+    SetGlobalAlpha(f64),
+    SetGlobalCompositeOperation(CompositionOrBlending),
+    StrokeRect(Rect<f32>, LineOptions, ShadowOptions, Transform2D<f32>),
+    StrokePath(Path, LineOptions, ShadowOptions, Transform2D<f32>),
+    /// Set whether the canvas draws in low-power mode, trading quality for less work on the
+    /// CPU: images are not smoothed, clips to rectangles that are axis-aligned in device space
+    /// are rounded to whole pixels rather than antialiased, and shadows are blurred with a
+    /// cheaper approximation of a Gaussian. Other clips are antialiased as usual. The mode is
+    /// off by default.
+    SetLowPowerMode(bool),
+    /// Set the image smoothing state of the context, which is kept to be reported by
+    /// [`Canvas2dMsg::GetRenderingState`]. Drawing images still smooths them as each of
+    /// their messages says.
+    SetImageSmoothing(bool, ImageSmoothingQuality),
+    /// Set how the glyphs of the text that the canvas draws are hinted. Tests can turn
+    /// hinting off so that text renders the same regardless of the platform.
+    SetGlyphHinting(GlyphHinting),
+    /// Set whether the baseline and the origins of the glyphs of the text that the canvas
+    /// draws are rounded to device pixels, which keeps text crisp, rather than positioned
+    /// at fractions of pixels, as they are by default.
+    SetTextPixelSnapping(bool),
+    /// Set how the edges of the rectangles that the canvas fills and strokes are rounded
+    /// to device pixels, after they are transformed, so that they line up with the pixel
+    /// grid. They aren't rounded by default.
+    SetCoordinateRounding(CoordinateRounding),
+    /// Reply with the settings that decide how smoothly the canvas renders.
+    GetRenderingState(IpcSender<RenderingState>),
+    /// Stroke a focus ring along the path, in the style last set with
+    /// [`Canvas2dMsg::SetFocusRingStyle`], for elements of the canvas that have focus.
+    DrawFocusRing(Path, Transform2D<f32>),
+    SetFocusRingStyle(FocusRingStyle),
+    /// Whether consecutive opaque solid-color rectangle fills of the same color may be
+    /// merged into a single fill. Translucent operations are never reordered.
+    SetBatchOpaqueFills(bool),
+    /// Dither subsequent gradient fills with noise derived from the given seed, which
+    /// reduces banding while rendering identically every time, preserving their mean color
+    /// in the given space. `None` disables dithering.
+    SetGradientDither(Option<u64>, DitherSpace),
+    /// Set the filter chain applied to subsequent drawing operations, in order.
+    SetFilter(Vec<FilterFunction>),
+    BlurRegion(Rect<u32>, f64),
+    /// Apply a 4x5 color matrix, given row by row in the form of SVG `feColorMatrix`, to the
+    /// pixels of the canvas within the rectangle, in device pixels.
+    ApplyColorMatrix(Rect<u32>, [f32; 20]),
+    /// Multiply the alpha of the pixels of the canvas within the rectangle, in device
+    /// pixels, by a factor between 0 and 1, leaving their colors as they are. This fades a
+    /// region in place, without redrawing it with a global alpha.
+    FadeRegion(Rect<u32>, f64),
+    /// Reduce each color channel of the pixels of the canvas within the rectangle, in device
+    /// pixels, to the number of evenly spaced levels, in place. Fewer than two levels are
+    /// taken as two.
+    Posterize(Rect<u32>, u32),
+    /// Mirror the pixels of the whole canvas in place, ignoring the drawing state.
+    FlipSurface(FlipAxis),
+    /// Write the pixels of the canvas, as they would be read back, to a PNG file at the
+    /// path, to capture a misbehaving canvas without changing the script that draws it. Only
+    /// debug builds do this, and failures are logged.
+    DebugDump(PathBuf),
+    /// Redirect drawing into a new, transparent layer until the matching
+    /// [`Canvas2dMsg::EndLayer`], which composites the whole layer with these options.
+    /// Layers can be nested.
+    BeginLayer(CompositionOptions),
+    EndLayer,
+    /// Reply with the memory held by the pixels of the canvas.
+    GetMemoryUsage(IpcSender<CanvasMemoryUsage>),
+    /// Reply with the [`CanvasDrawStats`] accumulated since they were last reset.
+    GetDrawStats(IpcSender<CanvasDrawStats>),
+    ResetDrawStats,
+    /// Reply with where the latest drawing operations of each class ran.
+    GetAcceleration(IpcSender<CanvasAcceleration>),
+    /// Reply with whether the bounding box of the last drawing operation, once transformed,
+    /// lay entirely outside of the canvas, which usually points to wrong coordinates.
+    GetLastDrawOutOfBounds(IpcSender<bool>),
+    /// Reply with whether the text of the last [`Canvas2dMsg::FillText`] was wider than its
+    /// maximum width, so that it was condensed to fit, or overflowed it if it could not be.
+    /// Pages can use this to mark truncated text, as with an ellipsis.
+    GetLastTextTruncated(IpcSender<bool>),
+    /// Reply with whether the alpha of every pixel of the canvas is 255, so that the
+    /// compositor can draw it without blending it with what is behind it.
+    IsFullyOpaque(IpcSender<bool>),
+    /// Whether [`Canvas2dMsg::UpdateImage`] defers sending the pixels of the canvas to
+    /// WebRender until the compositor next presents a frame, as signalled by
+    /// [`crate::ConstellationCanvasMsg::Vsync`]. Updates requested in between are coalesced.
+    SetFramePacing(bool),
+    /// Release the memory held by the surface of the canvas, discarding its pixels, as when
+    /// they were reset to transparent black anyway. Until the surface is allocated again,
+    /// cleared to transparent black, by the next message that uses it, the canvas shows a
+    /// transparent placeholder. Canvases with clips or layers in effect keep their surface.
+    ReclaimSurface,
+    /// Reply with the number of messages processed for the canvas so far, not counting
+    /// those that expect a reply, as queries like this one do. The pixels of the canvas
+    /// reflect at least that many messages.
+    GetSequence(IpcSender<u64>),
+    /// Reply with the number of times the canvas was recreated. The id of a canvas stays
+    /// the same when it is recreated while its surface does not, so caches of its contents
+    /// can tell them apart by this generation.
+    GetGeneration(IpcSender<u64>),
+    /// Update the image of the canvas in WebRender, then reply. With frame pacing, the
+    /// update waits for the next vsync unless it is sent immediately, as when the flag is
+    /// set for interactions such as dragging that need the latest frame shown now.
+    UpdateImage(IpcSender<()>, bool),
+    /// Process the wrapped message, then reply with the [`CanvasError`] that it raised, if
+    /// any, instead of only logging it. Like other messages that expect a reply, it is
+    /// processed even while the canvas is paused, after the messages buffered before it. The
+    /// wrapped message counts once towards [`Canvas2dMsg::GetSequence`].
+    ReportErrors(Box<Canvas2dMsg>, IpcSender<Result<(), CanvasError>>),
+}
+
+impl Canvas2dMsg {
+    /// The transform from the canvas coordinate space to CSS pixels that this message
+    /// draws with, if it draws.
+    pub fn transform_mut(&mut self) -> Option<&mut Transform2D<f32>> {
+        match self {
+            Canvas2dMsg::DrawImage(.., transform) |
+            Canvas2dMsg::DrawImageRounded(.., transform) |
+            Canvas2dMsg::DrawEncodedImage(.., transform) |
+            Canvas2dMsg::DrawEmptyImage(.., transform) |
+            Canvas2dMsg::DrawImageInOther(.., transform) |
+            Canvas2dMsg::CompositeWithMask(.., transform) |
+            Canvas2dMsg::ClearRect(_, transform) |
+            Canvas2dMsg::ClipPath(.., transform) |
+            Canvas2dMsg::SetClip(.., transform) |
+            Canvas2dMsg::SetRadialClip(.., transform) |
+            Canvas2dMsg::DrawFocusRing(_, transform) |
+            Canvas2dMsg::FillPath(.., transform) |
+            Canvas2dMsg::FillPathWithHoles(.., transform) |
+            Canvas2dMsg::FillText(.., transform) |
+            Canvas2dMsg::FillRect(.., transform) |
+            Canvas2dMsg::StrokeRect(.., transform) |
+            Canvas2dMsg::StrokePath(.., transform) => Some(transform),
+            _ => None,
+        }
+    }
+
+    /// Whether this message carries a sender that expects a reply, so that it can't wait
+    /// for a paused canvas to resume. [`Canvas2dMsg::ReportErrors`] does, whatever the
+    /// message it wraps.
+    pub fn expects_reply(&self) -> bool {
+        match self {
+            Canvas2dMsg::GetGlyphCacheSize(..) |
+            Canvas2dMsg::GetLastModified(..) |
+            Canvas2dMsg::GetClipBounds(..) |
+            Canvas2dMsg::GetWindingNumber(..) |
+            Canvas2dMsg::GetImageData(..) |
+            Canvas2dMsg::GetImageDataInTiles(..) |
+            Canvas2dMsg::GetImageDataInColorSpaces(..) |
+            Canvas2dMsg::GetImageDataAsFloat(..) |
+            Canvas2dMsg::GetAlphaMask(..) |
+            Canvas2dMsg::GetImageDataAligned(..) |
+            Canvas2dMsg::GetPixel(..) |
+            Canvas2dMsg::GetHistogram(..) |
+            Canvas2dMsg::MeasureText(..) |
+            Canvas2dMsg::WrapText(..) |
+            Canvas2dMsg::SaveRegion(..) |
+            Canvas2dMsg::GetRenderingState(..) |
+            Canvas2dMsg::GetMemoryUsage(..) |
+            Canvas2dMsg::GetDrawStats(..) |
+            Canvas2dMsg::GetAcceleration(..) |
+            Canvas2dMsg::GetLastDrawOutOfBounds(..) |
+            Canvas2dMsg::GetLastTextTruncated(..) |
+            Canvas2dMsg::IsFullyOpaque(..) |
+            Canvas2dMsg::GetSequence(..) |
+            Canvas2dMsg::GetGeneration(..) |
+            Canvas2dMsg::UpdateImage(..) |
+            Canvas2dMsg::ReportErrors(..) => true,
+            Canvas2dMsg::DrawImage(..) |
+            Canvas2dMsg::DrawImageRounded(..) |
+            Canvas2dMsg::DrawEncodedImage(..) |
+            Canvas2dMsg::DrawEmptyImage(..) |
+            Canvas2dMsg::DrawImageInOther(..) |
+            Canvas2dMsg::CompositeWithMask(..) |
+            Canvas2dMsg::ClearRect(..) |
+            Canvas2dMsg::ClipPath(..) |
+            Canvas2dMsg::PopClips(..) |
+            Canvas2dMsg::TrimGlyphCache |
+            Canvas2dMsg::SetViewport(..) |
+            Canvas2dMsg::SetClip(..) |
+            Canvas2dMsg::SetRadialClip(..) |
+            Canvas2dMsg::FillPath(..) |
+            Canvas2dMsg::FillPathWithHoles(..) |
+            Canvas2dMsg::FillText(..) |
+            Canvas2dMsg::FillRect(..) |
+            Canvas2dMsg::SetFallbackFontFamilies(..) |
+            Canvas2dMsg::SetTextGamma(..) |
+            Canvas2dMsg::SetShapeGamma(..) |
+            Canvas2dMsg::SetGamutMapping(..) |
+            Canvas2dMsg::SetGradientInterpolation(..) |
+            Canvas2dMsg::SetMaxShadowBlur(..) |
+            Canvas2dMsg::SetDevicePixelLineWidths(..) |
+            Canvas2dMsg::PutImageData(..) |
+            Canvas2dMsg::RestoreRegion(..) |
+            Canvas2dMsg::ReleaseRegion(..) |
+            Canvas2dMsg::BeginTransaction |
+            Canvas2dMsg::CommitTransaction |
+            Canvas2dMsg::RollbackTransaction |
+            Canvas2dMsg::PresentFrame(..) |
+            Canvas2dMsg::SetImageRendering(..) |
+            Canvas2dMsg::SetPremultipliedUpload(..) |
+            Canvas2dMsg::SetFillStyle(..) |
+            Canvas2dMsg::SetStrokeStyle(..) |
+            Canvas2dMsg::SetFillGradientStopColors(..) |
+            Canvas2dMsg::SetStrokeGradientStopColors(..) |
+            Canvas2dMsg::SetGlobalAlpha(..) |
+            Canvas2dMsg::SetGlobalCompositeOperation(..) |
+            Canvas2dMsg::StrokeRect(..) |
+            Canvas2dMsg::StrokePath(..) |
+            Canvas2dMsg::SetLowPowerMode(..) |
+            Canvas2dMsg::SetImageSmoothing(..) |
+            Canvas2dMsg::SetGlyphHinting(..) |
+            Canvas2dMsg::SetTextPixelSnapping(..) |
+            Canvas2dMsg::SetCoordinateRounding(..) |
+            Canvas2dMsg::DrawFocusRing(..) |
+            Canvas2dMsg::SetFocusRingStyle(..) |
+            Canvas2dMsg::SetBatchOpaqueFills(..) |
+            Canvas2dMsg::SetGradientDither(..) |
+            Canvas2dMsg::SetFilter(..) |
+            Canvas2dMsg::BlurRegion(..) |
+            Canvas2dMsg::ApplyColorMatrix(..) |
+            Canvas2dMsg::FadeRegion(..) |
+            Canvas2dMsg::Posterize(..) |
+            Canvas2dMsg::FlipSurface(..) |
+            Canvas2dMsg::DebugDump(..) |
+            Canvas2dMsg::BeginLayer(..) |
+            Canvas2dMsg::EndLayer |
+            Canvas2dMsg::ResetDrawStats |
+            Canvas2dMsg::SetFramePacing(..) |
+            Canvas2dMsg::ReclaimSurface => false,
+        }
+    }
+
+    /// Whether this message reads or writes the pixels of the surface of the canvas, or the
+    /// clips and layers that belong to it, so that a surface that was not allocated yet, or
+    /// was reclaimed, has to be allocated before it is processed. Messages that only set
+    /// drawing state, or query it, leave the surface as it is.
+    pub fn uses_surface(&self) -> bool {
+        match self {
+            Canvas2dMsg::DrawImage(..) |
+            Canvas2dMsg::DrawImageRounded(..) |
+            Canvas2dMsg::DrawEncodedImage(..) |
+            Canvas2dMsg::DrawEmptyImage(..) |
+            Canvas2dMsg::DrawImageInOther(..) |
+            Canvas2dMsg::CompositeWithMask(..) |
+            Canvas2dMsg::ClearRect(..) |
+            Canvas2dMsg::ClipPath(..) |
+            Canvas2dMsg::PopClips(..) |
+            Canvas2dMsg::SetViewport(..) |
+            Canvas2dMsg::SetClip(..) |
+            Canvas2dMsg::SetRadialClip(..) |
+            Canvas2dMsg::FillPath(..) |
+            Canvas2dMsg::FillPathWithHoles(..) |
+            Canvas2dMsg::FillText(..) |
+            Canvas2dMsg::FillRect(..) |
+            Canvas2dMsg::GetImageData(..) |
+            Canvas2dMsg::GetImageDataInTiles(..) |
+            Canvas2dMsg::GetImageDataInColorSpaces(..) |
+            Canvas2dMsg::GetImageDataAsFloat(..) |
+            Canvas2dMsg::GetAlphaMask(..) |
+            Canvas2dMsg::GetImageDataAligned(..) |
+            Canvas2dMsg::GetPixel(..) |
+            Canvas2dMsg::GetHistogram(..) |
+            Canvas2dMsg::PutImageData(..) |
+            Canvas2dMsg::SaveRegion(..) |
+            Canvas2dMsg::RestoreRegion(..) |
+            Canvas2dMsg::BeginTransaction |
+            Canvas2dMsg::CommitTransaction |
+            Canvas2dMsg::RollbackTransaction |
+            Canvas2dMsg::PresentFrame(..) |
+            Canvas2dMsg::StrokeRect(..) |
+            Canvas2dMsg::StrokePath(..) |
+            Canvas2dMsg::DrawFocusRing(..) |
+            Canvas2dMsg::BlurRegion(..) |
+            Canvas2dMsg::ApplyColorMatrix(..) |
+            Canvas2dMsg::FadeRegion(..) |
+            Canvas2dMsg::Posterize(..) |
+            Canvas2dMsg::FlipSurface(..) |
+            Canvas2dMsg::DebugDump(..) |
+            Canvas2dMsg::BeginLayer(..) |
+            Canvas2dMsg::EndLayer => true,
+            Canvas2dMsg::TrimGlyphCache |
+            Canvas2dMsg::GetGlyphCacheSize(..) |
+            Canvas2dMsg::GetLastModified(..) |
+            Canvas2dMsg::GetClipBounds(..) |
+            Canvas2dMsg::GetWindingNumber(..) |
+            Canvas2dMsg::MeasureText(..) |
+            Canvas2dMsg::WrapText(..) |
+            Canvas2dMsg::SetFallbackFontFamilies(..) |
+            Canvas2dMsg::SetTextGamma(..) |
+            Canvas2dMsg::SetShapeGamma(..) |
+            Canvas2dMsg::SetGamutMapping(..) |
+            Canvas2dMsg::SetGradientInterpolation(..) |
+            Canvas2dMsg::SetMaxShadowBlur(..) |
+            Canvas2dMsg::SetDevicePixelLineWidths(..) |
+            Canvas2dMsg::ReleaseRegion(..) |
+            Canvas2dMsg::SetImageRendering(..) |
+            Canvas2dMsg::SetPremultipliedUpload(..) |
+            Canvas2dMsg::SetFillStyle(..) |
+            Canvas2dMsg::SetStrokeStyle(..) |
+            Canvas2dMsg::SetFillGradientStopColors(..) |
+            Canvas2dMsg::SetStrokeGradientStopColors(..) |
+            Canvas2dMsg::SetGlobalAlpha(..) |
+            Canvas2dMsg::SetGlobalCompositeOperation(..) |
+            Canvas2dMsg::SetLowPowerMode(..) |
+            Canvas2dMsg::SetImageSmoothing(..) |
+            Canvas2dMsg::SetGlyphHinting(..) |
+            Canvas2dMsg::SetTextPixelSnapping(..) |
+            Canvas2dMsg::SetCoordinateRounding(..) |
+            Canvas2dMsg::GetRenderingState(..) |
+            Canvas2dMsg::SetFocusRingStyle(..) |
+            Canvas2dMsg::SetBatchOpaqueFills(..) |
+            Canvas2dMsg::SetGradientDither(..) |
+            Canvas2dMsg::SetFilter(..) |
+            Canvas2dMsg::GetMemoryUsage(..) |
+            Canvas2dMsg::GetDrawStats(..) |
+            Canvas2dMsg::ResetDrawStats |
+            Canvas2dMsg::GetAcceleration(..) |
+            Canvas2dMsg::GetLastDrawOutOfBounds(..) |
+            Canvas2dMsg::GetLastTextTruncated(..) |
+            Canvas2dMsg::IsFullyOpaque(..) |
+            Canvas2dMsg::SetFramePacing(..) |
+            Canvas2dMsg::ReclaimSurface |
+            Canvas2dMsg::GetSequence(..) |
+            Canvas2dMsg::GetGeneration(..) |
+            Canvas2dMsg::UpdateImage(..) => false,
+            Canvas2dMsg::ReportErrors(message, _) => message.uses_surface(),
+        }
+    }
+}
+
+/// The memory held by the pixels of one or more canvases, in bytes.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub struct CanvasMemoryUsage {
+    /// The surfaces that the canvases draw to.
+    pub surface: usize,
+    /// Buffers kept between drawing operations, such as the scratch surface for shadows.
+    pub auxiliary: usize,
+}
+
+impl CanvasMemoryUsage {
+    pub fn total(&self) -> usize {
+        self.surface + self.auxiliary
+    }
+}
+
+impl Add for CanvasMemoryUsage {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            surface: self.surface + other.surface,
+            auxiliary: self.auxiliary + other.auxiliary,
+        }
+    }
+}
+
+impl Sum for CanvasMemoryUsage {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), Add::add)
+    }
+}
+
+/// A horizontal band of the pixels read back for [`Canvas2dMsg::GetImageDataInTiles`].
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ImageDataTile {
+    /// The size of the whole image that the tiles make up.
+    pub image_size: Size2D<u32>,
+    /// The row of the image that this tile starts at.
+    pub y: u32,
+    pub pixels: IpcSnapshot,
+}
+
+impl ImageDataTile {
+    /// Receives every tile sent for a [`Canvas2dMsg::GetImageDataInTiles`] from `receiver`
+    /// and reassembles them into a single snapshot.
+    pub fn receive_image(receiver: &IpcReceiver<ImageDataTile>) -> Result<Snapshot, IpcError> {
+        let mut data = Vec::new();
+        loop {
+            let tile = receiver.recv()?;
+            let row_length = tile.image_size.width as usize * 4;
+            if data.is_empty() {
+                data = vec![0; tile.image_size.height as usize * row_length];
+            }
+            let start = tile.y as usize * row_length;
+            let tile_data = tile.pixels.data();
+            data[start..start + tile_data.len()].copy_from_slice(tile_data);
+
+            if tile.y + tile.pixels.size().height >= tile.image_size.height {
+                return Ok(Snapshot::from_vec(
+                    tile.image_size,
+                    tile.pixels.format(),
+                    tile.pixels.alpha_mode(),
+                    data,
+                ));
+            }
+        }
+    }
+}
+
+/// The pixels read back for [`Canvas2dMsg::GetImageDataAsFloat`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct FloatImageData {
+    pub size: Size2D<u32>,
+    /// The non-premultiplied RGBA channels of each pixel, row by row. Channels are
+    /// normalized so that the range of an 8-bit surface maps to 0 to 1.
+    pub pixels: Vec<f32>,
+}
+
+/// The pixels read back for [`Canvas2dMsg::GetAlphaMask`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AlphaMask {
+    pub size: Size2D<u32>,
+    /// The alpha of each pixel, row by row.
+    pub alpha: Vec<u8>,
+}
+
+/// The pixels read back for [`Canvas2dMsg::GetImageDataAligned`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AlignedImageData {
+    pub size: Size2D<u32>,
+    /// The number of bytes from the start of one row to the start of the next.
+    pub stride: usize,
+    /// The non-premultiplied RGBA pixels, row by row, each row followed by zeros up to
+    /// the stride.
+    pub data: Vec<u8>,
+}
+
+/// Counts of the drawing operations performed on a canvas, which pages can use to
+/// profile their own rendering.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub struct CanvasDrawStats {
+    pub fills: u64,
+    pub strokes: u64,
+    pub images: u64,
+    pub texts: u64,
+    /// The number of rectangles cleared, not counting clears skipped because they repeated
+    /// the clear of the previous message.
+    pub clears: u64,
+    /// The number of times the pixels of the canvas were sent to WebRender.
+    pub image_uploads: u64,
+    /// The sum of the areas, in device pixels, of the bounding boxes of the operations
+    /// above, clipped to the canvas.
+    pub pixels_touched: u64,
+    /// The number of shadows blurred with a cheaper approximation of a Gaussian blur, as in
+    /// low-power mode.
+    pub approximated_shadows: u64,
+    /// The number of operations that could be coalesced: opaque rectangle fills, which are
+    /// batched, and clears, which are skipped when they repeat the one before.
+    pub submitted_operations: u64,
+    /// The number of backend operations that the operations above were executed as, fewer
+    /// than them by as many as coalescing saved.
+    pub executed_operations: u64,
+    /// The number of bytes that weren't sent to WebRender because the updates of the image
+    /// requested between two vsyncs were sent as one.
+    pub upload_bytes_saved: u64,
+    /// The number of times the surface that shadow passes draw into was allocated, rather
+    /// than reused from an earlier pass.
+    pub scratch_allocations: u64,
+}
+
+/// Whether drawing ran on the GPU or on the CPU.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub enum Acceleration {
+    Software,
+    Hardware,
+}
+
+/// Where the latest drawing operation of each class ran on a canvas, as for
+/// [`Canvas2dMsg::GetAcceleration`], to find unexpected fallbacks to the CPU. The classes
+/// that weren't drawn yet report where the backend draws them.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub struct CanvasAcceleration {
+    pub fills: Acceleration,
+    pub strokes: Acceleration,
+    pub images: Acceleration,
+    pub texts: Acceleration,
+    /// The blurring of shadows, which is always done on the CPU.
+    pub shadows: Acceleration,
+    /// The filters and blending spaces that operations are drawn through, which are always
+    /// applied on the CPU.
+    pub filters: Acceleration,
+}
+
+/// The settings that decide how smoothly a canvas renders, as for
+/// [`Canvas2dMsg::GetRenderingState`].
+#[derive(Clone, Copy, Debug, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub struct RenderingState {
+    pub image_smoothing_enabled: bool,
+    pub image_smoothing_quality: ImageSmoothingQuality,
+    /// How glyphs are hinted, as set with [`Canvas2dMsg::SetGlyphHinting`].
+    pub glyph_hinting: GlyphHinting,
+}
+
+/// The number of pixels of a region of a canvas with each value of each of their
+/// non-premultiplied channels, as for [`Canvas2dMsg::GetHistogram`].
+#[derive(Clone, Debug, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub struct CanvasHistogram {
+    /// The 256 bins of each channel, indexed by value.
+    pub red: Vec<u64>,
+    pub green: Vec<u64>,
+    pub blue: Vec<u64>,
+    pub alpha: Vec<u64>,
+}
+
+impl Default for CanvasHistogram {
+    fn default() -> Self {
+        Self {
+            red: vec![0; 256],
+            green: vec![0; 256],
+            blue: vec![0; 256],
+            alpha: vec![0; 256],
+        }
+    }
+}
+
+/// The transfer function used to encode the pixel data exchanged with a canvas through
+/// [`Canvas2dMsg::GetImageData`] and [`Canvas2dMsg::PutImageData`]. This is independent of
+/// the color space of the canvas.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub enum ColorEncoding {
+    /// Channels are encoded with the sRGB transfer function, like the canvas surface.
+    #[default]
+    Srgb,
+    /// Channels are linear light values.
+    Linear,
+}
+
+/// The encoding of color channels in which a canvas composites the result of each drawing
+/// operation onto its pixels.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub enum BlendingSpace {
+    /// Channels are blended as they are stored, encoded with the sRGB transfer function, as
+    /// browsers have always done.
+    #[default]
+    Srgb,
+    /// Channels are converted to linear light before blending and back after, which gives
+    /// more accurate blends.
+    Linear,
+}
+
+/// How a canvas brings colors that lie outside of the sRGB gamut of its surface into it.
+/// <https://drafts.csswg.org/css-color-4/#gamut-mapping>
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub enum GamutMapping {
+    /// Each channel is clamped to its range on its own, as CSS currently does, which can
+    /// shift the hue of the color.
+    #[default]
+    Clip,
+    /// The color is desaturated towards the gray of the same luminance, in linear light,
+    /// until no channel is negative, and then darkened until none exceeds its range.
+    Scale,
+    /// The chroma of the color is reduced in Oklch, keeping its lightness and hue, until
+    /// clipping what remains is imperceptible, as in the CSS gamut mapping algorithm.
+    Perceptual,
+}
+
+/// How the colors between two stops of a gradient are interpolated when the stops differ in
+/// alpha.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub enum GradientInterpolation {
+    /// The color channels are premultiplied by alpha before they are interpolated, as
+    /// browsers do, so that a gradient to transparent black doesn't darken on the way.
+    #[default]
+    Premultiplied,
+    /// The color channels and alpha are interpolated separately, which makes the colors of
+    /// transparent stops show through, as the gray fringe of a gradient to transparent
+    /// black.
+    Unpremultiplied,
+}
+
+/// The encoding of color channels in which dithering a gradient preserves its mean color.
+/// Dithering picks one of the two stored values nearest to the exact color of each pixel,
+/// so the shade that an area averages to depends on how those values are weighted.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub enum DitherSpace {
+    /// The stored values average to the exact color as it is encoded with the sRGB transfer
+    /// function.
+    #[default]
+    Srgb,
+    /// The light that the stored values emit averages to that of the exact color, so that
+    /// dithered areas look the same shade as the gradient from a distance.
+    Linear,
+}
+
+/// How a decoded image is oriented before it is drawn.
+/// <https://html.spec.whatwg.org/multipage/#imageorientation>
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub enum ImageOrientation {
+    /// The pixels are drawn as they are stored.
+    #[default]
+    None,
+    /// The pixels are rotated and flipped according to the EXIF orientation of the image.
+    FromImage,
+}
+
+/// <https://html.spec.whatwg.org/multipage/#imagesmoothingquality>
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub enum ImageSmoothingQuality {
+    #[default]
+    Low,
+    Medium,
+    High,
+}
+
+/// How coordinates are rounded to device pixels, as set with
+/// [`Canvas2dMsg::SetCoordinateRounding`].
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub enum CoordinateRounding {
+    /// Keep fractional coordinates, as the specification does.
+    #[default]
+    None,
+    /// Round to the nearest device pixel.
+    Round,
+    /// Round towards the top left of the device.
+    Floor,
+    /// Round towards the bottom right of the device.
+    Ceil,
+}
+
+/// How the outlines of glyphs are fitted to the pixel grid when text is drawn, as set
+/// with [`Canvas2dMsg::SetGlyphHinting`].
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub enum GlyphHinting {
+    /// Hint glyphs as the rendering backend does by default.
+    #[default]
+    Platform,
+    /// Draw the exact outlines, which renders text the same on every machine.
+    None,
+    /// Fit outlines to the pixel grid vertically only.
+    Light,
+    /// Fit outlines to the pixel grid in both directions.
+    Full,
+}
+
+/// A color space that the pixels of a canvas can be read back in.
+/// <https://html.spec.whatwg.org/multipage/#predefinedcolorspace>
+#[derive(Clone, Copy, Debug, Deserialize, Eq, MallocSizeOf, PartialEq, Serialize)]
+pub enum PredefinedColorSpace {
+    Srgb,
+    DisplayP3,
 }
 
 #[derive(Clone, Debug, Deserialize, MallocSizeOf, Serialize)]
@@ -546,6 +1771,9 @@ pub struct LinearGradientStyle {
     pub x1: f64,
     pub y1: f64,
     pub stops: Vec<CanvasGradientStop>,
+    /// Whether the stops repeat along the axis of the gradient past its ends, like those of
+    /// CSS `repeating-linear-gradient()`, rather than the colors at the ends extending.
+    pub repeating: bool,
 }
 
 impl LinearGradientStyle {
@@ -555,6 +1783,7 @@ impl LinearGradientStyle {
         x1: f64,
         y1: f64,
         stops: Vec<CanvasGradientStop>,
+        repeating: bool,
     ) -> LinearGradientStyle {
         LinearGradientStyle {
             x0,
@@ -562,6 +1791,7 @@ impl LinearGradientStyle {
             x1,
             y1,
             stops,
+            repeating,
         }
     }
 }
@@ -601,7 +1831,10 @@ impl RadialGradientStyle {
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SurfaceStyle {
+    /// The pixels of a tile. These may be denser than `surface_size`, for instance when the
+    /// tile of a vector image was rasterized at device resolution.
     pub surface_data: IpcSnapshot,
+    /// The size of a tile in pattern space.
     pub surface_size: Size2D<u32>,
     pub repeat_x: bool,
     pub repeat_y: bool,
@@ -624,6 +1857,19 @@ impl SurfaceStyle {
             transform,
         }
     }
+
+    /// The scale from pattern space to the pixels of `surface_data`, which is the identity
+    /// unless the tile is denser than `surface_size`.
+    pub fn surface_data_scale(&self) -> Transform2D<f32> {
+        let data_size = self.surface_data.size();
+        if data_size == self.surface_size || self.surface_size.is_empty() {
+            return Transform2D::identity();
+        }
+        Transform2D::scale(
+            data_size.width as f32 / self.surface_size.width as f32,
+            data_size.height as f32 / self.surface_size.height as f32,
+        )
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -635,6 +1881,24 @@ pub enum FillOrStrokeStyle {
 }
 
 impl FillOrStrokeStyle {
+    /// Replace the colors of the stops of this gradient, in order, keeping their offsets and
+    /// the geometry of the gradient. Returns false, leaving the style as it is, if it is not
+    /// a gradient or does not have as many stops as there are colors.
+    pub fn set_gradient_stop_colors(&mut self, colors: &[AbsoluteColor]) -> bool {
+        let stops = match self {
+            Self::LinearGradient(gradient) => &mut gradient.stops,
+            Self::RadialGradient(gradient) => &mut gradient.stops,
+            Self::Color(..) | Self::Surface(..) => return false,
+        };
+        if stops.len() != colors.len() {
+            return false;
+        }
+        for (stop, color) in stops.iter_mut().zip(colors) {
+            stop.color = *color;
+        }
+        true
+    }
+
     pub fn is_zero_size_gradient(&self) -> bool {
         match self {
             Self::RadialGradient(pattern) => {
@@ -676,21 +1940,43 @@ impl FillOrStrokeStyle {
     }
 }
 
+/// <https://html.spec.whatwg.org/multipage/#dom-context-2d-linecap>
 #[derive(
-    Clone, Copy, Debug, Display, Deserialize, EnumString, MallocSizeOf, PartialEq, Serialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Display,
+    Deserialize,
+    EnumString,
+    MallocSizeOf,
+    PartialEq,
+    Serialize,
 )]
 pub enum LineCapStyle {
+    #[default]
     Butt = 0,
     Round = 1,
     Square = 2,
 }
 
+/// <https://html.spec.whatwg.org/multipage/#dom-context-2d-linejoin>
 #[derive(
-    Clone, Copy, Debug, Deserialize, Display, EnumString, MallocSizeOf, PartialEq, Serialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    Display,
+    EnumString,
+    MallocSizeOf,
+    PartialEq,
+    Serialize,
 )]
 pub enum LineJoinStyle {
     Round = 0,
     Bevel = 1,
+    #[default]
     Miter = 2,
 }
 
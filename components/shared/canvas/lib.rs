@@ -7,10 +7,12 @@
 #![deny(unsafe_code)]
 
 use crossbeam_channel::Sender;
+use devtools_traits::TimelineMarker;
 use euclid::default::Size2D;
+use ipc_channel::ipc::IpcSender;
 use webrender_api::ImageKey;
 
-use crate::canvas::CanvasId;
+use crate::canvas::{CanvasCreationOptions, CanvasError, CanvasId, CanvasMemoryUsage};
 
 pub mod canvas;
 #[macro_use]
@@ -18,8 +20,34 @@ pub mod webgl;
 
 pub enum ConstellationCanvasMsg {
     Create {
-        sender: Sender<Option<(CanvasId, ImageKey)>>,
+        /// Where to send the id and image key of the new canvas, or why it couldn't be
+        /// created.
+        sender: Sender<Result<(CanvasId, ImageKey), CanvasError>>,
+        /// The size of the canvas in CSS pixels.
         size: Size2D<u64>,
+        options: CanvasCreationOptions,
     },
+    /// Attach (or, with `None`, detach) a consumer of timeline markers. While one is
+    /// attached, a marker spanning each processed [`canvas::Canvas2dMsg`] is sent to it.
+    SetTimelineMarkerSender(Option<IpcSender<Option<TimelineMarker>>>),
+    /// Limit (or, with `None`, stop limiting) the number of messages processed for each
+    /// canvas in a frame of the canvas thread, which starts with each [`Self::Vsync`] or
+    /// otherwise every 16 milliseconds. The messages of a canvas beyond its budget are
+    /// deferred to later frames, so that a canvas flooding the thread with work can't starve
+    /// the others.
+    SetOperationBudget(Option<usize>),
+    /// Seed (or, with `None`, stop seeding) the randomness of the canvases created by the
+    /// thread from now on, so that rendering the same content produces the same pixels.
+    /// Gradient fills of these canvases are dithered with noise derived from the seed.
+    SetRenderSeed(Option<u64>),
+    /// Reply with the memory held by the pixels of every canvas of the thread, combined.
+    GetMemoryUsage(Sender<CanvasMemoryUsage>),
+    /// Reply with the number of images that the thread keeps decoded for canvases that
+    /// draw them again with [`canvas::Canvas2dMsg::DrawEncodedImage`].
+    GetImageCacheSize(Sender<usize>),
+    /// The compositor started a frame, as forwarded by the constellation with each animation
+    /// tick while canvases pace their updates. Canvases with frame pacing enabled send the
+    /// updates to their images that were requested since the previous one.
+    Vsync,
     Exit(Sender<()>),
 }
@@ -22,11 +22,12 @@ pub mod viewport_description;
 
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use bitflags::bitflags;
 use display_list::CompositorDisplayListInfo;
 use embedder_traits::ScreenGeometry;
-use euclid::default::Size2D as UntypedSize2D;
+use euclid::default::{Rect as UntypedRect, Size2D as UntypedSize2D};
 use ipc_channel::ipc::{self, IpcSharedMemory};
 use profile_traits::mem::{OpaqueSender, ReportsChan};
 use serde::{Deserialize, Serialize};
@@ -35,7 +36,7 @@ use webrender_api::{
     BuiltDisplayList, BuiltDisplayListDescriptor, ExternalImage, ExternalImageData,
     ExternalImageHandler, ExternalImageId, ExternalImageSource, ExternalScrollId,
     FontInstanceFlags, FontInstanceKey, FontKey, ImageData, ImageDescriptor, ImageKey,
-    NativeFontHandle, PipelineId as WebRenderPipelineId,
+    ImageRendering, NativeFontHandle, PipelineId as WebRenderPipelineId,
 };
 
 use crate::viewport_description::ViewportDescription;
@@ -274,6 +275,31 @@ impl CrossProcessCompositorApi {
         self.update_images([ImageUpdate::UpdateImage(key, descriptor, data)].into());
     }
 
+    /// Like [`Self::update_image`], but also declares how the image should be filtered when
+    /// it is presented at a size other than its own, that only the pixels within the
+    /// rectangles of `damage` have changed since the previous update, and, if it was timed,
+    /// how long the producer took to draw and upload them.
+    pub fn update_image_with_rendering(
+        &self,
+        key: ImageKey,
+        descriptor: ImageDescriptor,
+        data: SerializableImageData,
+        image_rendering: ImageRendering,
+        damage: Vec<UntypedRect<u32>>,
+        frame_time: Option<Duration>,
+    ) {
+        let mut updates: SmallVec<[ImageUpdate; 1]> = [
+            ImageUpdate::UpdateImagePartially(key, descriptor, data, damage),
+            ImageUpdate::SetImageRendering(key, image_rendering),
+        ]
+        .into_iter()
+        .collect();
+        if let Some(frame_time) = frame_time {
+            updates.push(ImageUpdate::SetFrameTime(key, frame_time));
+        }
+        self.update_images(updates);
+    }
+
     pub fn delete_image(&self, key: ImageKey) {
         self.update_images([ImageUpdate::DeleteImage(key)].into());
     }
@@ -497,6 +523,21 @@ pub enum ImageUpdate {
     DeleteImage(ImageKey),
     /// Update an existing image registration.
     UpdateImage(ImageKey, ImageDescriptor, SerializableImageData),
+    /// Update an existing image registration, of which only the pixels within the
+    /// rectangles, if any, have changed since the previous update. Only the screen area
+    /// showing those pixels needs to be composited again.
+    UpdateImagePartially(
+        ImageKey,
+        ImageDescriptor,
+        SerializableImageData,
+        Vec<UntypedRect<u32>>,
+    ),
+    /// Declare how the producer of an image would like it to be filtered when it is presented
+    /// at a size other than its own.
+    SetImageRendering(ImageKey, ImageRendering),
+    /// Report how long the producer of an image took to draw and upload its latest update,
+    /// so that presentation can be paced around images that are expensive to produce.
+    SetFrameTime(ImageKey, Duration),
 }
 
 #[derive(Debug, Deserialize, Serialize)]
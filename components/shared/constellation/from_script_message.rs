@@ -12,8 +12,13 @@ use base::id::{
     BroadcastChannelRouterId, BrowsingContextId, HistoryStateId, MessagePortId,
     MessagePortRouterId, PipelineId, ServiceWorkerId, ServiceWorkerRegistrationId, WebViewId,
 };
-use canvas_traits::canvas::{CanvasId, CanvasMsg};
-use devtools_traits::{DevtoolScriptControlMsg, ScriptToDevtoolsControlMsg, WorkerId};
+use canvas_traits::canvas::{
+    CanvasCreationOptions, CanvasDrawStats, CanvasId, CanvasMemoryUsage, CanvasMsg,
+    CanvasSizeLimits, CompositionOrBlending,
+};
+use devtools_traits::{
+    DevtoolScriptControlMsg, ScriptToDevtoolsControlMsg, TimelineMarker, WorkerId,
+};
 use embedder_traits::{
     AnimationState, EmbedderMsg, FocusSequenceNumber, JSValue, JavaScriptEvaluationError,
     JavaScriptEvaluationId, MediaSessionEvent, Theme, TouchEventResult, ViewportDetails,
@@ -27,6 +32,7 @@ use net_traits::policy_container::PolicyContainer;
 use net_traits::request::{Destination, InsecureRequestsPolicy, Referrer, RequestBody};
 use net_traits::storage_thread::StorageType;
 use net_traits::{CoreResourceMsg, ReferrerPolicy, ResourceThreads};
+use pixels::{EncodedImageType, IpcSnapshot};
 use profile_traits::mem::MemoryReportResult;
 use profile_traits::{mem, time as profile_time};
 use serde::{Deserialize, Serialize};
@@ -535,8 +541,38 @@ pub enum ScriptToConstellationMessage {
     /// 2D canvases may use the GPU and we don't want to give untrusted content access to the GPU.)
     CreateCanvasPaintThread(
         UntypedSize2D<u64>,
+        CanvasCreationOptions,
         IpcSender<Option<(IpcSender<CanvasMsg>, CanvasId, ImageKey)>>,
     ),
+    /// Requests the largest canvas that the active 2D canvas backend can allocate.
+    GetMaxCanvasSize(IpcSender<CanvasSizeLimits>),
+    /// Requests the types that canvases can encode their pixels as, for feature detection of
+    /// the types passed to `toBlob` and `toDataURL`.
+    GetSupportedEncodeFormats(IpcSender<Vec<EncodedImageType>>),
+    /// Requests the names of the local font families that the text of canvases can use, so
+    /// that content can pick one rather than silently falling back to another.
+    ListAvailableFonts(IpcSender<Vec<String>>),
+    /// Attach (or, with `None`, detach) a devtools consumer of the timeline markers of the
+    /// 2D canvas thread.
+    SetCanvasTimelineMarkerSender(Option<IpcSender<Option<TimelineMarker>>>),
+    /// Requests the counts of the drawing operations performed on a 2D canvas created by
+    /// this pipeline, for pages that profile their own rendering.
+    GetCanvasDrawStats(CanvasId, IpcSender<CanvasDrawStats>),
+    /// Clears the counts of the drawing operations performed on a 2D canvas created by this
+    /// pipeline.
+    ResetCanvasDrawStats(CanvasId),
+    /// Requests the memory held by the pixels of a 2D canvas created by this pipeline.
+    GetCanvasMemoryUsage(CanvasId, IpcSender<CanvasMemoryUsage>),
+    /// Composite the overlay onto the base with the operation and global alpha on the 2D
+    /// canvas thread, without drawing to any canvas, and reply with the result, which has the
+    /// size of the base.
+    BlendSnapshots(
+        IpcSnapshot,
+        IpcSnapshot,
+        CompositionOrBlending,
+        f64,
+        IpcSender<IpcSnapshot>,
+    ),
     /// Notifies the constellation that this pipeline is requesting focus.
     ///
     /// When this message is sent, the sender pipeline has already its local
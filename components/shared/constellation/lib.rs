@@ -55,6 +55,12 @@ pub enum EmbedderToConstellationMessage {
     ChangeViewportDetails(WebViewId, ViewportDetails, WindowSizeType),
     /// Inform the constellation of a theme change.
     ThemeChange(WebViewId, Theme),
+    /// Pin the animation timelines of the documents currently in a `WebView` to a fixed time
+    /// after their creation, for deterministic rendering of animated content in tests.
+    PinAnimationTimeline(WebViewId, Duration),
+    /// Seed (or, with `None`, stop seeding) any randomness that affects rendering, so that
+    /// rendering the same content produces identical output in tests.
+    SetRenderSeed(Option<u64>),
     /// Requests that the constellation instruct script/layout to try to layout again and tick
     /// animations.
     TickAnimation(Vec<WebViewId>),
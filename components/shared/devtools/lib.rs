@@ -177,6 +177,8 @@ pub struct TimelineMarker {
 pub enum TimelineMarkerType {
     Reflow,
     DOMEvent,
+    /// The processing of each message by the canvas paint thread.
+    Canvas,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
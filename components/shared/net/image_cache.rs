@@ -204,6 +204,15 @@ pub trait ImageCache: Sync + Send {
         size: DeviceIntSize,
     ) -> Option<RasterImage>;
 
+    /// Like [`ImageCache::rasterize_vector_image`], but if the given `image_id` hasn't been
+    /// rasterized at the given `size` yet, rasterizes it on the calling thread. Returns `None`
+    /// only if the `image_id` is unknown.
+    fn rasterize_vector_image_sync(
+        &self,
+        image_id: VectorImageId,
+        size: DeviceIntSize,
+    ) -> Option<RasterImage>;
+
     /// Adds a new listener to be notified once the given `image_id` has been rasterized at
     /// the given `size`. The listener will receive a `VectorImageRasterizationComplete`
     /// message on the given `sender`, even if the listener is called after rasterization
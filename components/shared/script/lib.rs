@@ -12,6 +12,7 @@
 use std::collections::HashMap;
 use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 
 use background_hang_monitor_api::BackgroundHangMonitorRegister;
 use base::cross_process_instant::CrossProcessInstant;
@@ -133,6 +134,8 @@ pub enum ScriptThreadMessage {
     Resize(PipelineId, ViewportDetails, WindowSizeType),
     /// Theme changed.
     ThemeChange(PipelineId, Theme),
+    /// Pin the animation timeline of a document to a fixed time after its creation.
+    PinAnimationTimeline(PipelineId, Duration),
     /// Notifies script that window has been resized but to not take immediate action.
     ResizeInactive(PipelineId, ViewportDetails),
     /// Window switched from fullscreen mode.
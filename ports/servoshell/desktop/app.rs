@@ -167,6 +167,9 @@ impl App {
 
         let servo = servo_builder.build();
         servo.setup_logging();
+        if let Some(seed) = window.render_seed() {
+            servo.set_render_seed(Some(seed));
+        }
 
         // Initialize WebDriver server here before `servo` is moved.
         let webdriver_receiver = self.servoshell_preferences.webdriver_port.map(|port| {
@@ -277,7 +280,12 @@ impl App {
                 state.shutdown();
                 self.state = AppState::ShuttingDown;
             },
-            PumpResult::Continue { .. } => state.repaint_servo_if_necessary(),
+            PumpResult::Continue { .. } => {
+                state.repaint_servo_if_necessary();
+                for window in self.windows.values() {
+                    window.expire_paint_callbacks();
+                }
+            },
         }
 
         !matches!(self.state, AppState::ShuttingDown)
@@ -422,15 +430,22 @@ impl App {
                     let requested_physical_rect =
                         (requested_rect.to_f32() * scale).round().to_i32();
 
-                    // Step 17. Set Width/Height.
-                    window.request_resize(&webview, requested_physical_rect.size());
-
-                    // Step 18. Set position of the window.
+                    // Step 18. Set position of the window. This is done before resizing, as
+                    // the reply below includes the position and may be sent right away.
                     window.set_position(requested_physical_rect.min);
 
-                    if let Err(error) = size_sender.send(window.window_rect()) {
-                        warn!("Failed to send window size: {error}");
-                    }
+                    // Step 17. Set Width/Height. Only reply once the page has been painted at
+                    // the new size, so that a screenshot taken right after is not stale.
+                    let window_clone = window.clone();
+                    window.request_resize_and_wait_for_paint(
+                        &webview,
+                        requested_physical_rect.size(),
+                        Box::new(move || {
+                            if let Err(error) = size_sender.send(window_clone.window_rect()) {
+                                warn!("Failed to send window size: {error}");
+                            }
+                        }),
+                    );
                 },
                 WebDriverCommandMsg::GetViewportSize(_webview_id, response_sender) => {
                     let window = self
@@ -3,13 +3,14 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
 use std::cell::{Ref, RefCell, RefMut};
-use std::collections::HashMap;
 use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
 use std::path::PathBuf;
 use std::rc::Rc;
 
 use crossbeam_channel::Receiver;
 use euclid::Vector2D;
+use image::RgbaImage;
 use keyboard_types::{Key, Modifiers, NamedKey, ShortcutMatcher};
 use log::{error, info};
 use servo::base::id::WebViewId;
@@ -31,7 +32,10 @@ use super::dialog::Dialog;
 use super::gamepad::GamepadSupport;
 use super::keyutils::CMD_OR_CONTROL;
 use super::window_trait::{LINE_HEIGHT, LINE_WIDTH, WindowPortsMethods};
-use crate::output_image::save_output_image_if_necessary;
+use crate::output_image::{
+    numbered_output_image_path, read_output_image, save_output_image,
+    save_output_image_if_necessary,
+};
 use crate::prefs::ServoShellPreferences;
 
 pub(crate) enum AppState {
@@ -93,6 +97,14 @@ pub struct RunningAppStateInner {
     /// Whether or not Servo needs to repaint its display. Currently this is global
     /// because every `WebView` shares a `RenderingContext`.
     need_repaint: bool,
+
+    /// Captures of webviews waiting for a frame that shows them, in the order that they were
+    /// requested. Only the webview of the first one is shown.
+    pending_captures: VecDeque<(WebViewId, Box<dyn FnOnce(Option<RgbaImage>)>)>,
+
+    /// Whether the output images of the other webviews have been requested, when exiting
+    /// after the first stable image.
+    exiting_after_stable_image: bool,
 }
 
 impl Drop for RunningAppState {
@@ -123,6 +135,8 @@ impl RunningAppState {
                 gamepad_support: GamepadSupport::maybe_new(),
                 need_update: false,
                 need_repaint: false,
+                pending_captures: Default::default(),
+                exiting_after_stable_image: false,
             }),
         }
     }
@@ -185,17 +199,104 @@ impl RunningAppState {
 
         // This needs to be done before presenting(), because `ReneringContext::read_to_image` reads
         // from the back buffer.
-        save_output_image_if_necessary(
-            &self.servoshell_preferences,
-            &self.inner().window.rendering_context(),
-        );
+        let rendering_context = self.inner().window.rendering_context();
+        let pending_capture = self.inner_mut().pending_captures.pop_front();
+        let captured_webview_id = pending_capture.as_ref().map(|(webview_id, _)| *webview_id);
+        match pending_capture {
+            Some((_, callback)) => callback(read_output_image(&rendering_context)),
+            None => {
+                save_output_image_if_necessary(&self.servoshell_preferences, &rendering_context)
+            },
+        }
 
         let mut inner_mut = self.inner_mut();
         inner_mut.window.rendering_context().present();
         inner_mut.need_repaint = false;
+        let window = inner_mut.window.clone();
+        drop(inner_mut);
+        window.notify_frame_painted();
+
+        if let Some(captured_webview_id) = captured_webview_id {
+            self.show_next_captured_webview(Some(captured_webview_id));
+        }
 
         if self.servoshell_preferences.exit_after_stable_image {
-            self.servo().start_shutting_down();
+            if !std::mem::replace(&mut self.inner_mut().exiting_after_stable_image, true) {
+                self.save_other_output_images();
+            }
+            // Exit once the images of the other webviews have been captured as well.
+            if self.inner().pending_captures.is_empty() {
+                self.servo().start_shutting_down();
+            }
+        }
+    }
+
+    /// Captures an output image of each webview other than the focused one, whose image has
+    /// just been saved. This lets headless runs capture the webviews opened by `window.open`.
+    /// The images are numbered in the order that the webviews were created.
+    fn save_other_output_images(&self) {
+        let Some(output_path) = self.servoshell_preferences.output_image_path.as_ref() else {
+            return;
+        };
+        let focused_webview_id = self.inner().focused_webview_id;
+        let other_webview_ids = self
+            .webviews()
+            .into_iter()
+            .map(|(webview_id, _)| webview_id)
+            .filter(|webview_id| Some(*webview_id) != focused_webview_id);
+        for (index, webview_id) in other_webview_ids.enumerate() {
+            let output_path = numbered_output_image_path(output_path, index + 1);
+            self.capture_webview(
+                webview_id,
+                Box::new(move |image| {
+                    if let Some(image) = image {
+                        save_output_image(&output_path, image);
+                    }
+                }),
+            );
+        }
+    }
+
+    /// Captures the contents of the webview with the given id, whether or not it is focused.
+    /// The webview is shown in place of the others, and `callback` is called with the next
+    /// frame painted, or with `None` if the webview does not exist or the frame could not be
+    /// read. The focused webview is shown again once there are no captures left.
+    pub(crate) fn capture_webview(
+        &self,
+        webview_id: WebViewId,
+        callback: Box<dyn FnOnce(Option<RgbaImage>)>,
+    ) {
+        if !self.inner().webviews.contains_key(&webview_id) {
+            callback(None);
+            return;
+        }
+
+        let mut inner_mut = self.inner_mut();
+        inner_mut.pending_captures.push_back((webview_id, callback));
+        if inner_mut.pending_captures.len() == 1 {
+            let shown_webview_id = inner_mut.focused_webview_id;
+            drop(inner_mut);
+            self.show_next_captured_webview(shown_webview_id);
+        }
+    }
+
+    /// Shows the webview of the first pending capture, or the focused webview if there is
+    /// none. Showing a different webview leads to a new frame, but if `shown_webview_id` is
+    /// already the one to show, a repaint has to be requested directly.
+    fn show_next_captured_webview(&self, shown_webview_id: Option<WebViewId>) {
+        let mut inner_mut = self.inner_mut();
+        let webview_id = inner_mut
+            .pending_captures
+            .front()
+            .map(|(webview_id, _)| *webview_id)
+            .or(inner_mut.focused_webview_id);
+        if webview_id == shown_webview_id {
+            inner_mut.need_repaint = true;
+            return;
+        }
+        if let Some(webview) = webview_id.and_then(|webview_id| inner_mut.webviews.get(&webview_id))
+        {
+            webview.show(true);
         }
     }
 
@@ -683,6 +784,9 @@ impl WebViewDelegate for RunningAppState {
         self.inner_mut().need_update = true;
 
         if status == LoadStatus::Complete {
+            if let Some(time) = self.inner().window.pinned_animation_time() {
+                webview.pin_animation_timeline(time);
+            }
             if let Some(sender) = self
                 .webdriver_senders
                 .borrow_mut()
@@ -4,8 +4,9 @@
 
 //! A headless window implementation.
 
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use euclid::num::Zero;
 use euclid::{Length, Point2D, Scale, Size2D};
@@ -13,7 +14,7 @@ use servo::servo_geometry::{
     DeviceIndependentIntRect, DeviceIndependentPixel, convert_rect_to_css_pixel,
 };
 use servo::webrender_api::units::{DeviceIntPoint, DeviceIntRect, DeviceIntSize, DevicePixel};
-use servo::{RenderingContext, ScreenGeometry, SoftwareRenderingContext, WebView};
+use servo::{RenderingContext, ScreenGeometry, SoftwareRenderingContext, Theme, WebView};
 use winit::dpi::PhysicalSize;
 
 use super::app_state::RunningAppState;
@@ -28,6 +29,39 @@ pub struct Window {
     // virtual top-left position of the window in device pixels.
     window_position: Cell<Point2D<i32, DevicePixel>>,
     rendering_context: Rc<SoftwareRenderingContext>,
+    theme: Theme,
+    pinned_animation_time: Option<Duration>,
+    render_seed: Option<u64>,
+    /// Callbacks waiting for the first frame painted after a resize.
+    resize_paint_callbacks: RefCell<PaintCallbacks>,
+}
+
+/// How long a resize waits for a frame painted at the new size. Resizes that don't change
+/// the layout of the page may never produce one.
+const RESIZE_PAINT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Callbacks waiting for the next painted frame, each until its deadline at the latest.
+#[derive(Default)]
+struct PaintCallbacks(Vec<(Instant, Box<dyn FnOnce()>)>);
+
+impl PaintCallbacks {
+    fn push(&mut self, deadline: Instant, callback: Box<dyn FnOnce()>) {
+        self.0.push((deadline, callback));
+    }
+
+    /// Removes every callback, to be called now that a frame has been painted.
+    fn take_all(&mut self) -> Vec<Box<dyn FnOnce()>> {
+        self.0.drain(..).map(|(_, callback)| callback).collect()
+    }
+
+    /// Removes the callbacks whose deadline has passed at `now`.
+    fn take_expired(&mut self, now: Instant) -> Vec<Box<dyn FnOnce()>> {
+        let (expired, waiting) = std::mem::take(&mut self.0)
+            .into_iter()
+            .partition(|(deadline, _)| *deadline <= now);
+        self.0 = waiting;
+        expired.into_iter().map(|(_, callback)| callback).collect()
+    }
 }
 
 impl Window {
@@ -58,6 +92,12 @@ impl Window {
             screen_size,
             window_position: Cell::new(Point2D::zero()),
             rendering_context: Rc::new(rendering_context),
+            theme: servoshell_preferences
+                .theme_override
+                .unwrap_or(Theme::Light),
+            pinned_animation_time: servoshell_preferences.pinned_animation_time,
+            render_seed: servoshell_preferences.render_seed,
+            resize_paint_callbacks: Default::default(),
         };
 
         Rc::new(window)
@@ -111,6 +151,43 @@ impl WindowPortsMethods for Window {
         Some(new_size)
     }
 
+    fn request_resize_and_wait_for_paint(
+        &self,
+        webview: &WebView,
+        outer_size: DeviceIntSize,
+        on_painted: Box<dyn FnOnce()>,
+    ) {
+        let old_size = self.inner_size.get();
+        self.request_resize(webview, outer_size);
+        if self.inner_size.get() == old_size {
+            on_painted();
+            return;
+        }
+
+        // When Servo is waiting for a stable image, the first frame painted after this is
+        // only painted once the page has been reflowed at the new size.
+        self.resize_paint_callbacks
+            .borrow_mut()
+            .push(Instant::now() + RESIZE_PAINT_TIMEOUT, on_painted);
+    }
+
+    fn notify_frame_painted(&self) {
+        let callbacks = self.resize_paint_callbacks.borrow_mut().take_all();
+        for callback in callbacks {
+            callback();
+        }
+    }
+
+    fn expire_paint_callbacks(&self) {
+        let callbacks = self
+            .resize_paint_callbacks
+            .borrow_mut()
+            .take_expired(Instant::now());
+        for callback in callbacks {
+            callback();
+        }
+    }
+
     fn device_hidpi_scale_factor(&self) -> Scale<f32, DeviceIndependentPixel, DevicePixel> {
         Scale::new(1.0)
     }
@@ -153,6 +230,18 @@ impl WindowPortsMethods for Window {
         Length::zero()
     }
 
+    fn theme(&self) -> Theme {
+        self.theme
+    }
+
+    fn pinned_animation_time(&self) -> Option<Duration> {
+        self.pinned_animation_time
+    }
+
+    fn render_seed(&self) -> Option<u64> {
+        self.render_seed
+    }
+
     fn window_rect(&self) -> DeviceIndependentIntRect {
         convert_rect_to_css_pixel(
             DeviceIntRect::from_origin_and_size(self.window_position.get(), self.inner_size.get()),
@@ -181,3 +270,58 @@ impl WindowPortsMethods for Window {
         ));
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    use servo::Theme;
+
+    use super::{PaintCallbacks, Window};
+    use crate::prefs::ServoShellPreferences;
+
+    #[test]
+    fn test_paint_callbacks() {
+        let called = Rc::new(Cell::new(0));
+        let callback = || -> Box<dyn FnOnce()> {
+            let called = called.clone();
+            Box::new(move || called.set(called.get() + 1))
+        };
+        let run = |callbacks: Vec<Box<dyn FnOnce()>>| callbacks.into_iter().for_each(|f| f());
+        let now = Instant::now();
+        let mut callbacks = PaintCallbacks::default();
+
+        // A painted frame runs every callback, whatever its deadline.
+        callbacks.push(now + Duration::from_secs(1), callback());
+        callbacks.push(now + Duration::from_secs(2), callback());
+        run(callbacks.take_expired(now));
+        assert_eq!(called.get(), 0);
+        run(callbacks.take_all());
+        assert_eq!(called.get(), 2);
+
+        // Without one, each callback runs once its deadline has passed.
+        callbacks.push(now + Duration::from_secs(1), callback());
+        callbacks.push(now + Duration::from_secs(2), callback());
+        run(callbacks.take_expired(now + Duration::from_secs(1)));
+        assert_eq!(called.get(), 3);
+        run(callbacks.take_expired(now + Duration::from_secs(3)));
+        assert_eq!(called.get(), 4);
+        assert!(callbacks.take_all().is_empty());
+    }
+
+    #[test]
+    fn test_theme_override() {
+        let theme = |theme_override| {
+            let servoshell_preferences = ServoShellPreferences {
+                theme_override,
+                ..Default::default()
+            };
+            Window::new(&servoshell_preferences).theme()
+        };
+        assert_eq!(theme(None), Theme::Light);
+        assert_eq!(theme(Some(Theme::Dark)), Theme::Dark);
+        assert_eq!(theme(Some(Theme::Light)), Theme::Light);
+    }
+}
@@ -6,6 +6,7 @@
 //! Implemented by headless and headed windows.
 
 use std::rc::Rc;
+use std::time::Duration;
 
 use euclid::{Length, Scale};
 use servo::servo_geometry::{DeviceIndependentIntRect, DeviceIndependentPixel};
@@ -40,6 +41,24 @@ pub trait WindowPortsMethods {
     /// This should be the same as `window.outerWidth` and `window.outerHeight``
     fn request_resize(&self, webview: &WebView, outer_size: DeviceIntSize)
     -> Option<DeviceIntSize>;
+    /// Like [`Self::request_resize`], but `on_painted` is only called once a frame laid out at
+    /// the new size has been painted, so that anything captured from then on is not stale.
+    /// Windows that are resized by the platform have no such frame to wait for and call it
+    /// immediately.
+    fn request_resize_and_wait_for_paint(
+        &self,
+        webview: &WebView,
+        outer_size: DeviceIntSize,
+        on_painted: Box<dyn FnOnce()>,
+    ) {
+        self.request_resize(webview, outer_size);
+        on_painted();
+    }
+    /// Called after a frame has been painted to this window.
+    fn notify_frame_painted(&self) {}
+    /// Called on every iteration of the event loop, so that callbacks of
+    /// [`Self::request_resize_and_wait_for_paint`] whose frame never comes are still called.
+    fn expire_paint_callbacks(&self) {}
     fn set_position(&self, _point: DeviceIntPoint) {}
     fn set_fullscreen(&self, _state: bool) {}
     fn set_cursor(&self, _cursor: Cursor) {}
@@ -64,6 +83,15 @@ pub trait WindowPortsMethods {
     fn theme(&self) -> servo::Theme {
         servo::Theme::Light
     }
+    /// The time after their creation at which the animation timelines of loaded documents
+    /// should be pinned, if any.
+    fn pinned_animation_time(&self) -> Option<Duration> {
+        None
+    }
+    /// The seed of any randomness that affects rendering, if rendering should be reproducible.
+    fn render_seed(&self) -> Option<u64> {
+        None
+    }
     fn window_rect(&self) -> DeviceIndependentIntRect;
     fn maximize(&self, webview: &WebView);
 }
@@ -2,10 +2,11 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use std::path::Path;
 use std::rc::Rc;
 
 use euclid::Point2D;
-use image::{DynamicImage, ImageFormat};
+use image::{DynamicImage, ImageFormat, RgbaImage};
 use log::error;
 use servo::RenderingContext;
 use servo::webrender_api::units::DeviceIntRect;
@@ -23,17 +24,45 @@ pub(crate) fn save_output_image_if_necessary<T>(
     let Some(output_path) = prefs.output_image_path.as_ref() else {
         return;
     };
+    let Some(image) = read_output_image(rendering_context) else {
+        return;
+    };
+    save_output_image(output_path, image);
+}
 
+/// Reads the whole back buffer of `rendering_context`, which must be done before presenting.
+pub(crate) fn read_output_image<T>(rendering_context: &Rc<T>) -> Option<RgbaImage>
+where
+    T: RenderingContext + ?Sized,
+{
     let size = rendering_context.size2d().to_i32();
     let viewport_rect = DeviceIntRect::from_origin_and_size(Point2D::origin(), size);
-    let Some(image) = rendering_context.read_to_image(viewport_rect) else {
+    let image = rendering_context.read_to_image(viewport_rect);
+    if image.is_none() {
         error!("Failed to read output image.");
-        return;
-    };
+    }
+    image
+}
 
+/// Saves `image` to `output_path`, in the format matching its extension or as a PNG.
+pub(crate) fn save_output_image(output_path: &str, image: RgbaImage) {
     let image_format = ImageFormat::from_path(output_path).unwrap_or(ImageFormat::Png);
     if let Err(error) = DynamicImage::ImageRgba8(image).save_with_format(output_path, image_format)
     {
         error!("Failed to save {output_path}: {error}.");
     }
 }
+
+/// Returns the path of the output image numbered `number`, which is `output_path` with the
+/// number appended to the file stem, so that `out.png` becomes `out-1.png`.
+pub(crate) fn numbered_output_image_path(output_path: &str, number: usize) -> String {
+    let path = Path::new(output_path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{stem}-{number}.{}", extension.to_string_lossy()),
+        None => format!("{stem}-{number}"),
+    };
+    path.with_file_name(file_name)
+        .to_string_lossy()
+        .into_owned()
+}
@@ -8,12 +8,14 @@ use std::io::Read;
 use std::path::{Path, PathBuf};
 #[cfg(any(target_os = "android", target_env = "ohos"))]
 use std::sync::OnceLock;
+use std::time::Duration;
 use std::{env, fs, process};
 
 use euclid::Size2D;
 use getopts::{Matches, Options};
 use log::{error, warn};
 use serde_json::Value;
+use servo::Theme;
 use servo::config::opts::{DebugOptions, Opts, OutputOptions};
 use servo::config::prefs::{PrefValue, Preferences};
 use servo::servo_geometry::DeviceIndependentPixel;
@@ -49,6 +51,16 @@ pub(crate) struct ServoShellPreferences {
     /// An override for the screen resolution. This is useful for testing behavior on different screen sizes,
     /// such as the screen of a mobile device.
     pub screen_size_override: Option<Size2D<u32, DeviceIndependentPixel>>,
+    /// An override for the platform theme reported by headless windows, which is what the
+    /// `prefers-color-scheme` media feature matches against. This makes it possible to test
+    /// themed content without changing operating system settings.
+    pub theme_override: Option<Theme>,
+    /// A time after the creation of each loaded document at which headless windows pin its
+    /// animation timeline, so that animated content is captured deterministically.
+    pub pinned_animation_time: Option<Duration>,
+    /// A seed for any randomness that affects rendering in headless mode, so that rendering
+    /// the same content again produces identical output.
+    pub render_seed: Option<u64>,
     /// If not-None, the path to a file to output the default WebView's rendered output
     /// after waiting for a stable image, this implies `Self::exit_after_load`.
     pub output_image_path: Option<String>,
@@ -81,6 +93,9 @@ impl Default for ServoShellPreferences {
             initial_window_size: Size2D::new(1024, 740),
             no_native_titlebar: true,
             screen_size_override: None,
+            theme_override: None,
+            pinned_animation_time: None,
+            render_seed: None,
             searchpage: "https://duckduckgo.com/html/?q=%s".into(),
             tracing_filter: None,
             url: None,
@@ -283,6 +298,31 @@ pub(crate) fn parse_command_line_arguments(args: Vec<String>) -> ArgumentParsing
         "Override the screen resolution in logical (device independent) pixels",
         "1024x768",
     );
+    opts.optopt(
+        "",
+        "theme",
+        "Override the platform theme reported to web content in headless mode",
+        "light|dark",
+    );
+    opts.optopt(
+        "",
+        "animation-time",
+        "Render animations in headless mode as they are this many milliseconds after the \
+         creation of the document",
+        "500",
+    );
+    opts.optopt(
+        "",
+        "render-seed",
+        "Seed any randomness that affects rendering in headless mode, for reproducible output",
+        "1234",
+    );
+    opts.optopt(
+        "",
+        "backdrop-color",
+        "Set the color painted behind pages and the parts of the viewport that they do not cover",
+        "#rrggbb[aa]",
+    );
     opts.optflag("M", "multiprocess", "Run in multiprocess mode");
     opts.optflag("B", "bhm", "Background Hang Monitor enabled");
     opts.optflag("S", "sandbox", "Run in a sandbox if multiprocess");
@@ -559,6 +599,31 @@ pub(crate) fn parse_command_line_arguments(args: Vec<String>) -> ArgumentParsing
         .opt_str("screen-size")
         .map(parse_resolution_string);
 
+    let theme_override = opt_match
+        .opt_str("theme")
+        .map(|theme| match theme.as_str() {
+            "light" => Theme::Light,
+            "dark" => Theme::Dark,
+            _ => args_fail(&format!(
+                "Error parsing option: --theme (expected light or dark, got {theme})"
+            )),
+        });
+
+    let pinned_animation_time = opt_match.opt_str("animation-time").map(|milliseconds| {
+        milliseconds
+            .parse()
+            .map(Duration::from_millis)
+            .unwrap_or_else(|err| {
+                args_fail(&format!("Error parsing option: --animation-time ({})", err))
+            })
+    });
+
+    let render_seed = opt_match.opt_str("render-seed").map(|seed| {
+        seed.parse().unwrap_or_else(|err| {
+            args_fail(&format!("Error parsing option: --render-seed ({})", err))
+        })
+    });
+
     // Make sure the default window size is not larger than any provided screen size.
     let default_window_size = Size2D::new(1024, 740);
     let default_window_size = screen_size_override
@@ -665,6 +730,9 @@ pub(crate) fn parse_command_line_arguments(args: Vec<String>) -> ArgumentParsing
         tracing_filter,
         initial_window_size,
         screen_size_override,
+        theme_override,
+        pinned_animation_time,
+        render_seed,
         output_image_path,
         exit_after_stable_image: exit_after_load,
         userscripts_directory: opt_match
@@ -687,6 +755,14 @@ pub(crate) fn parse_command_line_arguments(args: Vec<String>) -> ArgumentParsing
         preferences.user_agent = user_agent;
     }
 
+    if let Some(color) = opt_match.opt_str("backdrop-color") {
+        preferences.shell_background_color_rgba = parse_hex_color(&color).unwrap_or_else(|| {
+            args_fail(&format!(
+                "Error parsing option: --backdrop-color (expected #rrggbb[aa], got {color})"
+            ))
+        });
+    }
+
     let opts = Opts {
         debug: debug_options.clone(),
         wait_for_stable_image,
@@ -713,6 +789,20 @@ pub(crate) fn parse_command_line_arguments(args: Vec<String>) -> ArgumentParsing
     ArgumentParsingResult::ChromeProcess(opts, preferences, servoshell_preferences)
 }
 
+/// Parses a `#rrggbb` or `#rrggbbaa` color into normalized RGBA components.
+fn parse_hex_color(color: &str) -> Option<[f64; 4]> {
+    let hex = color.strip_prefix('#')?;
+    if !matches!(hex.len(), 6 | 8) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut components = [1.0; 4];
+    for (index, component) in components.iter_mut().enumerate().take(hex.len() / 2) {
+        let value = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+        *component = value as f64 / 255.0;
+    }
+    Some(components)
+}
+
 fn args_fail(msg: &str) -> ! {
     eprintln!("{}", msg);
     process::exit(1)
@@ -856,6 +946,86 @@ fn test_invalid_prefs_from_command_line_panics() {
     )
 }
 
+#[test]
+fn test_parse_theme_override_from_command_line() {
+    let parse_theme_override = |args: &[&str]| {
+        let args = args.iter().map(ToString::to_string).collect();
+        match parse_command_line_arguments(args) {
+            ArgumentParsingResult::ContentProcess(..) => {
+                unreachable!("No preferences for content process")
+            },
+            ArgumentParsingResult::ChromeProcess(_, _, servoshell_preferences) => {
+                servoshell_preferences.theme_override
+            },
+        }
+    };
+
+    assert_eq!(parse_theme_override(&["servo"]), None);
+    assert_eq!(
+        parse_theme_override(&["servo", "--theme", "dark"]),
+        Some(Theme::Dark)
+    );
+    assert_eq!(
+        parse_theme_override(&["servo", "--theme", "light"]),
+        Some(Theme::Light)
+    );
+}
+
+#[test]
+fn test_parse_pinned_animation_time_from_command_line() {
+    let args = ["servo", "--animation-time", "500"]
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    match parse_command_line_arguments(args) {
+        ArgumentParsingResult::ContentProcess(..) => {
+            unreachable!("No preferences for content process")
+        },
+        ArgumentParsingResult::ChromeProcess(_, _, servoshell_preferences) => assert_eq!(
+            servoshell_preferences.pinned_animation_time,
+            Some(Duration::from_millis(500))
+        ),
+    }
+}
+
+#[test]
+fn test_parse_render_seed_from_command_line() {
+    let args = ["servo", "--render-seed", "1234"]
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    match parse_command_line_arguments(args) {
+        ArgumentParsingResult::ContentProcess(..) => {
+            unreachable!("No preferences for content process")
+        },
+        ArgumentParsingResult::ChromeProcess(_, _, servoshell_preferences) => {
+            assert_eq!(servoshell_preferences.render_seed, Some(1234))
+        },
+    }
+}
+
+#[test]
+fn test_parse_backdrop_color_from_command_line() {
+    let args = ["servo", "--backdrop-color", "#00ff0080"]
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    match parse_command_line_arguments(args) {
+        ArgumentParsingResult::ContentProcess(..) => {
+            unreachable!("No preferences for content process")
+        },
+        ArgumentParsingResult::ChromeProcess(_, preferences, _) => assert_eq!(
+            preferences.shell_background_color_rgba,
+            [0.0, 1.0, 0.0, 128.0 / 255.0]
+        ),
+    }
+
+    assert_eq!(parse_hex_color("#ff0000"), Some([1.0, 0.0, 0.0, 1.0]));
+    assert_eq!(parse_hex_color("ff0000"), None);
+    assert_eq!(parse_hex_color("#ff00"), None);
+    assert_eq!(parse_hex_color("#gg0000"), None);
+}
+
 #[test]
 fn test_create_prefs_map() {
     let json_str = "{
@@ -4,6 +4,7 @@
 
 use std::path::Path;
 
+use crate::output_image::numbered_output_image_path;
 use crate::parser::{get_default_url, location_bar_input_to_url, parse_url_or_filename};
 
 #[cfg(not(target_os = "windows"))]
@@ -283,3 +284,14 @@ fn test_issue_35754() {
         "https://foo/bar",
     );
 }
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn test_numbered_output_image_path() {
+    assert_eq!(numbered_output_image_path("out.png", 1), "out-1.png");
+    assert_eq!(
+        numbered_output_image_path("captures/out.jpg", 2),
+        "captures/out-2.jpg"
+    );
+    assert_eq!(numbered_output_image_path("out", 3), "out-3");
+}